@@ -0,0 +1,84 @@
+#[macro_use]
+extern crate criterion;
+extern crate bitcoin_gcs;
+
+use criterion::{BenchmarkId, Criterion};
+
+use bitcoin_gcs::golomb::GolombEncoder;
+use bitcoin_gcs::{BASIC_FILTER_M, BASIC_FILTER_P, Filter, FilterKey};
+
+const SIZES: [usize; 3] = [10, 10_000, 1_000_000];
+
+fn items(n: usize) -> Vec<Vec<u8>> {
+    (0..n).map(|i| format!("item {}", i).into_bytes()).collect()
+}
+
+fn key() -> FilterKey {
+    FilterKey::new(0x0706050403020100, 0x0f0e0d0c0b0a0908)
+}
+
+fn build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    group.sample_size(10);
+
+    for n in SIZES.iter() {
+        let data = items(*n);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &data, |b, data| {
+            b.iter(|| Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key(), data))
+        });
+    }
+
+    group.finish();
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    group.sample_size(10);
+
+    // The differences between the values of a basic filter, which have a
+    // quotient of 1 on average.
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key(), items(1_000_000));
+    let mut last_value = 0;
+    let deltas = filter.values()
+        .map(|value| {
+            let value = value.unwrap();
+            let delta = value - last_value;
+            last_value = value;
+            delta
+        })
+        .collect::<Vec<u64>>();
+
+    group.bench_with_input(BenchmarkId::from_parameter(deltas.len()), &deltas, |b, deltas| {
+        b.iter(|| {
+            let mut encoder = GolombEncoder::new(Vec::with_capacity(filter.as_bytes().len()), BASIC_FILTER_P);
+            for delta in deltas {
+                encoder.encode(*delta).unwrap();
+            }
+            encoder.finish().unwrap()
+        })
+    });
+
+    group.finish();
+}
+
+fn match_any(c: &mut Criterion) {
+    let mut group = c.benchmark_group("match_any");
+    group.sample_size(10);
+
+    // A hundred queries, none of them in the filter so it's decoded fully.
+    let queries = (0..100)
+        .map(|i| format!("query {}", i).into_bytes())
+        .collect::<Vec<Vec<u8>>>();
+
+    for n in SIZES.iter() {
+        let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key(), items(*n));
+        group.bench_with_input(BenchmarkId::from_parameter(n), &filter, |b, filter| {
+            b.iter(|| filter.is_member_any(key(), &queries))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, build, encode, match_any);
+criterion_main!(benches);
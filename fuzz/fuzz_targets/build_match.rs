@@ -0,0 +1,18 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate bitcoin_gcs;
+
+use bitcoin_gcs::{BASIC_FILTER_M, BASIC_FILTER_P, Filter, FilterKey};
+
+// Every item a filter is built from must match it.
+fuzz_target!(|data: &[u8]| {
+    let items = data.chunks(7).collect::<Vec<&[u8]>>();
+    let key = FilterKey::new(0x0706050403020100, 0x0f0e0d0c0b0a0908);
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &items);
+
+    assert_eq!(filter.validate_encoding(), Ok(()));
+    for item in &items {
+        assert_eq!(filter.is_member(key, item), Ok(true));
+    }
+});
@@ -0,0 +1,22 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate bitcoin_gcs;
+
+use bitcoin_gcs::{BASIC_FILTER_M, BASIC_FILTER_P, Filter, FilterKey};
+
+// Decoding and matching arbitrary filter data must never panic.
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 4 {
+        return;
+    }
+
+    let n = u32::from(data[0]) | (u32::from(data[1]) << 8);
+    let filter = Filter::from_bytes(n, BASIC_FILTER_P, BASIC_FILTER_M, data[2..].to_vec());
+    let key = FilterKey::default();
+
+    let _ = filter.validate_encoding();
+    let _ = filter.is_member(key, &data[..2]);
+    let _ = filter.is_member_any(key, &[&data[..1], &data[1..2]]);
+    let _ = filter.values().count();
+});
@@ -0,0 +1,85 @@
+#ifndef BITCOIN_GCS_H
+#define BITCOIN_GCS_H
+
+/* This file is generated by cbindgen, don't edit it manually. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+// Describes a serialized Golomb Coded Set (GCS) filter.
+typedef struct GcsFilter GcsFilter;
+
+// Builds a filter with parameters `p` and `m` from `count` items.
+//
+// Returns null if any pointer is invalid, P is larger than 32 or `N * M`
+// doesn't fit in 64 bits.
+//
+// # Safety
+//
+// `key` must point to 16 bytes, `items` and `item_lens` to `count` elements
+// each, and every item to as many bytes as its length.
+struct GcsFilter *gcs_filter_build(uint8_t p,
+                                   uint64_t m,
+                                   const uint8_t *key,
+                                   const uint8_t *const *items,
+                                   const size_t *item_lens,
+                                   size_t count);
+
+// Creates a filter from its set length (N), parameters and Golomb-Rice
+// coded data.
+//
+// Returns null if `data` is null, P is larger than 32 or `N * M` doesn't
+// fit in 64 bits.
+//
+// # Safety
+//
+// `data` must point to `len` bytes.
+struct GcsFilter *gcs_filter_from_bytes(uint32_t n,
+                                        uint8_t p,
+                                        uint64_t m,
+                                        const uint8_t *data,
+                                        size_t len);
+
+// Checks whether any of `count` items is likely to be a member of the
+// filter.
+//
+// Returns 1 on a match, 0 if there's none and -1 if any pointer is invalid
+// or the filter data is corrupt.
+//
+// # Safety
+//
+// `filter` must have been created by this library, `key` must point to 16
+// bytes, `items` and `item_lens` to `count` elements each, and every item
+// to as many bytes as its length.
+int gcs_filter_match(const struct GcsFilter *filter,
+                     const uint8_t *key,
+                     const uint8_t *const *items,
+                     const size_t *item_lens,
+                     size_t count);
+
+// Returns the set length (N) of the filter.
+//
+// # Safety
+//
+// `filter` must have been created by this library.
+uint32_t gcs_filter_n(const struct GcsFilter *filter);
+
+// Returns a pointer to the Golomb-Rice coded data of the filter, and writes
+// its length to `len`.
+//
+// The pointer is valid until the filter is freed.
+//
+// # Safety
+//
+// `filter` must have been created by this library and `len` must be a
+// valid pointer.
+const uint8_t *gcs_filter_data(const struct GcsFilter *filter, size_t *len);
+
+// Frees a filter, null pointers are ignored.
+//
+// # Safety
+//
+// `filter` must have been created by this library and not freed before.
+void gcs_filter_free(struct GcsFilter *filter);
+
+#endif  /* BITCOIN_GCS_H */
@@ -0,0 +1,142 @@
+//! Integrity checks of stored filters.
+//!
+//! [`verify_store`][1] rebuilds a random sample of the filters of a
+//! [`FilterStore`][2] from their blocks and re-derives their filter headers,
+//! so a long-running filter server can check in the background that it
+//! isn't serving filters or headers corrupted on disk or built by a buggy
+//! version.
+//!
+//! [1]: fn.verify_store.html
+//! [2]: ../store/struct.FilterStore.html
+
+use bitcoin::blockdata::block::Block;
+use bitcoin::blockdata::script::Script;
+use bitcoin::network::serialize::BitcoinHash;
+
+use rand::{thread_rng, Rng};
+
+use builder::{verify_basic_filter, FilterMismatch};
+use store::FilterStore;
+use {FilterHeader, FilterHeaderChain};
+
+/// A source of the blocks of the best chain, e.g. the block storage of a
+/// node.
+pub trait FullBlockSource {
+    /// The error returned when a block can't be retrieved.
+    type Error;
+
+    /// Returns the block at `height` along with the previous output scripts
+    /// it spends, in the order of its inputs, or `None` if the source
+    /// doesn't have it.
+    fn block(&mut self, height: u32) -> Result<Option<(Block, Vec<Script>)>, Self::Error>;
+}
+
+/// An inconsistency found by [`verify_store`][1].
+///
+/// [1]: fn.verify_store.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// The store has no filter at the given height.
+    MissingFilter(u32),
+    /// The filter at the given height can't be read from the store.
+    UnreadableFilter(u32),
+    /// The block source has no block at the given height.
+    MissingBlock(u32),
+    /// The filter at the given height was stored for another block.
+    BlockHashMismatch(u32),
+    /// The filter at the given height isn't the filter of its block.
+    FilterMismatch(u32, FilterMismatch),
+    /// The filter header at the given height isn't the header of the stored
+    /// filter.
+    HeaderMismatch(u32),
+}
+
+/// The result of [`verify_store`][1].
+///
+/// [1]: fn.verify_store.html
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditReport {
+    /// The heights of the sampled filters.
+    pub checked: Vec<u32>,
+    /// The inconsistencies found, in the order of their heights, at most
+    /// one per height.
+    pub inconsistencies: Vec<Inconsistency>,
+}
+
+impl AuditReport {
+    /// Returns `true` if no inconsistency was found.
+    pub fn is_consistent(&self) -> bool {
+        self.inconsistencies.is_empty()
+    }
+}
+
+/// Checks a random sample of the basic filters of the blocks of
+/// `header_chain` stored in `store`, each block being sampled with
+/// probability `sample_rate`.
+///
+/// The filter of each sampled block is rebuilt from the block, and its
+/// filter header is derived from the stored filter and the previous header
+/// of the chain, unless the previous header is below the checkpoint of the
+/// chain. An error is returned only if the block source fails.
+///
+/// # Panics
+///
+/// This function panics if `sample_rate` isn't in the range `[0, 1]`.
+pub fn verify_store<B: FullBlockSource>(store: &FilterStore,
+                                        header_chain: &FilterHeaderChain,
+                                        block_source: &mut B,
+                                        sample_rate: f64) -> Result<AuditReport, B::Error> {
+    assert!((0.0..=1.0).contains(&sample_rate), "the sample rate must be in the range [0, 1]");
+
+    let mut rng = thread_rng();
+    let mut report = AuditReport::default();
+    for height in header_chain.start_height()..header_chain.len() as u32 {
+        if !rng.gen_bool(sample_rate) {
+            continue;
+        }
+
+        report.checked.push(height);
+        if let Some(inconsistency) = verify_height(store, header_chain, block_source, height)? {
+            report.inconsistencies.push(inconsistency);
+        }
+    }
+
+    Ok(report)
+}
+
+fn verify_height<B: FullBlockSource>(store: &FilterStore,
+                                     header_chain: &FilterHeaderChain,
+                                     block_source: &mut B,
+                                     height: u32) -> Result<Option<Inconsistency>, B::Error> {
+    let block_hash = match store.block_hash(height) {
+        Some(block_hash) => block_hash,
+        None => return Ok(Some(Inconsistency::MissingFilter(height))),
+    };
+    let filter = match store.get(height) {
+        Ok(Some(filter)) => filter,
+        Ok(None) => return Ok(Some(Inconsistency::MissingFilter(height))),
+        Err(_) => return Ok(Some(Inconsistency::UnreadableFilter(height))),
+    };
+
+    let prev_header = match height {
+        0 => Some(FilterHeader::zero()),
+        _ => header_chain.get(height - 1).cloned(),
+    };
+    if let (Some(prev_header), Some(header)) = (prev_header, header_chain.get(height)) {
+        if filter.filter_header(&prev_header) != *header {
+            return Ok(Some(Inconsistency::HeaderMismatch(height)));
+        }
+    }
+
+    let (block, prev_scripts) = match block_source.block(height)? {
+        Some(block) => block,
+        None => return Ok(Some(Inconsistency::MissingBlock(height))),
+    };
+    if block.bitcoin_hash() != block_hash {
+        return Ok(Some(Inconsistency::BlockHashMismatch(height)));
+    }
+
+    Ok(verify_basic_filter(&block, &prev_scripts, &filter)
+        .err()
+        .map(|mismatch| Inconsistency::FilterMismatch(height, mismatch)))
+}
@@ -0,0 +1,156 @@
+//! Builds, matches and inspects BIP158 basic filters, to debug them against
+//! the ones returned by the `getblockfilter` RPC call of Bitcoin Core.
+
+extern crate bitcoin;
+extern crate bitcoin_gcs;
+
+use std::env;
+use std::fs;
+use std::process;
+
+use bitcoin::blockdata::block::Block;
+use bitcoin::blockdata::script::Script;
+use bitcoin::network::serialize::{deserialize, BitcoinHash};
+use bitcoin::util::hash::Sha256dHash;
+
+use bitcoin_gcs::builder::build_basic_filter_with_prev_scripts;
+use bitcoin_gcs::{Filter, FilterKey};
+
+const USAGE: &str = "\
+Usage:
+    gcs-filter build <block-file> [<prev-scripts-file>]
+    gcs-filter match <filter> <block-hash> <scripts-file>
+    gcs-filter inspect <filter> [--values]
+
+Blocks are read as the hex returned by `getblock <hash> 0`, and scripts as
+one hex script per line. Filters are given as the hex of the `filter` field
+returned by `getblockfilter`.
+
+The filter of a block spending outputs only matches the one of
+`getblockfilter` if the scripts of the spent outputs are given, in the order
+of the inputs.";
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<&str>>();
+
+    let result = match args[..] {
+        ["build", block] => build(block, None),
+        ["build", block, prev_scripts] => build(block, Some(prev_scripts)),
+        ["match", filter, block_hash, scripts] => match_scripts(filter, block_hash, scripts),
+        ["inspect", filter] => inspect(filter, false),
+        ["inspect", filter, "--values"] => inspect(filter, true),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Builds the basic filter of a block.
+fn build(block: &str, prev_scripts: Option<&str>) -> Result<(), String> {
+    let block = read_hex(block)?;
+    let block: Block = deserialize(&block).map_err(|e| format!("invalid block: {}", e))?;
+    let prev_scripts = match prev_scripts {
+        Some(path) => read_scripts(path)?,
+        None => {
+            let inputs = block.txdata.iter().skip(1).map(|tx| tx.input.len()).sum::<usize>();
+            if inputs > 0 {
+                eprintln!("warning: the block spends {} outputs but no previous output scripts were given, the \
+                           filter won't match getblockfilter", inputs);
+            }
+            Vec::new()
+        }
+    };
+
+    let filter = build_basic_filter_with_prev_scripts(&block, &prev_scripts);
+    println!("block hash: {}", block.bitcoin_hash().be_hex_string());
+    println!("filter: {}", filter);
+    Ok(())
+}
+
+/// Matches each script against the filter of a block.
+fn match_scripts(filter: &str, block_hash: &str, scripts: &str) -> Result<(), String> {
+    let filter = parse_filter(filter)?;
+    let block_hash = Sha256dHash::from_hex(block_hash).map_err(|_| "invalid block hash".to_owned())?;
+    let scripts = read_scripts(scripts)?;
+
+    let matches = filter.match_all(FilterKey::from(&block_hash), scripts.iter().map(Script::data))
+        .map_err(|e| format!("invalid filter: {}", e))?;
+    for (script, matched) in scripts.iter().zip(matches) {
+        let script = script.data();
+        println!("{} {}", encode_hex(&script), if matched { "match" } else { "no match" });
+    }
+    Ok(())
+}
+
+/// Prints the parameters and statistics of a filter, and its values.
+fn inspect(filter: &str, values: bool) -> Result<(), String> {
+    let filter = parse_filter(filter)?;
+    let stats = filter.detailed_stats().map_err(|e| format!("invalid filter: {}", e))?;
+
+    println!("n: {}", filter.n());
+    println!("p: {}", filter.p());
+    println!("m: {}", filter.m());
+    println!("serialized size: {}", stats.serialized_size);
+    println!("bits per entry: {:.2}", stats.bits_per_entry);
+    println!("false positive rate: {:e}", stats.fp_rate);
+    if let Some(quotients) = stats.quotients {
+        println!("quotients: {:?}", quotients);
+    }
+    match filter.validate_encoding() {
+        Ok(()) => println!("encoding: valid"),
+        Err(e) => println!("encoding: {}", e),
+    }
+
+    if values {
+        for value in filter.values() {
+            println!("{}", value.map_err(|e| format!("invalid filter: {}", e))?);
+        }
+    }
+    Ok(())
+}
+
+fn parse_filter(filter: &str) -> Result<Filter, String> {
+    filter.parse().map_err(|e| format!("invalid filter: {}", e))
+}
+
+/// Reads a file holding a single hex string.
+fn read_hex(path: &str) -> Result<Vec<u8>, String> {
+    let hex = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    decode_hex(hex.trim()).ok_or_else(|| format!("{}: invalid hex string", path))
+}
+
+/// Reads a file holding a hex script per line, empty lines are skipped.
+fn read_scripts(path: &str) -> Result<Vec<Script>, String> {
+    let scripts = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    scripts.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            decode_hex(line)
+                .map(Script::from)
+                .ok_or_else(|| format!("{}: invalid script {}", path, line))
+        })
+        .collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,261 @@
+use io::{self, Read, Write};
+
+/// How the bits of a bitstream are packed into bytes.
+///
+/// BIP158, and the btcd and Bitcoin Core implementations, pack the bits
+/// most significant bit first, so the Golomb-Rice remainders, which are
+/// always written most significant bit first, read as big-endian integers
+/// in the hex of a filter. `LsbFirst` packs the same sequence of bits from
+/// the least significant bit of each byte instead, i.e. it reverses the bits
+/// of each byte. It isn't used by any filter on the network, but it's the
+/// usual mistake of other implementations, so it helps diagnosing them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BitOrder {
+    /// Most significant bit first, as in BIP158.
+    #[default]
+    MsbFirst,
+    /// Least significant bit first.
+    LsbFirst,
+}
+
+impl BitOrder {
+    /// Converts a byte between the BIP158 order and this one.
+    fn pack(self, byte: u8) -> u8 {
+        match self {
+            BitOrder::MsbFirst => byte,
+            BitOrder::LsbFirst => byte.reverse_bits(),
+        }
+    }
+}
+
+/// Reads bits, most significant bit first unless another [order][1] is
+/// set, from a byte reader.
+///
+/// Bytes are read eight at a time into a 64-bit word, so runs of bits are
+/// taken from the word at once instead of bit by bit. The reader may be
+/// read past the last bit returned.
+///
+/// [1]: #method.set_order
+#[derive(Debug, Clone)]
+pub struct BitReader<R> {
+    reader: R,
+    order: BitOrder,
+    /// The buffered bits, from the most significant bit of the word.
+    word: u64,
+    available: u32,
+    position: u64,
+}
+
+impl<R: Read> BitReader<R> {
+    /// Creates a new `BitReader` positioned at the first bit of `reader`.
+    pub fn new(reader: R) -> BitReader<R> {
+        BitReader {
+            reader,
+            order: BitOrder::MsbFirst,
+            word: 0,
+            available: 0,
+            position: 0,
+        }
+    }
+
+    /// Sets how the bits are packed into the bytes read.
+    pub fn set_order(&mut self, order: BitOrder) {
+        self.order = order;
+    }
+
+    /// Returns the number of bits read so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Reads a big-endian integer of `bits` bits, up to 64.
+    pub fn read(&mut self, bits: u32) -> io::Result<u64> {
+        debug_assert!(bits <= 64);
+
+        let mut value = 0u64;
+        let mut bits = bits;
+        while bits > 0 {
+            if self.available == 0 {
+                self.refill()?;
+            }
+
+            let n = bits.min(self.available);
+            value = shl(value, n) | (self.word >> (64 - n));
+            self.consume(n);
+            bits -= n;
+        }
+
+        Ok(value)
+    }
+
+    /// Reads a run of set bits and the unset bit ending it, as in a unary
+    /// coded value, returning the number of set bits.
+    ///
+    /// Reading stops once more than `limit` set bits are read, returning
+    /// their count, so huge runs aren't read through.
+    pub fn read_ones(&mut self, limit: u64) -> io::Result<u64> {
+        let mut count = 0u64;
+        loop {
+            if self.available == 0 {
+                self.refill()?;
+            }
+
+            // The bits below the available ones are unset, so the run can't
+            // extend past them.
+            let ones = (!self.word).leading_zeros().min(self.available);
+            count += u64::from(ones);
+            if count > limit {
+                self.consume(ones);
+                return Ok(count);
+            }
+            if ones < self.available {
+                self.consume(ones + 1);
+                return Ok(count);
+            }
+            self.consume(ones);
+        }
+    }
+
+    /// Reads up to eight bytes into the word, failing if there are none.
+    fn refill(&mut self) -> io::Result<()> {
+        let mut bytes = [0u8; 8];
+        let mut filled = 0;
+        while filled < bytes.len() {
+            match self.reader.read(&mut bytes[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        if filled == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        if self.order != BitOrder::MsbFirst {
+            for byte in bytes[..filled].iter_mut() {
+                *byte = self.order.pack(*byte);
+            }
+        }
+        self.word = u64::from_be_bytes(bytes);
+        self.available = filled as u32 * 8;
+        Ok(())
+    }
+
+    fn consume(&mut self, bits: u32) {
+        self.word = shl(self.word, bits);
+        self.available -= bits;
+        self.position += u64::from(bits);
+    }
+}
+
+/// Shifts left by up to 64 bits.
+fn shl(value: u64, bits: u32) -> u64 {
+    value.checked_shl(bits).unwrap_or(0)
+}
+
+/// Writes bits, most significant bit first unless another [order][2] is
+/// set, to a byte writer.
+///
+/// Bits are buffered in a 64-bit word, which is written eight bytes at a
+/// time when full. The last bytes are written, padded with zeroes, by
+/// [`finish`][1].
+///
+/// [1]: #method.finish
+/// [2]: #method.set_order
+#[derive(Debug)]
+pub struct BitWriter<W> {
+    writer: W,
+    order: BitOrder,
+    /// The buffered bits, from the most significant bit of the word.
+    word: u64,
+    used: u32,
+}
+
+impl<W: Write> BitWriter<W> {
+    /// Creates a new `BitWriter` writing to `writer`.
+    pub fn new(writer: W) -> BitWriter<W> {
+        BitWriter {
+            writer,
+            order: BitOrder::MsbFirst,
+            word: 0,
+            used: 0,
+        }
+    }
+
+    /// Sets how the bits are packed into the bytes written.
+    pub fn set_order(&mut self, order: BitOrder) {
+        self.order = order;
+    }
+
+    /// Writes a single bit.
+    pub fn write_bit(&mut self, bit: bool) -> io::Result<()> {
+        self.write(1, u64::from(bit))
+    }
+
+    /// Writes the `bits` least significant bits of `value` as a big-endian
+    /// integer, up to 64.
+    pub fn write(&mut self, bits: u32, value: u64) -> io::Result<()> {
+        debug_assert!(bits <= 64);
+
+        if bits == 0 {
+            return Ok(());
+        }
+        let value = if bits == 64 { value } else { value & ((1 << bits) - 1) };
+
+        let free = 64 - self.used;
+        if bits < free {
+            self.word |= value << (free - bits);
+            self.used += bits;
+            return Ok(());
+        }
+
+        // Fill the word, and start the next one with the bits that didn't
+        // fit.
+        let rest = bits - free;
+        self.word |= value >> rest;
+        self.flush_word()?;
+        if rest > 0 {
+            self.word = value << (64 - rest);
+            self.used = rest;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `count` set bits, as in a unary coded value.
+    pub fn write_ones(&mut self, count: u64) -> io::Result<()> {
+        let mut count = count;
+        while count > 0 {
+            let bits = count.min(64);
+            self.write(bits as u32, u64::MAX)?;
+            count -= bits;
+        }
+
+        Ok(())
+    }
+
+    /// Pads the last byte with zeroes and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let bytes = self.packed_word();
+        self.writer.write_all(&bytes[..self.used.div_ceil(8) as usize])?;
+
+        Ok(self.writer)
+    }
+
+    fn flush_word(&mut self) -> io::Result<()> {
+        let bytes = self.packed_word();
+        self.writer.write_all(&bytes)?;
+        self.word = 0;
+        self.used = 0;
+        Ok(())
+    }
+
+    fn packed_word(&self) -> [u8; 8] {
+        let mut bytes = self.word.to_be_bytes();
+        if self.order != BitOrder::MsbFirst {
+            for byte in bytes.iter_mut() {
+                *byte = self.order.pack(*byte);
+            }
+        }
+        bytes
+    }
+}
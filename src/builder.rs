@@ -1,48 +1,121 @@
 use bitcoin::blockdata::block::Block;
-use bitcoin::blockdata::transaction::TxOutRef;
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::{Instruction, Script};
+use bitcoin::blockdata::transaction::{Transaction, TxOutRef};
 use bitcoin::network::serialize::BitcoinHash;
 use bitcoin::util::hash::Sha256dHash;
 
 use byteorder::{LittleEndian, ByteOrder};
 
-use {DEFAULT_P, Filter};
+use {hash_to_range, optimal_m, optimal_p, siphash24, BASIC_FILTER_M, BASIC_FILTER_P, BASIC_FILTER_TYPE, DecodeError, Filter, FilterKey,
+     FilterParams, SipHash24};
+
+/// How output scripts are turned into filter entries by
+/// [`Builder::add_output_script`][1].
+///
+/// [1]: struct.Builder.html#method.add_output_script
+#[derive(Debug, Clone, Copy, Default)]
+pub enum EntryPolicy {
+    /// The whole script is a single entry, as in BIP158.
+    #[default]
+    FullScript,
+    /// Each non-empty data push of the script is an entry, as in the original
+    /// BIP158 draft.
+    DataPushes,
+    /// The entries are extracted by the given function.
+    Custom(fn(&Script) -> Vec<Vec<u8>>),
+}
 
 /// A GCS filter builder.
+///
+/// The entries are kept until the filter is built, unless the builder is
+/// switched to [streaming][1] mode.
+///
+/// Builders are created with their parameters and key by
+/// [`with_params`][2], so they can't build filters with an unset M or hash
+/// the entries with an unset key by mistake.
+///
+/// [1]: #method.streaming
+/// [2]: #method.with_params
 #[derive(Debug)]
 pub struct Builder {
     p: u8,
-    key: (u64, u64),
-    data: Vec<Vec<u8>>,
+    m: u64,
+    key: FilterKey,
+    data: Entries,
+    hashes: Vec<u64>,
+    frozen: bool,
+    key_set: bool,
+    script_filter: fn(&Script) -> bool,
+    entry_policy: EntryPolicy,
+    source: EntrySource,
+    provenance: Option<Vec<(Vec<u8>, EntrySource)>>,
 }
 
 impl Builder {
     // Constructors
 
-    /// Creates a new `Builder`.
+    /// Creates a builder of filters with the given parameters, whose entries
+    /// are hashed with `key`.
+    pub fn with_params(params: FilterParams, key: FilterKey) -> Builder {
+        Builder {
+            p: params.p(),
+            m: params.m(),
+            key,
+            key_set: true,
+            ..Builder::unset()
+        }
+    }
+
+    /// Creates a new `Builder` with P and M set to zero and the all-zero
+    /// key, which have to be set before building a filter.
+    #[deprecated(note = "use `Builder::with_params`, the parameters and the key are easily left unset")]
     pub fn new() -> Builder {
+        Builder::unset()
+    }
+
+    fn unset() -> Builder {
         Builder {
             p: 0,
-            key: (0, 0),
-            data: Vec::new(),
+            m: 0,
+            key: FilterKey::default(),
+            data: Entries::default(),
+            hashes: Vec::new(),
+            frozen: false,
+            key_set: false,
+            script_filter: accept_script,
+            entry_policy: EntryPolicy::default(),
+            source: EntrySource::default(),
+            provenance: None,
         }
     }
 
     // Building functions
 
     /// This functions derives a key from a `Sha256dHash` by truncating the
-    /// bytes truncating the hash to the appropiate [key size][1].
+    /// hash to the appropiate [key size][1].
     ///
-    /// [1]: constant.KEY_SIZE.html
+    /// [1]: ../constant.KEY_SIZE.html
     pub fn derive_key(&mut self, hash: &Sha256dHash) -> &mut Builder {
-        let key0 = LittleEndian::read_u64(&hash[0..8]);
-        let key1 = LittleEndian::read_u64(&hash[8..16]);
-        self.key = (key0, key1);
-        self
+        self.set_key(FilterKey::from(hash))
+    }
+
+    /// Sets a cryptographically random filter key.
+    ///
+    /// This is useful for filters that aren't bound to a block, the key can
+    /// be retrieved afterwards with [`key`][1].
+    ///
+    /// [1]: #method.key
+    #[cfg(feature = "rand")]
+    pub fn random_key(&mut self) -> &mut Builder {
+        self.set_key(FilterKey::random(&mut ::rand::thread_rng()))
     }
 
     /// Sets the filter key.
-    pub fn set_key(&mut self, key: (u64, u64)) -> &mut Builder {
+    pub fn set_key(&mut self, key: FilterKey) -> &mut Builder {
+        self.assert_not_frozen();
         self.key = key;
+        self.key_set = true;
         self
     }
 
@@ -50,22 +123,134 @@ impl Builder {
     ///
     /// # Panics
     ///
-    /// This function panics if P is larger than 32.
+    /// This function panics if P is larger than 32, or if the builder is in
+    /// streaming mode.
     pub fn set_p(&mut self, p: u8) -> &mut Builder {
+        self.assert_not_frozen();
         assert!(p <= 32, "P is too big");
         self.p = p;
         self
     }
 
+    /// Sets the inverse of the false positive rate (M).
+    pub fn set_m(&mut self, m: u64) -> &mut Builder {
+        self.assert_not_frozen();
+        self.m = m;
+        self
+    }
+
+    /// Sets the [optimal][1] P and M for a false positive rate of at most
+    /// `rate`, for a filter of the entries added so far.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `rate` isn't in the range `(0, 1]`, or if the
+    /// builder is in streaming mode.
+    ///
+    /// [1]: ../fn.optimal_p.html
+    pub fn set_target_fp_rate(&mut self, rate: f64) -> &mut Builder {
+        let n = (self.data.len() + self.hashes.len()) as u32;
+        let p = optimal_p(n, rate);
+        self.set_p(p).set_m(optimal_m(n, p))
+    }
+
+    /// Sets the predicate deciding which scripts are added by
+    /// [`add_output_script`][1], by default every script is added.
+    ///
+    /// [1]: #method.add_output_script
+    pub fn set_script_filter(&mut self, filter: fn(&Script) -> bool) -> &mut Builder {
+        self.script_filter = filter;
+        self
+    }
+
+    /// Sets how [`add_output_script`][1] turns scripts into entries, by
+    /// default the whole script is added.
+    ///
+    /// [1]: #method.add_output_script
+    pub fn set_entry_policy(&mut self, policy: EntryPolicy) -> &mut Builder {
+        self.entry_policy = policy;
+        self
+    }
+
+    /// Records the [source][1] of each entry added from now on, to be
+    /// returned in the manifest of the filter by
+    /// [`build_with_manifest`][2].
+    ///
+    /// [1]: #method.set_entry_source
+    /// [2]: #method.build_with_manifest
+    pub fn track_provenance(&mut self) -> &mut Builder {
+        if self.provenance.is_none() {
+            self.provenance = Some(Vec::new());
+        }
+        self
+    }
+
+    /// Sets the source recorded for the entries added from now on, when
+    /// [provenance tracking][1] is enabled.
+    ///
+    /// [1]: #method.track_provenance
+    pub fn set_entry_source(&mut self, source: EntrySource) -> &mut Builder {
+        self.source = source;
+        self
+    }
+
+    /// Switches the builder to streaming mode, where entries are hashed as
+    /// they're added and only their hashes are kept, instead of a copy of
+    /// each entry.
+    ///
+    /// The key and the parameters are frozen, the setters panic if called
+    /// afterwards. Entries already added are hashed right away.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the key or M haven't been set.
+    pub fn streaming(&mut self) -> &mut Builder {
+        if !self.frozen {
+            assert!(self.key_set, "the filter key must be set before streaming");
+            assert!(self.m != 0, "M must be set before streaming");
+
+            self.frozen = true;
+
+            let key = self.key;
+            let data = ::std::mem::take(&mut self.data);
+            self.hashes.extend(data.iter().map(|d| siphash24(key, d)));
+        }
+        self
+    }
+
     /// Reserve more space for filter entries.
     pub fn reserve(&mut self, n: usize) -> &mut Builder {
-        self.data.reserve(n);
+        if self.frozen {
+            self.hashes.reserve(n);
+        } else {
+            self.data.reserve(n);
+        }
         self
     }
 
     /// Adds an entry to be included in the GCS filter when it's built.
     pub fn add_entry(&mut self, data: &[u8]) -> &mut Builder {
-        self.data.push(data.to_vec());
+        if let Some(ref mut provenance) = self.provenance {
+            provenance.push((data.to_vec(), self.source));
+        }
+
+        if self.frozen {
+            self.hashes.push(siphash24(self.key, data));
+        } else {
+            self.data.push(data);
+        }
+        self
+    }
+
+    /// Adds every entry of an iterator, e.g. the scripts of a block.
+    pub fn extend<I>(&mut self, entries: I) -> &mut Builder
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        for entry in entries {
+            self.add_entry(entry.as_ref());
+        }
         self
     }
 
@@ -74,7 +259,7 @@ impl Builder {
         let index = outpoint.index as u32;
 
         let mut entry = [0u8; 32 + 4];
-        (&mut entry[0..32]).copy_from_slice(&txid);
+        entry[0..32].copy_from_slice(&txid);
         LittleEndian::write_u32(&mut entry[32..36], index);
 
         self.add_entry(&entry);
@@ -88,7 +273,65 @@ impl Builder {
         self
     }
 
-    // TODO: add_hash, add_script, add_witness.
+    /// Adds an output script following the [entry policy][1], if it's
+    /// accepted by the [script filter][2].
+    ///
+    /// [1]: #method.set_entry_policy
+    /// [2]: #method.set_script_filter
+    pub fn add_output_script(&mut self, script: &Script) -> &mut Builder {
+        if !(self.script_filter)(script) {
+            return self;
+        }
+
+        match self.entry_policy {
+            EntryPolicy::FullScript => self.add_entry(&script.data()),
+            EntryPolicy::DataPushes => self.add_script(script),
+            EntryPolicy::Custom(extract) => {
+                for entry in extract(script) {
+                    self.add_entry(&entry);
+                }
+                self
+            }
+        }
+    }
+
+    /// Adds each data push of a script as a separate entry.
+    ///
+    /// Empty pushes are skipped, and if the script can't be parsed no entry
+    /// is added at all.
+    pub fn add_script(&mut self, script: &Script) -> &mut Builder {
+        let mut pushes = Vec::new();
+        for instruction in script.iter(false) {
+            match instruction {
+                Instruction::PushBytes(data) => {
+                    if !data.is_empty() {
+                        pushes.push(data);
+                    }
+                }
+                Instruction::Op(_) => {}
+                Instruction::Error(_) => return self,
+            }
+        }
+
+        for data in pushes {
+            self.add_entry(data);
+        }
+        self
+    }
+
+    /// Adds each data push of an input script (`scriptSig`) as a separate
+    /// entry.
+    pub fn add_script_sig(&mut self, script_sig: &Script) -> &mut Builder {
+        self.add_script(script_sig)
+    }
+
+    /// Adds each item of an input witness stack as a separate entry.
+    pub fn add_witness(&mut self, witness: &[Vec<u8>]) -> &mut Builder {
+        for item in witness {
+            self.add_entry(item);
+        }
+        self
+    }
 
     // Accessors
 
@@ -96,25 +339,225 @@ impl Builder {
     /// created with [`random_key`][1].
     ///
     /// [1]: #method.random_key
-    pub fn key(&self) -> (u64, u64) {
+    pub fn key(&self) -> FilterKey {
         self.key
     }
 
+    /// Estimates the length in bytes of the filter data, see
+    /// [`Filter::estimated_size`][1].
+    ///
+    /// Duplicate entries are counted, so the estimate may be larger than the
+    /// actual size.
+    ///
+    /// [1]: ../struct.Filter.html#method.estimated_size
+    pub fn estimated_size(&self) -> usize {
+        let n = self.data.len() + self.hashes.len();
+        Filter::estimated_size(n as u32, self.p, self.m)
+    }
+
+    /// Returns `true` if the builder is in [streaming][1] mode.
+    ///
+    /// [1]: #method.streaming
+    pub fn is_streaming(&self) -> bool {
+        self.frozen
+    }
+
     // Build function
     
     /// Builds the GCS filter.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if M hasn't been set.
     pub fn build(self) -> Filter {
-        Filter::build(self.p, self.key, &self.data)
+        assert!(self.m != 0, "M must be set");
+
+        if self.frozen {
+            Filter::from_hashes(self.p, self.m, self.hashes)
+        } else {
+            Filter::build(self.p, self.m, self.key, self.data.iter())
+        }
+    }
+
+    /// Builds the GCS filter along with the manifest of its entries.
+    ///
+    /// The manifest is empty unless [provenance tracking][1] is enabled.
+    ///
+    /// [1]: #method.track_provenance
+    pub fn build_with_manifest(mut self) -> (Filter, FilterManifest) {
+        let key = self.key;
+        let provenance = self.provenance.take().unwrap_or_default();
+        let filter = self.build();
+
+        let entries = provenance.into_iter()
+            .map(|(data, source)| ManifestEntry {
+                value: hash_to_range::<SipHash24>(key, &data, filter.modulus()),
+                data,
+                source,
+            })
+            .collect();
+
+        let manifest = FilterManifest {
+            key,
+            n: filter.n(),
+            entries,
+        };
+        (filter, manifest)
+    }
+
+    fn assert_not_frozen(&self) {
+        assert!(!self.frozen, "the builder parameters are frozen");
     }
 }
 
+impl Default for Builder {
+    /// Same as the deprecated [`Builder::new`][1].
+    ///
+    /// [1]: #method.new
+    fn default() -> Builder {
+        Builder::unset()
+    }
+}
+
+impl<D: AsRef<[u8]>> Extend<D> for Builder {
+    fn extend<I: IntoIterator<Item = D>>(&mut self, entries: I) {
+        Builder::extend(self, entries);
+    }
+}
+
+/// The entries of a builder, stored one after another in a single buffer,
+/// so batch indexers adding the entries of many blocks don't allocate each
+/// one of them.
+#[derive(Debug, Default)]
+struct Entries {
+    bytes: Vec<u8>,
+    ends: Vec<usize>,
+}
+
+impl Entries {
+    fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    fn reserve(&mut self, n: usize) {
+        self.ends.reserve(n);
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.bytes.extend_from_slice(data);
+        self.ends.push(self.bytes.len());
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        let starts = ::std::iter::once(0).chain(self.ends.iter().cloned());
+        starts.zip(self.ends.iter()).map(move |(start, end)| &self.bytes[start..*end])
+    }
+}
+
+fn accept_script(_script: &Script) -> bool {
+    true
+}
+
+/// Where a filter entry comes from.
+///
+/// Fields that don't apply to an entry, or that the caller didn't set, are
+/// `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EntrySource {
+    /// The transaction the entry was extracted from.
+    pub txid: Option<Sha256dHash>,
+    /// The index of the output, for entries of an output script.
+    pub vout: Option<u32>,
+    /// The index of the input, for entries of an input or of the previous
+    /// output script it spends.
+    pub input: Option<u32>,
+    /// The class of the script the entry was extracted from.
+    pub script_class: Option<ScriptClass>,
+}
+
+/// An entry of a filter along with its source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// The entry.
+    pub data: Vec<u8>,
+    /// The value the entry is hashed to in the filter.
+    pub value: u64,
+    /// Where the entry comes from.
+    pub source: EntrySource,
+}
+
+/// The entries of a filter and where they come from, as recorded by a
+/// [`Builder`][1] with provenance tracking.
+///
+/// Entries are listed in the order they were added, an entry added more
+/// than once is listed with each of its sources. The values of a
+/// [`FilterMismatch`][2] can be looked up with [`by_value`][3] to find the
+/// entries a filter misses.
+///
+/// [1]: struct.Builder.html#method.track_provenance
+/// [2]: struct.FilterMismatch.html
+/// [3]: #method.by_value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterManifest {
+    key: FilterKey,
+    n: u32,
+    entries: Vec<ManifestEntry>,
+}
+
+impl FilterManifest {
+    // Accessors
+
+    /// Returns the key the filter is built with.
+    pub fn key(&self) -> FilterKey {
+        self.key
+    }
+
+    /// Returns the set length (N) of the filter.
+    pub fn n(&self) -> u32 {
+        self.n
+    }
+
+    /// Returns the entries, in the order they were added.
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+
+    /// Returns the sources of an entry, empty if it isn't in the filter.
+    pub fn sources<'a>(&'a self, data: &'a [u8]) -> impl Iterator<Item = &'a EntrySource> + 'a {
+        self.entries.iter()
+            .filter(move |entry| entry.data == data)
+            .map(|entry| &entry.source)
+    }
+
+    /// Returns the entries hashed to `value`.
+    pub fn by_value(&self, value: u64) -> impl Iterator<Item = &ManifestEntry> + '_ {
+        self.entries.iter().filter(move |entry| entry.value == value)
+    }
+}
+
+/// Returns whether an output script belongs in a basic filter, BIP158
+/// excludes empty scripts and scripts starting with `OP_RETURN`.
+pub fn is_basic_filter_script(script: &Script) -> bool {
+    match script.data().first() {
+        Some(&op) => op != opcodes::All::OP_RETURN as u8,
+        None => false,
+    }
+}
+
+/// Builds the basic filter of a block as described by the original BIP158
+/// draft, which contained the outpoints spent by the block instead of their
+/// scripts.
+///
+/// Use [`build_basic_filter_with_prev_scripts`][1] to build filters matching
+/// the final specification.
+///
+/// [1]: fn.build_basic_filter_with_prev_scripts.html
 pub fn build_basic_filter(block: &Block) -> Filter {
     let blockhash = block.bitcoin_hash();
 
-    let mut builder = Builder::new();
+    let mut builder = Builder::with_params(FilterParams::basic(), FilterKey::from(&blockhash));
 
-    builder.set_p(DEFAULT_P);
-	builder.derive_key(&blockhash);
+    builder.set_script_filter(is_basic_filter_script);
 
     let mut n = 0;
 
@@ -123,10 +566,10 @@ pub fn build_basic_filter(block: &Block) -> Filter {
  
 		// Skip the inputs for the coinbase transaction
         if i != 0 {
-            n += tx.input.iter().count();
+            n += tx.input.len();
         }
 
-        n += tx.output.iter().count();
+        n += tx.output.len();
     }
 
     builder.reserve(n);
@@ -144,8 +587,8 @@ pub fn build_basic_filter(block: &Block) -> Filter {
 			// the txid:index to the filters data slices.
 			for txin in tx.input.iter() {
                 let outpoint = TxOutRef {
-                    txid: txin.prev_hash.clone(),
-                    index: txin.prev_index.clone() as usize,
+                    txid: txin.prev_hash,
+                    index: txin.prev_index as usize,
                 };
 
 				builder.add_outpoint(&outpoint);
@@ -155,11 +598,489 @@ pub fn build_basic_filter(block: &Block) -> Filter {
 		// For each output in a transaction, we'll add each of the
 		// individual data pushes within the script.
 		for txout in tx.output.iter() {
-            let data = txout.script_pubkey.data();
-
-			builder.add_entry(data.as_slice());
+			builder.add_output_script(&txout.script_pubkey);
 		}
 	}
 
     builder.build()
 }
+
+/// A type of block filter.
+///
+/// Filter types define which entries of a block go into its filter, so
+/// custom filter types can be built with [`build_filter`][1] and served with
+/// their own type byte on the P2P network.
+///
+/// [1]: fn.build_filter.html
+pub trait FilterType {
+    /// The filter type byte used in the P2P messages.
+    fn filter_type_byte() -> u8;
+
+    /// The P and M parameters of the filter, the basic filter ones by
+    /// default.
+    ///
+    /// P can't be larger than 32 and M can't be zero, as checked by
+    /// [`FilterParams::new`][1].
+    ///
+    /// [1]: ../struct.FilterParams.html#method.new
+    fn params() -> (u8, u64) {
+        (BASIC_FILTER_P, BASIC_FILTER_M)
+    }
+
+    /// Extracts the filter entries of a block, `prev_scripts` are the
+    /// previous output scripts spent by the block.
+    ///
+    /// The entries are borrowed from the block and the scripts, so they're
+    /// added to the filter without being copied one by one.
+    fn extract_entries<'a>(block: &'a Block, prev_scripts: &'a [Script]) -> Vec<&'a [u8]>;
+}
+
+/// The BIP158 basic filter type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BasicFilter;
+
+impl FilterType for BasicFilter {
+    fn filter_type_byte() -> u8 {
+        BASIC_FILTER_TYPE
+    }
+
+    fn extract_entries<'a>(block: &'a Block, prev_scripts: &'a [Script]) -> Vec<&'a [u8]> {
+        basic_filter_entries(&block.txdata, prev_scripts)
+    }
+}
+
+/// Extracts the basic filter entries of the transactions of a block.
+fn basic_filter_entries<'a>(txdata: &'a [Transaction], prev_scripts: &'a [Script]) -> Vec<&'a [u8]> {
+    let n = txdata.iter().map(|tx| tx.output.len()).sum::<usize>();
+    let mut entries = Vec::with_capacity(n + prev_scripts.len());
+
+    let outputs = txdata.iter()
+        .flat_map(|tx| tx.output.iter())
+        .map(|txout| &txout.script_pubkey)
+        .filter(|script| is_basic_filter_script(script));
+    entries.extend(outputs.map(|script| &script[..]));
+
+    // Spent scripts are only skipped when empty, like Bitcoin Core does.
+    let spent = prev_scripts.iter().filter(|s| !s.is_empty());
+    entries.extend(spent.map(|script| &script[..]));
+
+    entries
+}
+
+/// Builds the filter of the given type for a block.
+///
+/// The filter is keyed by the block hash.
+///
+/// # Panics
+///
+/// This function panics if the [parameters][1] of the filter type are out
+/// of range, or if `N * M` doesn't fit in a `u64`.
+///
+/// [1]: trait.FilterType.html#method.params
+pub fn build_filter<T: FilterType>(block: &Block, prev_scripts: &[Script]) -> Filter {
+    let (p, m) = T::params();
+    let params = FilterParams::new(p, m).expect("invalid filter type parameters");
+
+    let mut builder = Builder::with_params(params, FilterKey::from(&block.bitcoin_hash()));
+    builder.extend(T::extract_entries(block, prev_scripts));
+    builder.build()
+}
+
+/// Builds the BIP158 basic filter of a block.
+///
+/// The filter contains the output scripts of every transaction of the block
+/// and the previous output scripts spent by the block, `prev_scripts`. Empty
+/// and `OP_RETURN` output scripts are skipped.
+pub fn build_basic_filter_with_prev_scripts(block: &Block, prev_scripts: &[Script]) -> Filter {
+    build_filter::<BasicFilter>(block, prev_scripts)
+}
+
+/// The number of blocks whose filters are built at a time by
+/// [`build_filters_parallel`][1].
+///
+/// [1]: fn.build_filters_parallel.html
+#[cfg(feature = "rayon")]
+const PARALLEL_BATCH: usize = 256;
+
+/// Builds the BIP158 basic filters of a sequence of blocks, along with the
+/// previous output scripts spent by each one, returning them in the order of
+/// the blocks.
+///
+/// Without the `rayon` feature the filters are built one after another.
+#[cfg(not(feature = "rayon"))]
+pub fn build_filters_parallel<I, S>(blocks: I) -> Vec<Filter>
+where
+    I: IntoIterator<Item = (Block, S)>,
+    S: AsRef<[Script]>,
+{
+    blocks.into_iter()
+        .map(|(block, prev_scripts)| build_basic_filter_with_prev_scripts(&block, prev_scripts.as_ref()))
+        .collect()
+}
+
+/// Builds the BIP158 basic filters of a sequence of blocks, along with the
+/// previous output scripts spent by each one, returning them in the order of
+/// the blocks.
+///
+/// The blocks are taken from `blocks` in batches, and the entries of the
+/// blocks of a batch are extracted, hashed, sorted and encoded over the
+/// rayon thread pool, so building the filter index of a whole chain doesn't
+/// need to keep every block in memory.
+#[cfg(feature = "rayon")]
+pub fn build_filters_parallel<I, S>(blocks: I) -> Vec<Filter>
+where
+    I: IntoIterator<Item = (Block, S)>,
+    S: AsRef<[Script]> + Send,
+{
+    use rayon::prelude::*;
+
+    let mut blocks = blocks.into_iter();
+    let mut filters = Vec::new();
+    loop {
+        let batch = blocks.by_ref().take(PARALLEL_BATCH).collect::<Vec<(Block, S)>>();
+        if batch.is_empty() {
+            return filters;
+        }
+
+        filters.par_extend(batch.into_par_iter().map(|(block, prev_scripts)| {
+            build_basic_filter_with_prev_scripts(&block, prev_scripts.as_ref())
+        }));
+    }
+}
+
+/// Builds the BIP158 basic filter of a block along with the manifest of its
+/// entries, to find where the entries of a filter come from.
+///
+/// `prev_scripts` are the previous output scripts spent by the block, in
+/// the order of the inputs of its transactions but the coinbase. The filter
+/// is the same one built by [`build_basic_filter_with_prev_scripts`][1].
+///
+/// [1]: fn.build_basic_filter_with_prev_scripts.html
+pub fn build_basic_filter_with_manifest(block: &Block, prev_scripts: &[Script]) -> (Filter, FilterManifest) {
+    let mut builder = Builder::with_params(FilterParams::basic(), FilterKey::from(&block.bitcoin_hash()));
+    builder.track_provenance();
+
+    for tx in block.txdata.iter() {
+        let txid = tx.txid();
+        for (vout, txout) in tx.output.iter().enumerate() {
+            let script = &txout.script_pubkey;
+            if is_basic_filter_script(script) {
+                builder.set_entry_source(EntrySource {
+                    txid: Some(txid),
+                    vout: Some(vout as u32),
+                    script_class: Some(ScriptClass::of(script)),
+                    ..EntrySource::default()
+                });
+                builder.add_entry(&script.data());
+            }
+        }
+    }
+
+    // Spent scripts beyond the inputs of the block are still added, with
+    // an unknown source.
+    let inputs = block.txdata.iter()
+        .skip(1)
+        .flat_map(|tx| {
+            let txid = tx.txid();
+            (0..tx.input.len()).map(move |input| (Some(txid), Some(input as u32)))
+        })
+        .chain(::std::iter::repeat((None, None)));
+
+    for (script, (txid, input)) in prev_scripts.iter().zip(inputs) {
+        if !script.is_empty() {
+            builder.set_entry_source(EntrySource {
+                txid,
+                input,
+                script_class: Some(ScriptClass::of(script)),
+                ..EntrySource::default()
+            });
+            builder.add_entry(&script.data());
+        }
+    }
+
+    builder.build_with_manifest()
+}
+
+/// The prospective BIP158 basic filter of a block template, previewed before
+/// the block is mined.
+///
+/// The filter is keyed by the block hash, which is only known once the block
+/// is found, so the preview keeps the unique entries of the template. Their
+/// number and the estimated filter size are known up front, e.g. to account
+/// for the filter bandwidth of a template, and the filter is [built][1] as
+/// soon as the block is found without going through the template again.
+///
+/// The entries include the outputs of the coinbase transaction, so the
+/// preview has to be redone if they change.
+///
+/// [1]: #method.build
+#[derive(Debug, Clone)]
+pub struct TemplateFilter {
+    entries: Vec<Vec<u8>>,
+}
+
+impl TemplateFilter {
+    // Constructors
+
+    /// Previews the basic filter of a block template, given its transactions,
+    /// starting with the coinbase, and the previous output scripts they
+    /// spend.
+    pub fn new(txdata: &[Transaction], prev_scripts: &[Script]) -> TemplateFilter {
+        let mut entries = basic_filter_entries(txdata, prev_scripts);
+        entries.sort();
+        entries.dedup();
+        TemplateFilter {
+            entries: entries.into_iter().map(<[u8]>::to_vec).collect(),
+        }
+    }
+
+    // Accessors
+
+    /// Returns the set length (N) of the filter.
+    pub fn n(&self) -> u32 {
+        assert!(self.entries.len() <= u32::MAX as usize, "N is too big");
+        self.entries.len() as u32
+    }
+
+    /// Estimates the length in bytes of the filter data, see
+    /// [`Filter::estimated_size`][1].
+    ///
+    /// The actual size depends on the block hash, but it's usually within a
+    /// few bytes of the estimate.
+    ///
+    /// [1]: ../struct.Filter.html#method.estimated_size
+    pub fn estimated_size(&self) -> usize {
+        Filter::estimated_size(self.n(), BASIC_FILTER_P, BASIC_FILTER_M)
+    }
+
+    /// Returns the unique entries of the filter, sorted.
+    pub fn entries(&self) -> &[Vec<u8>] {
+        &self.entries
+    }
+
+    // Build function
+
+    /// Builds the basic filter of the block mined from the template, keyed by
+    /// its hash.
+    pub fn build(&self, block_hash: &Sha256dHash) -> Filter {
+        Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::from(block_hash), &self.entries)
+    }
+}
+
+/// Standard output script templates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScriptClass {
+    /// Pay to public key.
+    P2pk,
+    /// Pay to public key hash.
+    P2pkh,
+    /// Pay to script hash.
+    P2sh,
+    /// Pay to witness public key hash (segwit v0).
+    P2wpkh,
+    /// Pay to witness script hash (segwit v0).
+    P2wsh,
+    /// Pay to taproot (segwit v1).
+    P2tr,
+    /// Other witness programs, of unknown versions or lengths.
+    WitnessUnknown,
+    /// `OP_RETURN` data carrier.
+    NullData,
+    /// Any other script.
+    NonStandard,
+}
+
+impl ScriptClass {
+    /// Classifies an output script by its template.
+    pub fn of(script: &Script) -> ScriptClass {
+        let data = script.data();
+        let len = data.len();
+
+        if len == 25 && data[..3] == [0x76, 0xa9, 0x14] && data[23..] == [0x88, 0xac] {
+            ScriptClass::P2pkh
+        } else if len == 23 && data[..2] == [0xa9, 0x14] && data[22] == 0x87 {
+            ScriptClass::P2sh
+        } else if len == 22 && data[..2] == [0x00, 0x14] {
+            ScriptClass::P2wpkh
+        } else if len == 34 && data[..2] == [0x00, 0x20] {
+            ScriptClass::P2wsh
+        } else if len == 34 && data[..2] == [0x51, 0x20] {
+            ScriptClass::P2tr
+        } else if is_witness_program(&data) {
+            ScriptClass::WitnessUnknown
+        } else if ((len == 35 && data[0] == 33) || (len == 67 && data[0] == 65)) && data[len - 1] == 0xac {
+            ScriptClass::P2pk
+        } else if data.first() == Some(&(opcodes::All::OP_RETURN as u8)) {
+            ScriptClass::NullData
+        } else {
+            ScriptClass::NonStandard
+        }
+    }
+}
+
+/// Returns whether a script is a witness program, a version opcode (`OP_0`
+/// or `OP_1` to `OP_16`) followed by a push of 2 to 40 bytes.
+fn is_witness_program(data: &[u8]) -> bool {
+    data.len() >= 4
+        && (data[0] == 0x00 || (0x51..=0x60).contains(&data[0]))
+        && (2..=40).contains(&data[1])
+        && data.len() == data[1] as usize + 2
+}
+
+/// Builds a filter of the output scripts of a block, and the previous output
+/// scripts it spends, of the given classes.
+///
+/// The filter has the basic filter parameters and is keyed by the block
+/// hash. It's meant for indexing services which serve smaller filters for
+/// specific wallets, e.g. only taproot scripts, with a custom filter type.
+pub fn build_script_class_filter(block: &Block, prev_scripts: &[Script], classes: &[ScriptClass]) -> Filter {
+    let scripts = block.txdata.iter()
+        .flat_map(|tx| tx.output.iter())
+        .map(|txout| &txout.script_pubkey)
+        .chain(prev_scripts)
+        .filter(|script| classes.contains(&ScriptClass::of(script)))
+        .map(Script::data);
+
+    Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::from(&block.bitcoin_hash()), scripts)
+}
+
+/// Builds the extended filter of a block, as described by the original BIP158
+/// draft.
+pub fn build_extended_filter(block: &Block) -> Filter {
+    let blockhash = block.bitcoin_hash();
+
+    let mut builder = Builder::with_params(FilterParams::basic(), FilterKey::from(&blockhash));
+
+    // The extended filter contains the data pushes of each input script and
+    // the items of each input witness, the coinbase transaction has no real
+    // inputs so it's skipped.
+    for tx in block.txdata.iter().skip(1) {
+        for (i, txin) in tx.input.iter().enumerate() {
+            builder.add_script_sig(&txin.script_sig);
+
+            if let Some(witness) = tx.witness.get(i) {
+                builder.add_witness(witness);
+            }
+        }
+    }
+
+    builder.build()
+}
+
+/// Builds the filter of a single transaction, e.g. an unconfirmed one.
+///
+/// The filter contains the output scripts of the transaction, skipping empty
+/// and `OP_RETURN` ones, and the outpoints it spends. M is `2^P`, so the
+/// false positive rate is `1/2^P`.
+///
+/// # Panics
+///
+/// This function panics if P is larger than 32.
+pub fn build_tx_filter(tx: &Transaction, key: FilterKey, p: u8) -> Filter {
+    assert!(p <= 32, "P is too big");
+    let params = FilterParams::new(p, 1 << p).expect("P is checked");
+
+    let mut builder = Builder::with_params(params, key);
+    builder.set_script_filter(is_basic_filter_script);
+    builder.reserve(tx.input.len() + tx.output.len());
+
+    for txin in tx.input.iter() {
+        builder.add_outpoint(&TxOutRef {
+            txid: txin.prev_hash,
+            index: txin.prev_index as usize,
+        });
+    }
+
+    for txout in tx.output.iter() {
+        builder.add_output_script(&txout.script_pubkey);
+    }
+
+    builder.build()
+}
+
+/// Differences between a filter and the filter it was expected to be.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FilterMismatch {
+    /// The expected and the actual set length (N), if they differ.
+    pub n: Option<(u32, u32)>,
+    /// Whether the serialized filters differ.
+    pub bytes: bool,
+    /// Values of the expected filter missing from the actual filter.
+    pub missing: Vec<u64>,
+    /// Values of the actual filter not in the expected filter.
+    pub unexpected: Vec<u64>,
+    /// The error found while decoding the actual filter, if any.
+    pub decode_error: Option<DecodeError>,
+    /// The error found while decoding the expected filter, if any, e.g. when
+    /// it's read from storage instead of built.
+    pub expected_decode_error: Option<DecodeError>,
+}
+
+/// Verifies a basic filter served by a peer by building the filter of the
+/// block and comparing both.
+///
+/// `prev_scripts` are the previous output scripts spent by the block, as
+/// required by [`build_basic_filter_with_prev_scripts`][1].
+///
+/// [1]: fn.build_basic_filter_with_prev_scripts.html
+pub fn verify_basic_filter(block: &Block,
+                           prev_scripts: &[Script],
+                           filter: &Filter) -> Result<(), FilterMismatch> {
+    verify_filter(&build_basic_filter_with_prev_scripts(block, prev_scripts), filter)
+}
+
+/// Compares a filter against the filter it's expected to be.
+///
+/// The values of both filters are only compared when their set lengths (N)
+/// match, otherwise they're reduced to different ranges and can't be compared
+/// to find which entries are missing.
+pub fn verify_filter(expected: &Filter, filter: &Filter) -> Result<(), FilterMismatch> {
+    if expected.n() == filter.n() && expected.as_bytes() == filter.as_bytes() {
+        return Ok(());
+    }
+
+    let mut mismatch = FilterMismatch {
+        bytes: true,
+        ..FilterMismatch::default()
+    };
+
+    if expected.n() != filter.n() {
+        mismatch.n = Some((expected.n(), filter.n()));
+        return Err(mismatch);
+    }
+
+    let expected = match expected.values().collect::<Result<Vec<u64>, _>>() {
+        Ok(values) => values,
+        Err(e) => {
+            mismatch.expected_decode_error = Some(e);
+            return Err(mismatch);
+        }
+    };
+    let actual = match filter.values().collect::<Result<Vec<u64>, _>>() {
+        Ok(values) => values,
+        Err(e) => {
+            mismatch.decode_error = Some(e);
+            return Err(mismatch);
+        }
+    };
+
+    // Walk both sorted value lists, collecting the values only present in
+    // one of them.
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() && j < actual.len() {
+        if expected[i] < actual[j] {
+            mismatch.missing.push(expected[i]);
+            i += 1;
+        } else if expected[i] > actual[j] {
+            mismatch.unexpected.push(actual[j]);
+            j += 1;
+        } else {
+            i += 1;
+            j += 1;
+        }
+    }
+    mismatch.missing.extend_from_slice(&expected[i..]);
+    mismatch.unexpected.extend_from_slice(&actual[j..]);
+
+    Err(mismatch)
+}
@@ -0,0 +1,499 @@
+#[cfg(feature = "builder")]
+use bitcoin::blockdata::block::Block;
+#[cfg(feature = "builder")]
+use bitcoin::blockdata::script::Script;
+use bitcoin::util::hash::Sha256dHash;
+
+#[cfg(feature = "builder")]
+use builder::verify_basic_filter;
+use {DecodeError, Filter, FilterHeader};
+
+/// Number of blocks between two consecutive `cfcheckpt` checkpoints.
+pub const CHECKPOINT_INTERVAL: u32 = 1000;
+
+/// A filter header which doesn't match its checkpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointMismatch {
+    /// Height of the mismatching header.
+    pub height: u32,
+    /// The header the checkpoint commits to.
+    pub checkpoint: FilterHeader,
+    /// The header found in the chain.
+    pub header: FilterHeader,
+}
+
+/// A chain of filter headers starting at the genesis block, or at a
+/// trusted [checkpoint][1].
+///
+/// Headers are accumulated block by block, the header at index `i` is the
+/// filter header of the block at height `i`. The chain can be persisted
+/// with [`to_bytes`][2].
+///
+/// [1]: #method.from_checkpoint
+/// [2]: #method.to_bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterHeaderChain {
+    start_height: u32,
+    prev_header: FilterHeader,
+    headers: Vec<FilterHeader>,
+}
+
+impl FilterHeaderChain {
+    // Constructors
+
+    /// Creates an empty `FilterHeaderChain`.
+    pub fn new() -> FilterHeaderChain {
+        FilterHeaderChain {
+            start_height: 0,
+            prev_header: FilterHeader::zero(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// Creates a chain starting at a trusted checkpoint, the filter header
+    /// of the block at `height`, e.g. one hardcoded in a client, so the
+    /// headers below it don't have to be synced.
+    ///
+    /// The headers below the checkpoint are unknown.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `height` is `u32::MAX`.
+    pub fn from_checkpoint(height: u32, header: FilterHeader) -> FilterHeaderChain {
+        FilterHeaderChain {
+            start_height: height.checked_add(1).expect("the checkpoint height is too big"),
+            prev_header: header,
+            headers: Vec::new(),
+        }
+    }
+
+    /// Reads a chain serialized with [`to_bytes`][1].
+    ///
+    /// [1]: #method.to_bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<FilterHeaderChain, DecodeError> {
+        if bytes.len() < 4 + 32 || !(bytes.len() - 4).is_multiple_of(32) {
+            return Err(DecodeError::UnexpectedEof);
+        }
+
+        let mut start_height = [0u8; 4];
+        start_height.copy_from_slice(&bytes[..4]);
+        let mut headers = bytes[4..].chunks(32)
+            .map(|header| FilterHeader::from_hash(Sha256dHash::from(header)));
+
+        Ok(FilterHeaderChain {
+            start_height: u32::from_le_bytes(start_height),
+            prev_header: headers.next().expect("the length is checked"),
+            headers: headers.collect(),
+        })
+    }
+
+    /// Computes the chain of filter headers of the blocks whose hashes and
+    /// filters are yielded by `filters`, starting at the genesis block.
+    ///
+    /// This is what a full node needs to serve `cfheaders` and, with
+    /// [`checkpoints`][1], `cfcheckpt`.
+    ///
+    /// [1]: #method.checkpoints
+    pub fn from_filters<I>(filters: I) -> FilterHeaderChain
+    where
+        I: IntoIterator<Item = (Sha256dHash, Filter)>,
+    {
+        let mut chain = FilterHeaderChain::new();
+        chain.headers.extend(FilterHeaders::new(FilterHeader::zero(), filters).map(|(_, header)| header));
+        chain
+    }
+
+    // Accessors
+
+    /// Number of headers in the chain, including the unknown ones below
+    /// its checkpoint, which is the height of the next block.
+    pub fn len(&self) -> usize {
+        self.start_height as usize + self.headers.len()
+    }
+
+    /// Returns true if the chain has no headers.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Height of the last header, `None` if the chain is empty.
+    pub fn height(&self) -> Option<u32> {
+        match self.len() {
+            0 => None,
+            n => Some(n as u32 - 1),
+        }
+    }
+
+    /// Returns the height of the first known header, the checkpoint if the
+    /// chain starts at one.
+    pub fn start_height(&self) -> u32 {
+        self.start_height.saturating_sub(1)
+    }
+
+    /// Returns the last header of the chain, or the all-zero header if the
+    /// chain is empty.
+    pub fn tip(&self) -> FilterHeader {
+        self.headers.last().cloned().unwrap_or(self.prev_header)
+    }
+
+    /// Returns the header at the given height, `None` if it's above the tip
+    /// or below the checkpoint of the chain.
+    pub fn get(&self, height: u32) -> Option<&FilterHeader> {
+        if height >= self.start_height {
+            self.headers.get((height - self.start_height) as usize)
+        } else if height + 1 == self.start_height {
+            Some(&self.prev_header)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the headers at every [checkpoint interval][1] in the chain, as
+    /// served by `cfcheckpt`.
+    ///
+    /// The headers are returned up to the first one below the checkpoint of
+    /// the chain, if it starts at one.
+    ///
+    /// [1]: constant.CHECKPOINT_INTERVAL.html
+    pub fn checkpoints(&self) -> Vec<FilterHeader> {
+        (1..)
+            .map(|i| self.get(i * CHECKPOINT_INTERVAL))
+            .take_while(Option::is_some)
+            .map(|header| *header.expect("the header is known"))
+            .collect()
+    }
+
+    /// Serializes the chain, to persist it, as the height after its
+    /// checkpoint (zero if it starts at the genesis block) as a
+    /// little-endian `u32` followed by the checkpoint, or the all-zero
+    /// header, and the headers above it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 + 32 * (self.headers.len() + 1));
+        bytes.extend_from_slice(&self.start_height.to_le_bytes());
+        for header in Some(&self.prev_header).into_iter().chain(&self.headers) {
+            bytes.extend_from_slice(&header.as_hash()[..]);
+        }
+        bytes
+    }
+
+    // Chain updates
+
+    /// Appends the filter header of the next block given its filter.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the modulus of the filter isn't `N * M`, see
+    /// [`Filter::filter_hash`][1].
+    ///
+    /// [1]: ../struct.Filter.html#method.filter_hash
+    pub fn push_filter(&mut self, filter: &Filter) -> FilterHeader {
+        self.push_filter_hash(&filter.filter_hash())
+    }
+
+    /// Appends the filter header of the next block given its filter hash.
+    pub fn push_filter_hash(&mut self, filter_hash: &Sha256dHash) -> FilterHeader {
+        let header = FilterHeader::from_filter_hash(filter_hash, &self.tip());
+        self.headers.push(header);
+        header
+    }
+
+    /// Removes the headers from `height` on, e.g. when the blocks are
+    /// disconnected in a reorganization.
+    ///
+    /// The checkpoint of the chain, if any, is kept.
+    pub fn truncate(&mut self, height: u32) {
+        self.headers.truncate(height.saturating_sub(self.start_height) as usize);
+    }
+
+    /// Appends a batch of filter hashes, as received in `cfheaders`, only if
+    /// the resulting tip matches `checkpoint`.
+    ///
+    /// On mismatch the chain is left untouched. An empty batch on an empty
+    /// chain has no tip to check, it's accepted as is.
+    pub fn push_checked(&mut self,
+                        filter_hashes: &[Sha256dHash],
+                        checkpoint: &FilterHeader) -> Result<(), CheckpointMismatch> {
+        if filter_hashes.is_empty() && self.is_empty() {
+            return Ok(());
+        }
+
+        let mut prev = self.tip();
+        let headers = filter_hashes.iter()
+            .map(|filter_hash| {
+                prev = FilterHeader::from_filter_hash(filter_hash, &prev);
+                prev
+            })
+            .collect::<Vec<FilterHeader>>();
+
+        if prev != *checkpoint {
+            return Err(CheckpointMismatch {
+                height: (self.len() + headers.len()) as u32 - 1,
+                checkpoint: *checkpoint,
+                header: prev,
+            });
+        }
+
+        self.headers.extend(headers);
+        Ok(())
+    }
+
+    // Verification
+
+    /// Verifies the chain against the checkpoints received in `cfcheckpt`.
+    ///
+    /// Only checkpoints up to the height of the chain, and above its own
+    /// checkpoint, are checked, the first mismatch is returned.
+    pub fn verify_checkpoints(&self, checkpoints: &[FilterHeader]) -> Result<(), CheckpointMismatch> {
+        for (i, checkpoint) in checkpoints.iter().enumerate() {
+            let height = (i as u32 + 1) * CHECKPOINT_INTERVAL;
+            match self.get(height) {
+                Some(header) if header != checkpoint => {
+                    return Err(CheckpointMismatch {
+                        height,
+                        checkpoint: *checkpoint,
+                        header: *header,
+                    });
+                }
+                Some(_) => {}
+                None if (height as usize) < self.len() => {}
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finds the height at which a peer's headers, starting at
+    /// `start_height`, diverge from this chain.
+    ///
+    /// Returns `None` if all the overlapping headers match.
+    ///
+    /// The headers below the checkpoint of the chain, if any, aren't
+    /// compared.
+    pub fn find_divergence(&self, start_height: u32, headers: &[FilterHeader]) -> Option<u32> {
+        (start_height..)
+            .zip(headers)
+            .take_while(|&(height, _)| (height as usize) < self.len())
+            .find(|&(height, header)| self.get(height).is_some_and(|ours| ours != header))
+            .map(|(height, _)| height)
+    }
+}
+
+impl Default for FilterHeaderChain {
+    fn default() -> FilterHeaderChain {
+        FilterHeaderChain::new()
+    }
+}
+
+/// An iterator computing the filter headers of a stream of blocks.
+///
+/// For each block hash and filter it yields the block hash along with the
+/// filter header of the block, chained to the header of the previous block,
+/// so the headers can be indexed as the blocks are processed.
+#[derive(Debug, Clone)]
+pub struct FilterHeaders<I> {
+    filters: I,
+    prev_header: FilterHeader,
+}
+
+impl<I: Iterator<Item = (Sha256dHash, Filter)>> FilterHeaders<I> {
+    /// Creates an iterator over the filter headers of the blocks yielded by
+    /// `filters`, `prev_header` being the header of the block before the
+    /// first one, the [all-zero header][1] if the first one is the genesis
+    /// block.
+    ///
+    /// [1]: struct.FilterHeader.html#method.zero
+    pub fn new<T>(prev_header: FilterHeader, filters: T) -> FilterHeaders<I>
+    where
+        T: IntoIterator<Item = (Sha256dHash, Filter), IntoIter = I>,
+    {
+        FilterHeaders {
+            filters: filters.into_iter(),
+            prev_header,
+        }
+    }
+
+    /// Returns the header of the last block processed, or the one given on
+    /// construction if none has been processed yet.
+    pub fn prev_header(&self) -> FilterHeader {
+        self.prev_header
+    }
+}
+
+impl<I: Iterator<Item = (Sha256dHash, Filter)>> Iterator for FilterHeaders<I> {
+    type Item = (Sha256dHash, FilterHeader);
+
+    fn next(&mut self) -> Option<(Sha256dHash, FilterHeader)> {
+        let (block_hash, filter) = self.filters.next()?;
+        self.prev_header = filter.filter_header(&self.prev_header);
+        Some((block_hash, self.prev_header))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.filters.size_hint()
+    }
+}
+
+/// The peer, or peers, that served invalid filter headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultyPeer {
+    /// The first peer.
+    A,
+    /// The second peer.
+    B,
+    /// Both peers.
+    Both,
+}
+
+/// Finds the first block whose filter headers differ between two peers, and
+/// which of them is lying, as required by BIP157.
+///
+/// The resolver keeps the range of heights in which the first mismatch is.
+/// The headers of both peers at the [bisect height][1] are requested and
+/// given to [`update`][2] until the range is a single height. Then the block
+/// and both peers' filters at that height are downloaded and checked with
+/// [`resolve`][3].
+///
+/// [1]: #method.bisect_height
+/// [2]: #method.update
+/// [3]: #method.resolve
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderMismatchResolver {
+    low: u32,
+    high: u32,
+    prev_header: FilterHeader,
+    headers: (FilterHeader, FilterHeader),
+}
+
+impl HeaderMismatchResolver {
+    // Constructors
+
+    /// Creates a resolver for peers agreeing on `prev_header`, the header of
+    /// the block before `start_height`, whose headers at `stop_height` are
+    /// `header_a` and `header_b`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `start_height` is larger than `stop_height`.
+    pub fn new(start_height: u32,
+               prev_header: FilterHeader,
+               stop_height: u32,
+               header_a: FilterHeader,
+               header_b: FilterHeader) -> HeaderMismatchResolver {
+        assert!(start_height <= stop_height, "empty height range");
+
+        HeaderMismatchResolver {
+            low: start_height,
+            high: stop_height,
+            prev_header,
+            headers: (header_a, header_b),
+        }
+    }
+
+    /// Creates a resolver from the checkpoints served by two peers in
+    /// `cfcheckpt`, starting at the first checkpoint they disagree on.
+    ///
+    /// Returns `None` if the checkpoints served by both peers match.
+    pub fn from_checkpoints(checkpoints_a: &[FilterHeader],
+                            checkpoints_b: &[FilterHeader]) -> Option<HeaderMismatchResolver> {
+        let i = checkpoints_a.iter()
+            .zip(checkpoints_b)
+            .position(|(a, b)| a != b)?;
+
+        let (start_height, prev_header) = match i {
+            0 => (0, FilterHeader::zero()),
+            i => (i as u32 * CHECKPOINT_INTERVAL + 1, checkpoints_a[i - 1]),
+        };
+
+        Some(HeaderMismatchResolver::new(start_height,
+                                         prev_header,
+                                         (i as u32 + 1) * CHECKPOINT_INTERVAL,
+                                         checkpoints_a[i],
+                                         checkpoints_b[i]))
+    }
+
+    // Accessors
+
+    /// Returns the height whose headers should be requested next, or `None`
+    /// once the height of the first mismatch is known.
+    pub fn bisect_height(&self) -> Option<u32> {
+        if self.low == self.high {
+            None
+        } else {
+            Some(self.low + (self.high - self.low) / 2)
+        }
+    }
+
+    /// Returns the height of the first block whose filter headers differ,
+    /// once it's known.
+    pub fn mismatch_height(&self) -> Option<u32> {
+        if self.low == self.high {
+            Some(self.low)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the header both peers agree on before the range of heights
+    /// in which the first mismatch is.
+    pub fn prev_header(&self) -> FilterHeader {
+        self.prev_header
+    }
+
+    // Bisection
+
+    /// Narrows the range with the headers served by both peers at the
+    /// [bisect height][1].
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `height` is out of the range.
+    ///
+    /// [1]: #method.bisect_height
+    pub fn update(&mut self, height: u32, header_a: FilterHeader, header_b: FilterHeader) {
+        assert!(self.low <= height && height < self.high, "height out of range");
+
+        if header_a == header_b {
+            self.low = height + 1;
+            self.prev_header = header_a;
+        } else {
+            self.high = height;
+            self.headers = (header_a, header_b);
+        }
+    }
+
+    /// Checks the basic filters served by both peers at the
+    /// [mismatch height][1] against the block at that height, returning the
+    /// peers whose filter doesn't match the block or their header.
+    ///
+    /// `prev_scripts` are the previous output scripts spent by the block, as
+    /// required by [`build_basic_filter_with_prev_scripts`][2]. Returns
+    /// `None` if both filters are valid.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the mismatch height isn't known yet.
+    ///
+    /// [1]: #method.mismatch_height
+    /// [2]: builder/fn.build_basic_filter_with_prev_scripts.html
+    #[cfg(feature = "builder")]
+    pub fn resolve(&self,
+                   block: &Block,
+                   prev_scripts: &[Script],
+                   filter_a: &Filter,
+                   filter_b: &Filter) -> Option<FaultyPeer> {
+        assert!(self.low == self.high, "the mismatch height isn't known yet");
+
+        let is_valid = |header: &FilterHeader, filter: &Filter| {
+            filter.filter_header(&self.prev_header) == *header
+                && verify_basic_filter(block, prev_scripts, filter).is_ok()
+        };
+
+        match (is_valid(&self.headers.0, filter_a), is_valid(&self.headers.1, filter_b)) {
+            (true, true) => None,
+            (false, true) => Some(FaultyPeer::A),
+            (true, false) => Some(FaultyPeer::B),
+            (false, false) => Some(FaultyPeer::Both),
+        }
+    }
+}
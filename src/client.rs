@@ -0,0 +1,421 @@
+//! BIP157 light client flow.
+//!
+//! A [`Client`][1] syncs the filter headers of a peer and fetches the
+//! filters of the blocks it needs, over any asynchronous message
+//! [`Transport`][2]:
+//!
+//! 1. The peer must advertise [`NODE_COMPACT_FILTERS`][8].
+//! 2. [`fetch_checkpoints`][3] requests `cfcheckpt` for the tip of the chain.
+//! 3. [`sync_headers`][4] requests `cfheaders` in batches of
+//!    [`MAX_CFHEADERS`][9] blocks, checking the headers against the
+//!    checkpoints.
+//! 4. [`get_filters`][5] requests `cfilters` on demand, checking each
+//!    filter against its header.
+//!
+//! Failed requests are retried up to the [retry limit][6], and the events a
+//! peer should be scored for are [reported][7] to the transport.
+//!
+//! [1]: struct.Client.html
+//! [2]: trait.Transport.html
+//! [3]: struct.Client.html#method.fetch_checkpoints
+//! [4]: struct.Client.html#method.sync_headers
+//! [5]: struct.Client.html#method.get_filters
+//! [6]: struct.Client.html#method.set_max_retries
+//! [7]: trait.Transport.html#method.report
+//! [8]: ../p2p/constant.NODE_COMPACT_FILTERS.html
+//! [9]: ../p2p/constant.MAX_CFHEADERS.html
+
+use std::error;
+use std::fmt;
+
+use futures::future::{self, Either, Loop};
+use futures::Future;
+
+use bitcoin::util::hash::Sha256dHash;
+
+use p2p::{filter_type_params, CFCheckpt, CFHeaders, CFilter, GetCFCheckpt, GetCFHeaders, GetCFilters, MAX_CFHEADERS,
+          MAX_CFILTERS, NODE_COMPACT_FILTERS};
+use {CheckpointMismatch, FilterHeader, FilterHeaderChain, CHECKPOINT_INTERVAL};
+
+/// A request sent to a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// `getcfcheckpt`, answered with a `cfcheckpt`.
+    GetCFCheckpt(GetCFCheckpt),
+    /// `getcfheaders`, answered with a `cfheaders`.
+    GetCFHeaders(GetCFHeaders),
+    /// `getcfilters`, answered with a `cfilter` per block.
+    GetCFilters(GetCFilters),
+}
+
+/// The response of a peer to a [`Request`][1].
+///
+/// [1]: enum.Request.html
+#[derive(Debug, Clone)]
+pub enum Response {
+    /// `cfcheckpt` message.
+    CFCheckpt(CFCheckpt),
+    /// `cfheaders` message.
+    CFHeaders(CFHeaders),
+    /// The `cfilter` messages received for a `getcfilters`, in order.
+    CFilters(Vec<CFilter>),
+}
+
+/// Events a peer should be scored for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerEvent {
+    /// A request failed, and may be retried.
+    RequestFailed,
+    /// The peer answered with a response not matching the request.
+    UnexpectedResponse,
+    /// The filter headers of the peer don't match its checkpoints.
+    CheckpointMismatch(u32),
+    /// The filter of the block at the given height doesn't match its header.
+    InvalidFilter(u32),
+}
+
+/// An asynchronous connection to a peer, exchanging BIP157 messages.
+pub trait Transport {
+    /// The error returned when a request fails.
+    type Error;
+    /// The future returned by [`request`][1].
+    ///
+    /// [1]: #tymethod.request
+    type Future: Future<Item = Response, Error = Self::Error>;
+
+    /// Returns the services advertised by the peer in its `version` message.
+    fn services(&self) -> u64;
+
+    /// Sends a request to the peer, resolving to its response.
+    fn request(&mut self, request: Request) -> Self::Future;
+
+    /// Reports an event the peer should be scored for, e.g. to disconnect
+    /// or ban misbehaving peers. Events are ignored by default.
+    fn report(&mut self, _event: PeerEvent) {}
+}
+
+/// Errors that can occur while syncing with a peer.
+#[derive(Debug)]
+pub enum ClientError<E> {
+    /// The transport failed, after retrying.
+    Transport(E),
+    /// The peer doesn't advertise `NODE_COMPACT_FILTERS`.
+    NoCompactFilters,
+    /// The filter type isn't known.
+    UnknownFilterType(u8),
+    /// The peer answered with a response not matching the request.
+    UnexpectedResponse,
+    /// The filter headers of the peer don't match its checkpoints.
+    CheckpointMismatch(CheckpointMismatch),
+    /// The filter of the block at the given height doesn't match its header.
+    InvalidFilter(u32),
+    /// The filter header of the block at the given height isn't synced, or
+    /// the block isn't in the chain.
+    UnknownHeight(u32),
+}
+
+impl<E: fmt::Display> fmt::Display for ClientError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientError::Transport(ref e) => write!(f, "transport error: {}", e),
+            ClientError::NoCompactFilters => f.write_str("the peer doesn't serve compact filters"),
+            ClientError::UnknownFilterType(filter_type) => write!(f, "unknown filter type {}", filter_type),
+            ClientError::UnexpectedResponse => f.write_str("unexpected response"),
+            ClientError::CheckpointMismatch(ref mismatch) => {
+                write!(f, "filter header mismatch at height {}", mismatch.height)
+            }
+            ClientError::InvalidFilter(height) => write!(f, "invalid filter at height {}", height),
+            ClientError::UnknownHeight(height) => write!(f, "unknown height {}", height),
+        }
+    }
+}
+
+impl<E: error::Error> error::Error for ClientError<E> {
+    fn description(&self) -> &str {
+        match *self {
+            ClientError::Transport(_) => "transport error",
+            ClientError::NoCompactFilters => "the peer doesn't serve compact filters",
+            ClientError::UnknownFilterType(_) => "unknown filter type",
+            ClientError::UnexpectedResponse => "unexpected response",
+            ClientError::CheckpointMismatch(_) => "filter header mismatch",
+            ClientError::InvalidFilter(_) => "invalid filter",
+            ClientError::UnknownHeight(_) => "unknown height",
+        }
+    }
+}
+
+/// A BIP157 client syncing the filters of a single peer.
+///
+/// The client is given the hashes of the blocks of the best chain, from the
+/// genesis block on, which are used as the stop hashes of the requests and
+/// to check the responses. The asynchronous methods take the client by value
+/// and give it back along with the result.
+#[derive(Debug)]
+pub struct Client<T> {
+    transport: T,
+    filter_type: u8,
+    block_hashes: Vec<Sha256dHash>,
+    checkpoints: Vec<FilterHeader>,
+    headers: FilterHeaderChain,
+    max_retries: u32,
+}
+
+impl<T: Transport> Client<T> {
+    // Constructors
+
+    /// Creates a client fetching filters of the given type from the peer
+    /// behind `transport`.
+    ///
+    /// Fails if the peer doesn't advertise `NODE_COMPACT_FILTERS`, or if the
+    /// filter type is unknown.
+    pub fn new(transport: T, filter_type: u8, block_hashes: Vec<Sha256dHash>)
+        -> Result<Client<T>, ClientError<T::Error>>
+    {
+        if transport.services() & NODE_COMPACT_FILTERS == 0 {
+            return Err(ClientError::NoCompactFilters);
+        }
+        if filter_type_params(filter_type).is_none() {
+            return Err(ClientError::UnknownFilterType(filter_type));
+        }
+
+        Ok(Client {
+            transport,
+            filter_type,
+            block_hashes,
+            checkpoints: Vec::new(),
+            headers: FilterHeaderChain::new(),
+            max_retries: 3,
+        })
+    }
+
+    // Accessors
+
+    /// Returns the transport.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns the checkpoints received from the peer.
+    pub fn checkpoints(&self) -> &[FilterHeader] {
+        &self.checkpoints
+    }
+
+    /// Returns the filter headers synced so far.
+    pub fn headers(&self) -> &FilterHeaderChain {
+        &self.headers
+    }
+
+    /// Drops the client, returning the transport.
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    // Modifiers
+
+    /// Sets how many times a failed request is retried, 3 by default.
+    pub fn set_max_retries(&mut self, max_retries: u32) -> &mut Client<T> {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the filter headers synced so far, e.g. a chain starting at a
+    /// [trusted checkpoint][1] or one persisted by a previous client, so
+    /// only the headers above its tip are synced.
+    ///
+    /// [1]: ../struct.FilterHeaderChain.html#method.from_checkpoint
+    pub fn set_headers(&mut self, headers: FilterHeaderChain) -> &mut Client<T> {
+        self.headers = headers;
+        self
+    }
+
+    /// Appends the hash of a new block of the best chain.
+    pub fn push_block_hash(&mut self, block_hash: Sha256dHash) -> &mut Client<T> {
+        self.block_hashes.push(block_hash);
+        self
+    }
+
+    // Syncing
+
+    /// Requests the checkpoints of the peer up to the tip of the chain.
+    pub fn fetch_checkpoints(self) -> impl Future<Item = Client<T>, Error = ClientError<T::Error>> {
+        let stop_hash = match self.block_hashes.last() {
+            Some(hash) => *hash,
+            None => return Either::A(future::ok(self)),
+        };
+        let request = Request::GetCFCheckpt(GetCFCheckpt {
+            filter_type: self.filter_type,
+            stop_hash,
+        });
+
+        Either::B(self.request(request).and_then(move |(mut client, response)| {
+            match response {
+                Response::CFCheckpt(ref checkpt)
+                    if checkpt.filter_type == client.filter_type && checkpt.stop_hash == stop_hash =>
+                {
+                    client.checkpoints = checkpt.filter_headers.clone();
+                    Ok(client)
+                }
+                _ => client.unexpected_response(),
+            }
+        }))
+    }
+
+    /// Syncs the filter headers up to the tip of the chain, checking them
+    /// against the checkpoints.
+    ///
+    /// The headers already synced aren't requested again. On a checkpoint
+    /// mismatch the headers of the failing batch are discarded.
+    pub fn sync_headers(self) -> impl Future<Item = Client<T>, Error = ClientError<T::Error>> {
+        future::loop_fn(self, |client| {
+            let start_height = client.headers.len() as u32;
+            if start_height as usize >= client.block_hashes.len() {
+                return Either::A(future::ok(Loop::Break(client)));
+            }
+
+            let stop_height = (start_height + MAX_CFHEADERS - 1).min(client.block_hashes.len() as u32 - 1);
+            let stop_hash = client.block_hashes[stop_height as usize];
+            let request = Request::GetCFHeaders(GetCFHeaders {
+                filter_type: client.filter_type,
+                start_height,
+                stop_hash,
+            });
+
+            Either::B(client.request(request).and_then(move |(mut client, response)| {
+                let cfheaders = match response {
+                    Response::CFHeaders(cfheaders) => cfheaders,
+                    _ => return client.unexpected_response(),
+                };
+
+                let count = (stop_height - start_height + 1) as usize;
+                if cfheaders.filter_type != client.filter_type
+                    || cfheaders.stop_hash != stop_hash
+                    || cfheaders.previous_filter_header != client.headers.tip()
+                    || cfheaders.filter_hashes.len() != count
+                {
+                    return client.unexpected_response();
+                }
+
+                client.push_headers(start_height, &cfheaders.filter_hashes)?;
+                Ok(Loop::Continue(client))
+            }))
+        })
+    }
+
+    /// Requests the filters of the blocks from `start_height` to
+    /// `stop_height`, both included, checking them against their synced
+    /// headers.
+    ///
+    /// Ranges larger than [`MAX_CFILTERS`][1] are split in several requests.
+    ///
+    /// [1]: ../p2p/constant.MAX_CFILTERS.html
+    pub fn get_filters(self, start_height: u32, stop_height: u32)
+        -> impl Future<Item = (Client<T>, Vec<CFilter>), Error = ClientError<T::Error>>
+    {
+        future::loop_fn((self, start_height, Vec::new()), move |(client, start_height, mut filters)| {
+            if start_height > stop_height {
+                return Either::A(future::ok(Loop::Break((client, filters))));
+            }
+            if client.headers.get(stop_height).is_none() || stop_height as usize >= client.block_hashes.len() {
+                return Either::A(future::err(ClientError::UnknownHeight(stop_height)));
+            }
+
+            let batch_stop = (start_height + MAX_CFILTERS - 1).min(stop_height);
+            let request = Request::GetCFilters(GetCFilters {
+                filter_type: client.filter_type,
+                start_height,
+                stop_hash: client.block_hashes[batch_stop as usize],
+            });
+
+            Either::B(client.request(request).and_then(move |(mut client, response)| {
+                let batch = match response {
+                    Response::CFilters(batch) => batch,
+                    _ => return client.unexpected_response(),
+                };
+                if batch.len() != (batch_stop - start_height + 1) as usize {
+                    return client.unexpected_response();
+                }
+
+                for (height, cfilter) in (start_height..).zip(&batch) {
+                    if cfilter.filter_type != client.filter_type
+                        || cfilter.block_hash != client.block_hashes[height as usize]
+                    {
+                        return client.unexpected_response();
+                    }
+                    client.check_filter(height, cfilter)?;
+                }
+
+                filters.extend(batch);
+                Ok(Loop::Continue((client, batch_stop + 1, filters)))
+            }))
+        })
+    }
+
+    /// Sends a request, retrying it if it fails.
+    fn request(self, request: Request)
+        -> impl Future<Item = (Client<T>, Response), Error = ClientError<T::Error>>
+    {
+        future::loop_fn((self, 0), move |(mut client, retries)| {
+            client.transport.request(request.clone()).then(move |result| match result {
+                Ok(response) => Ok(Loop::Break((client, response))),
+                Err(e) => {
+                    client.transport.report(PeerEvent::RequestFailed);
+                    if retries < client.max_retries {
+                        Ok(Loop::Continue((client, retries + 1)))
+                    } else {
+                        Err(ClientError::Transport(e))
+                    }
+                }
+            })
+        })
+    }
+
+    fn unexpected_response<R>(&mut self) -> Result<R, ClientError<T::Error>> {
+        self.transport.report(PeerEvent::UnexpectedResponse);
+        Err(ClientError::UnexpectedResponse)
+    }
+
+    /// Appends the headers of a `cfheaders` batch, checking them against
+    /// the checkpoints first.
+    fn push_headers(&mut self, start_height: u32, filter_hashes: &[Sha256dHash]) -> Result<(), ClientError<T::Error>> {
+        let mut prev_header = self.headers.tip();
+        for (height, filter_hash) in (start_height..).zip(filter_hashes) {
+            let header = FilterHeader::from_filter_hash(filter_hash, &prev_header);
+            prev_header = header;
+
+            if height == 0 || height % CHECKPOINT_INTERVAL != 0 {
+                continue;
+            }
+            match self.checkpoints.get((height / CHECKPOINT_INTERVAL - 1) as usize) {
+                Some(checkpoint) if *checkpoint != header => {
+                    self.transport.report(PeerEvent::CheckpointMismatch(height));
+                    return Err(ClientError::CheckpointMismatch(CheckpointMismatch {
+                        height,
+                        checkpoint: *checkpoint,
+                        header,
+                    }));
+                }
+                _ => {}
+            }
+        }
+
+        for filter_hash in filter_hashes {
+            self.headers.push_filter_hash(filter_hash);
+        }
+        Ok(())
+    }
+
+    /// Checks a filter against its synced header.
+    fn check_filter(&mut self, height: u32, cfilter: &CFilter) -> Result<(), ClientError<T::Error>> {
+        let prev_header = match height {
+            0 => FilterHeader::zero(),
+            _ => self.headers.get(height - 1).cloned().ok_or(ClientError::UnknownHeight(height - 1))?,
+        };
+        let header = self.headers.get(height).cloned().ok_or(ClientError::UnknownHeight(height))?;
+
+        if cfilter.filter.filter_header(&prev_header) != header {
+            self.transport.report(PeerEvent::InvalidFilter(height));
+            return Err(ClientError::InvalidFilter(height));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! Merkle commitments to the filters of a block.
+//!
+//! BIP157 filter headers chain the filters of a single type. A soft fork
+//! committing to filters in blocks would rather commit to every filter type
+//! of a block at once, with the Merkle root of their filter hashes, so light
+//! clients can check the filter they use with a [`MerkleProof`][1] without
+//! downloading the others.
+//!
+//! The roots of consecutive blocks are chained like the filter hashes of
+//! BIP157, see [`FilterCommitment::header`][2], so a
+//! [`FilterHeaderChain`][3] of roots commits to the filters of every block.
+//!
+//! The tree is built like the transaction tree of Bitcoin blocks: the last
+//! hash of a level with an odd number of hashes is paired with itself.
+//!
+//! [1]: struct.MerkleProof.html
+//! [2]: struct.FilterCommitment.html#method.header
+//! [3]: ../struct.FilterHeaderChain.html
+
+use bitcoin::util::hash::Sha256dHash;
+
+use {Filter, FilterHeader};
+
+/// The filter hashes of the filters of a block and their Merkle root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterCommitment {
+    filter_hashes: Vec<Sha256dHash>,
+    root: Sha256dHash,
+}
+
+impl FilterCommitment {
+    // Constructors
+
+    /// Commits to the filters of a block, in the order given, e.g. by filter
+    /// type.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the modulus of a filter isn't `N * M`, see
+    /// [`Filter::filter_hash`][1].
+    ///
+    /// [1]: ../struct.Filter.html#method.filter_hash
+    pub fn new<'a, I>(filters: I) -> FilterCommitment
+    where
+        I: IntoIterator<Item = &'a Filter>,
+    {
+        FilterCommitment::from_filter_hashes(filters.into_iter().map(Filter::filter_hash).collect())
+    }
+
+    /// Commits to the filters with the given filter hashes.
+    ///
+    /// The root of an empty commitment is the all-zero hash.
+    pub fn from_filter_hashes(filter_hashes: Vec<Sha256dHash>) -> FilterCommitment {
+        let mut level = filter_hashes.clone();
+        while level.len() > 1 {
+            level = next_level(&level);
+        }
+        let root = level.pop().unwrap_or_else(|| Sha256dHash::from(&[0u8; 32][..]));
+
+        FilterCommitment {
+            filter_hashes,
+            root,
+        }
+    }
+
+    // Accessors
+
+    /// Returns the Merkle root of the filter hashes.
+    pub fn root(&self) -> Sha256dHash {
+        self.root
+    }
+
+    /// Returns the committed filter hashes.
+    pub fn filter_hashes(&self) -> &[Sha256dHash] {
+        &self.filter_hashes
+    }
+
+    /// Calculates the header of this commitment given the header of the
+    /// previous block, `double-SHA256(root || prev_header)`.
+    pub fn header(&self, prev_header: &FilterHeader) -> FilterHeader {
+        FilterHeader::from_filter_hash(&self.root, prev_header)
+    }
+
+    /// Returns the proof of inclusion of the filter at `index`, `None` if
+    /// there's no such filter.
+    pub fn prove(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.filter_hashes.len() {
+            return None;
+        }
+
+        let mut branch = Vec::new();
+        let mut level = self.filter_hashes.clone();
+        let mut i = index;
+        while level.len() > 1 {
+            branch.push(*level.get(i ^ 1).unwrap_or(&level[i]));
+            level = next_level(&level);
+            i /= 2;
+        }
+
+        Some(MerkleProof {
+            index: index as u32,
+            leaves: self.filter_hashes.len() as u32,
+            branch,
+        })
+    }
+}
+
+/// A proof that a filter is committed to by the root of a
+/// [`FilterCommitment`][1].
+///
+/// [1]: struct.FilterCommitment.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    index: u32,
+    leaves: u32,
+    branch: Vec<Sha256dHash>,
+}
+
+impl MerkleProof {
+    // Constructors
+
+    /// Creates a proof of the filter at `index` out of `leaves` filters,
+    /// with the hashes of its Merkle branch from the bottom up.
+    pub fn new(index: u32, leaves: u32, branch: Vec<Sha256dHash>) -> MerkleProof {
+        MerkleProof {
+            index,
+            leaves,
+            branch,
+        }
+    }
+
+    // Accessors
+
+    /// Returns the position of the filter in the commitment.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Returns the number of filters in the commitment.
+    pub fn leaves(&self) -> u32 {
+        self.leaves
+    }
+
+    /// Returns the hashes of the Merkle branch, from the bottom up.
+    pub fn branch(&self) -> &[Sha256dHash] {
+        &self.branch
+    }
+
+    /// Calculates the root committing to a filter with hash `filter_hash`,
+    /// `None` if the branch doesn't fit the number of filters.
+    pub fn root(&self, filter_hash: &Sha256dHash) -> Option<Sha256dHash> {
+        if self.index >= self.leaves || self.branch.len() != tree_depth(self.leaves) {
+            return None;
+        }
+
+        let mut hash = *filter_hash;
+        let mut i = self.index;
+        let mut len = self.leaves;
+        for sibling in &self.branch {
+            // The duplicated last hash of a level is the node itself, so a
+            // proof can't move a filter past the end of the commitment.
+            if i ^ 1 >= len && *sibling != hash {
+                return None;
+            }
+
+            hash = match i & 1 {
+                0 => hash_pair(&hash, sibling),
+                _ => hash_pair(sibling, &hash),
+            };
+            i /= 2;
+            len = len.div_ceil(2);
+        }
+        Some(hash)
+    }
+
+    /// Returns `true` if `filter` is committed to by `root`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the modulus of the filter isn't `N * M`, see
+    /// [`Filter::filter_hash`][1].
+    ///
+    /// [1]: ../struct.Filter.html#method.filter_hash
+    pub fn verify(&self, filter: &Filter, root: &Sha256dHash) -> bool {
+        self.root(&filter.filter_hash()).as_ref() == Some(root)
+    }
+}
+
+/// Returns the number of levels above the leaves of a tree.
+fn tree_depth(leaves: u32) -> usize {
+    let mut depth = 0;
+    let mut len = leaves;
+    while len > 1 {
+        len = len.div_ceil(2);
+        depth += 1;
+    }
+    depth
+}
+
+/// Hashes the pairs of a level of the tree.
+fn next_level(level: &[Sha256dHash]) -> Vec<Sha256dHash> {
+    level.chunks(2)
+        .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+        .collect()
+}
+
+/// Calculates `double-SHA256(left || right)`.
+fn hash_pair(left: &Sha256dHash, right: &Sha256dHash) -> Sha256dHash {
+    let mut data = [0u8; 64];
+    data[0..32].copy_from_slice(&left[..]);
+    data[32..64].copy_from_slice(&right[..]);
+    Sha256dHash::from_data(&data)
+}
@@ -0,0 +1,444 @@
+//! Filter databases.
+//!
+//! A [`FilterDatabase`][1] stores the filters of the blocks of a chain keyed
+//! by height and block hash. It's implemented by the flat-file
+//! [`FilterStore`][2], and with the `sled-store` and `rocksdb-store` features
+//! by adapters storing the filters in an existing [sled][3] tree or
+//! [RocksDB][4] database, so indexers can keep them along with the rest of
+//! their data.
+//!
+//! The adapters keep two kinds of keys:
+//!
+//! ```text
+//! 'h' | height (u32, big-endian)  =>  block hash (32 bytes) | N (u32) | P (u8) | M (u64) | data
+//! 'b' | block hash (32 bytes)     =>  height (u32, big-endian)
+//! ```
+//!
+//! with the other integers in little-endian, so the filters are iterated in
+//! the order of their heights.
+//!
+//! [1]: trait.FilterDatabase.html
+//! [2]: ../store/struct.FilterStore.html
+//! [3]: https://github.com/spacejam/sled
+//! [4]: https://github.com/rust-rocksdb/rust-rocksdb
+
+use std::error;
+use std::fmt;
+use std::io;
+#[cfg(any(feature = "sled-store", feature = "rocksdb-store"))]
+use std::ops::Bound;
+use std::ops::RangeBounds;
+#[cfg(feature = "rocksdb-store")]
+use std::sync::Arc;
+
+use bitcoin::util::hash::Sha256dHash;
+
+#[cfg(feature = "rocksdb-store")]
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+
+use store::FilterStore;
+#[cfg(any(feature = "sled-store", feature = "rocksdb-store"))]
+use checked_modulus;
+use {EncodeError, Filter};
+
+/// A filter, along with the height and the hash of its block.
+pub type FilterRecord = (u32, Sha256dHash, Filter);
+
+/// A store of the filters of the blocks of a chain.
+pub trait FilterDatabase {
+    /// The error returned when the database fails.
+    type Error;
+
+    /// Returns the hash of the block at `height` along with its filter.
+    fn get_by_height(&self, height: u32) -> Result<Option<(Sha256dHash, Filter)>, Self::Error>;
+
+    /// Returns the filter of a block given its hash.
+    fn get_by_hash(&self, block_hash: &Sha256dHash) -> Result<Option<Filter>, Self::Error>;
+
+    /// Stores the filter of the block at `height`, replacing the filter
+    /// stored at that height, if any.
+    ///
+    /// Filters whose modulus isn't `N * M` can't be stored, as only N, P and
+    /// M are.
+    fn put(&mut self, height: u32, block_hash: &Sha256dHash, filter: &Filter) -> Result<(), Self::Error>;
+
+    /// Removes the filters below `height`.
+    fn prune_below(&mut self, height: u32) -> Result<(), Self::Error>;
+
+    /// Returns an iterator over the filters in a range of heights, in
+    /// ascending order.
+    fn iter_range<R: RangeBounds<u32>>(&self, range: R)
+        -> Box<dyn Iterator<Item = Result<FilterRecord, Self::Error>> + '_>;
+}
+
+impl FilterDatabase for FilterStore {
+    type Error = io::Error;
+
+    fn get_by_height(&self, height: u32) -> io::Result<Option<(Sha256dHash, Filter)>> {
+        match self.block_hash(height) {
+            Some(block_hash) => self.get(height).map(|filter| filter.map(|f| (block_hash, f))),
+            None => Ok(None),
+        }
+    }
+
+    fn get_by_hash(&self, block_hash: &Sha256dHash) -> io::Result<Option<Filter>> {
+        FilterStore::get_by_hash(self, block_hash)
+    }
+
+    fn put(&mut self, height: u32, block_hash: &Sha256dHash, filter: &Filter) -> io::Result<()> {
+        FilterStore::put(self, height, block_hash, filter)
+    }
+
+    fn prune_below(&mut self, height: u32) -> io::Result<()> {
+        self.prune(height)
+    }
+
+    fn iter_range<R: RangeBounds<u32>>(&self, range: R)
+        -> Box<dyn Iterator<Item = io::Result<FilterRecord>> + '_>
+    {
+        Box::new(self.range(range))
+    }
+}
+
+/// Errors of the key-value store adapters.
+#[derive(Debug)]
+pub enum DatabaseError<E> {
+    /// The key-value store failed.
+    Backend(E),
+    /// A stored value isn't a valid filter record.
+    InvalidRecord,
+    /// The filter can't be stored.
+    Encode(EncodeError),
+}
+
+impl<E: fmt::Display> fmt::Display for DatabaseError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DatabaseError::Backend(ref e) => write!(f, "key-value store error: {}", e),
+            DatabaseError::InvalidRecord => write!(f, "invalid filter record"),
+            DatabaseError::Encode(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl<E: error::Error> error::Error for DatabaseError<E> {
+    fn description(&self) -> &str {
+        match *self {
+            DatabaseError::Backend(_) => "key-value store error",
+            DatabaseError::InvalidRecord => "invalid filter record",
+            DatabaseError::Encode(_) => "the filter can't be stored",
+        }
+    }
+}
+
+/// A [`FilterDatabase`][1] stored in a [sled][2] tree.
+///
+/// The tree shouldn't hold anything but the filters, e.g. open a dedicated
+/// tree with `Db::open_tree`.
+///
+/// [1]: trait.FilterDatabase.html
+/// [2]: https://github.com/spacejam/sled
+#[cfg(feature = "sled-store")]
+#[derive(Debug, Clone)]
+pub struct SledDatabase {
+    tree: ::sled::Tree,
+}
+
+#[cfg(feature = "sled-store")]
+impl SledDatabase {
+    // Constructors
+
+    /// Creates a database of the filters stored in `tree`.
+    pub fn new(tree: ::sled::Tree) -> SledDatabase {
+        SledDatabase { tree }
+    }
+
+    // Accessors
+
+    /// Returns the tree the filters are stored in.
+    pub fn tree(&self) -> &::sled::Tree {
+        &self.tree
+    }
+}
+
+#[cfg(feature = "sled-store")]
+impl FilterDatabase for SledDatabase {
+    type Error = DatabaseError<::sled::Error>;
+
+    fn get_by_height(&self, height: u32) -> Result<Option<(Sha256dHash, Filter)>, Self::Error> {
+        match self.tree.get(height_key(height)).map_err(DatabaseError::Backend)? {
+            Some(value) => decode_record(&value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn get_by_hash(&self, block_hash: &Sha256dHash) -> Result<Option<Filter>, Self::Error> {
+        match self.tree.get(hash_key(block_hash)).map_err(DatabaseError::Backend)? {
+            Some(height) => {
+                let height = decode_height(&height)?;
+                Ok(self.get_by_height(height)?.map(|(_, filter)| filter))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn put(&mut self, height: u32, block_hash: &Sha256dHash, filter: &Filter) -> Result<(), Self::Error> {
+        let mut batch = ::sled::Batch::default();
+        if let Some((old_hash, _)) = self.get_by_height(height)? {
+            batch.remove(&hash_key(&old_hash)[..]);
+        }
+        batch.insert(&height_key(height)[..], encode_record(block_hash, filter)?);
+        batch.insert(&hash_key(block_hash)[..], &height.to_be_bytes()[..]);
+
+        self.tree.apply_batch(batch).map_err(DatabaseError::Backend)
+    }
+
+    fn prune_below(&mut self, height: u32) -> Result<(), Self::Error> {
+        let mut batch = ::sled::Batch::default();
+        for record in self.iter_range(..height) {
+            let (height, block_hash, _) = record?;
+            batch.remove(&height_key(height)[..]);
+            batch.remove(&hash_key(&block_hash)[..]);
+        }
+
+        self.tree.apply_batch(batch).map_err(DatabaseError::Backend)
+    }
+
+    fn iter_range<R: RangeBounds<u32>>(&self, range: R)
+        -> Box<dyn Iterator<Item = Result<FilterRecord, Self::Error>> + '_>
+    {
+        let (start, end) = height_bounds(range);
+        let records = self.tree.range(height_key(start as u32)..)
+            .map(|entry| {
+                let (key, value) = entry.map_err(DatabaseError::Backend)?;
+                decode_entry(&key, &value)
+            });
+
+        Box::new(take_range(records, start, end))
+    }
+}
+
+/// A [`FilterDatabase`][1] stored in a [RocksDB][2] database shared with
+/// other data.
+///
+/// The keys of the filters start with a prefix, which no other key of the
+/// database should start with.
+///
+/// [1]: trait.FilterDatabase.html
+/// [2]: https://github.com/rust-rocksdb/rust-rocksdb
+#[cfg(feature = "rocksdb-store")]
+pub struct RocksDatabase {
+    db: Arc<DB>,
+    prefix: Vec<u8>,
+}
+
+#[cfg(feature = "rocksdb-store")]
+impl RocksDatabase {
+    // Constructors
+
+    /// Creates a database of the filters stored in `db` under the keys
+    /// starting with `prefix`.
+    pub fn new(db: Arc<DB>, prefix: &[u8]) -> RocksDatabase {
+        RocksDatabase {
+            db,
+            prefix: prefix.to_vec(),
+        }
+    }
+
+    // Accessors
+
+    /// Returns the database the filters are stored in.
+    pub fn db(&self) -> &Arc<DB> {
+        &self.db
+    }
+
+    fn key(&self, key: &[u8]) -> Vec<u8> {
+        let mut prefixed = self.prefix.clone();
+        prefixed.extend_from_slice(key);
+        prefixed
+    }
+}
+
+#[cfg(feature = "rocksdb-store")]
+impl fmt::Debug for RocksDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RocksDatabase")
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+#[cfg(feature = "rocksdb-store")]
+impl FilterDatabase for RocksDatabase {
+    type Error = DatabaseError<::rocksdb::Error>;
+
+    fn get_by_height(&self, height: u32) -> Result<Option<(Sha256dHash, Filter)>, Self::Error> {
+        match self.db.get(self.key(&height_key(height))).map_err(DatabaseError::Backend)? {
+            Some(value) => decode_record(&value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn get_by_hash(&self, block_hash: &Sha256dHash) -> Result<Option<Filter>, Self::Error> {
+        match self.db.get(self.key(&hash_key(block_hash))).map_err(DatabaseError::Backend)? {
+            Some(height) => {
+                let height = decode_height(&height)?;
+                Ok(self.get_by_height(height)?.map(|(_, filter)| filter))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn put(&mut self, height: u32, block_hash: &Sha256dHash, filter: &Filter) -> Result<(), Self::Error> {
+        let mut batch = WriteBatch::default();
+        if let Some((old_hash, _)) = self.get_by_height(height)? {
+            batch.delete(self.key(&hash_key(&old_hash)));
+        }
+        batch.put(self.key(&height_key(height)), encode_record(block_hash, filter)?);
+        batch.put(self.key(&hash_key(block_hash)), height.to_be_bytes());
+
+        self.db.write(batch).map_err(DatabaseError::Backend)
+    }
+
+    fn prune_below(&mut self, height: u32) -> Result<(), Self::Error> {
+        let mut batch = WriteBatch::default();
+        for record in self.iter_range(..height) {
+            let (height, block_hash, _) = record?;
+            batch.delete(self.key(&height_key(height)));
+            batch.delete(self.key(&hash_key(&block_hash)));
+        }
+
+        self.db.write(batch).map_err(DatabaseError::Backend)
+    }
+
+    fn iter_range<R: RangeBounds<u32>>(&self, range: R)
+        -> Box<dyn Iterator<Item = Result<FilterRecord, Self::Error>> + '_>
+    {
+        let (start, end) = height_bounds(range);
+        let start_key = self.key(&height_key(start as u32));
+        let prefix_len = self.prefix.len();
+        let records = self.db.iterator(IteratorMode::From(&start_key, Direction::Forward))
+            .take_while(move |entry| match *entry {
+                Ok((ref key, _)) => key.starts_with(&self.prefix),
+                Err(_) => true,
+            })
+            .map(move |entry| {
+                let (key, value) = entry.map_err(DatabaseError::Backend)?;
+                decode_entry(&key[prefix_len..], &value)
+            });
+
+        Box::new(take_range(records, start, end))
+    }
+}
+
+#[cfg(any(feature = "sled-store", feature = "rocksdb-store"))]
+fn height_key(height: u32) -> [u8; 5] {
+    let height = height.to_be_bytes();
+    [b'h', height[0], height[1], height[2], height[3]]
+}
+
+#[cfg(any(feature = "sled-store", feature = "rocksdb-store"))]
+fn hash_key(block_hash: &Sha256dHash) -> [u8; 33] {
+    let mut key = [b'b'; 33];
+    key[1..].copy_from_slice(&block_hash[..]);
+    key
+}
+
+/// Returns the first height of a range, and the height after its last one.
+#[cfg(any(feature = "sled-store", feature = "rocksdb-store"))]
+fn height_bounds<R: RangeBounds<u32>>(range: R) -> (u64, u64) {
+    let start = match range.start_bound() {
+        Bound::Included(&h) => u64::from(h),
+        Bound::Excluded(&h) => u64::from(h) + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&h) => u64::from(h) + 1,
+        Bound::Excluded(&h) => u64::from(h),
+        Bound::Unbounded => 1 << 32,
+    };
+    (start, end)
+}
+
+/// Takes the records of the heights from `start` to `end`, from an iterator
+/// over the records after `start`, stopping at the first error.
+#[cfg(any(feature = "sled-store", feature = "rocksdb-store"))]
+fn take_range<'a, I, E>(records: I, start: u64, end: u64) -> impl Iterator<Item = Result<FilterRecord, E>> + 'a
+where
+    I: Iterator<Item = Result<Option<FilterRecord>, E>> + 'a,
+    E: 'a,
+{
+    let mut failed = start >= end;
+    records
+        .take_while(move |record| {
+            let more = !failed && match *record {
+                Ok(Some((height, _, _))) => u64::from(height) < end,
+                Ok(None) => false,
+                Err(_) => true,
+            };
+            failed = failed || record.is_err();
+            more
+        })
+        .map(|record| record.map(|record| record.expect("records past the filters are skipped")))
+}
+
+/// Decodes a key of the database and its value, returning `None` if the key
+/// isn't the height of a filter.
+#[cfg(any(feature = "sled-store", feature = "rocksdb-store"))]
+fn decode_entry<E>(key: &[u8], value: &[u8]) -> Result<Option<FilterRecord>, DatabaseError<E>> {
+    if key.first() != Some(&b'h') {
+        return Ok(None);
+    }
+
+    let height = decode_height(&key[1..])?;
+    let (block_hash, filter) = decode_record(value)?;
+    Ok(Some((height, block_hash, filter)))
+}
+
+#[cfg(any(feature = "sled-store", feature = "rocksdb-store"))]
+fn decode_height<E>(data: &[u8]) -> Result<u32, DatabaseError<E>> {
+    if data.len() != 4 {
+        return Err(DatabaseError::InvalidRecord);
+    }
+
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(data);
+    Ok(u32::from_be_bytes(bytes))
+}
+
+#[cfg(any(feature = "sled-store", feature = "rocksdb-store"))]
+fn encode_record<E>(block_hash: &Sha256dHash, filter: &Filter) -> Result<Vec<u8>, DatabaseError<E>> {
+    if !filter.has_nm_modulus() {
+        return Err(DatabaseError::Encode(EncodeError::ModulusMismatch));
+    }
+
+    let mut record = Vec::with_capacity(32 + 4 + 1 + 8 + filter.data.len());
+    record.extend_from_slice(&block_hash[..]);
+    record.extend_from_slice(&filter.n.to_le_bytes());
+    record.push(filter.p);
+    record.extend_from_slice(&filter.m.to_le_bytes());
+    record.extend_from_slice(&filter.data);
+    Ok(record)
+}
+
+#[cfg(any(feature = "sled-store", feature = "rocksdb-store"))]
+fn decode_record<E>(record: &[u8]) -> Result<(Sha256dHash, Filter), DatabaseError<E>> {
+    if record.len() < 32 + 4 + 1 + 8 {
+        return Err(DatabaseError::InvalidRecord);
+    }
+
+    let mut n = [0u8; 4];
+    n.copy_from_slice(&record[32..36]);
+    let n = u32::from_le_bytes(n);
+    let p = record[36];
+    let mut m = [0u8; 8];
+    m.copy_from_slice(&record[37..45]);
+    let m = u64::from_le_bytes(m);
+
+    if p > 32 || checked_modulus(n, m).is_none() {
+        return Err(DatabaseError::InvalidRecord);
+    }
+
+    let block_hash = Sha256dHash::from(&record[..32]);
+    Ok((block_hash, Filter::from_bytes(n, p, m, record[45..].to_vec())))
+}
@@ -0,0 +1,165 @@
+//! Watching the scripts derived from an output descriptor.
+//!
+//! A [`DescriptorWatch`][1] derives the output scripts of a range of indices
+//! of a [`ScriptDescriptor`][2], e.g. a ranged miniscript descriptor, to be
+//! watched with a [`WatchList`][3] or a [`Rescan`][4]. As matches are found
+//! it keeps track of the highest used index and derives more scripts, so
+//! there are always [`gap_limit`][5] unused scripts after it.
+//!
+//! [1]: struct.DescriptorWatch.html
+//! [2]: trait.ScriptDescriptor.html
+//! [3]: ../struct.WatchList.html
+//! [4]: ../rescan/struct.Rescan.html
+//! [5]: struct.DescriptorWatch.html#method.gap_limit
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use bitcoin::blockdata::script::Script;
+
+use WatchList;
+
+/// The default gap limit, as in BIP44.
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// An output descriptor with a derivation index, e.g. the `/*` of
+/// `wpkh(xpub.../0/*)`.
+///
+/// It's implemented for closures, so descriptors of other crates can be
+/// wrapped with `|index| descriptor.derive(index).script_pubkey()`.
+pub trait ScriptDescriptor {
+    /// Returns the output script at `index`.
+    fn script_pubkey(&self, index: u32) -> Script;
+}
+
+impl<F: Fn(u32) -> Script> ScriptDescriptor for F {
+    fn script_pubkey(&self, index: u32) -> Script {
+        self(index)
+    }
+}
+
+/// The scripts derived from a descriptor, extended to keep a gap limit.
+#[derive(Debug, Clone)]
+pub struct DescriptorWatch<D> {
+    descriptor: D,
+    start: u32,
+    scripts: Vec<Script>,
+    indices: HashMap<Vec<u8>, u32>,
+    gap_limit: u32,
+    highest_used: Option<u32>,
+}
+
+impl<D: ScriptDescriptor> DescriptorWatch<D> {
+    // Constructors
+
+    /// Derives the scripts of `range` from `descriptor`, with the
+    /// [default gap limit][1].
+    ///
+    /// [1]: constant.DEFAULT_GAP_LIMIT.html
+    pub fn new(descriptor: D, range: Range<u32>) -> DescriptorWatch<D> {
+        let mut watch = DescriptorWatch {
+            descriptor,
+            start: range.start,
+            scripts: Vec::new(),
+            indices: HashMap::new(),
+            gap_limit: DEFAULT_GAP_LIMIT,
+            highest_used: None,
+        };
+        watch.derive_to(range.end);
+        watch
+    }
+
+    // Accessors
+
+    /// Returns the descriptor.
+    pub fn descriptor(&self) -> &D {
+        &self.descriptor
+    }
+
+    /// Returns the range of derived indices.
+    pub fn range(&self) -> Range<u32> {
+        self.start..self.end()
+    }
+
+    /// Returns the number of unused scripts kept after the highest used
+    /// index.
+    pub fn gap_limit(&self) -> u32 {
+        self.gap_limit
+    }
+
+    /// Returns the highest index [marked as used][1], if any.
+    ///
+    /// [1]: #method.mark_used
+    pub fn highest_used(&self) -> Option<u32> {
+        self.highest_used
+    }
+
+    /// Returns the script at `index`, if it was derived.
+    pub fn script(&self, index: u32) -> Option<&Script> {
+        index.checked_sub(self.start)
+            .and_then(|i| self.scripts.get(i as usize))
+    }
+
+    /// Returns the derived scripts, from the start of the range.
+    pub fn scripts(&self) -> &[Script] {
+        &self.scripts
+    }
+
+    /// Returns the index of a derived script, to [mark it as used][1] when
+    /// it's found in a block.
+    ///
+    /// [1]: #method.mark_used
+    pub fn index_of(&self, script: &Script) -> Option<u32> {
+        self.indices.get(&script.data()).cloned()
+    }
+
+    /// Watches all the derived scripts with `watch_list`.
+    pub fn watch(&self, watch_list: &mut WatchList) {
+        for script in &self.scripts {
+            watch_list.insert_script(script);
+        }
+    }
+
+    // Modifiers
+
+    /// Sets the gap limit, deriving more scripts if needed, and returns the
+    /// newly derived ones.
+    pub fn set_gap_limit(&mut self, gap_limit: u32) -> &[Script] {
+        self.gap_limit = gap_limit;
+        self.extend()
+    }
+
+    /// Marks the script at `index` as used, e.g. when a transaction paying
+    /// to it was found, and returns the scripts derived to keep the gap
+    /// limit.
+    ///
+    /// The returned scripts have to be watched too, and the blocks after the
+    /// one the script was used in rescanned for them.
+    pub fn mark_used(&mut self, index: u32) -> &[Script] {
+        if index >= self.start && self.highest_used.is_none_or(|highest| index > highest) {
+            self.highest_used = Some(index);
+        }
+        self.extend()
+    }
+
+    fn end(&self) -> u32 {
+        self.start + self.scripts.len() as u32
+    }
+
+    fn extend(&mut self) -> &[Script] {
+        let end = self.end();
+        if let Some(highest_used) = self.highest_used {
+            self.derive_to(highest_used.saturating_add(1).saturating_add(self.gap_limit));
+        }
+        let derived = (end - self.start) as usize;
+        &self.scripts[derived..]
+    }
+
+    fn derive_to(&mut self, end: u32) {
+        for index in self.end()..end {
+            let script = self.descriptor.script_pubkey(index);
+            self.indices.insert(script.data(), index);
+            self.scripts.push(script);
+        }
+    }
+}
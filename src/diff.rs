@@ -0,0 +1,146 @@
+//! Comparison of filters.
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use core::fmt;
+
+use {DecodeError, Filter, GcsHasher};
+
+/// The differences between two filters, returned by [`diff`][1].
+///
+/// [1]: fn.diff.html
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterDiff {
+    /// The set lengths (N) of both filters, if they differ.
+    pub n: Option<(u32, u32)>,
+    /// The Golomb-Rice parameters (P) of both filters, if they differ.
+    pub p: Option<(u8, u8)>,
+    /// The inverse false positive rates (M) of both filters, if they differ.
+    pub m: Option<(u64, u64)>,
+    /// The values of the first filter that aren't in the second one.
+    pub only_in_a: Vec<u64>,
+    /// The values of the second filter that aren't in the first one.
+    pub only_in_b: Vec<u64>,
+    /// The offset of the first bit that differs between the bitstreams of
+    /// both filters, or the length of the shortest one if it's a prefix of
+    /// the other.
+    pub first_divergence: Option<u64>,
+    /// The errors found decoding the first and the second filter, the values
+    /// after them aren't compared.
+    pub errors: (Option<DecodeError>, Option<DecodeError>),
+}
+
+impl FilterDiff {
+    /// Returns `true` if both filters have the same parameters and data.
+    pub fn is_identical(&self) -> bool {
+        self.n.is_none() && self.p.is_none() && self.m.is_none() && self.first_divergence.is_none()
+    }
+}
+
+/// Summarizes the differences, e.g. to report a filter not matching a test
+/// vector.
+impl fmt::Display for FilterDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_identical() {
+            return write!(f, "identical filters");
+        }
+
+        write!(f, "filters differ")?;
+        if let Some((a, b)) = self.n {
+            write!(f, ", N {} != {}", a, b)?;
+        }
+        if let Some((a, b)) = self.p {
+            write!(f, ", P {} != {}", a, b)?;
+        }
+        if let Some((a, b)) = self.m {
+            write!(f, ", M {} != {}", a, b)?;
+        }
+        if let Some(offset) = self.first_divergence {
+            write!(f, ", first differing bit at {}", offset)?;
+        }
+        if !self.only_in_a.is_empty() {
+            write!(f, ", only in a: {:?}", self.only_in_a)?;
+        }
+        if !self.only_in_b.is_empty() {
+            write!(f, ", only in b: {:?}", self.only_in_b)?;
+        }
+        if let Some(ref e) = self.errors.0 {
+            write!(f, ", a is invalid: {}", e)?;
+        }
+        if let Some(ref e) = self.errors.1 {
+            write!(f, ", b is invalid: {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares two filters, listing the values present in only one of them and
+/// locating the first bit where their bitstreams diverge.
+///
+/// The values are compared even if the parameters of the filters differ,
+/// but then they're reduced to different ranges and are unlikely to match.
+pub fn diff<H, A, B>(a: &Filter<H, A>, b: &Filter<H, B>) -> FilterDiff
+where
+    H: GcsHasher,
+    A: AsRef<[u8]>,
+    B: AsRef<[u8]>,
+{
+    let (a_values, a_error) = decode_values(a);
+    let (b_values, b_error) = decode_values(b);
+
+    let mut diff = FilterDiff {
+        n: differing(a.n(), b.n()),
+        p: differing(a.p(), b.p()),
+        m: differing(a.m(), b.m()),
+        first_divergence: first_divergence(a.as_bytes(), b.as_bytes()),
+        errors: (a_error, b_error),
+        ..FilterDiff::default()
+    };
+
+    let (mut i, mut j) = (0, 0);
+    while i < a_values.len() && j < b_values.len() {
+        if a_values[i] < b_values[j] {
+            diff.only_in_a.push(a_values[i]);
+            i += 1;
+        } else if a_values[i] > b_values[j] {
+            diff.only_in_b.push(b_values[j]);
+            j += 1;
+        } else {
+            i += 1;
+            j += 1;
+        }
+    }
+    diff.only_in_a.extend_from_slice(&a_values[i..]);
+    diff.only_in_b.extend_from_slice(&b_values[j..]);
+
+    diff
+}
+
+fn differing<T: PartialEq>(a: T, b: T) -> Option<(T, T)> {
+    if a != b {
+        Some((a, b))
+    } else {
+        None
+    }
+}
+
+/// Decodes the values of a filter up to the first invalid one.
+fn decode_values<H: GcsHasher, D: AsRef<[u8]>>(filter: &Filter<H, D>) -> (Vec<u64>, Option<DecodeError>) {
+    let mut values = Vec::with_capacity(filter.n() as usize);
+    for value in filter.values() {
+        match value {
+            Ok(value) => values.push(value),
+            Err(e) => return (values, Some(e)),
+        }
+    }
+    (values, None)
+}
+
+fn first_divergence(a: &[u8], b: &[u8]) -> Option<u64> {
+    match a.iter().zip(b).position(|(x, y)| x != y) {
+        Some(i) => Some(i as u64 * 8 + u64::from((a[i] ^ b[i]).leading_zeros())),
+        None if a.len() != b.len() => Some(a.len().min(b.len()) as u64 * 8),
+        None => None,
+    }
+}
@@ -0,0 +1,179 @@
+//! Electrum protocol scripthashes.
+//!
+//! Electrum servers identify the scripts of a wallet by their
+//! [scripthash][1], the SHA256 of the script in reverse byte order. As the
+//! hash can't be reverted, a bridge serving the Electrum protocol from
+//! filters keeps the scripts it was asked about in a
+//! [`ScriptHashIndex`][2], to turn the scripthashes of the requests back
+//! into the raw scripts matched against basic filters.
+//!
+//! [1]: https://electrumx.readthedocs.io/en/latest/protocol-basics.html#script-hashes
+//! [2]: struct.ScriptHashIndex.html
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::util::address::Address;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use {decode_hex, ParseFilterError};
+
+/// The Electrum scripthash of a script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ScriptHash([u8; 32]);
+
+impl ScriptHash {
+    // Constructors
+
+    /// Creates a `ScriptHash` from the SHA256 of a script.
+    pub fn from_bytes(bytes: [u8; 32]) -> ScriptHash {
+        ScriptHash(bytes)
+    }
+
+    /// Calculates the scripthash of `script`.
+    pub fn from_script(script: &Script) -> ScriptHash {
+        let mut hasher = Sha256::new();
+        hasher.input(&script.data());
+
+        let mut bytes = [0u8; 32];
+        hasher.result(&mut bytes);
+        ScriptHash(bytes)
+    }
+
+    /// Calculates the scripthash of the output script of `address`.
+    pub fn from_address(address: &Address) -> ScriptHash {
+        ScriptHash::from_script(&address.script_pubkey())
+    }
+
+    // Accessors
+
+    /// Returns the SHA256 of the script, in the byte order it's hashed in.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+/// Formats the scripthash as hex in reverse byte order, as in the Electrum
+/// protocol.
+impl fmt::Display for ScriptHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter().rev() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a scripthash from hex in reverse byte order, as [displayed][1].
+///
+/// [1]: #impl-Display
+impl FromStr for ScriptHash {
+    type Err = ParseFilterError;
+
+    fn from_str(s: &str) -> Result<ScriptHash, ParseFilterError> {
+        let bytes = decode_hex(s)
+            .filter(|bytes| bytes.len() == 32)
+            .ok_or(ParseFilterError::InvalidHex)?;
+
+        let mut hash = [0u8; 32];
+        for (byte, hex_byte) in hash.iter_mut().zip(bytes.iter().rev()) {
+            *byte = *hex_byte;
+        }
+        Ok(ScriptHash(hash))
+    }
+}
+
+/// The scripts of a wallet, by their scripthash.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptHashIndex {
+    scripts: HashMap<ScriptHash, Script>,
+}
+
+impl ScriptHashIndex {
+    // Constructors
+
+    /// Creates an empty `ScriptHashIndex`.
+    pub fn new() -> ScriptHashIndex {
+        ScriptHashIndex::default()
+    }
+
+    // Accessors
+
+    /// Returns the number of scripts in the index.
+    pub fn len(&self) -> usize {
+        self.scripts.len()
+    }
+
+    /// Returns `true` if the index has no scripts.
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    /// Returns the script of `script_hash`, if it was [inserted][1].
+    ///
+    /// [1]: #method.insert
+    pub fn script(&self, script_hash: &ScriptHash) -> Option<&Script> {
+        self.scripts.get(script_hash)
+    }
+
+    /// Returns the raw scripts of `script_hashes`, the items to match
+    /// against basic filters.
+    ///
+    /// An error is returned with the first scripthash whose script isn't in
+    /// the index, as leaving it out could miss the blocks paying to it.
+    pub fn query_items<'a, I>(&self, script_hashes: I) -> Result<Vec<Vec<u8>>, UnknownScriptHash>
+        where I: IntoIterator<Item = &'a ScriptHash>
+    {
+        script_hashes.into_iter()
+            .map(|script_hash| {
+                self.script(script_hash)
+                    .map(Script::data)
+                    .ok_or(UnknownScriptHash(*script_hash))
+            })
+            .collect()
+    }
+
+    // Modifiers
+
+    /// Adds `script` to the index and returns its scripthash.
+    pub fn insert(&mut self, script: Script) -> ScriptHash {
+        let script_hash = ScriptHash::from_script(&script);
+        self.scripts.insert(script_hash, script);
+        script_hash
+    }
+
+    /// Adds the output script of `address` to the index and returns its
+    /// scripthash.
+    pub fn insert_address(&mut self, address: &Address) -> ScriptHash {
+        self.insert(address.script_pubkey())
+    }
+
+    /// Removes the script of `script_hash` from the index and returns it.
+    pub fn remove(&mut self, script_hash: &ScriptHash) -> Option<Script> {
+        self.scripts.remove(script_hash)
+    }
+}
+
+/// The error returned by [`ScriptHashIndex::query_items`][1] when a
+/// scripthash isn't in the index.
+///
+/// [1]: struct.ScriptHashIndex.html#method.query_items
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownScriptHash(pub ScriptHash);
+
+impl fmt::Display for UnknownScriptHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown scripthash {}", self.0)
+    }
+}
+
+impl error::Error for UnknownScriptHash {
+    fn description(&self) -> &str {
+        "unknown scripthash"
+    }
+}
@@ -0,0 +1,235 @@
+use core::fmt;
+
+use io;
+
+/// Errors that can occur while decoding a filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The bitstream ended before all the values could be read.
+    UnexpectedEof,
+    /// A unary coded quotient is larger than any value the filter can hold.
+    QuotientTooLarge,
+    /// A value overflows when added to the previous one.
+    Overflow,
+    /// A value is out of the range of the filter.
+    ValueOutOfRange,
+    /// There's data after the last value.
+    TrailingData,
+    /// The padding bits after the last value aren't zero.
+    NonZeroPadding,
+    /// The underlying reader failed.
+    Io(io::ErrorKind),
+    /// N is inconsistent with the data size, see [`DecodeLimits`][1].
+    ///
+    /// [1]: struct.DecodeLimits.html
+    Limit(LimitError),
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(e: io::Error) -> DecodeError {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => DecodeError::UnexpectedEof,
+            kind => DecodeError::Io(kind),
+        }
+    }
+}
+
+impl From<LimitError> for DecodeError {
+    fn from(e: LimitError) -> DecodeError {
+        DecodeError::Limit(e)
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::UnexpectedEof => write!(f, "unexpected end of filter"),
+            DecodeError::QuotientTooLarge => write!(f, "quotient out of range"),
+            DecodeError::Overflow => write!(f, "value overflow"),
+            DecodeError::ValueOutOfRange => write!(f, "value out of range"),
+            DecodeError::TrailingData => write!(f, "trailing data after the last value"),
+            DecodeError::NonZeroPadding => write!(f, "non-zero padding bits"),
+            DecodeError::Io(kind) => write!(f, "I/O error: {:?}", kind),
+            DecodeError::Limit(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for DecodeError {
+    fn description(&self) -> &str {
+        match *self {
+            DecodeError::UnexpectedEof => "unexpected end of filter",
+            DecodeError::QuotientTooLarge => "quotient out of range",
+            DecodeError::Overflow => "value overflow",
+            DecodeError::ValueOutOfRange => "value out of range",
+            DecodeError::TrailingData => "trailing data after the last value",
+            DecodeError::NonZeroPadding => "non-zero padding bits",
+            DecodeError::Io(_) => "I/O error",
+            DecodeError::Limit(_) => "decoding limit exceeded",
+        }
+    }
+}
+
+/// Errors that can occur in the set operations between filters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetError {
+    /// The filters have different P or modulus, so their values can't be
+    /// compared.
+    IncompatibleParams,
+    /// One of the filters couldn't be decoded.
+    Decode(DecodeError),
+}
+
+impl From<DecodeError> for SetError {
+    fn from(e: DecodeError) -> SetError {
+        SetError::Decode(e)
+    }
+}
+
+impl fmt::Display for SetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SetError::IncompatibleParams => write!(f, "incompatible filter parameters"),
+            SetError::Decode(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for SetError {
+    fn description(&self) -> &str {
+        match *self {
+            SetError::IncompatibleParams => "incompatible filter parameters",
+            SetError::Decode(_) => "decode error",
+        }
+    }
+}
+
+/// Errors that can occur while serializing a filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The modulus of the filter isn't `N * M`, as for the filters returned
+    /// by the set operations. The serialization only holds N, so the filter
+    /// would be read back with a different modulus and wouldn't match its
+    /// items.
+    ModulusMismatch,
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EncodeError::ModulusMismatch => write!(f, "the filter modulus isn't N * M"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for EncodeError {
+    fn description(&self) -> &str {
+        match *self {
+            EncodeError::ModulusMismatch => "the filter modulus isn't N * M",
+        }
+    }
+}
+
+/// Errors that can occur while parsing a filter from a string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseFilterError {
+    /// The string isn't valid hex.
+    InvalidHex,
+    /// The set length (N) is missing or too big.
+    InvalidSetLength,
+    /// The filter exceeds the decoding limits.
+    Limit(LimitError),
+}
+
+impl From<LimitError> for ParseFilterError {
+    fn from(e: LimitError) -> ParseFilterError {
+        ParseFilterError::Limit(e)
+    }
+}
+
+impl fmt::Display for ParseFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseFilterError::InvalidHex => write!(f, "invalid hex string"),
+            ParseFilterError::InvalidSetLength => write!(f, "invalid filter set length"),
+            ParseFilterError::Limit(ref e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for ParseFilterError {
+    fn description(&self) -> &str {
+        match *self {
+            ParseFilterError::InvalidHex => "invalid hex string",
+            ParseFilterError::InvalidSetLength => "invalid filter set length",
+            ParseFilterError::Limit(_) => "filter exceeds the decoding limits",
+        }
+    }
+}
+
+/// Errors returned when a filter exceeds its [decoding limits][1].
+///
+/// [1]: struct.DecodeLimits.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitError {
+    /// The set length (N) is above the limit, or the data is too short to
+    /// hold that many items.
+    TooManyItems(u32),
+    /// The filter data is larger than the limit, in bytes.
+    TooLarge(usize),
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LimitError::TooManyItems(n) => write!(f, "too many filter items ({})", n),
+            LimitError::TooLarge(size) => write!(f, "filter too large ({} bytes)", size),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for LimitError {
+    fn description(&self) -> &str {
+        match *self {
+            LimitError::TooManyItems(_) => "too many filter items",
+            LimitError::TooLarge(_) => "filter too large",
+        }
+    }
+}
+
+/// Errors that can occur while checking the parameters of a filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamsError {
+    /// P is larger than 32.
+    PTooLarge,
+    /// M is zero.
+    ZeroM,
+    /// The modulus (`N * M`) doesn't fit in a `u64`.
+    ModulusOverflow,
+}
+
+impl fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParamsError::PTooLarge => write!(f, "P is larger than 32"),
+            ParamsError::ZeroM => write!(f, "M is zero"),
+            ParamsError::ModulusOverflow => write!(f, "N * M overflows"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for ParamsError {
+    fn description(&self) -> &str {
+        match *self {
+            ParamsError::PTooLarge => "P is larger than 32",
+            ParamsError::ZeroM => "M is zero",
+            ParamsError::ModulusOverflow => "N * M overflows",
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Filters that can tell true matches from false positives.
+
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeSet;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::collections::BTreeSet;
+
+#[cfg(feature = "builder")]
+use bitcoin::blockdata::block::Block;
+#[cfg(feature = "builder")]
+use bitcoin::blockdata::script::Script;
+#[cfg(feature = "builder")]
+use bitcoin::network::serialize::BitcoinHash;
+
+#[cfg(feature = "builder")]
+use builder::{BasicFilter, FilterType};
+use {DecodeError, Filter, FilterKey};
+
+/// A filter along with the entries it was built from.
+///
+/// The filter matches items within its false positive rate, the entries
+/// [confirm][1] whether an item is actually in the set. Wallets rescanning
+/// the chain can use it to check which of their scripts are really in a
+/// block whose filter matched, once the block is downloaded.
+///
+/// [1]: #method.confirm
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExactFilter {
+    filter: Filter,
+    key: FilterKey,
+    entries: BTreeSet<Vec<u8>>,
+}
+
+impl ExactFilter {
+    // Constructors
+
+    /// Builds a filter of the given entries, keeping them.
+    pub fn build<I>(p: u8, m: u64, key: FilterKey, entries: I) -> ExactFilter
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let entries = entries.into_iter()
+            .map(|entry| entry.as_ref().to_vec())
+            .collect::<BTreeSet<Vec<u8>>>();
+
+        ExactFilter {
+            filter: Filter::build(p, m, key, &entries),
+            key,
+            entries,
+        }
+    }
+
+    /// Creates an `ExactFilter` from a filter built with `key` and the
+    /// entries it was built from.
+    ///
+    /// The entries aren't checked against the filter.
+    pub fn new<I>(filter: Filter, key: FilterKey, entries: I) -> ExactFilter
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        ExactFilter {
+            filter,
+            key,
+            entries: entries.into_iter().map(|entry| entry.as_ref().to_vec()).collect(),
+        }
+    }
+
+    /// Builds the BIP158 basic filter of a block, keeping its entries.
+    ///
+    /// `prev_scripts` are the previous output scripts spent by the block, as
+    /// required by [`build_basic_filter_with_prev_scripts`][1].
+    ///
+    /// [1]: builder/fn.build_basic_filter_with_prev_scripts.html
+    #[cfg(feature = "builder")]
+    pub fn from_block(block: &Block, prev_scripts: &[Script]) -> ExactFilter {
+        let (p, m) = BasicFilter::params();
+        let key = FilterKey::from(&block.bitcoin_hash());
+        ExactFilter::build(p, m, key, BasicFilter::extract_entries(block, prev_scripts))
+    }
+
+    // Accessors
+
+    /// Returns the filter.
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    /// Returns the key the filter is built with.
+    pub fn key(&self) -> FilterKey {
+        self.key
+    }
+
+    /// Returns an iterator over the entries, in ascending order.
+    pub fn entries(&self) -> impl Iterator<Item = &[u8]> {
+        self.entries.iter().map(|entry| entry.as_slice())
+    }
+
+    /// Drops the entries, returning the filter.
+    pub fn into_filter(self) -> Filter {
+        self.filter
+    }
+
+    // Matching
+
+    /// Checks whether the item is likely (within the false positive rate) to
+    /// be in the filter, see [`Filter::is_member`][1].
+    ///
+    /// [1]: struct.Filter.html#method.is_member
+    pub fn is_member(&self, data: &[u8]) -> Result<bool, DecodeError> {
+        self.filter.is_member(self.key, data)
+    }
+
+    /// Returns `true` if the item is one of the entries.
+    pub fn confirm(&self, data: &[u8]) -> bool {
+        self.entries.contains(data)
+    }
+
+    /// Confirms each item, returning whether it's one of the entries in the
+    /// order the items are given.
+    pub fn confirm_all<I>(&self, data: I) -> Vec<bool>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        data.into_iter().map(|item| self.confirm(item.as_ref())).collect()
+    }
+
+    /// Returns the scripts that are entries of the filter, e.g. the wallet
+    /// scripts really paid or spent in the block of a
+    /// [block filter][1].
+    ///
+    /// [1]: #method.from_block
+    #[cfg(feature = "builder")]
+    pub fn confirmed_scripts<'a>(&self, scripts: &'a [Script]) -> Vec<&'a Script> {
+        scripts.iter()
+            .filter(|script| self.confirm(&script.data()))
+            .collect()
+    }
+}
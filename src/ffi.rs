@@ -0,0 +1,199 @@
+//! C API.
+//!
+//! The header `include/bitcoin_gcs.h` is generated from this module with
+//! `cbindgen --config cbindgen.toml --output include/bitcoin_gcs.h`.
+//!
+//! Filters are handled through opaque pointers created by
+//! `gcs_filter_build` or `gcs_filter_from_bytes`, which must be released
+//! with `gcs_filter_free`. Items are given as an array of pointers along
+//! with an array of their lengths.
+
+use std::os::raw::c_int;
+use std::ptr;
+use std::slice;
+
+use {checked_modulus, Filter, FilterKey, KEY_SIZE};
+
+// `Filter` is generic over its hasher, so it's wrapped in a plain type that
+// cbindgen can declare as opaque.
+
+/// Describes a serialized Golomb Coded Set (GCS) filter.
+pub struct GcsFilter(Filter);
+
+/// Collects `count` items from the pointer and length arrays.
+unsafe fn items<'a>(items: *const *const u8, lens: *const usize, count: usize) -> Option<Vec<&'a [u8]>> {
+    if count == 0 {
+        return Some(Vec::new());
+    }
+    if items.is_null() || lens.is_null() {
+        return None;
+    }
+
+    let items = slice::from_raw_parts(items, count);
+    let lens = slice::from_raw_parts(lens, count);
+    items.iter()
+        .zip(lens)
+        .map(|(item, len)| {
+            if *len == 0 {
+                Some(&[][..])
+            } else if item.is_null() {
+                None
+            } else {
+                Some(slice::from_raw_parts(*item, *len))
+            }
+        })
+        .collect()
+}
+
+unsafe fn key(key: *const u8) -> Option<FilterKey> {
+    if key.is_null() {
+        return None;
+    }
+
+    let mut bytes = [0u8; KEY_SIZE];
+    bytes.copy_from_slice(slice::from_raw_parts(key, KEY_SIZE));
+    Some(FilterKey::from(bytes))
+}
+
+/// Builds a filter with parameters `p` and `m` from `count` items.
+///
+/// Returns null if any pointer is invalid, P is larger than 32 or `N * M`
+/// doesn't fit in 64 bits.
+///
+/// # Safety
+///
+/// `key` must point to 16 bytes, `items` and `item_lens` to `count` elements
+/// each, and every item to as many bytes as its length.
+#[no_mangle]
+pub unsafe extern "C" fn gcs_filter_build(p: u8,
+                                          m: u64,
+                                          key: *const u8,
+                                          items: *const *const u8,
+                                          item_lens: *const usize,
+                                          count: usize) -> *mut GcsFilter {
+    let key = match self::key(key) {
+        Some(key) => key,
+        None => return ptr::null_mut(),
+    };
+    let mut items = match self::items(items, item_lens, count) {
+        Some(items) => items,
+        None => return ptr::null_mut(),
+    };
+
+    // N is the number of unique items.
+    items.sort();
+    items.dedup();
+    if p > 32 || items.len() > u32::MAX as usize || checked_modulus(items.len() as u32, m).is_none() {
+        return ptr::null_mut();
+    }
+
+    Box::into_raw(Box::new(GcsFilter(Filter::build(p, m, key, items))))
+}
+
+/// Creates a filter from its set length (N), parameters and Golomb-Rice
+/// coded data.
+///
+/// Returns null if `data` is null, P is larger than 32 or `N * M` doesn't
+/// fit in 64 bits.
+///
+/// # Safety
+///
+/// `data` must point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn gcs_filter_from_bytes(n: u32,
+                                               p: u8,
+                                               m: u64,
+                                               data: *const u8,
+                                               len: usize) -> *mut GcsFilter {
+    if (data.is_null() && len > 0) || p > 32 || checked_modulus(n, m).is_none() {
+        return ptr::null_mut();
+    }
+
+    let data = if len == 0 {
+        Vec::new()
+    } else {
+        slice::from_raw_parts(data, len).to_vec()
+    };
+    Box::into_raw(Box::new(GcsFilter(Filter::from_bytes(n, p, m, data))))
+}
+
+/// Checks whether any of `count` items is likely to be a member of the
+/// filter.
+///
+/// Returns 1 on a match, 0 if there's none and -1 if any pointer is invalid
+/// or the filter data is corrupt.
+///
+/// # Safety
+///
+/// `filter` must have been created by this library, `key` must point to 16
+/// bytes, `items` and `item_lens` to `count` elements each, and every item
+/// to as many bytes as its length.
+#[no_mangle]
+pub unsafe extern "C" fn gcs_filter_match(filter: *const GcsFilter,
+                                          key: *const u8,
+                                          items: *const *const u8,
+                                          item_lens: *const usize,
+                                          count: usize) -> c_int {
+    let filter = match filter.as_ref() {
+        Some(filter) => &filter.0,
+        None => return -1,
+    };
+    let key = match self::key(key) {
+        Some(key) => key,
+        None => return -1,
+    };
+    let items = match self::items(items, item_lens, count) {
+        Some(items) => items,
+        None => return -1,
+    };
+
+    match filter.is_member_any(key, items) {
+        Ok(true) => 1,
+        Ok(false) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Returns the set length (N) of the filter.
+///
+/// # Safety
+///
+/// `filter` must have been created by this library.
+#[no_mangle]
+pub unsafe extern "C" fn gcs_filter_n(filter: *const GcsFilter) -> u32 {
+    filter.as_ref().map(|f| f.0.n()).unwrap_or(0)
+}
+
+/// Returns a pointer to the Golomb-Rice coded data of the filter, and writes
+/// its length to `len`.
+///
+/// The pointer is valid until the filter is freed.
+///
+/// # Safety
+///
+/// `filter` must have been created by this library and `len` must be a
+/// valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn gcs_filter_data(filter: *const GcsFilter, len: *mut usize) -> *const u8 {
+    match filter.as_ref() {
+        Some(filter) => {
+            if !len.is_null() {
+                *len = filter.0.as_bytes().len();
+            }
+            filter.0.as_bytes().as_ptr()
+        }
+        None => ptr::null(),
+    }
+}
+
+/// Frees a filter, null pointers are ignored.
+///
+/// # Safety
+///
+/// `filter` must have been created by this library and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn gcs_filter_free(filter: *mut GcsFilter) {
+    if !filter.is_null() {
+        drop(Box::from_raw(filter));
+    }
+}
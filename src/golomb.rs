@@ -0,0 +1,153 @@
+//! Golomb-Rice coding.
+//!
+//! A Golomb-Rice code with parameter `P` represents a value as its quotient
+//! by 2<sup>P</sup> coded in unary (a run of 1s terminated by a 0), followed
+//! by the `P` bit remainder as a big-endian integer. It's a compact encoding
+//! for geometrically distributed values, such as the differences between the
+//! sorted values of a set.
+//!
+//! The coded bits are packed into bytes most significant bit first, as in
+//! BIP158, unless another [`BitOrder`][1] is set.
+//!
+//! [1]: enum.BitOrder.html
+
+pub use bits::BitOrder;
+
+use bits::{BitReader, BitWriter};
+use io::{self, Read, Write};
+use DecodeError;
+
+/// Writes Golomb-Rice coded values.
+#[derive(Debug)]
+pub struct GolombEncoder<W> {
+    writer: BitWriter<W>,
+    p: u8,
+}
+
+impl<W: Write> GolombEncoder<W> {
+    /// Creates a new `GolombEncoder` with the Rice parameter `p`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if P is larger than 32.
+    pub fn new(writer: W, p: u8) -> GolombEncoder<W> {
+        assert!(p <= 32, "P is too big");
+
+        GolombEncoder {
+            writer: BitWriter::new(writer),
+            p,
+        }
+    }
+
+    /// Packs the coded bits in the given order.
+    pub fn with_bit_order(mut self, order: BitOrder) -> GolombEncoder<W> {
+        self.writer.set_order(order);
+        self
+    }
+
+    /// Writes a value.
+    pub fn encode(&mut self, value: u64) -> io::Result<()> {
+        let remainder = value & ((1u64 << self.p) - 1);
+        let quotient = value >> self.p;
+
+        // Most codes fit in a word, the unary coded quotient, its
+        // terminating zero and the remainder are written at once.
+        let bits = quotient + 1 + u64::from(self.p);
+        if bits <= 64 {
+            let ones = ((1u64 << quotient) - 1) << (u64::from(self.p) + 1);
+            return self.writer.write(bits as u32, ones | remainder);
+        }
+
+        // Write the P multiple in unary.
+        self.writer.write_ones(quotient)?;
+        self.writer.write_bit(false)?;
+
+        // Write the remainder as a big-endian integer with enough bits
+        // to represent the appropriate collision probability.
+        self.writer.write(u32::from(self.p), remainder)
+    }
+
+    /// Writes the `bits` least significant bits of `value` as is, used to
+    /// resume encoding in the middle of a byte.
+    pub fn write_bits(&mut self, bits: u32, value: u64) -> io::Result<()> {
+        self.writer.write(bits, value)
+    }
+
+    /// Pads the coded values to a byte boundary and returns the underlying
+    /// writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.writer.finish()
+    }
+}
+
+/// Reads Golomb-Rice coded values.
+///
+/// The quotient of the values can be bounded, so a corrupt or malicious
+/// input can't make the decoder run through huge unary runs or produce
+/// values out of the expected range.
+#[derive(Debug, Clone)]
+pub struct GolombDecoder<R> {
+    reader: BitReader<R>,
+    p: u8,
+    max_quotient: u64,
+}
+
+impl<R: Read> GolombDecoder<R> {
+    /// Creates a new `GolombDecoder` with the Rice parameter `p`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if P is larger than 32.
+    pub fn new(reader: R, p: u8) -> GolombDecoder<R> {
+        assert!(p <= 32, "P is too big");
+
+        GolombDecoder {
+            reader: BitReader::new(reader),
+            p,
+            max_quotient: u64::MAX >> p,
+        }
+    }
+
+    /// Rejects values whose quotient is larger than `max_quotient`.
+    pub fn with_max_quotient(mut self, max_quotient: u64) -> GolombDecoder<R> {
+        self.max_quotient = max_quotient;
+        self
+    }
+
+    /// Reads coded bits packed in the given order.
+    pub fn with_bit_order(mut self, order: BitOrder) -> GolombDecoder<R> {
+        self.reader.set_order(order);
+        self
+    }
+
+    /// Returns the number of bits read so far.
+    pub fn bit_position(&self) -> u64 {
+        self.reader.position()
+    }
+
+    /// Skips `bits` bits, used to start decoding in the middle of a byte.
+    pub fn skip_bits(&mut self, bits: u32) -> io::Result<()> {
+        let mut bits = bits;
+        while bits > 0 {
+            let n = bits.min(64);
+            self.reader.read(n)?;
+            bits -= n;
+        }
+        Ok(())
+    }
+
+    /// Reads a value.
+    pub fn decode(&mut self) -> Result<u64, DecodeError> {
+        // Count the 1s until we reach a 0.
+        let quotient = self.reader.read_ones(self.max_quotient)?;
+        if quotient > self.max_quotient {
+            return Err(DecodeError::QuotientTooLarge);
+        }
+
+        // Read P bits.
+        let remainder = self.reader.read(u32::from(self.p))?;
+
+        // Add the multiple and the remainder.
+        Ok((quotient << u64::from(self.p)) + remainder)
+    }
+}
@@ -0,0 +1,39 @@
+//! Hash functions used to map the items of a filter to integers.
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use {siphash24, siphash24_batch, FilterKey};
+
+/// A keyed 64-bit hash function for the items of a filter.
+///
+/// BIP158 filters are always hashed with [`SipHash24`][1], the default of
+/// [`Filter`][2]. Other hash functions can be used by applications that don't
+/// have to interoperate with Bitcoin.
+///
+/// [1]: struct.SipHash24.html
+/// [2]: struct.Filter.html
+pub trait GcsHasher {
+    /// Hashes an item with the given key.
+    fn hash(key: FilterKey, data: &[u8]) -> u64;
+
+    /// Hashes many items with the same key, returning the hashes in the same
+    /// order as the items.
+    fn hash_batch(key: FilterKey, items: &[&[u8]]) -> Vec<u64> {
+        items.iter().map(|item| Self::hash(key, item)).collect()
+    }
+}
+
+/// SipHash-2-4, as used by BIP158.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct SipHash24;
+
+impl GcsHasher for SipHash24 {
+    fn hash(key: FilterKey, data: &[u8]) -> u64 {
+        siphash24(key, data)
+    }
+
+    fn hash_batch(key: FilterKey, items: &[&[u8]]) -> Vec<u64> {
+        siphash24_batch(key, items)
+    }
+}
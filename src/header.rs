@@ -0,0 +1,112 @@
+use core::fmt;
+use core::str::FromStr;
+
+use bitcoin::network::encodable::{ConsensusDecodable, ConsensusEncodable};
+use bitcoin::network::serialize::{SimpleDecoder, SimpleEncoder};
+use bitcoin::util::hash::Sha256dHash;
+
+use {decode_hex, Filter, ParseFilterError, SipHash24};
+
+/// A BIP157 filter header.
+///
+/// Filter headers commit to a filter and to all the filters before it,
+/// forming a chain parallel to the block chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FilterHeader(Sha256dHash);
+
+impl FilterHeader {
+    // Constructors
+
+    /// Creates a `FilterHeader` from its hash.
+    pub fn from_hash(hash: Sha256dHash) -> FilterHeader {
+        FilterHeader(hash)
+    }
+
+    /// Returns the all-zero header which precedes the genesis block filter
+    /// header.
+    pub fn zero() -> FilterHeader {
+        FilterHeader(Sha256dHash::from(&[0u8; 32][..]))
+    }
+
+    /// Calculates the filter header of a block from its filter hash and the
+    /// filter header of the previous block.
+    ///
+    /// The header is computed as `double-SHA256(filter_hash || prev_header)`.
+    pub fn from_filter_hash(filter_hash: &Sha256dHash, prev_header: &FilterHeader) -> FilterHeader {
+        let mut data = [0u8; 64];
+        data[0..32].copy_from_slice(&filter_hash[..]);
+        data[32..64].copy_from_slice(&prev_header.0[..]);
+
+        FilterHeader(Sha256dHash::from_data(&data))
+    }
+
+    // Accessors
+
+    /// Returns the hash of this header.
+    pub fn as_hash(&self) -> &Sha256dHash {
+        &self.0
+    }
+}
+
+/// Formats the header as hex in reverse byte order, like Bitcoin Core
+/// displays hashes.
+impl fmt::Display for FilterHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0[..].iter().rev() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a header from hex in reverse byte order, as [displayed][1].
+///
+/// [1]: #impl-Display
+impl FromStr for FilterHeader {
+    type Err = ParseFilterError;
+
+    fn from_str(s: &str) -> Result<FilterHeader, ParseFilterError> {
+        let mut bytes = decode_hex(s)
+            .filter(|bytes| bytes.len() == 32)
+            .ok_or(ParseFilterError::InvalidHex)?;
+        bytes.reverse();
+        Ok(FilterHeader(Sha256dHash::from(&bytes[..])))
+    }
+}
+
+impl<S: SimpleEncoder> ConsensusEncodable<S> for FilterHeader {
+    fn consensus_encode(&self, s: &mut S) -> Result<(), S::Error> {
+        self.0.consensus_encode(s)
+    }
+}
+
+impl<D: SimpleDecoder> ConsensusDecodable<D> for FilterHeader {
+    fn consensus_decode(d: &mut D) -> Result<FilterHeader, D::Error> {
+        Ok(FilterHeader(ConsensusDecodable::consensus_decode(d)?))
+    }
+}
+
+impl<D: AsRef<[u8]>> Filter<SipHash24, D> {
+    /// Calculates the filter hash, the double-SHA256 of the N-prefixed
+    /// serialization of the filter.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the modulus of the filter isn't `N * M`.
+    pub fn filter_hash(&self) -> Sha256dHash {
+        Sha256dHash::from_data(&self.to_nbytes())
+    }
+
+    /// Calculates the filter header of this filter given the filter header of
+    /// the previous block.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the modulus of the filter isn't `N * M`, see
+    /// [`filter_hash`][1].
+    ///
+    /// [1]: #method.filter_hash
+    pub fn filter_header(&self, prev_header: &FilterHeader) -> FilterHeader {
+        FilterHeader::from_filter_hash(&self.filter_hash(), prev_header)
+    }
+}
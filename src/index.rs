@@ -0,0 +1,47 @@
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use golomb::GolombDecoder;
+use io::Read;
+use DecodeError;
+
+/// Positions of every `stride` values of a filter bitstream.
+#[derive(Debug, Clone)]
+pub struct FilterIndex {
+    stride: u32,
+    /// The bit offset following each indexed value, and the value itself.
+    checkpoints: Vec<(u64, u64)>,
+}
+
+impl FilterIndex {
+    /// Indexes the `n` values read from `reader`.
+    pub fn build<R: Read>(mut reader: GolombDecoder<R>,
+                          n: u32,
+                          stride: u32) -> Result<FilterIndex, DecodeError> {
+        assert!(stride > 0, "stride can't be zero");
+
+        let mut checkpoints = Vec::with_capacity((n / stride) as usize);
+        let mut last_value = 0u64;
+        for i in 1..=n {
+            last_value = last_value.checked_add(reader.decode()?)
+                .ok_or(DecodeError::Overflow)?;
+            if i % stride == 0 {
+                checkpoints.push((reader.bit_position(), last_value));
+            }
+        }
+
+        Ok(FilterIndex { stride, checkpoints })
+    }
+
+    /// Returns the closest indexed position whose value is below `value`,
+    /// along with the number of values read up to it.
+    pub fn seek(&self, value: u64) -> Option<(u64, u64, u32)> {
+        match self.checkpoints.partition_point(|c| c.1 < value) {
+            0 => None,
+            i => {
+                let (bit_offset, value) = self.checkpoints[i - 1];
+                Some((bit_offset, value, i as u32 * self.stride))
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Filters whose data is stored inline.
+
+use core::fmt;
+use core::hash::{Hash, Hasher};
+
+use {split_nbytes, DecodeLimits, Filter, GcsHasher, ParseFilterError, SipHash24};
+
+/// Filter data of up to `CAP` bytes stored inline, without a heap
+/// allocation.
+#[derive(Clone, Copy)]
+pub struct InlineData<const CAP: usize> {
+    len: usize,
+    bytes: [u8; CAP],
+}
+
+impl<const CAP: usize> InlineData<CAP> {
+    /// Copies `data`, returning `None` if it's longer than `CAP` bytes.
+    pub fn from_slice(data: &[u8]) -> Option<InlineData<CAP>> {
+        if data.len() > CAP {
+            return None;
+        }
+
+        let mut bytes = [0u8; CAP];
+        bytes[..data.len()].copy_from_slice(data);
+        Some(InlineData {
+            len: data.len(),
+            bytes,
+        })
+    }
+}
+
+impl<const CAP: usize> AsRef<[u8]> for InlineData<CAP> {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl<const CAP: usize> fmt::Debug for InlineData<CAP> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_ref(), f)
+    }
+}
+
+impl<const CAP: usize> PartialEq for InlineData<CAP> {
+    fn eq(&self, other: &InlineData<CAP>) -> bool {
+        self.as_ref() == other.as_ref()
+    }
+}
+
+impl<const CAP: usize> Eq for InlineData<CAP> {}
+
+impl<const CAP: usize> Hash for InlineData<CAP> {
+    fn hash<S: Hasher>(&self, state: &mut S) {
+        self.as_ref().hash(state)
+    }
+}
+
+/// A filter whose data is stored inline in up to `CAP` bytes.
+///
+/// Tiny filters, like the filters of single transactions, are dominated by
+/// the cost of allocating their data. These are matched by the same code as
+/// any other filter, without that allocation.
+pub type SmallFilter<const CAP: usize, H = SipHash24> = Filter<H, InlineData<CAP>>;
+
+impl<H: GcsHasher, const CAP: usize> Filter<H, InlineData<CAP>> {
+    // Constructors
+
+    /// Construct a `SmallFilter` from a built set, returning `None` if the
+    /// data is longer than `CAP` bytes.
+    ///
+    /// See [`from_bytes`][1].
+    ///
+    /// # Panics
+    ///
+    /// This function panics if P is larger than 32, or if `N * M` doesn't
+    /// fit in a `u64`.
+    ///
+    /// [1]: struct.Filter.html#method.from_bytes
+    pub fn from_inline(n: u32, p: u8, m: u64, data: &[u8]) -> Option<SmallFilter<CAP, H>> {
+        InlineData::from_slice(data).map(|data| Filter::from_data(n, p, m, data))
+    }
+
+    /// Construct a `SmallFilter` from its N-prefixed serialization,
+    /// returning `None` if the data is longer than `CAP` bytes.
+    ///
+    /// See [`from_nbytes`][1].
+    ///
+    /// [1]: struct.Filter.html#method.from_nbytes
+    pub fn from_inline_nbytes(p: u8, m: u64, data: &[u8])
+        -> Result<Option<SmallFilter<CAP, H>>, ParseFilterError>
+    {
+        let (n, data) = split_nbytes(data)?;
+        DecodeLimits::default().check(n, p, data.len())?;
+        Ok(Filter::from_inline(n, p, m, data))
+    }
+}
+
+impl<H: GcsHasher> Filter<H> {
+    /// Copies the filter to a [`SmallFilter`][1], returning `None` if its
+    /// data is longer than `CAP` bytes.
+    ///
+    /// The [modulus][2] of the filter is kept, its [index][3] isn't.
+    ///
+    /// [1]: type.SmallFilter.html
+    /// [2]: #method.modulus
+    /// [3]: #method.build_index
+    pub fn to_inline<const CAP: usize>(&self) -> Option<SmallFilter<CAP, H>> {
+        InlineData::from_slice(self.as_bytes())
+            .map(|data| Filter::from_data_with_modulus(self.n(), self.p(), self.m(), self.modulus(), data))
+    }
+}
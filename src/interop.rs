@@ -0,0 +1,161 @@
+//! Differential testing against Bitcoin Core.
+//!
+//! Fixtures are [test vectors][1] whose filters and headers were computed
+//! by Bitcoin Core, in the format of its `blockfilters.json` test data, like
+//! `tests/testnet-19.json`. They're [loaded][2] from prerecorded files, or
+//! [recorded][3] from the results of the `getblock` and `getblockfilter`
+//! RPC calls of a `bitcoind` node, and [verified][4] by rebuilding their
+//! filters and headers, so more blocks can be checked for byte-equality
+//! with Bitcoin Core.
+//!
+//! [1]: ../testgen/struct.TestVector.html
+//! [2]: fn.load_fixtures.html
+//! [3]: fn.fixture_from_rpc.html
+//! [4]: fn.verify_fixture.html
+
+use std::error;
+use std::fmt;
+
+use bitcoin::network::serialize::BitcoinHash;
+
+use serde_json::{self, Value};
+
+use rpc::BlockFilterRpc;
+use testgen::TestVector;
+use {diff, FilterDiff, FilterHeader};
+
+/// Errors returned when fixtures can't be loaded or recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixtureError {
+    /// The fixtures aren't a JSON array.
+    InvalidJson,
+    /// The row at the given index, counting the columns, is invalid.
+    InvalidRow(usize),
+    /// The block hash of the row at the given index isn't the hash of its
+    /// block.
+    BlockHashMismatch(usize),
+    /// The RPC results are invalid.
+    InvalidRpcResult,
+}
+
+impl fmt::Display for FixtureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FixtureError::InvalidJson => write!(f, "fixtures aren't a JSON array"),
+            FixtureError::InvalidRow(row) => write!(f, "invalid fixture at row {}", row),
+            FixtureError::BlockHashMismatch(row) => write!(f, "block hash mismatch at row {}", row),
+            FixtureError::InvalidRpcResult => write!(f, "invalid RPC result"),
+        }
+    }
+}
+
+impl error::Error for FixtureError {
+    fn description(&self) -> &str {
+        match *self {
+            FixtureError::InvalidJson => "fixtures aren't a JSON array",
+            FixtureError::InvalidRow(_) => "invalid fixture",
+            FixtureError::BlockHashMismatch(_) => "block hash mismatch",
+            FixtureError::InvalidRpcResult => "invalid RPC result",
+        }
+    }
+}
+
+/// A difference between a fixture and the filter or header rebuilt from its
+/// block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The rebuilt filter differs from the recorded one.
+    Filter(Box<FilterDiff>),
+    /// The rebuilt filter header differs from the recorded one.
+    Header {
+        /// The recorded header.
+        expected: FilterHeader,
+        /// The rebuilt header.
+        actual: FilterHeader,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Mismatch::Filter(ref diff) => write!(f, "filter mismatch: {}", diff),
+            Mismatch::Header { ref expected, ref actual } => {
+                write!(f, "header mismatch: expected {}, got {}", expected, actual)
+            }
+        }
+    }
+}
+
+/// Loads fixtures from JSON in the format of the test vectors.
+///
+/// The first row, describing the columns, is skipped, and the block hash of
+/// each row is checked against its block.
+pub fn load_fixtures(json: &str) -> Result<Vec<TestVector>, FixtureError> {
+    let json: Value = serde_json::from_str(json).map_err(|_| FixtureError::InvalidJson)?;
+    let rows = json.as_array().ok_or(FixtureError::InvalidJson)?;
+
+    rows.iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, row)| load_fixture(i, row))
+        .collect()
+}
+
+/// Records a fixture from the results of the RPC calls of Bitcoin Core.
+///
+/// The block is the hex returned by `getblock <hash> 0`, and the filter
+/// results are the ones of `getblockfilter` for the previous block and for
+/// the block. The previous output scripts aren't returned by those calls,
+/// they have to be gathered from the spent transactions.
+pub fn fixture_from_rpc(height: u32, block: &str, prev_scripts: &[&str], prev_filter: &BlockFilterRpc,
+                        filter: &BlockFilterRpc, notes: &str) -> Result<TestVector, FixtureError>
+{
+    let prev_header = prev_filter.to_header().map_err(|_| FixtureError::InvalidRpcResult)?;
+    let mut fixture = TestVector::from_hex(height, block, prev_scripts, prev_header, notes)
+        .map_err(|_| FixtureError::InvalidRpcResult)?;
+    fixture.filter = filter.to_filter().map_err(|_| FixtureError::InvalidRpcResult)?;
+    fixture.header = filter.to_header().map_err(|_| FixtureError::InvalidRpcResult)?;
+    Ok(fixture)
+}
+
+/// Rebuilds the filter and the header of a fixture from its block and its
+/// previous header, checking that they're the recorded ones.
+pub fn verify_fixture(fixture: &TestVector) -> Result<(), Mismatch> {
+    let rebuilt = TestVector::new(fixture.height, fixture.block.clone(), fixture.prev_scripts.clone(),
+                                  fixture.prev_header, &fixture.notes);
+
+    let filter_diff = diff(&fixture.filter, &rebuilt.filter);
+    if !filter_diff.is_identical() {
+        return Err(Mismatch::Filter(Box::new(filter_diff)));
+    }
+    if fixture.header != rebuilt.header {
+        return Err(Mismatch::Header {
+            expected: fixture.header,
+            actual: rebuilt.header,
+        });
+    }
+    Ok(())
+}
+
+/// Loads the row at index `i`.
+fn load_fixture(i: usize, row: &Value) -> Result<TestVector, FixtureError> {
+    let invalid = FixtureError::InvalidRow(i);
+    let row = row.as_array().filter(|row| row.len() == 8).ok_or(invalid)?;
+    let str_at = |column: usize| row[column].as_str().ok_or(invalid);
+
+    let height = row[0].as_u64().filter(|height| *height <= u64::from(u32::MAX)).ok_or(invalid)? as u32;
+    let prev_scripts = row[3].as_array()
+        .and_then(|scripts| scripts.iter().map(Value::as_str).collect::<Option<Vec<&str>>>())
+        .ok_or(invalid)?;
+    let prev_header = str_at(4)?.parse::<FilterHeader>().map_err(|_| invalid)?;
+
+    let mut fixture = TestVector::from_hex(height, str_at(2)?, &prev_scripts, prev_header, str_at(7)?)
+        .map_err(|_| invalid)?;
+    fixture.filter = str_at(5)?.parse().map_err(|_| invalid)?;
+    fixture.header = str_at(6)?.parse().map_err(|_| invalid)?;
+
+    if !str_at(1)?.eq_ignore_ascii_case(&fixture.block.bitcoin_hash().be_hex_string()) {
+        return Err(FixtureError::BlockHashMismatch(i));
+    }
+    Ok(fixture)
+}
@@ -0,0 +1,80 @@
+#[cfg(feature = "bitcoin")]
+use bitcoin::util::hash::Sha256dHash;
+#[cfg(feature = "rand")]
+use rand::{CryptoRng, RngCore};
+
+/// Size in bytes of a filter key.
+pub const KEY_SIZE: usize = 16;
+
+/// A SipHash key used to hash the items of a filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct FilterKey {
+    k0: u64,
+    k1: u64,
+}
+
+impl FilterKey {
+    // Constructors
+
+    /// Creates a `FilterKey` from the two SipHash key halves.
+    pub fn new(k0: u64, k1: u64) -> FilterKey {
+        FilterKey { k0, k1 }
+    }
+
+    /// Generates a random `FilterKey` using a cryptographically secure random
+    /// number generator.
+    #[cfg(feature = "rand")]
+    pub fn random<R: RngCore + CryptoRng>(rng: &mut R) -> FilterKey {
+        FilterKey::new(rng.next_u64(), rng.next_u64())
+    }
+
+    // Accessors
+
+    /// Returns the two SipHash key halves.
+    pub fn keys(&self) -> (u64, u64) {
+        (self.k0, self.k1)
+    }
+
+    /// Returns the key as bytes, each half is encoded as a little-endian
+    /// integer.
+    pub fn to_bytes(&self) -> [u8; KEY_SIZE] {
+        let mut bytes = [0u8; KEY_SIZE];
+        for i in 0..8 {
+            bytes[i] = (self.k0 >> (8 * i)) as u8;
+            bytes[i + 8] = (self.k1 >> (8 * i)) as u8;
+        }
+        bytes
+    }
+}
+
+impl From<(u64, u64)> for FilterKey {
+    fn from(keys: (u64, u64)) -> FilterKey {
+        FilterKey::new(keys.0, keys.1)
+    }
+}
+
+impl From<[u8; KEY_SIZE]> for FilterKey {
+    /// Reads each key half as a little-endian integer.
+    fn from(bytes: [u8; KEY_SIZE]) -> FilterKey {
+        let mut k0 = 0u64;
+        let mut k1 = 0u64;
+        for i in (0..8).rev() {
+            k0 = (k0 << 8) | u64::from(bytes[i]);
+            k1 = (k1 << 8) | u64::from(bytes[i + 8]);
+        }
+        FilterKey::new(k0, k1)
+    }
+}
+
+#[cfg(feature = "bitcoin")]
+impl<'a> From<&'a Sha256dHash> for FilterKey {
+    /// Derives a key from a hash by truncating it to [`KEY_SIZE`][1] bytes,
+    /// as done by BIP158 with block hashes.
+    ///
+    /// [1]: constant.KEY_SIZE.html
+    fn from(hash: &'a Sha256dHash) -> FilterKey {
+        let mut bytes = [0u8; KEY_SIZE];
+        bytes.copy_from_slice(&hash[0..KEY_SIZE]);
+        FilterKey::from(bytes)
+    }
+}
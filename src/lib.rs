@@ -1,30 +1,187 @@
-extern crate bitstream_io;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate core;
+
 extern crate siphasher;
 
 #[cfg(feature = "builder")]
 extern crate byteorder;
-#[cfg(any(feature = "builder", feature = "decode"))]
+#[cfg(feature = "bitcoin")]
 extern crate bitcoin;
+#[cfg(feature = "electrum")]
+extern crate crypto;
+#[cfg(any(feature = "async", feature = "client"))]
+extern crate futures;
+#[cfg(feature = "mmap")]
+extern crate memmap;
+#[cfg(feature = "rand")]
+extern crate rand;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "rocksdb-store")]
+extern crate rocksdb;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "interop")]
+extern crate serde_json;
+#[cfg(feature = "sled-store")]
+extern crate sled;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "wasm")]
+extern crate js_sys;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
 
+#[cfg(feature = "audit")]
+pub mod audit;
 #[cfg(feature = "builder")]
 pub mod builder;
+mod bits;
+#[cfg(feature = "bitcoin")]
+mod chain;
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "commitment")]
+pub mod commitment;
+#[cfg(feature = "store")]
+pub mod database;
+#[cfg(feature = "descriptor")]
+pub mod descriptor;
+mod diff;
+#[cfg(feature = "electrum")]
+pub mod electrum;
+mod error;
+mod exact;
+#[cfg(feature = "cffi")]
+pub mod ffi;
+pub mod golomb;
+mod hasher;
+#[cfg(feature = "bitcoin")]
+mod header;
+mod index;
+mod inline;
+#[cfg(feature = "interop")]
+pub mod interop;
+mod io;
+mod key;
+mod limits;
+#[cfg(feature = "std")]
+mod metrics;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+mod ops;
+mod params;
+#[cfg(feature = "p2p")]
+pub mod p2p;
+mod query;
+mod rolling;
+mod set;
+#[cfg(feature = "rescan")]
+pub mod rescan;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+#[cfg(feature = "store")]
+pub mod script_index;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "builder")]
+pub mod silent_payments;
+#[cfg(feature = "simd")]
+mod simd;
+#[cfg(feature = "store")]
+pub mod store;
+#[cfg(feature = "testgen")]
+pub mod testgen;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "bitcoin")]
+pub use chain::{CheckpointMismatch, FaultyPeer, FilterHeaderChain, FilterHeaders, HeaderMismatchResolver,
+                CHECKPOINT_INTERVAL};
+pub use diff::{diff, FilterDiff};
+pub use error::{DecodeError, EncodeError, LimitError, ParamsError, ParseFilterError, SetError};
+pub use exact::ExactFilter;
+#[cfg(feature = "bitcoin")]
+pub use header::FilterHeader;
+pub use hasher::{GcsHasher, SipHash24};
+pub use inline::{InlineData, SmallFilter};
+pub use key::{FilterKey, KEY_SIZE};
+pub use limits::{DecodeLimits, MAX_FILTER_SIZE};
+#[cfg(feature = "std")]
+pub use metrics::{Metrics, NoMetrics};
+pub use params::FilterParams;
+#[cfg(feature = "std")]
+pub use query::MatchEngine;
+pub use query::{match_blocks, QuerySet, WatchList, DEFAULT_CACHED_KEYS};
+pub use rolling::RollingFilter;
+pub use set::GcsSet;
 
-use std::io::{self, Cursor};
-use std::hash::Hasher;
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+use core::str::FromStr;
+use core::hash::{Hash, Hasher};
+use core::iter::FromIterator;
+use core::marker::PhantomData;
+use core::ops::Range;
 
-use bitstream_io::{BE, BitReader, BitWriter};
+use golomb::{GolombDecoder, GolombEncoder};
+use index::FilterIndex;
+#[cfg(feature = "simd")]
+use simd::siphash24_batch;
 use siphasher::sip::SipHasher24;
 
 /// Default collision probability (2<sup>-20</sup>).
 pub const DEFAULT_P: u8 = 20;
 
+/// Golomb-Rice coding parameter (P) of the BIP158 basic filter.
+pub const BASIC_FILTER_P: u8 = 19;
+
+/// Inverse false positive rate (M) of the BIP158 basic filter.
+pub const BASIC_FILTER_M: u64 = 784931;
+
+/// Filter type of the BIP158 basic filter.
+pub const BASIC_FILTER_TYPE: u8 = 0x00;
+
+/// The number of quotient counts of [`FilterStats`][1], the last one counts
+/// every larger quotient.
+///
+/// [1]: struct.FilterStats.html
+pub const MAX_QUOTIENT_BUCKETS: usize = 64;
+
 /// Describes a serialized Golomb Coded Set (GCS) filter.
-#[derive(Debug, Clone)]
-pub struct Filter {
+///
+/// The items are hashed with `H`, SipHash-2-4 by default as in BIP158, see
+/// [`GcsHasher`][3].
+///
+/// The filter data is stored in `D`, a `Vec<u8>` by default. Filters can
+/// also borrow their data, e.g. from a memory-mapped file, with
+/// [`from_slice`][4], so it isn't copied to be matched.
+///
+/// Filters are equal when they have the same parameters and data, the
+/// [index][1] isn't compared. They're ordered by their
+/// [N-prefixed serialization][2].
+///
+/// [1]: #method.build_index
+/// [2]: #method.to_nbytes
+/// [3]: trait.GcsHasher.html
+/// [4]: #method.from_slice
+pub struct Filter<H: GcsHasher = SipHash24, D: AsRef<[u8]> = Vec<u8>> {
     n: u32,
     p: u8,
-    modulus_np: u64,
-    data: Vec<u8>,
+    m: u64,
+    modulus_nm: u64,
+    data: D,
+    index: Option<FilterIndex>,
+    hasher: PhantomData<H>,
 }
 
 impl Filter {
@@ -32,214 +189,1325 @@ impl Filter {
 
     /// Build a new `Filter` from the given data.
     ///
+    /// Items are hashed into the range `[0, N * M)`, where `M` is the inverse
+    /// of the false positive rate, and the differences between them are
+    /// Golomb-Rice coded with parameter `P`. Duplicate items are only added
+    /// once, so the set length (N) of the filter may be smaller than the
+    /// number of items given.
+    ///
+    /// The items can be given as any iterator of byte slices (`&Vec<u8>`,
+    /// `&[u8]`, arrays...), so they don't need to be copied beforehand.
+    ///
+    /// # Panics
+    ///
+    /// If the set length is too big the function panics, also if the false
+    /// positive rate is too big, or if `N * M` doesn't fit in a `u64`, the
+    /// function also panics.
+    pub fn build<I>(p: u8, m: u64, key: FilterKey, data: I) -> Filter
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        Filter::build_with_hasher(p, m, key, data)
+    }
+
+    /// Construct a `Filter` from a built set.
+    pub fn from_bytes(n: u32, p: u8, m: u64, data: Vec<u8>) -> Filter {
+        Filter::from_bytes_with_hasher(n, p, m, data)
+    }
+
+    /// Construct a `Filter` from a built set, checking that the data holds
+    /// exactly N values in the range `[0, N * M)` followed by zero padding
+    /// bits up to the end of the last byte.
+    ///
+    /// Unlike [`from_bytes`][1], which defers any decoding to the matching
+    /// functions, this decodes the whole filter once, so it should be used
+    /// with filters received from untrusted sources.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if P is larger than 32, or if `N * M` doesn't
+    /// fit in a `u64`.
+    ///
+    /// [1]: #method.from_bytes
+    pub fn decode_checked(n: u32, p: u8, m: u64, data: Vec<u8>) -> Result<Filter, DecodeError> {
+        let filter = Filter::from_bytes(n, p, m, data);
+        filter.check_encoding(true)?;
+        Ok(filter)
+    }
+
+    /// Construct a `Filter` from a built set with checked parameters,
+    /// returning an error instead of panicking if `N * M` doesn't fit in a
+    /// `u64`.
+    pub fn from_bytes_with_params(n: u32, params: FilterParams, data: Vec<u8>)
+        -> Result<Filter, ParamsError>
+    {
+        params.modulus(n)?;
+        Ok(Filter::from_bytes(n, params.p(), params.m(), data))
+    }
+
+    /// Construct a `Filter` from a built set, checking N and the data size
+    /// against `limits`.
+    pub fn from_bytes_with_limits(n: u32, p: u8, m: u64, data: Vec<u8>, limits: &DecodeLimits)
+        -> Result<Filter, LimitError>
+    {
+        limits.check(n, p, data.len())?;
+        Ok(Filter::from_bytes(n, p, m, data))
+    }
+
+    /// Construct a `Filter` from its serialization prefixed by the set length
+    /// (N) as a `VarInt`, as used on the P2P network.
+    ///
+    /// This is the inverse of [`to_nbytes`][1]. The filter is checked
+    /// against the [default limits][2], but the filter data isn't decoded,
+    /// see [`validate_encoding`][3].
+    ///
+    /// [1]: #method.to_nbytes
+    /// [2]: struct.DecodeLimits.html
+    /// [3]: #method.validate_encoding
+    pub fn from_nbytes(p: u8, m: u64, data: &[u8]) -> Result<Filter, ParseFilterError> {
+        Filter::from_nbytes_with_limits(p, m, data, &DecodeLimits::default())
+    }
+
+    /// Construct a `Filter` from its N-prefixed serialization, checking N
+    /// and the data size against `limits`.
+    ///
+    /// See [`from_nbytes`][1].
+    ///
+    /// [1]: #method.from_nbytes
+    pub fn from_nbytes_with_limits(p: u8, m: u64, data: &[u8], limits: &DecodeLimits)
+        -> Result<Filter, ParseFilterError>
+    {
+        let (n, data) = split_nbytes(data)?;
+        limits.check(n, p, data.len())?;
+        Ok(Filter::from_bytes(n, p, m, data.to_vec()))
+    }
+
+    // Utilities
+
+    /// Estimates the length in bytes of the Golomb-Rice coded data of a
+    /// filter with `n` items and parameters `p` and `m`, without building it.
+    ///
+    /// Each value takes `P + 1` bits plus its unary coded quotient, which is
+    /// `M / 2^P` on average.
+    pub fn estimated_size(n: u32, p: u8, m: u64) -> usize {
+        assert!(p <= 32, "P is too big");
+
+        let n = u128::from(n);
+        let bits = n * (u128::from(p) + 1) + ((n * u128::from(m)) >> p);
+        bits.div_ceil(8) as usize
+    }
+}
+
+impl<H: GcsHasher> Filter<H> {
+    // Constructors
+
+    /// Build a new `Filter` from the given data, hashing the items with `H`.
+    ///
+    /// See [`build`][1].
+    ///
     /// # Panics
     ///
     /// If the set length is too big the function panics, also if the false
     /// positive rate is too big the function also panics.
-    pub fn build(p: u8, key: (u64, u64), data: &Vec<Vec<u8>>) -> Filter {
-        // Check that data.len() (N) isn't larger than a u32.
-        assert!(data.len() <= u32::max_value() as usize, "N is too big");
+    ///
+    /// [1]: #method.build
+    pub fn build_with_hasher<I>(p: u8, m: u64, key: FilterKey, data: I) -> Filter<H>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("build_filter", p, m).entered();
+
+        // Remove duplicate items, they would otherwise be counted in N and
+        // encoded as zero differences.
+        let data = data.into_iter().collect::<Vec<I::Item>>();
+        let mut items: Vec<&[u8]> = data.iter().map(|d| d.as_ref()).collect();
+        items.sort();
+        items.dedup();
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(entries = data.len(), duplicates = data.len() - items.len());
+
+        // Check that items.len() (N) isn't larger than a u32.
+        assert!(items.len() <= u32::MAX as usize, "N is too big");
         assert!(p <= 32, "P is too big");
 
         let mut filter = Filter {
-            n: data.len() as u32,
+            n: items.len() as u32,
             p,
-            modulus_np: 0,
+            m,
+            modulus_nm: 0,
             data: Vec::new(),
+            index: None,
+            hasher: PhantomData,
         };
 
-        filter.modulus_np = u64::from(filter.n) << filter.p;
+        filter.modulus_nm = modulus(filter.n, filter.m);
 
         // Check if we need to do any work.
         if filter.is_empty() {
             return filter;
         }
 
-        let mut values = Vec::with_capacity(filter.n as usize);
-        for datum in data {
-            let v = siphash24(key, datum);
-            let v = reduce(v, filter.modulus_np);
-            values.push(v);
-        }
+        #[cfg(feature = "tracing")]
+        let encode_span = tracing::trace_span!("encode", n = filter.n).entered();
+
+        let mut values = hash_items::<H>(key, &items, filter.modulus_nm);
         values.sort();
 
-        // Write the sorted list of values into the filter bitstream,
-        // compressing it using Golomb coding.
-        let mut data: Vec<u8> = Vec::new();
+        filter.data = encode_values(filter.p, &values);
+
+        #[cfg(feature = "tracing")]
         {
-            let mut value: u64;
-            let mut last_value = 0u64;
-            let mut remainder: u64;
-            let mut bstream: BitWriter<BE> = BitWriter::new(&mut data);
-            for v in values.iter() {
-                // Calculate the difference between this value and the last,
-                // modulo P.
-                remainder = (*v - last_value) & ((1u64 << u64::from(filter.p)) - 1);
-
-                // Calculate the difference between this value and the last,
-                // divided by P.
-                value = (*v - last_value - remainder) >> u64::from(filter.p);
-                last_value = *v;
-
-                // Write the P multiple into the bitstream in unary; the
-                // average should be around 1 (2 bits - 0b10).
-                while value > 0 {
-                    bstream.write_bit(true).unwrap();
-                    value -= 1;
-                }
-                bstream.write_bit(false).unwrap();
+            drop(encode_span);
+            tracing::debug!(n = filter.n, bytes = filter.data.len(), "built filter");
+        }
 
-                // Write the remainder as a big-endian integer with enough bits
-                // to represent the appropriate collision probability.
-                bstream.write(u32::from(filter.p), remainder).unwrap();
-            }
+        filter
+    }
+
+    /// Constructs a filter from the hashes of its items, duplicate hashes
+    /// are only added once.
+    fn from_hashes(p: u8, m: u64, mut hashes: Vec<u64>) -> Filter<H> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("build_filter", p, m).entered();
+        #[cfg(feature = "tracing")]
+        let entries = hashes.len();
+
+        hashes.sort();
+        hashes.dedup();
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(entries, duplicates = entries - hashes.len());
+
+        assert!(hashes.len() <= u32::MAX as usize, "N is too big");
+        assert!(p <= 32, "P is too big");
+
+        let n = hashes.len() as u32;
+        let modulus_nm = modulus(n, m);
+
+        // The reduction preserves the order of the hashes, so the values are
+        // already sorted.
+        let values = hashes.iter()
+            .map(|h| hash_value(*h, modulus_nm))
+            .collect::<Vec<u64>>();
+
+        let data = {
+            #[cfg(feature = "tracing")]
+            let _span = tracing::trace_span!("encode", n).entered();
+            encode_values(p, &values)
+        };
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(n, bytes = data.len(), "built filter");
+
+        Filter {
+            n,
+            p,
+            m,
+            modulus_nm,
+            data,
+            index: None,
+            hasher: PhantomData,
         }
+    }
 
-        filter.data = data;
+    /// Construct a `Filter` from a built set, whose items are hashed with
+    /// `H`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if P is larger than 32, or if `N * M` doesn't
+    /// fit in a `u64`.
+    pub fn from_bytes_with_hasher(n: u32, p: u8, m: u64, data: Vec<u8>) -> Filter<H> {
+        Filter::from_data(n, p, m, data)
+    }
+}
 
-        filter
+/// A filter borrowing its data, e.g. from a received message or a
+/// [memory-mapped store][1], to be matched without copying it.
+///
+/// Owned filters are borrowed with [`Filter::as_filter_ref`][2], and a
+/// `FilterRef` is copied to an owned filter with
+/// [`into_owned`][3] or `Filter::from`.
+///
+/// [1]: store/struct.FilterStore.html#method.map
+/// [2]: struct.Filter.html#method.as_filter_ref
+/// [3]: struct.Filter.html#method.into_owned
+pub type FilterRef<'a, H = SipHash24> = Filter<H, &'a [u8]>;
+
+impl<'a> FilterRef<'a> {
+    // Constructors
+
+    /// Construct a `Filter` borrowing the data of a built set.
+    ///
+    /// See [`from_bytes`][1].
+    ///
+    /// # Panics
+    ///
+    /// This function panics if P is larger than 32, or if `N * M` doesn't
+    /// fit in a `u64`.
+    ///
+    /// [1]: #method.from_bytes
+    pub fn from_slice(n: u32, p: u8, m: u64, data: &'a [u8]) -> FilterRef<'a> {
+        Filter::from_data(n, p, m, data)
     }
 
-    /// Construct a `Filter` from a built set.
-    pub fn from_bytes(n: u32, p: u8, data: Vec<u8>) -> Filter {
+    /// Construct a `Filter` borrowing the data of its N-prefixed
+    /// serialization, e.g. the `filter` field of a `cfilter` message, so it
+    /// can be matched in place.
+    ///
+    /// See [`from_nbytes`][1].
+    ///
+    /// [1]: #method.from_nbytes
+    pub fn from_nslice(p: u8, m: u64, data: &'a [u8]) -> Result<FilterRef<'a>, ParseFilterError> {
+        let (n, data) = split_nbytes(data)?;
+        DecodeLimits::default().check(n, p, data.len())?;
+        Ok(Filter::from_slice(n, p, m, data))
+    }
+}
+
+impl<H: GcsHasher, D: AsRef<[u8]>> Filter<H, D> {
+    // Constructors
+
+    /// Construct a `Filter` from a built set stored in `data`, whose items
+    /// are hashed with `H`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if P is larger than 32, or if `N * M` doesn't
+    /// fit in a `u64`.
+    pub fn from_data(n: u32, p: u8, m: u64, data: D) -> Filter<H, D> {
         assert!(p <= 32, "P is too big");
 
         Filter {
             n,
             p,
-            modulus_np: u64::from(n) << p,
+            m,
+            modulus_nm: modulus(n, m),
             data,
+            index: None,
+            hasher: PhantomData,
         }
     }
 
-    #[cfg(feature = "decode")]
-    pub fn from_nbytes(p: u8, data: &[u8]) -> Result<Filter, bitcoin::util::Error> {
-        use bitcoin::network::encodable::{ConsensusDecodable, VarInt};
-        use bitcoin::network::serialize::RawDecoder;
-        use bitcoin::util::Error;
+    /// Construct a `Filter` from a built set whose values are reduced to
+    /// `modulus` instead of `N * M`, like the filters returned by the set
+    /// operations, so they keep matching the same items once reloaded.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if P is larger than 32.
+    pub fn from_data_with_modulus(n: u32, p: u8, m: u64, modulus: u64, data: D) -> Filter<H, D> {
+        assert!(p <= 32, "P is too big");
 
-        let (n, pos) = {
-            let mut cursor = Cursor::new(data);
-            let mut decoder = RawDecoder::new(&mut cursor);
-            let n = VarInt::consensus_decode(&mut decoder)?;
-            (n.0, n.encoded_length() as usize)
-        };
+        Filter {
+            n,
+            p,
+            m,
+            modulus_nm: modulus,
+            data,
+            index: None,
+            hasher: PhantomData,
+        }
+    }
 
-        if n >= u64::from(u32::max_value()) {
-            return Err(Error::ParseFailed);
+    /// Returns a [`FilterRef`][1] borrowing the data of this filter.
+    ///
+    /// The [index][2] of the filter isn't kept.
+    ///
+    /// [1]: type.FilterRef.html
+    /// [2]: #method.build_index
+    pub fn as_filter_ref(&self) -> FilterRef<'_, H> {
+        Filter {
+            n: self.n,
+            p: self.p,
+            m: self.m,
+            modulus_nm: self.modulus_nm,
+            data: self.as_bytes(),
+            index: None,
+            hasher: PhantomData,
         }
+    }
 
-        let filter = Filter::from_bytes(n as u32, p, (&data[pos..]).to_vec());
-        Ok(filter)
+    /// Copies the filter data to a `Filter` that owns it.
+    pub fn into_owned(self) -> Filter<H> {
+        Filter {
+            n: self.n,
+            p: self.p,
+            m: self.m,
+            modulus_nm: self.modulus_nm,
+            data: self.data.as_ref().to_vec(),
+            index: self.index,
+            hasher: PhantomData,
+        }
     }
 
     // Accessors
     
-    /// Returns the set length (N).
+    /// Returns the set length (N), that is, the number of unique items in the
+    /// filter.
     pub fn n(&self) -> u32 { self.n }
 
     /// Returns the false positive rate (P).
     pub fn p(&self) -> u8 { self.p }
 
+    /// Returns the inverse of the false positive rate (M).
+    pub fn m(&self) -> u64 { self.m }
+
+    /// Returns the range the values of the filter are reduced to, `N * M`
+    /// for built filters.
+    ///
+    /// The filters returned by the set operations, like [`merge`][1], keep
+    /// the modulus of the filters they come from, so it has to be given to
+    /// [`from_data_with_modulus`][2] to reload them.
+    ///
+    /// [1]: #method.merge
+    /// [2]: #method.from_data_with_modulus
+    pub fn modulus(&self) -> u64 { self.modulus_nm }
+
+    /// Returns `true` if the modulus is `N * M`, recomputed from the current
+    /// N and M, as for built and parsed filters.
+    ///
+    /// This is `false` for the filters returned by the set operations that
+    /// changed N.
+    pub fn has_nm_modulus(&self) -> bool { checked_modulus(self.n, self.m) == Some(self.modulus_nm) }
+
     /// Returns the serialized format of the filter.
-    pub fn as_bytes(&self) -> &[u8] { self.data.as_slice() }
+    pub fn as_bytes(&self) -> &[u8] { self.data.as_ref() }
+
+    /// Returns the serialized format of the filter prefixed by the set length
+    /// (N) as a `VarInt`, as used on the P2P network.
+    ///
+    /// This is the inverse of `from_nbytes`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the modulus of the filter isn't `N * M`, see
+    /// [`try_to_nbytes`][1].
+    ///
+    /// [1]: #method.try_to_nbytes
+    pub fn to_nbytes(&self) -> Vec<u8> {
+        match self.try_to_nbytes() {
+            Ok(bytes) => bytes,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Returns the N-prefixed serialization of the filter, or an error if its
+    /// modulus isn't `N * M`, e.g. if it's the result of a set operation.
+    ///
+    /// The serialization doesn't hold the modulus, so such filters would be
+    /// read back with a different one and wouldn't match their items.
+    pub fn try_to_nbytes(&self) -> Result<Vec<u8>, EncodeError> {
+        if !self.has_nm_modulus() {
+            return Err(EncodeError::ModulusMismatch);
+        }
+
+        let mut bytes = Vec::with_capacity(self.serialized_size());
+        write_compact_size(&mut bytes, u64::from(self.n));
+        bytes.extend_from_slice(self.as_bytes());
+        Ok(bytes)
+    }
+
+    /// Returns the length of the N-prefixed serialization of the filter.
+    pub fn serialized_size(&self) -> usize {
+        compact_size_len(u64::from(self.n)) + self.as_bytes().len()
+    }
+
+    /// Fully decodes the filter, checking that it holds exactly N values in
+    /// the range `[0, N * M)` and no data after them besides the padding of
+    /// the last byte.
+    ///
+    /// Filters received from untrusted sources can be validated once with
+    /// this function instead of handling decoding errors on every match.
+    pub fn validate_encoding(&self) -> Result<(), DecodeError> {
+        self.check_encoding(false)
+    }
+
+    fn check_encoding(&self, strict: bool) -> Result<(), DecodeError> {
+        let mut reader = self.reader();
+        let mut last_value = 0u64;
+        for _ in 0..self.n {
+            last_value = last_value.checked_add(reader.decode()?)
+                .ok_or(DecodeError::Overflow)?;
+        }
+
+        if self.n > 0 && last_value >= self.modulus_nm {
+            return Err(DecodeError::ValueOutOfRange);
+        }
+
+        let bit_position = reader.bit_position();
+        let data = self.as_bytes();
+        if bit_position.div_ceil(8) != data.len() as u64 {
+            return Err(DecodeError::TrailingData);
+        }
+
+        // The bits after the last value are written as zeros.
+        let padding = (8 - bit_position % 8) % 8;
+        if strict && padding > 0 && data[data.len() - 1] & ((1 << padding) - 1) != 0 {
+            return Err(DecodeError::NonZeroPadding);
+        }
+
+        Ok(())
+    }
 
     pub fn is_empty(&self) -> bool {
         self.n == 0
     }
 
+    /// Returns an iterator over the values of the set, in ascending order.
+    ///
+    /// The values are the hashed and reduced items the filter was built
+    /// from, they're decoded lazily from the Golomb-Rice coded bitstream. If
+    /// the bitstream is corrupt the iterator yields an error and stops.
+    pub fn values(&self) -> Values<'_> {
+        Values {
+            reader: self.reader(),
+            remaining: self.n,
+            last_value: 0,
+        }
+    }
+
+    /// Decodes every value of the set into `buf`, in ascending order.
+    ///
+    /// The buffer is cleared first, so it can be reused to decode the
+    /// filters of many blocks without allocating for each one. If the
+    /// bitstream is corrupt an error is returned and `buf` holds the values
+    /// decoded before it.
+    pub fn decode_into(&self, buf: &mut Vec<u64>) -> Result<(), DecodeError> {
+        buf.clear();
+        buf.reserve(self.n as usize);
+        for value in self.values() {
+            buf.push(value?);
+        }
+        Ok(())
+    }
+
+    /// Dumps the Golomb-Rice codes of the filter, one per line, to diagnose
+    /// encoding differences with other implementations.
+    ///
+    /// Each line has the index of the entry, the bit offset of its code,
+    /// the unary coded quotient and the remainder bits of the difference
+    /// with the previous value, and the value itself, e.g.:
+    ///
+    /// ```text
+    /// 0 @0: 0 1111111101010001000 quotient 0 remainder 522888 value 522888
+    /// ```
+    ///
+    /// The padding bits follow the last entry, a decoding error ends the
+    /// dump.
+    pub fn debug_bits(&self) -> String {
+        use core::fmt::Write;
+
+        let mut dump = String::new();
+        let mut reader = self.reader();
+        let mut value = 0u64;
+
+        for i in 0..self.n {
+            let start = reader.bit_position();
+            let delta = match reader.decode() {
+                Ok(delta) => delta,
+                Err(e) => {
+                    let _ = writeln!(dump, "{} @{}: error: {}", i, start, e);
+                    return dump;
+                }
+            };
+            let quotient = delta >> self.p;
+            let remainder = delta - (quotient << self.p);
+            value = value.wrapping_add(delta);
+
+            let _ = write!(dump, "{} @{}: ", i, start);
+            if quotient > 64 {
+                let _ = write!(dump, "1{{{}}}", quotient);
+            } else {
+                dump.extend((0..quotient).map(|_| '1'));
+            }
+            dump.push_str("0 ");
+            if self.p > 0 {
+                let _ = write!(dump, "{:01$b}", remainder, self.p as usize);
+            }
+            let _ = writeln!(dump, " quotient {} remainder {} value {}", quotient, remainder, value);
+        }
+
+        let start = reader.bit_position();
+        let bytes = self.as_bytes();
+        let _ = write!(dump, "padding @{}: ", start);
+        for bit in start..(bytes.len() as u64 * 8) {
+            let byte = bytes[(bit / 8) as usize];
+            dump.push(if (byte >> (7 - bit % 8)) & 1 == 1 { '1' } else { '0' });
+        }
+        dump.push('\n');
+        dump
+    }
+
+    /// Returns a reader over the differences coded in the bitstream.
+    fn reader(&self) -> GolombDecoder<&[u8]> {
+        // No difference can be larger than the modulus.
+        let max_quotient = self.modulus_nm >> self.p;
+        GolombDecoder::new(self.as_bytes(), self.p)
+            .with_max_quotient(max_quotient)
+    }
+
+    /// Builds an index of the filter values, recording the bit offset and
+    /// the value of every `stride` entries.
+    ///
+    /// Once built, [`is_member`][1] seeks to the closest indexed value below
+    /// the searched one instead of decoding the filter from the start, which
+    /// is much faster for large filters. The index is dropped if the filter
+    /// data is invalid.
+    ///
+    /// [1]: #method.is_member
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `stride` is zero.
+    pub fn build_index(&mut self, stride: u32) -> Result<(), DecodeError> {
+        self.index = None;
+        self.index = Some(FilterIndex::build(self.reader(), self.n, stride)?);
+        Ok(())
+    }
+
+    /// Returns statistics about the filter size and its false positive rate,
+    /// without decoding it.
+    pub fn stats(&self) -> FilterStats {
+        let bits_per_entry = if self.n == 0 {
+            0.0
+        } else {
+            (self.as_bytes().len() * 8) as f64 / f64::from(self.n)
+        };
+
+        FilterStats {
+            n: self.n,
+            serialized_size: self.serialized_size(),
+            bits_per_entry,
+            fp_rate: 1.0 / self.m.max(1) as f64,
+            quotients: None,
+        }
+    }
+
+    /// Like [`stats`][1], also decoding the filter to count the quotients of
+    /// the coded differences.
+    ///
+    /// Quotients of [`MAX_QUOTIENT_BUCKETS`][2] - 1 and above are counted
+    /// together in the last bucket.
+    ///
+    /// [1]: #method.stats
+    /// [2]: constant.MAX_QUOTIENT_BUCKETS.html
+    pub fn detailed_stats(&self) -> Result<FilterStats, DecodeError> {
+        let mut quotients = Vec::new();
+        let mut last_value = 0;
+        for value in self.values() {
+            let value = value?;
+            // A single difference can have a quotient as large as the filter
+            // data in bits, so the counts are capped rather than sized by it.
+            let quotient = ((value - last_value) >> self.p).min(MAX_QUOTIENT_BUCKETS as u64 - 1) as usize;
+            if quotient >= quotients.len() {
+                quotients.resize(quotient + 1, 0);
+            }
+            quotients[quotient] += 1;
+            last_value = value;
+        }
+
+        Ok(FilterStats {
+            quotients: Some(quotients),
+            ..self.stats()
+        })
+    }
+
+    /// Returns a reader positioned at the given bit offset of the bitstream.
+    fn reader_at(&self, bit_offset: u64) -> Result<GolombDecoder<&[u8]>, DecodeError> {
+        let start = (bit_offset / 8) as usize;
+        let mut reader = GolombDecoder::new(&self.as_bytes()[start..], self.p)
+            .with_max_quotient(self.modulus_nm >> self.p);
+        reader.skip_bits((bit_offset % 8) as u32)?;
+        Ok(reader)
+    }
+
     // Set operations
 
     /// Checks whether a value is likely (within collision probability) to be a
     /// member of the set represented by the filter.
-    pub fn is_member(&self, key: (u64, u64), data: &[u8]) -> bool {
-        let mut cursor = Cursor::new(&self.data);
-        let mut bstream = BitReader::new(&mut cursor);
-
+    ///
+    /// An error is returned if the filter data is found to be invalid before
+    /// the value is found, so corrupt filters can be told apart from filters
+    /// not matching.
+    pub fn is_member(&self, key: FilterKey, data: &[u8]) -> Result<bool, DecodeError> {
         // We hash our search term with the same parameters as the filter.
-        let term = siphash24(key, data);
-        let term = reduce(term, u64::from(self.p));
+        let term = hash_to_range::<H>(key, data, self.modulus_nm);
+
+        // Seek to the closest indexed value below the term, if there's an
+        // index.
+        let (mut bstream, mut last_value, mut read) = match self.index.as_ref().and_then(|i| i.seek(term)) {
+            Some((bit_offset, value, read)) => (self.reader_at(bit_offset)?, value, read),
+            None => (self.reader(), 0u64, 0u32),
+        };
+        let first = read;
 
         // Go through the search filter and look for the desired value.
-        let mut last_value = 0u64;
-        while last_value < term {
+        while read < self.n {
             // Read the difference between previous and new value from
-            // bitstream.
-            let value = match read_full_u64(self, &mut bstream) {
-                Ok(v) => v,
-                // The kind is ErrorKind::UnexpectedEof
-                Err(_) => return false,
-            };
-
-            // Add the previous value to it.
-            let value = value + last_value;
-            if value == term {
-                return true;
+            // bitstream, and add the previous value to it.
+            let value = bstream.decode()?
+                .checked_add(last_value)
+                .ok_or(DecodeError::Overflow)?;
+            // Values are sorted, so the term isn't a member once a value
+            // reaches it without being equal.
+            if value >= term {
+                trace_match(self.n, read - first + 1, value == term);
+                return Ok(value == term);
             }
 
             last_value = value;
+            read += 1;
         }
 
-        false
+        trace_match(self.n, read - first, false);
+        Ok(false)
     }
 
     /// Checks whether any value is likely (within collision probability) to be a
     /// member of the set represented by the filter faster than calling
     /// [`is_member`][1] for each value individually.
     ///
+    /// The values can be given as any iterator of byte slices (`Vec<u8>`,
+    /// `&[u8]`, arrays...), so there's no need to copy them beforehand.
+    ///
+    /// An empty filter or an empty set of values never match. An error is
+    /// returned if the filter data is found to be invalid before a match.
+    ///
     /// [1]: #method.is_member
-    pub fn is_member_any(&self, key: (u64, u64), data: &Vec<Vec<u8>>) -> bool {
-        let mut cursor = Cursor::new(&self.data);
-        let mut bstream = BitReader::new(&mut cursor);
-
+    pub fn is_member_any<I>(&self, key: FilterKey, data: I) -> Result<bool, DecodeError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
         // Create an uncompressed filter of the search values.
-        let mut values = Vec::with_capacity(data.len());
-
-        for datum in data.iter() {
-            // For each datum, we assign the initial hash to a uint64.
-            let v = siphash24(key, datum.as_slice());
+        let mut values = data.into_iter()
+            .map(|datum| hash_to_range::<H>(key, datum.as_ref(), self.modulus_nm))
+            .collect::<Vec<u64>>();
+        values.sort();
 
-            // We'll then reduce the value down to the range of our
-            // modulus.
-            let v = reduce(v, u64::from(self.p));
-            values.push(v);
+        if values.is_empty() || self.is_empty() {
+            return Ok(false);
         }
-        values.sort();
 
         // Zip down the filters, comparing values until we either run out of
         // values to compare in one of the filters or we reach a matching
         // value.
-        let mut last_value = (0, values[0]);
-        let mut i = 1;
-        while last_value.0 != last_value.1 {
-            // Check which filter to advance to make sure we're comparing
-            // the right values.
-            if last_value.0 > last_value.1 {
-                // Advance filter created from search terms or return
-                // false if we're at the end because nothing matched.
-                if i < values.len() {
-                    last_value.1 = values[i];
-                    i += 1;
-                } else {
-                    return false;
+        let mut i = 0;
+        for (read, value) in self.values().enumerate() {
+            let value = value?;
+
+            while values[i] < value {
+                i += 1;
+                if i == values.len() {
+                    trace_match(self.n, read as u32 + 1, false);
+                    return Ok(false);
+                }
+            }
+
+            if values[i] == value {
+                trace_match(self.n, read as u32 + 1, true);
+                return Ok(true);
+            }
+        }
+
+        trace_match(self.n, self.n, false);
+        Ok(false)
+    }
+
+    /// Like [`is_member_any`][1], but also reports which filter value
+    /// matched and which query it was matched by.
+    ///
+    /// This is meant for debugging false positives, it's slightly slower than
+    /// `is_member_any` because the query indexes need to be tracked.
+    ///
+    /// [1]: #method.is_member_any
+    pub fn match_any_verbose<I>(&self, key: FilterKey, data: I) -> Result<Match, DecodeError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut values = data.into_iter()
+            .enumerate()
+            .map(|(i, datum)| (hash_to_range::<H>(key, datum.as_ref(), self.modulus_nm), i))
+            .collect::<Vec<(u64, usize)>>();
+        values.sort();
+
+        if values.is_empty() || self.is_empty() {
+            return Ok(Match::default());
+        }
+
+        let mut i = 0;
+        for value in self.values() {
+            let value = value?;
+
+            while values[i].0 < value {
+                i += 1;
+                if i == values.len() {
+                    return Ok(Match::default());
                 }
-            } else if last_value.1 > last_value.0 {
-                // Advance filter we're searching or return false if
-                // we're at the end because nothing matched.
-                let value = match read_full_u64(self, &mut bstream) {
-                    Ok(v) => v,
-                    // The kind is ErrorKind::UnexpectedEof
-                    Err(_) => return false,
-                };
-
-                last_value.0 += value;
+            }
+
+            if values[i].0 == value {
+                return Ok(Match {
+                    matched: true,
+                    value: Some(value),
+                    query_index: Some(values[i].1),
+                });
+            }
+        }
+
+        Ok(Match::default())
+    }
+
+    /// Checks which of the given values are likely (within collision
+    /// probability) to be members of the set represented by the filter.
+    ///
+    /// The returned vector has one entry per query, in the same order as the
+    /// queries were given, so the filter is decoded only once no matter how
+    /// many queries there are. An error is returned if the filter data is
+    /// invalid.
+    pub fn match_all<I>(&self, key: FilterKey, queries: I) -> Result<Vec<bool>, DecodeError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        // Hash the queries, remembering where each one came from.
+        let mut values: Vec<(u64, usize)> = queries.into_iter()
+            .enumerate()
+            .map(|(i, query)| (hash_to_range::<H>(key, query.as_ref(), self.modulus_nm), i))
+            .collect();
+        values.sort();
+
+        let mut matches = vec![false; values.len()];
+
+        // Walk the filter once, advancing over the sorted queries as the
+        // filter values grow and flagging every query equal to one of them.
+        let mut filter_values = self.values();
+        let (mut read, mut matched) = (0, false);
+        let mut i = 0;
+        while i < values.len() {
+            let last_value = match filter_values.next() {
+                Some(value) => value?,
+                None => break,
+            };
+            read += 1;
+
+            while i < values.len() && values[i].0 < last_value {
+                i += 1;
+            }
+
+            while i < values.len() && values[i].0 == last_value {
+                matches[values[i].1] = true;
+                matched = true;
+                i += 1;
+            }
+        }
+
+        trace_match(self.n, read, matched);
+        Ok(matches)
+    }
+
+    /// Locates the code of a value in the bitstream, returning a
+    /// [proof][1] of its membership, or `None` if the value isn't in the set.
+    ///
+    /// The value is a hashed and reduced item, like the ones yielded by
+    /// [`values`][2].
+    ///
+    /// [1]: struct.MemberProof.html
+    /// [2]: #method.values
+    pub fn prove_member(&self, value: u64) -> Result<Option<MemberProof>, DecodeError> {
+        // A positioned reader counts bits from the byte it starts at.
+        let (mut bstream, start, mut last_value, mut read) = match self.index.as_ref().and_then(|i| i.seek(value)) {
+            Some((bit_offset, value, read)) => (self.reader_at(bit_offset)?, bit_offset & !7, value, read),
+            None => (self.reader(), 0, 0u64, 0u32),
+        };
+
+        while read < self.n {
+            let bit_offset = start + bstream.bit_position();
+            let delta = bstream.decode()?;
+            let current = delta.checked_add(last_value).ok_or(DecodeError::Overflow)?;
+            if current > value {
+                break;
+            }
+            if current == value {
+                let quotient = delta >> self.p;
+                return Ok(Some(MemberProof {
+                    index: read,
+                    bit_offset,
+                    prev_value: last_value,
+                    quotient,
+                    remainder: delta - (quotient << self.p),
+                }));
+            }
+
+            last_value = current;
+            read += 1;
+        }
+
+        Ok(None)
+    }
+}
+
+impl<H: GcsHasher, D: AsRef<[u8]>> fmt::Debug for Filter<H, D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Filter")
+            .field("n", &self.n)
+            .field("p", &self.p)
+            .field("m", &self.m)
+            .field("modulus_nm", &self.modulus_nm)
+            .field("data", &self.as_bytes())
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+impl<H: GcsHasher, D: AsRef<[u8]> + Clone> Clone for Filter<H, D> {
+    fn clone(&self) -> Filter<H, D> {
+        Filter {
+            n: self.n,
+            p: self.p,
+            m: self.m,
+            modulus_nm: self.modulus_nm,
+            data: self.data.clone(),
+            index: self.index.clone(),
+            hasher: PhantomData,
+        }
+    }
+}
+
+impl<H: GcsHasher, D: AsRef<[u8]>> PartialEq for Filter<H, D> {
+    fn eq(&self, other: &Filter<H, D>) -> bool {
+        self.n == other.n
+            && self.p == other.p
+            && self.m == other.m
+            && self.modulus_nm == other.modulus_nm
+            && self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl<H: GcsHasher, D: AsRef<[u8]>> Eq for Filter<H, D> {}
+
+impl<H: GcsHasher, D: AsRef<[u8]>> Hash for Filter<H, D> {
+    fn hash<S: Hasher>(&self, state: &mut S) {
+        self.n.hash(state);
+        self.p.hash(state);
+        self.m.hash(state);
+        self.modulus_nm.hash(state);
+        self.as_bytes().hash(state);
+    }
+}
+
+impl<H: GcsHasher, D: AsRef<[u8]>> PartialOrd for Filter<H, D> {
+    fn partial_cmp(&self, other: &Filter<H, D>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<H: GcsHasher, D: AsRef<[u8]>> Ord for Filter<H, D> {
+    fn cmp(&self, other: &Filter<H, D>) -> Ordering {
+        let mut prefix = Vec::with_capacity(9);
+        write_compact_size(&mut prefix, u64::from(self.n));
+        let mut other_prefix = Vec::with_capacity(9);
+        write_compact_size(&mut other_prefix, u64::from(other.n));
+
+        // Filters with the same serialization are ordered by their
+        // parameters, to be consistent with `Eq`.
+        prefix.iter().chain(self.as_bytes())
+            .cmp(other_prefix.iter().chain(other.as_bytes()))
+            .then(self.p.cmp(&other.p))
+            .then(self.m.cmp(&other.m))
+            .then(self.modulus_nm.cmp(&other.modulus_nm))
+    }
+}
+
+impl<'a, H: GcsHasher> From<FilterRef<'a, H>> for Filter<H> {
+    fn from(filter: FilterRef<'a, H>) -> Filter<H> {
+        filter.into_owned()
+    }
+}
+
+impl<'a, H: GcsHasher> From<&'a Filter<H>> for FilterRef<'a, H> {
+    fn from(filter: &'a Filter<H>) -> FilterRef<'a, H> {
+        filter.as_filter_ref()
+    }
+}
+
+/// Builds a filter with the basic filter parameters and the all-zero key.
+///
+/// Filters bound to a block should be [built][1] with the key derived from
+/// its hash instead.
+///
+/// [1]: struct.Filter.html#method.build
+impl<D: AsRef<[u8]>> FromIterator<D> for Filter {
+    fn from_iter<I: IntoIterator<Item = D>>(data: I) -> Filter {
+        Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::default(), data)
+    }
+}
+
+/// Formats the filter as the hex of its [N-prefixed serialization][1], like
+/// the `getblockfilter` RPC of Bitcoin Core.
+///
+/// The serialization doesn't hold the [modulus][2], so the hex of a filter
+/// whose modulus isn't `N * M` can't be parsed back to the same filter.
+///
+/// [1]: struct.Filter.html#method.to_nbytes
+/// [2]: struct.Filter.html#method.modulus
+impl<H: GcsHasher, D: AsRef<[u8]>> fmt::LowerHex for Filter<H, D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut n = Vec::with_capacity(9);
+        write_compact_size(&mut n, u64::from(self.n));
+        for byte in n.iter().chain(self.as_bytes()) {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats the filter as its [hex][1], followed by its modulus if it isn't
+/// `N * M`, e.g. `0100 (modulus 5)`.
+///
+/// [1]: #impl-LowerHex
+impl<H: GcsHasher, D: AsRef<[u8]>> fmt::Display for Filter<H, D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(self, f)?;
+        if !self.has_nm_modulus() {
+            write!(f, " (modulus {})", self.modulus_nm)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a filter from the hex of its [N-prefixed serialization][1].
+///
+/// The serialization doesn't include the filter parameters, so the basic
+/// filter ones are assumed.
+///
+/// [1]: struct.Filter.html#method.to_nbytes
+impl<H: GcsHasher> FromStr for Filter<H> {
+    type Err = ParseFilterError;
+
+    fn from_str(s: &str) -> Result<Filter<H>, ParseFilterError> {
+        let bytes = decode_hex(s).ok_or(ParseFilterError::InvalidHex)?;
+        let (n, data) = split_nbytes(&bytes)?;
+        DecodeLimits::default().check(n, BASIC_FILTER_P, data.len())?;
+        Ok(Filter::from_bytes_with_hasher(n, BASIC_FILTER_P, BASIC_FILTER_M, data.to_vec()))
+    }
+}
+
+/// Decodes a hex string, `None` if it isn't valid hex.
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    let pairs = s.as_bytes().chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+
+    pairs
+        .map(|pair| Some((hex_digit(pair[0])? << 4) | hex_digit(pair[1])?))
+        .collect()
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "bitcoin")]
+impl<H: GcsHasher, D: AsRef<[u8]>> Filter<H, D> {
+    // Wallet queries
+
+    /// Checks whether the block of this basic filter likely pays to any of
+    /// the given output scripts.
+    pub fn contains_payment_to<'a, I>(&self, key: FilterKey, scripts: I) -> Result<bool, DecodeError>
+    where
+        I: IntoIterator<Item = &'a bitcoin::blockdata::script::Script>,
+    {
+        self.is_member_any(key, scripts.into_iter().map(|script| script.data()))
+    }
+
+    /// Checks whether the block of this basic filter likely spends any of
+    /// the given outputs, e.g. the `witness_utxo` of the inputs of a PSBT.
+    ///
+    /// Basic filters commit to the scripts of the spent outputs rather than
+    /// to their outpoints, so this matches the output scripts, and a block
+    /// spending another output with the same script matches too.
+    pub fn contains_spend_of<'a, I>(&self, key: FilterKey, outputs: I) -> Result<bool, DecodeError>
+    where
+        I: IntoIterator<Item = &'a bitcoin::blockdata::transaction::TxOut>,
+    {
+        self.contains_payment_to(key, outputs.into_iter().map(|output| &output.script_pubkey))
+    }
+
+    /// Checks whether the block of this basic filter likely pays to or
+    /// spends from `address`, by matching its output script.
+    pub fn matches_address(&self, key: FilterKey, address: &bitcoin::util::address::Address)
+        -> Result<bool, DecodeError>
+    {
+        self.is_member(key, &address.script_pubkey().data())
+    }
+
+    /// Checks whether the block of this basic filter likely pays to or
+    /// spends from any of the given addresses.
+    ///
+    /// See [`matches_address`][1].
+    ///
+    /// [1]: #method.matches_address
+    pub fn matches_any_address<'a, I>(&self, key: FilterKey, addresses: I) -> Result<bool, DecodeError>
+    where
+        I: IntoIterator<Item = &'a bitcoin::util::address::Address>,
+    {
+        self.is_member_any(key, addresses.into_iter().map(|address| address.script_pubkey().data()))
+    }
+}
+
+/// Encodes the filter as the `filter` field of the BIP157 `cfilter` message,
+/// the [N-prefixed serialization][1] prefixed by its length.
+///
+/// Encoding panics if the modulus of the filter isn't `N * M`.
+///
+/// [1]: struct.Filter.html#method.to_nbytes
+#[cfg(feature = "decode")]
+impl<S: bitcoin::network::serialize::SimpleEncoder>
+    bitcoin::network::encodable::ConsensusEncodable<S> for Filter
+{
+    fn consensus_encode(&self, s: &mut S) -> Result<(), S::Error> {
+        self.to_nbytes().consensus_encode(s)
+    }
+}
+
+/// Decodes a filter encoded as the `filter` field of the BIP157 `cfilter`
+/// message.
+///
+/// The encoding doesn't include the filter parameters, so the basic filter
+/// ones are assumed. Use [`from_nbytes`][1] to decode filters of other types.
+///
+/// Filters larger than the [default limits][2] are rejected before their data
+/// is read.
+///
+/// [1]: struct.Filter.html#method.from_nbytes
+/// [2]: struct.DecodeLimits.html
+#[cfg(feature = "decode")]
+impl<D: bitcoin::network::serialize::SimpleDecoder>
+    bitcoin::network::encodable::ConsensusDecodable<D> for Filter
+{
+    fn consensus_decode(d: &mut D) -> Result<Filter, D::Error> {
+        let bytes = decode_nbytes(d, &DecodeLimits::default())?;
+        Filter::from_nbytes(BASIC_FILTER_P, BASIC_FILTER_M, &bytes)
+            .map_err(|e| d.error(e.to_string()))
+    }
+}
+
+/// Reads the `VarInt` length prefixed bytes of an N-prefixed filter, failing
+/// before allocating them if they can't fit in `limits`.
+#[cfg(feature = "decode")]
+fn decode_nbytes<D: bitcoin::network::serialize::SimpleDecoder>(d: &mut D, limits: &DecodeLimits)
+    -> Result<Vec<u8>, D::Error>
+{
+    use bitcoin::network::encodable::{ConsensusDecodable, VarInt};
+
+    // The data is preceded by N, a `VarInt` of up to 9 bytes.
+    let VarInt(len) = ConsensusDecodable::consensus_decode(d)?;
+    if len > limits.max_size().saturating_add(9) as u64 {
+        return Err(d.error(LimitError::TooLarge(len as usize).to_string()));
+    }
+
+    let mut bytes = Vec::with_capacity(len as usize);
+    for _ in 0..len {
+        bytes.push(d.read_u8()?);
+    }
+    Ok(bytes)
+}
+
+/// The result of [`Filter::match_any_verbose`][1].
+///
+/// [1]: struct.Filter.html#method.match_any_verbose
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Match {
+    /// Whether any query matched.
+    pub matched: bool,
+    /// The filter value that matched.
+    pub value: Option<u64>,
+    /// The index of the query that matched, in the order they were given.
+    pub query_index: Option<usize>,
+}
+
+/// The location of the code of a value in the bitstream of a filter,
+/// returned by [`Filter::prove_member`][1].
+///
+/// A server holding the filter can convince a client that a value is in it
+/// by sending the proof along with the bytes holding its code, instead of
+/// the whole filter. The previous value is taken on trust, it can be
+/// checked with the proof of the previous value.
+///
+/// [1]: struct.Filter.html#method.prove_member
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemberProof {
+    /// The index of the value in the set.
+    pub index: u32,
+    /// The offset of the code of the value in the bitstream, in bits.
+    pub bit_offset: u64,
+    /// The previous value of the set, zero for the first value.
+    pub prev_value: u64,
+    /// The unary coded quotient of the difference with the previous value.
+    pub quotient: u64,
+    /// The remainder of the difference with the previous value.
+    pub remainder: u64,
+}
+
+impl MemberProof {
+    /// Returns the proven value given the filter parameter P, `None` if the
+    /// remainder doesn't fit in P bits or the value overflows.
+    pub fn value(&self, p: u8) -> Option<u64> {
+        if p > 32 || self.remainder >> p != 0 {
+            return None;
+        }
+
+        self.quotient.checked_mul(1 << p)
+            .and_then(|delta| delta.checked_add(self.remainder))
+            .and_then(|delta| delta.checked_add(self.prev_value))
+    }
+
+    /// Returns the range of bytes of the bitstream holding the code of the
+    /// value given the filter parameter P, to be sent along with the proof.
+    pub fn code_bytes(&self, p: u8) -> Range<u64> {
+        let end = self.bit_offset + self.quotient + 1 + u64::from(p);
+        self.bit_offset / 8..end.div_ceil(8)
+    }
+
+    /// Returns `true` if the proof proves `value`, and the code at its
+    /// offset is its quotient and remainder.
+    ///
+    /// `window` holds bytes of the bitstream of the filter starting at
+    /// `window_bit_offset`, which must be byte aligned, like the ones in
+    /// [`code_bytes`][1]. Only the code of the value is decoded, so the set
+    /// length (N) and the range of the value have to be checked by the
+    /// caller.
+    ///
+    /// [1]: #method.code_bytes
+    pub fn verify(&self, p: u8, window: &[u8], window_bit_offset: u64, value: u64) -> bool {
+        if self.value(p) != Some(value) || !window_bit_offset.is_multiple_of(8) {
+            return false;
+        }
+        if self.bit_offset < window_bit_offset || self.bit_offset - window_bit_offset >= window.len() as u64 * 8 {
+            return false;
+        }
+
+        let bit_offset = self.bit_offset - window_bit_offset;
+        let mut reader = GolombDecoder::new(&window[(bit_offset / 8) as usize..], p)
+            .with_max_quotient(self.quotient);
+        if reader.skip_bits((bit_offset % 8) as u32).is_err() {
+            return false;
+        }
+        match reader.decode() {
+            Ok(delta) => delta == (self.quotient << p) + self.remainder,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Statistics about a filter, returned by [`Filter::stats`][1] and
+/// [`Filter::detailed_stats`][2].
+///
+/// [1]: struct.Filter.html#method.stats
+/// [2]: struct.Filter.html#method.detailed_stats
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FilterStats {
+    /// The number of items (N).
+    pub n: u32,
+    /// The length of the N-prefixed serialization.
+    pub serialized_size: usize,
+    /// The average number of bits of filter data per item.
+    pub bits_per_entry: f64,
+    /// The probability of a query not in the filter matching, that is, `1/M`.
+    pub fp_rate: f64,
+    /// The number of coded differences with each quotient, only counted by
+    /// [`Filter::detailed_stats`][1], up to [`MAX_QUOTIENT_BUCKETS`][2]
+    /// counts.
+    ///
+    /// [1]: struct.Filter.html#method.detailed_stats
+    /// [2]: constant.MAX_QUOTIENT_BUCKETS.html
+    pub quotients: Option<Vec<u32>>,
+}
+
+/// An iterator over the values of a `Filter`.
+///
+/// This is created by [`Filter::values`][1].
+///
+/// [1]: struct.Filter.html#method.values
+#[derive(Debug, Clone)]
+pub struct Values<'a> {
+    reader: GolombDecoder<&'a [u8]>,
+    remaining: u32,
+    last_value: u64,
+}
+
+impl<'a> Iterator for Values<'a> {
+    type Item = Result<u64, DecodeError>;
+
+    fn next(&mut self) -> Option<Result<u64, DecodeError>> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let value = self.reader.decode()
+            .and_then(|delta| {
+                self.last_value.checked_add(delta).ok_or(DecodeError::Overflow)
+            });
+
+        match value {
+            Ok(value) => {
+                self.remaining -= 1;
+                self.last_value = value;
+                Some(Ok(value))
+            }
+            Err(e) => {
+                // Don't keep reading from a corrupt bitstream.
+                self.remaining = 0;
+                Some(Err(e))
             }
         }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining as usize))
+    }
+}
 
-        // If we've made it this far, an element matched between filters so we
-        // return true.
-        true
+/// Hashes each item and reduces it to the range `[0, modulus)`.
+#[cfg(not(feature = "rayon"))]
+fn hash_items<H: GcsHasher>(key: FilterKey, items: &[&[u8]], modulus: u64) -> Vec<u64> {
+    let mut values = H::hash_batch(key, items);
+    for v in values.iter_mut() {
+        *v = hash_value(*v, modulus);
     }
+    values
+}
+
+/// Hashes each item and reduces it to the range `[0, modulus)`, spreading
+/// the work over the rayon thread pool.
+#[cfg(feature = "rayon")]
+fn hash_items<H: GcsHasher>(key: FilterKey, items: &[&[u8]], modulus: u64) -> Vec<u64> {
+    use rayon::prelude::*;
+
+    items.par_chunks(1024)
+        .flat_map_iter(|chunk| H::hash_batch(key, chunk))
+        .map(|v| hash_value(v, modulus))
+        .collect()
+}
+
+/// Hashes each item with SipHash-2-4, returning the hashes in the same
+/// order as the items.
+#[cfg(not(feature = "simd"))]
+fn siphash24_batch(key: FilterKey, items: &[&[u8]]) -> Vec<u64> {
+    items.iter().map(|item| siphash24(key, item)).collect()
 }
 
 /// Calculate a mapping that is more or less equivalent to x mod N.
@@ -258,27 +1526,185 @@ pub fn reduce(x: u64, n: u64) -> u64 {
     ((u128::from(x) * u128::from(n)) >> 64) as u64
 }
 
+/// Hashes an item with SipHash-2-4 and maps it to the range `[0, N * M)` of a
+/// filter with `n` items and parameter `m`.
+///
+/// This is the value an item is encoded as when a BIP158 filter is built, and
+/// the one searched for when the filter is matched, both go through the same
+/// mapping.
+///
+/// # Panics
+///
+/// If `N * M` doesn't fit in a `u64`.
+pub fn map_to_range(key: FilterKey, data: &[u8], n: u32, m: u64) -> u64 {
+    hash_to_range::<SipHash24>(key, data, modulus(n, m))
+}
+
+/// Hashes an item with `H` and reduces it to the range `[0, modulus)`.
+fn hash_to_range<H: GcsHasher>(key: FilterKey, data: &[u8], modulus: u64) -> u64 {
+    hash_value(H::hash(key, data), modulus)
+}
+
+/// Reduces the hash of an item to its value in the range `[0, modulus)`.
+///
+/// Items hashed beforehand, in batches or kept as hashes, are mapped with
+/// this too, so built and matched values can't diverge.
+fn hash_value(hash: u64, modulus: u64) -> u64 {
+    reduce(hash, modulus)
+}
+
 /// Calculate SipHash 2-4
-pub fn siphash24(key: (u64, u64), data: &[u8]) -> u64 {
-    let mut hasher = SipHasher24::new_with_keys(key.0, key.1);
+pub fn siphash24(key: FilterKey, data: &[u8]) -> u64 {
+    let (k0, k1) = key.keys();
+    let mut hasher = SipHasher24::new_with_keys(k0, k1);
     hasher.write(data);
     hasher.finish()
 }
 
-/// Reads a value represented by the sum of a unary multiple of
-/// the filter's P modulus (`2**P`) and a big-endian P-bit remainder.
-fn read_full_u64(filter: &Filter, bstream: &mut BitReader<BE>) -> io::Result<u64> {
-	let mut quotient = 0u64;
+/// Returns the smallest P whose [optimal M][1] gives a false positive rate of
+/// at most `target_fp_rate`, for a filter of `n` items.
+///
+/// This is 32 if the rate can't be reached with any P.
+///
+/// # Panics
+///
+/// This function panics if `target_fp_rate` isn't in the range `(0, 1]`.
+///
+/// [1]: fn.optimal_m.html
+pub fn optimal_p(n: u32, target_fp_rate: f64) -> u8 {
+    assert!(target_fp_rate > 0.0 && target_fp_rate <= 1.0, "invalid false positive rate");
+
+    let m = 1.0 / target_fp_rate;
+    (0..=32).find(|&p| optimal_m(n, p) as f64 >= m).unwrap_or(32)
+}
 
-	// Count the 1s until we reach a 0.
-	let c = bstream.read_bit()?;
-	while c {
-		quotient += 1;
-	}
+/// Returns the M that minimizes the size of a filter of `n` items with
+/// Golomb-Rice parameter P, that is, `2^P * 1.497137` as described in
+/// [*Golomb-coded set parameter selection*][1].
+///
+/// M is capped so that `N * M` fits in a `u64`.
+///
+/// # Panics
+///
+/// This function panics if P is larger than 32.
+///
+/// [1]: https://gist.github.com/sipa/576d5f09c3b86c3b1b75598d799fc845
+pub fn optimal_m(n: u32, p: u8) -> u64 {
+    assert!(p <= 32, "P is too big");
 
-	// Read P bits.
-	let remainder: u64 = bstream.read(u32::from(filter.p))?;
+    let m = ((1u64 << p) as f64 * 1.497137 + 0.5) as u64;
+    m.min(u64::MAX / u64::from(n).max(1))
+}
 
-	// Add the multiple and the remainder.
-	Ok((quotient << u64::from(filter.p)) + remainder)
+/// Returns `N * M`, computed without overflowing, or `None` if it doesn't fit
+/// in a `u64`.
+fn checked_modulus(n: u32, m: u64) -> Option<u64> {
+    let modulus = u128::from(n) * u128::from(m);
+    if modulus > u128::from(u64::MAX) {
+        None
+    } else {
+        Some(modulus as u64)
+    }
+}
+
+/// Returns `N * M`, panicking if it doesn't fit in a `u64`.
+fn modulus(n: u32, m: u64) -> u64 {
+    checked_modulus(n, m).expect("N * M is too big")
+}
+
+/// Splits an N-prefixed filter serialization into N and the filter data.
+fn split_nbytes(data: &[u8]) -> Result<(u32, &[u8]), ParseFilterError> {
+    let (n, len) = read_compact_size(data).ok_or(ParseFilterError::InvalidSetLength)?;
+    if n > u64::from(u32::MAX) {
+        return Err(ParseFilterError::InvalidSetLength);
+    }
+
+    Ok((n as u32, &data[len..]))
+}
+
+/// Writes the sorted list of values into a filter bitstream, compressing the
+/// differences between them using Golomb coding.
+fn encode_values(p: u8, values: &[u64]) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+    {
+        let mut last_value = 0u64;
+        let mut encoder = GolombEncoder::new(&mut data, p);
+        for v in values.iter() {
+            encoder.encode(*v - last_value)
+                .expect("writing to a vector can't fail");
+            last_value = *v;
+        }
+        encoder.finish().expect("writing to a vector can't fail");
+    }
+    data
+}
+
+/// Serializes an outpoint as a filter entry, the txid followed by the
+/// output index as a little-endian `u32`.
+#[cfg(feature = "bitcoin")]
+fn outpoint_entry(outpoint: &bitcoin::blockdata::transaction::TxOutRef) -> [u8; 32 + 4] {
+    let mut entry = [0u8; 32 + 4];
+    entry[0..32].copy_from_slice(&outpoint.txid.data());
+    entry[32..36].copy_from_slice(&(outpoint.index as u32).to_le_bytes());
+    entry
+}
+
+/// Reports how many of the `n` values of a filter were decoded to match
+/// it, the filter was exited early if it's less than `n`.
+#[inline]
+fn trace_match(n: u32, read: u32, matched: bool) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(n, read, matched, "matched filter");
+    #[cfg(not(feature = "tracing"))]
+    let _ = (n, read, matched);
+}
+
+/// Returns the length of `n` encoded as a `VarInt` (CompactSize).
+fn compact_size_len(n: u64) -> usize {
+    match n {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x10000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+/// Reads a `VarInt` (CompactSize) from the start of `data`, returning it
+/// along with its encoded length.
+fn read_compact_size(data: &[u8]) -> Option<(u64, usize)> {
+    let len = match *data.first()? {
+        0xfd => 3,
+        0xfe => 5,
+        0xff => 9,
+        n => return Some((u64::from(n), 1)),
+    };
+
+    let bytes = data.get(1..len)?;
+    let n = bytes.iter()
+        .rev()
+        .fold(0u64, |n, b| (n << 8) | u64::from(*b));
+    Some((n, len))
+}
+
+/// Appends `n` encoded as a `VarInt` (CompactSize) to `buf`.
+fn write_compact_size(buf: &mut Vec<u8>, n: u64) {
+    match compact_size_len(n) {
+        1 => buf.push(n as u8),
+        3 => {
+            buf.push(0xfd);
+            buf.extend_from_slice(&[n as u8, (n >> 8) as u8]);
+        }
+        5 => {
+            buf.push(0xfe);
+            for i in 0..4 {
+                buf.push((n >> (8 * i)) as u8);
+            }
+        }
+        _ => {
+            buf.push(0xff);
+            for i in 0..8 {
+                buf.push((n >> (8 * i)) as u8);
+            }
+        }
+    }
 }
@@ -0,0 +1,71 @@
+//! Limits on the size of the filters decoded from untrusted sources.
+
+use LimitError;
+
+/// The largest filter data accepted by default, in bytes.
+///
+/// This is the bound on the `cfilter` messages of BIP157, basic filters of
+/// real blocks are far below it.
+pub const MAX_FILTER_SIZE: usize = 256 * 1024;
+
+/// Limits on the set length (N) and the data size of a decoded filter.
+///
+/// The set length of a serialized filter is a `VarInt` chosen by the sender,
+/// so it's also checked to fit in the data, where each value takes at least
+/// `P + 1` bits. This keeps a few bytes claiming billions of items from
+/// making every match of the filter scan for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DecodeLimits {
+    max_n: u32,
+    max_size: usize,
+}
+
+impl DecodeLimits {
+    // Constructors
+
+    /// Creates limits of at most `max_n` items and `max_size` bytes of
+    /// filter data.
+    pub fn new(max_n: u32, max_size: usize) -> DecodeLimits {
+        DecodeLimits { max_n, max_size }
+    }
+
+    /// Creates limits that only check the set length against the data
+    /// size, for filters from trusted sources.
+    pub fn unlimited() -> DecodeLimits {
+        DecodeLimits::new(u32::MAX, usize::MAX)
+    }
+
+    // Accessors
+
+    /// Returns the largest accepted set length (N).
+    pub fn max_n(&self) -> u32 {
+        self.max_n
+    }
+
+    /// Returns the largest accepted filter data size, in bytes.
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Checks a filter of `n` items with parameter `p` and `size` bytes of
+    /// data, without the set length prefix.
+    pub fn check(&self, n: u32, p: u8, size: usize) -> Result<(), LimitError> {
+        if size > self.max_size {
+            return Err(LimitError::TooLarge(size));
+        }
+        if n > self.max_n || u128::from(n) * (u128::from(p) + 1) > size as u128 * 8 {
+            return Err(LimitError::TooManyItems(n));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for DecodeLimits {
+    /// Any set length that fits in at most [`MAX_FILTER_SIZE`][1] bytes.
+    ///
+    /// [1]: constant.MAX_FILTER_SIZE.html
+    fn default() -> DecodeLimits {
+        DecodeLimits::new(u32::MAX, MAX_FILTER_SIZE)
+    }
+}
@@ -0,0 +1,64 @@
+//! Activity counters of the rescan, storage and serving subsystems.
+
+#[cfg(any(feature = "rescan", feature = "store"))]
+use std::fmt;
+#[cfg(any(feature = "rescan", feature = "store"))]
+use std::sync::Arc;
+
+/// Callbacks invoked by the [`Rescan`][1], [`FilterStore`][2] and
+/// [`FilterServer`][3] subsystems, to export their activity as counters,
+/// e.g. to Prometheus.
+///
+/// Every callback does nothing by default, so only the relevant ones have to
+/// be implemented.
+///
+/// [1]: rescan/struct.Rescan.html
+/// [2]: store/struct.FilterStore.html
+/// [3]: server/struct.FilterServer.html
+pub trait Metrics: Send + Sync {
+    /// Called when a filter is added to a store, whether it was built
+    /// locally or received from a peer.
+    fn filter_stored(&self) {}
+
+    /// Called when a filter is encoded, either to be stored or to be sent to
+    /// a peer, with the size in bytes of its [N-prefixed serialization][1].
+    ///
+    /// [1]: struct.Filter.html#method.serialized_size
+    fn bytes_encoded(&self, _bytes: usize) {}
+
+    /// Called when a filter is matched against the watched items, with
+    /// whether it matched.
+    fn matches_run(&self, _matched: bool) {}
+
+    /// Called when a matching block is found not to contain any watched
+    /// item.
+    fn false_positive_confirmed(&self) {}
+}
+
+/// The [`Metrics`][1] used by default, which ignores every callback.
+///
+/// [1]: trait.Metrics.html
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoMetrics;
+
+impl Metrics for NoMetrics {}
+
+/// The metrics shared by the subsystems, which don't have to implement
+/// `Debug`.
+#[cfg(any(feature = "rescan", feature = "store"))]
+#[derive(Clone)]
+pub struct SharedMetrics(pub Arc<dyn Metrics>);
+
+#[cfg(any(feature = "rescan", feature = "store"))]
+impl Default for SharedMetrics {
+    fn default() -> SharedMetrics {
+        SharedMetrics(Arc::new(NoMetrics))
+    }
+}
+
+#[cfg(any(feature = "rescan", feature = "store"))]
+impl fmt::Debug for SharedMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SharedMetrics")
+    }
+}
@@ -0,0 +1,124 @@
+//! Asynchronous filter sources and sinks.
+//!
+//! These traits let the filters be fetched from, or stored to, asynchronous
+//! P2P stacks and databases with [*futures*][1], e.g. to drive a
+//! [`Rescan`][2] or [extend a filter header chain][3] from a *tokio* event
+//! loop.
+//!
+//! [1]: https://docs.rs/futures/0.1
+//! [2]: ../rescan/struct.Rescan.html
+//! [3]: fn.sync_headers.html
+
+use futures::future::{self, Either, FutureResult, Loop};
+use futures::Future;
+
+use bitcoin::util::hash::Sha256dHash;
+
+#[cfg(feature = "store")]
+use std::io;
+
+use rescan::{FilterSource, RescanError};
+#[cfg(feature = "store")]
+use store::FilterStore;
+use {Filter, FilterHeaderChain};
+
+/// An asynchronous source of block filters.
+pub trait AsyncFilterSource {
+    /// The error returned when a filter can't be retrieved.
+    type Error;
+    /// The future returned by [`get_filter`][1].
+    ///
+    /// [1]: #tymethod.get_filter
+    type Future: Future<Item = Option<(Sha256dHash, Filter)>, Error = Self::Error>;
+
+    /// Retrieves the hash of the block at `height` along with its filter, or
+    /// `None` if the source doesn't have it.
+    fn get_filter(&mut self, height: u32) -> Self::Future;
+}
+
+/// An asynchronous destination of block filters.
+pub trait AsyncFilterSink {
+    /// The error returned when a filter can't be stored.
+    type Error;
+    /// The future returned by [`put_filter`][1].
+    ///
+    /// [1]: #tymethod.put_filter
+    type Future: Future<Item = (), Error = Self::Error>;
+
+    /// Stores the filter of the block at `height`, replacing any other
+    /// filter at that height.
+    fn put_filter(&mut self, height: u32, block_hash: &Sha256dHash, filter: &Filter) -> Self::Future;
+}
+
+/// Adapts a synchronous filter source, or a [`FilterStore`][1], to the
+/// asynchronous traits.
+///
+/// The calls are made right away and the returned futures are already
+/// resolved, so this is only suitable for sources that don't block for long,
+/// like local storage.
+///
+/// [1]: ../store/struct.FilterStore.html
+#[derive(Debug)]
+pub struct Blocking<T>(pub T);
+
+impl<T> Blocking<T> {
+    /// Returns the wrapped source.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: FilterSource> AsyncFilterSource for Blocking<T> {
+    type Error = T::Error;
+    type Future = FutureResult<Option<(Sha256dHash, Filter)>, T::Error>;
+
+    fn get_filter(&mut self, height: u32) -> Self::Future {
+        future::result(self.0.get_filter(height))
+    }
+}
+
+#[cfg(feature = "store")]
+impl AsyncFilterSink for Blocking<FilterStore> {
+    type Error = io::Error;
+    type Future = FutureResult<(), io::Error>;
+
+    fn put_filter(&mut self, height: u32, block_hash: &Sha256dHash, filter: &Filter) -> Self::Future {
+        future::result(self.0.put(height, block_hash, filter))
+    }
+}
+
+/// Extends a filter header chain with the headers of the filters retrieved
+/// from an asynchronous source, up to the block at `stop_height`.
+///
+/// The chain and the source are given back once the chain reaches
+/// `stop_height`. Errors are the ones of a [rescan][1], a filter the source
+/// doesn't have is a `MissingFilter` error.
+///
+/// # Panics
+///
+/// The future panics if the modulus of a retrieved filter isn't `N * M`,
+/// see [`Filter::filter_hash`][2].
+///
+/// [1]: ../rescan/enum.RescanError.html
+/// [2]: ../struct.Filter.html#method.filter_hash
+pub fn sync_headers<S>(chain: FilterHeaderChain, source: S, stop_height: u32)
+    -> impl Future<Item = (FilterHeaderChain, S), Error = RescanError<S::Error>>
+where
+    S: AsyncFilterSource,
+{
+    future::loop_fn((chain, source), move |(mut chain, mut source)| {
+        let height = chain.len() as u32;
+        if height > stop_height {
+            return Either::A(future::ok(Loop::Break((chain, source))));
+        }
+
+        Either::B(source.get_filter(height).then(move |result| {
+            let (_, filter) = result
+                .map_err(RescanError::Source)?
+                .ok_or(RescanError::MissingFilter(height))?;
+
+            chain.push_filter(&filter);
+            Ok(Loop::Continue((chain, source)))
+        }))
+    })
+}
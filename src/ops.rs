@@ -0,0 +1,247 @@
+//! Set operations between filters.
+//!
+//! The values of a filter are reduced to the range `[0, N * M)`, so they
+//! can only be compared with the values of filters with the same modulus,
+//! built with the same key. The resulting filters keep that modulus, even if
+//! their set length (N) changes, so they keep matching the same items.
+//! Entries [inserted][1] into a filter are reduced to its modulus too.
+//!
+//! The reduction discards the low bits of the hashes, so the values can't be
+//! mapped to the `N * M` of the new set length without the items. The
+//! serializations only hold N, so the resulting filters [can't be
+//! serialized][2] or stored unless N is unchanged, they have to be rebuilt
+//! from their items instead.
+//!
+//! [1]: ../struct.Filter.html#method.insert_all
+//! [2]: ../struct.Filter.html#method.try_to_nbytes
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use core::marker::PhantomData;
+
+use golomb::GolombEncoder;
+use {encode_values, hash_to_range, DecodeError, Filter, FilterKey, GcsHasher, SetError};
+
+impl<H: GcsHasher> Filter<H> {
+    /// Returns the union of both filters.
+    ///
+    /// Both filters must have been built with the same key, and have the
+    /// same P and modulus, unless one of them is empty. The union keeps that
+    /// modulus, so it can only be matched in memory, not serialized.
+    pub fn merge(&self, other: &Filter<H>) -> Result<Filter<H>, SetError> {
+        self.check_compatible(other)?;
+        if self.is_empty() {
+            return Ok(other.clone());
+        }
+        if other.is_empty() {
+            return Ok(self.clone());
+        }
+
+        let a = self.values().collect::<Result<Vec<u64>, _>>()?;
+        let b = other.values().collect::<Result<Vec<u64>, _>>()?;
+
+        Ok(self.with_sorted_values(&merge_sorted(&a, &b)))
+    }
+
+    /// Returns the values present in both filters.
+    ///
+    /// The same requirements as in [`merge`][1] apply.
+    ///
+    /// [1]: #method.merge
+    pub fn intersect(&self, other: &Filter<H>) -> Result<Filter<H>, SetError> {
+        self.check_compatible(other)?;
+        if self.is_empty() {
+            return Ok(self.clone());
+        }
+        if other.is_empty() {
+            return Ok(other.clone());
+        }
+
+        let a = self.values().collect::<Result<Vec<u64>, _>>()?;
+        let b = other.values().collect::<Result<Vec<u64>, _>>()?;
+
+        let mut values = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() && j < b.len() {
+            if a[i] < b[j] {
+                i += 1;
+            } else if a[i] > b[j] {
+                j += 1;
+            } else {
+                values.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+
+        Ok(self.with_sorted_values(&values))
+    }
+
+    /// Returns the values of this filter that aren't present in `other`.
+    ///
+    /// The same requirements as in [`merge`][1] apply.
+    ///
+    /// [1]: #method.merge
+    pub fn difference(&self, other: &Filter<H>) -> Result<Filter<H>, SetError> {
+        self.check_compatible(other)?;
+        if self.is_empty() || other.is_empty() {
+            return Ok(self.clone());
+        }
+
+        let a = self.values().collect::<Result<Vec<u64>, _>>()?;
+        let b = other.values().collect::<Result<Vec<u64>, _>>()?;
+
+        let mut values = Vec::new();
+        let mut j = 0;
+        for v in a {
+            while j < b.len() && b[j] < v {
+                j += 1;
+            }
+            if j == b.len() || b[j] != v {
+                values.push(v);
+            }
+        }
+
+        Ok(self.with_sorted_values(&values))
+    }
+
+    /// Adds the items of `entries`, hashed with `key`, to the filter.
+    ///
+    /// As with the other set operations the filter keeps its modulus, so it
+    /// keeps matching the items it was built from, unless it's empty, in
+    /// which case it's built from the entries.
+    ///
+    /// The codes of the values below the smallest new one are kept as is,
+    /// and only the rest of the bitstream is re-encoded, which makes keeping
+    /// e.g. a mempool filter matched in memory up to date cheaper than
+    /// rebuilding it. The filter is left unchanged if its data is invalid.
+    ///
+    /// The values can't be re-encoded for the modulus of the new set length
+    /// without the items, so once entries are added the filter [can't be
+    /// serialized][1]. Filters to be stored or relayed, e.g. to peers, have
+    /// to be rebuilt from all their items, or kept in a
+    /// [`RollingFilter`][2], which rebuilds them from cached hashes.
+    ///
+    /// [1]: #method.try_to_nbytes
+    /// [2]: struct.RollingFilter.html
+    pub fn insert_all<I>(&mut self, key: FilterKey, entries: I) -> Result<(), DecodeError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        if self.is_empty() {
+            *self = Filter::build_with_hasher(self.p, self.m, key, entries);
+            return Ok(());
+        }
+
+        let mut new_values = entries.into_iter()
+            .map(|entry| hash_to_range::<H>(key, entry.as_ref(), self.modulus_nm))
+            .collect::<Vec<u64>>();
+        new_values.sort_unstable();
+        new_values.dedup();
+        let first = match new_values.first() {
+            Some(first) => *first,
+            None => return Ok(()),
+        };
+
+        // Split the values at the smallest new one, recording where the
+        // codes of the values below it end.
+        let (mut kept, mut kept_bits, mut base) = (0u32, 0u64, 0u64);
+        let mut tail = Vec::new();
+        let mut reader = self.reader();
+        let mut last_value = 0u64;
+        for _ in 0..self.n {
+            let value = reader.decode()?
+                .checked_add(last_value)
+                .ok_or(DecodeError::Overflow)?;
+            if tail.is_empty() && value < first {
+                kept += 1;
+                kept_bits = reader.bit_position();
+                base = value;
+            } else {
+                tail.push(value);
+            }
+            last_value = value;
+        }
+
+        let values = merge_sorted(&tail, &new_values);
+        let n = u64::from(kept) + values.len() as u64;
+        assert!(n <= u64::from(u32::MAX), "N is too big");
+
+        let mut data = self.as_bytes()[..(kept_bits / 8) as usize].to_vec();
+        {
+            let mut encoder = GolombEncoder::new(&mut data, self.p);
+            let partial_bits = (kept_bits % 8) as u32;
+            if partial_bits > 0 {
+                let byte = self.as_bytes()[(kept_bits / 8) as usize];
+                encoder.write_bits(partial_bits, u64::from(byte >> (8 - partial_bits)))
+                    .expect("writing to a vector can't fail");
+            }
+
+            let mut last_value = base;
+            for v in values {
+                encoder.encode(v - last_value)
+                    .expect("writing to a vector can't fail");
+                last_value = v;
+            }
+            encoder.finish().expect("writing to a vector can't fail");
+        }
+
+        self.n = n as u32;
+        self.data = data;
+        self.index = None;
+        Ok(())
+    }
+
+    fn check_compatible(&self, other: &Filter<H>) -> Result<(), SetError> {
+        if self.p != other.p {
+            return Err(SetError::IncompatibleParams);
+        }
+
+        if !self.is_empty() && !other.is_empty() && self.modulus_nm != other.modulus_nm {
+            return Err(SetError::IncompatibleParams);
+        }
+
+        Ok(())
+    }
+
+    /// Creates a filter with the parameters of this one from a sorted list
+    /// of values.
+    fn with_sorted_values(&self, values: &[u64]) -> Filter<H> {
+        assert!(values.len() <= u32::MAX as usize, "N is too big");
+
+        Filter {
+            n: values.len() as u32,
+            p: self.p,
+            m: self.m,
+            modulus_nm: self.modulus_nm,
+            data: encode_values(self.p, values),
+            index: None,
+            hasher: PhantomData,
+        }
+    }
+}
+
+/// Merges two sorted lists of values, keeping a single copy of the values
+/// present in both.
+fn merge_sorted(a: &[u64], b: &[u64]) -> Vec<u64> {
+    let mut values = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] < b[j] {
+            values.push(a[i]);
+            i += 1;
+        } else if a[i] > b[j] {
+            values.push(b[j]);
+            j += 1;
+        } else {
+            values.push(a[i]);
+            i += 1;
+            j += 1;
+        }
+    }
+    values.extend_from_slice(&a[i..]);
+    values.extend_from_slice(&b[j..]);
+    values
+}
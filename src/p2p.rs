@@ -0,0 +1,188 @@
+//! BIP157 compact filter P2P messages.
+//!
+//! The messages implement the consensus encoding traits of rust-bitcoin, so
+//! they can be serialized and deserialized with
+//! `bitcoin::network::serialize::{serialize, deserialize}`.
+
+use bitcoin::network::encodable::{ConsensusDecodable, ConsensusEncodable};
+use bitcoin::network::serialize::{SimpleDecoder, SimpleEncoder};
+use bitcoin::util::hash::Sha256dHash;
+
+use {decode_nbytes, BASIC_FILTER_M, BASIC_FILTER_P, BASIC_FILTER_TYPE, DecodeLimits, Filter, FilterHeader};
+
+macro_rules! impl_message_encoding {
+    ($thing:ident, $command:expr, $($field:ident),+) => {
+        impl $thing {
+            /// The command name of the message.
+            pub const COMMAND: &'static str = $command;
+        }
+
+        impl<S: SimpleEncoder> ConsensusEncodable<S> for $thing {
+            fn consensus_encode(&self, s: &mut S) -> Result<(), S::Error> {
+                $(self.$field.consensus_encode(s)?;)+
+                Ok(())
+            }
+        }
+
+        impl<D: SimpleDecoder> ConsensusDecodable<D> for $thing {
+            fn consensus_decode(d: &mut D) -> Result<$thing, D::Error> {
+                Ok($thing {
+                    $($field: ConsensusDecodable::consensus_decode(d)?),+
+                })
+            }
+        }
+    }
+}
+
+/// The service bit of the peers serving compact filters.
+pub const NODE_COMPACT_FILTERS: u64 = 1 << 6;
+
+/// Maximum number of filter headers requested in a `getcfheaders`.
+pub const MAX_CFHEADERS: u32 = 2000;
+
+/// Maximum number of filters requested in a `getcfilters`.
+pub const MAX_CFILTERS: u32 = 1000;
+
+/// Returns the P and M parameters of a filter type.
+pub fn filter_type_params(filter_type: u8) -> Option<(u8, u64)> {
+    match filter_type {
+        BASIC_FILTER_TYPE => Some((BASIC_FILTER_P, BASIC_FILTER_M)),
+        _ => None,
+    }
+}
+
+/// `getcfilters` message, requests the filters of a range of blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetCFilters {
+    /// Type of the requested filters.
+    pub filter_type: u8,
+    /// Height of the first block of the range.
+    pub start_height: u32,
+    /// Hash of the last block of the range.
+    pub stop_hash: Sha256dHash,
+}
+
+impl_message_encoding!(GetCFilters, "getcfilters", filter_type, start_height, stop_hash);
+
+/// `cfilter` message, a filter of a single block.
+///
+/// Encoding the message panics if the modulus of the filter isn't `N * M`,
+/// see [`Filter::try_to_nbytes`][1].
+///
+/// [1]: ../struct.Filter.html#method.try_to_nbytes
+#[derive(Debug, Clone)]
+pub struct CFilter {
+    /// Type of the filter.
+    pub filter_type: u8,
+    /// Hash of the block the filter belongs to.
+    pub block_hash: Sha256dHash,
+    /// The filter.
+    pub filter: Filter,
+}
+
+impl CFilter {
+    /// The command name of the message.
+    pub const COMMAND: &'static str = "cfilter";
+
+    /// Decodes the message, rejecting filters that exceed `limits` before
+    /// reading their data.
+    ///
+    /// The `ConsensusDecodable` implementation uses the [default limits][1].
+    ///
+    /// [1]: ../struct.DecodeLimits.html
+    pub fn consensus_decode_with_limits<D: SimpleDecoder>(d: &mut D, limits: &DecodeLimits)
+        -> Result<CFilter, D::Error>
+    {
+        let filter_type: u8 = ConsensusDecodable::consensus_decode(d)?;
+        let block_hash = ConsensusDecodable::consensus_decode(d)?;
+
+        // The P and M parameters aren't serialized, they're implied by the
+        // filter type.
+        let (p, m) = match filter_type_params(filter_type) {
+            Some(params) => params,
+            None => return Err(d.error(format!("unknown filter type {}", filter_type))),
+        };
+
+        let bytes = decode_nbytes(d, limits)?;
+        let filter = match Filter::from_nbytes_with_limits(p, m, &bytes, limits) {
+            Ok(filter) => filter,
+            Err(e) => return Err(d.error(format!("invalid filter: {}", e))),
+        };
+
+        Ok(CFilter {
+            filter_type,
+            block_hash,
+            filter,
+        })
+    }
+}
+
+impl<S: SimpleEncoder> ConsensusEncodable<S> for CFilter {
+    fn consensus_encode(&self, s: &mut S) -> Result<(), S::Error> {
+        self.filter_type.consensus_encode(s)?;
+        self.block_hash.consensus_encode(s)?;
+        self.filter.to_nbytes().consensus_encode(s)
+    }
+}
+
+impl<D: SimpleDecoder> ConsensusDecodable<D> for CFilter {
+    fn consensus_decode(d: &mut D) -> Result<CFilter, D::Error> {
+        CFilter::consensus_decode_with_limits(d, &DecodeLimits::default())
+    }
+}
+
+/// `getcfheaders` message, requests the filter headers of a range of blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetCFHeaders {
+    /// Type of the requested filter headers.
+    pub filter_type: u8,
+    /// Height of the first block of the range.
+    pub start_height: u32,
+    /// Hash of the last block of the range.
+    pub stop_hash: Sha256dHash,
+}
+
+impl_message_encoding!(GetCFHeaders, "getcfheaders", filter_type, start_height, stop_hash);
+
+/// `cfheaders` message, the filter hashes of a range of blocks.
+///
+/// The filter headers of the range can be derived from the previous filter
+/// header and the filter hashes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CFHeaders {
+    /// Type of the filters.
+    pub filter_type: u8,
+    /// Hash of the last block of the range.
+    pub stop_hash: Sha256dHash,
+    /// Filter header of the block preceding the range.
+    pub previous_filter_header: FilterHeader,
+    /// Filter hashes of each block of the range.
+    pub filter_hashes: Vec<Sha256dHash>,
+}
+
+impl_message_encoding!(CFHeaders, "cfheaders", filter_type, stop_hash,
+                       previous_filter_header, filter_hashes);
+
+/// `getcfcheckpt` message, requests evenly spaced filter headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetCFCheckpt {
+    /// Type of the requested filter headers.
+    pub filter_type: u8,
+    /// Hash of the last block of the chain.
+    pub stop_hash: Sha256dHash,
+}
+
+impl_message_encoding!(GetCFCheckpt, "getcfcheckpt", filter_type, stop_hash);
+
+/// `cfcheckpt` message, the filter headers at every 1000th block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CFCheckpt {
+    /// Type of the filter headers.
+    pub filter_type: u8,
+    /// Hash of the last block of the chain.
+    pub stop_hash: Sha256dHash,
+    /// Filter headers at heights 1000, 2000...
+    pub filter_headers: Vec<FilterHeader>,
+}
+
+impl_message_encoding!(CFCheckpt, "cfcheckpt", filter_type, stop_hash, filter_headers);
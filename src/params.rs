@@ -0,0 +1,65 @@
+//! Checked filter parameters.
+
+use {checked_modulus, ParamsError, BASIC_FILTER_M, BASIC_FILTER_P};
+
+/// The Golomb-Rice coding parameter (P) and the inverse false positive rate
+/// (M) of a filter, checked to be in the supported range.
+///
+/// P can be at most 32 and M can't be zero. The modulus of the filter values,
+/// `N * M`, has to fit in a `u64` too, which depends on the number of items
+/// of each filter, see [`modulus`][1].
+///
+/// [1]: #method.modulus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FilterParams {
+    p: u8,
+    m: u64,
+}
+
+impl FilterParams {
+    // Constructors
+
+    /// Checks that `p` and `m` are in the supported range.
+    pub fn new(p: u8, m: u64) -> Result<FilterParams, ParamsError> {
+        if p > 32 {
+            return Err(ParamsError::PTooLarge);
+        }
+        if m == 0 {
+            return Err(ParamsError::ZeroM);
+        }
+
+        Ok(FilterParams { p, m })
+    }
+
+    /// Returns the parameters of the BIP158 basic filter.
+    pub fn basic() -> FilterParams {
+        FilterParams {
+            p: BASIC_FILTER_P,
+            m: BASIC_FILTER_M,
+        }
+    }
+
+    // Accessors
+
+    /// Returns the Golomb-Rice coding parameter (P).
+    pub fn p(&self) -> u8 {
+        self.p
+    }
+
+    /// Returns the inverse false positive rate (M).
+    pub fn m(&self) -> u64 {
+        self.m
+    }
+
+    /// Returns the modulus of the values of a filter of `n` items, that is,
+    /// `N * M`.
+    pub fn modulus(&self, n: u32) -> Result<u64, ParamsError> {
+        checked_modulus(n, self.m).ok_or(ParamsError::ModulusOverflow)
+    }
+
+    /// Returns the largest number of items a filter with these parameters
+    /// can hold.
+    pub fn max_n(&self) -> u32 {
+        (u64::MAX / self.m).min(u64::from(u32::MAX)) as u32
+    }
+}
@@ -0,0 +1,556 @@
+#[cfg(not(feature = "std"))]
+use alloc::collections::{BTreeMap, BTreeSet};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::collections::{BTreeMap, BTreeSet};
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[cfg(feature = "bitcoin")]
+use bitcoin::blockdata::script::Script;
+#[cfg(feature = "bitcoin")]
+use bitcoin::blockdata::transaction::TxOutRef;
+
+#[cfg(feature = "bitcoin")]
+use outpoint_entry;
+use {hash_value, siphash24, siphash24_batch, DecodeError, Filter, FilterKey, SipHash24};
+
+/// A set of items to match against many filters.
+///
+/// The items are hashed once for a given key, and as the reduction of the
+/// hashes to the range of a filter preserves their order, they're also sorted
+/// only once. Matching the same items against several filters built with the
+/// same key only has to decode each filter.
+///
+/// The raw items are kept, so the set can be hashed again when the key
+/// changes, e.g. with BIP158 filters whose key is derived from the block
+/// hash.
+#[derive(Debug, Clone)]
+pub struct QuerySet {
+    key: FilterKey,
+    items: Vec<Vec<u8>>,
+    hashes: Vec<u64>,
+}
+
+impl QuerySet {
+    // Constructors
+
+    /// Creates a new `QuerySet` from the given items, hashed with `key`.
+    pub fn new<I>(key: FilterKey, items: I) -> QuerySet
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut query = QuerySet {
+            key,
+            items: items.into_iter().map(|i| i.as_ref().to_vec()).collect(),
+            hashes: Vec::new(),
+        };
+        query.rehash();
+        query
+    }
+
+    // Accessors
+
+    /// Returns the key the items are hashed with.
+    pub fn key(&self) -> FilterKey {
+        self.key
+    }
+
+    /// Returns the number of items in the set.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if there are no items in the set.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns the items of the set.
+    pub fn items(&self) -> &[Vec<u8>] {
+        &self.items
+    }
+
+    // Modifiers
+
+    /// Hashes the items with a new key, if it's different from the current
+    /// one.
+    pub fn set_key(&mut self, key: FilterKey) {
+        if self.key != key {
+            self.key = key;
+            self.rehash();
+        }
+    }
+
+    /// Adds an item to the set.
+    pub fn insert(&mut self, item: &[u8]) {
+        let hash = siphash24(self.key, item);
+        let pos = match self.hashes.binary_search(&hash) {
+            Ok(pos) | Err(pos) => pos,
+        };
+
+        self.items.push(item.to_vec());
+        self.hashes.insert(pos, hash);
+    }
+
+    fn rehash(&mut self) {
+        self.hashes = self.hashes_for(self.key);
+    }
+
+    /// Returns the sorted hashes of the items with the given key.
+    fn hashes_for(&self, key: FilterKey) -> Vec<u64> {
+        let items = self.items.iter().map(|i| i.as_slice()).collect::<Vec<&[u8]>>();
+        let mut hashes = siphash24_batch(key, &items);
+        hashes.sort();
+        hashes
+    }
+
+    /// Checks the set against a filter built with `key`, hashing the items
+    /// again only if it isn't the key of the set.
+    fn matches(&self, filter: &Filter, key: FilterKey) -> Result<bool, DecodeError> {
+        if key == self.key {
+            filter.match_hashes(&self.hashes)
+        } else {
+            filter.match_hashes(&self.hashes_for(key))
+        }
+    }
+}
+
+/// The number of keys whose hashes are cached by a [`WatchList`][1] unless
+/// [set otherwise][2].
+///
+/// [1]: struct.WatchList.html
+/// [2]: struct.WatchList.html#method.set_cache_capacity
+pub const DEFAULT_CACHED_KEYS: usize = 64;
+
+/// The hashes of some items for the last used keys.
+#[derive(Debug, Clone)]
+struct KeyCache<T> {
+    capacity: usize,
+    uses: u64,
+    entries: BTreeMap<FilterKey, (u64, T)>,
+}
+
+impl<T> KeyCache<T> {
+    fn new(capacity: usize) -> KeyCache<T> {
+        KeyCache {
+            capacity,
+            uses: 0,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn get(&mut self, key: FilterKey) -> Option<&T> {
+        self.uses += 1;
+        let uses = self.uses;
+        self.entries.get_mut(&key).map(|entry| {
+            entry.0 = uses;
+            &entry.1
+        })
+    }
+
+    fn values_mut(&mut self) -> impl Iterator<Item = (&FilterKey, &mut T)> {
+        self.entries.iter_mut().map(|(key, entry)| (key, &mut entry.1))
+    }
+
+    fn insert(&mut self, key: FilterKey, value: T) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.uses += 1;
+        self.entries.insert(key, (self.uses, value));
+        self.evict();
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Drops the least recently used keys above the capacity.
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            let oldest = self.entries.iter()
+                .min_by_key(|&(_, &(uses, _))| uses)
+                .map(|(key, _)| *key)
+                .expect("the cache isn't empty");
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+/// The items watched by a wallet, matched against filters built with many
+/// keys.
+///
+/// Unlike a [`QuerySet`][1], the hashes of the items are cached for the last
+/// [`DEFAULT_CACHED_KEYS`][2] keys they're matched with, so matching the same
+/// filters again, e.g. after the wallet derives new addresses, doesn't hash
+/// every item again. Items can be added or removed at any time, only the
+/// hashes of those items are updated in the cache.
+///
+/// [1]: struct.QuerySet.html
+/// [2]: constant.DEFAULT_CACHED_KEYS.html
+#[derive(Debug, Clone)]
+pub struct WatchList {
+    items: BTreeSet<Vec<u8>>,
+    hashes: KeyCache<Vec<u64>>,
+}
+
+impl WatchList {
+    // Constructors
+
+    /// Creates an empty `WatchList`.
+    pub fn new() -> WatchList {
+        WatchList {
+            items: BTreeSet::new(),
+            hashes: KeyCache::new(DEFAULT_CACHED_KEYS),
+        }
+    }
+
+    // Accessors
+
+    /// Returns the number of watched items.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if no item is watched.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns `true` if the item is watched.
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.items.contains(item)
+    }
+
+    /// Returns an iterator over the watched items.
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        self.items.iter().map(|i| i.as_slice())
+    }
+
+    /// Returns the number of keys whose hashes are cached.
+    pub fn cached_keys(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Returns the largest number of keys whose hashes are cached.
+    pub fn cache_capacity(&self) -> usize {
+        self.hashes.capacity
+    }
+
+    // Modifiers
+
+    /// Sets the largest number of keys whose hashes are cached, dropping the
+    /// least recently used ones above it. With zero, nothing is cached.
+    ///
+    /// Filters built with a key per block, like BIP158 ones, are better
+    /// matched without caching unless they're matched again.
+    pub fn set_cache_capacity(&mut self, keys: usize) -> &mut WatchList {
+        self.hashes.set_capacity(keys);
+        self
+    }
+
+    /// Watches an item, returning `false` if it was already watched.
+    pub fn insert(&mut self, item: &[u8]) -> bool {
+        if !self.items.insert(item.to_vec()) {
+            return false;
+        }
+
+        for (key, hashes) in self.hashes.values_mut() {
+            let hash = siphash24(*key, item);
+            let pos = match hashes.binary_search(&hash) {
+                Ok(pos) | Err(pos) => pos,
+            };
+            hashes.insert(pos, hash);
+        }
+        true
+    }
+
+    /// Stops watching an item, returning `false` if it wasn't watched.
+    pub fn remove(&mut self, item: &[u8]) -> bool {
+        if !self.items.remove(item) {
+            return false;
+        }
+
+        for (key, hashes) in self.hashes.values_mut() {
+            if let Ok(pos) = hashes.binary_search(&siphash24(*key, item)) {
+                hashes.remove(pos);
+            }
+        }
+        true
+    }
+
+    /// Watches an output script.
+    #[cfg(feature = "bitcoin")]
+    pub fn insert_script(&mut self, script: &Script) -> bool {
+        self.insert(&script.data())
+    }
+
+    /// Stops watching an output script.
+    #[cfg(feature = "bitcoin")]
+    pub fn remove_script(&mut self, script: &Script) -> bool {
+        self.remove(&script.data())
+    }
+
+    /// Watches an outpoint, as included in the filters of the original
+    /// BIP158 draft.
+    #[cfg(feature = "bitcoin")]
+    pub fn insert_outpoint(&mut self, outpoint: &TxOutRef) -> bool {
+        self.insert(&outpoint_entry(outpoint))
+    }
+
+    /// Stops watching an outpoint.
+    #[cfg(feature = "bitcoin")]
+    pub fn remove_outpoint(&mut self, outpoint: &TxOutRef) -> bool {
+        self.remove(&outpoint_entry(outpoint))
+    }
+
+    /// Drops the cached hashes of every key.
+    pub fn clear_cache(&mut self) {
+        self.hashes.clear();
+    }
+
+    // Matching
+
+    /// Checks whether any watched item is likely (within collision
+    /// probability) to be a member of a filter built with `key`.
+    ///
+    /// The hashes of the items for `key` are cached. An error is returned if
+    /// the filter data is found to be invalid before a match.
+    pub fn matches<D>(&mut self, filter: &Filter<SipHash24, D>, key: FilterKey) -> Result<bool, DecodeError>
+    where
+        D: AsRef<[u8]>,
+    {
+        if let Some(hashes) = self.hashes.get(key) {
+            return filter.match_hashes(hashes);
+        }
+
+        let hashes = sorted_hashes(key, &self.items);
+        let matches = filter.match_hashes(&hashes);
+        self.hashes.insert(key, hashes);
+        matches
+    }
+}
+
+impl Default for WatchList {
+    fn default() -> WatchList {
+        WatchList::new()
+    }
+}
+
+/// Hashes the items with `key`, returning the hashes sorted.
+fn sorted_hashes<'a, I>(key: FilterKey, items: I) -> Vec<u64>
+where
+    I: IntoIterator<Item = &'a Vec<u8>>,
+{
+    let items = items.into_iter().map(|i| i.as_slice()).collect::<Vec<&[u8]>>();
+    let mut hashes = siphash24_batch(key, &items);
+    hashes.sort();
+    hashes
+}
+
+/// Number of locks the cache of a [`MatchEngine`][1] is split into.
+///
+/// [1]: struct.MatchEngine.html
+#[cfg(feature = "std")]
+const SHARDS: usize = 16;
+
+/// A [`WatchList`][1] shared by many threads, e.g. workers matching the
+/// filters of different blocks against the same wallet.
+///
+/// The sorted hashes of the items are cached for the last used keys, as in a
+/// `WatchList` and up to about its [cache capacity][2], so the threads
+/// matching filters built with the same key share the hashing work. The
+/// cache is split in several locks by key, which are only held to look up
+/// and store the hashes, not while hashing or matching.
+///
+/// [1]: struct.WatchList.html
+/// [2]: struct.WatchList.html#method.set_cache_capacity
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct MatchEngine {
+    items: Vec<Vec<u8>>,
+    shards: Vec<Mutex<KeyCache<Arc<Vec<u64>>>>>,
+}
+
+#[cfg(feature = "std")]
+impl MatchEngine {
+    // Constructors
+
+    /// Creates a `MatchEngine` for the items of `watch_list`, keeping its
+    /// cached hashes.
+    pub fn new(watch_list: WatchList) -> MatchEngine {
+        // Keys are spread evenly over the shards, each one caches its share
+        // of the capacity.
+        let capacity = watch_list.cache_capacity().div_ceil(SHARDS);
+        let engine = MatchEngine {
+            items: watch_list.items.into_iter().collect(),
+            shards: (0..SHARDS).map(|_| Mutex::new(KeyCache::new(capacity))).collect(),
+        };
+
+        for (key, (_, hashes)) in watch_list.hashes.entries {
+            engine.shard(key).insert(key, Arc::new(hashes));
+        }
+        engine
+    }
+
+    // Accessors
+
+    /// Returns the number of watched items.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if no item is watched.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns the number of keys whose hashes are cached.
+    pub fn cached_keys(&self) -> usize {
+        (0..SHARDS).map(|i| self.lock(i).len()).sum()
+    }
+
+    /// Drops the cached hashes of every key.
+    pub fn clear_cache(&self) {
+        for i in 0..SHARDS {
+            self.lock(i).clear();
+        }
+    }
+
+    // Matching
+
+    /// Checks whether any watched item is likely (within collision
+    /// probability) to be a member of a filter built with `key`.
+    ///
+    /// The hashes of the items for `key` are cached. An error is returned if
+    /// the filter data is found to be invalid before a match.
+    pub fn matches<D>(&self, filter: &Filter<SipHash24, D>, key: FilterKey) -> Result<bool, DecodeError>
+    where
+        D: AsRef<[u8]>,
+    {
+        let cached = self.shard(key).get(key).cloned();
+        let hashes = match cached {
+            Some(hashes) => hashes,
+            None => {
+                // Other threads may hash the items for the same key in the
+                // meantime, whichever finishes last replaces the others.
+                let hashes = Arc::new(sorted_hashes(key, &self.items));
+                self.shard(key).insert(key, hashes.clone());
+                hashes
+            }
+        };
+
+        filter.match_hashes(&hashes)
+    }
+
+    fn shard(&self, key: FilterKey) -> MutexGuard<'_, KeyCache<Arc<Vec<u64>>>> {
+        self.lock(key.keys().0 as usize % SHARDS)
+    }
+
+    fn lock(&self, i: usize) -> MutexGuard<'_, KeyCache<Arc<Vec<u64>>>> {
+        // The maps are always left in a consistent state, even if a thread
+        // panicked while holding the lock.
+        self.shards[i].lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<WatchList> for MatchEngine {
+    fn from(watch_list: WatchList) -> MatchEngine {
+        MatchEngine::new(watch_list)
+    }
+}
+
+/// Returns the heights of the blocks whose filter matches any item of the
+/// query set.
+///
+/// Each filter is matched with its own key, e.g. the one derived from the
+/// hash of its block. The heights are returned in the order of `filters`.
+/// An error is returned if any filter data is found to be invalid.
+#[cfg(not(feature = "rayon"))]
+pub fn match_blocks(
+    filters: &[(u32, Filter, FilterKey)],
+    query: &QuerySet,
+) -> Result<Vec<u32>, DecodeError> {
+    let mut heights = Vec::new();
+    for &(height, ref filter, key) in filters {
+        if query.matches(filter, key)? {
+            heights.push(height);
+        }
+    }
+    Ok(heights)
+}
+
+/// Returns the heights of the blocks whose filter matches any item of the
+/// query set, matching the filters over the rayon thread pool.
+///
+/// Each filter is matched with its own key, e.g. the one derived from the
+/// hash of its block. The heights are returned in the order of `filters`.
+/// An error is returned if any filter data is found to be invalid.
+#[cfg(feature = "rayon")]
+pub fn match_blocks(
+    filters: &[(u32, Filter, FilterKey)],
+    query: &QuerySet,
+) -> Result<Vec<u32>, DecodeError> {
+    use rayon::prelude::*;
+
+    let matches = filters.par_iter()
+        .map(|&(height, ref filter, key)| {
+            query.matches(filter, key).map(|m| if m { Some(height) } else { None })
+        })
+        .collect::<Result<Vec<Option<u32>>, DecodeError>>()?;
+
+    Ok(matches.into_iter().flatten().collect())
+}
+
+impl<D: AsRef<[u8]>> Filter<SipHash24, D> {
+    /// Checks whether any item of the query set is likely (within collision
+    /// probability) to be a member of the set represented by the filter.
+    ///
+    /// The query set must have been hashed with the key of the filter. An
+    /// error is returned if the filter data is found to be invalid before a
+    /// match.
+    pub fn match_query_set(&self, query: &QuerySet) -> Result<bool, DecodeError> {
+        self.match_hashes(&query.hashes)
+    }
+
+    fn match_hashes(&self, hashes: &[u64]) -> Result<bool, DecodeError> {
+        let mut queries = hashes.iter()
+            .map(|h| hash_value(*h, self.modulus_nm))
+            .peekable();
+
+        for value in self.values() {
+            let value = value?;
+
+            // Skip the queries smaller than the current filter value, they
+            // can't be in the filter.
+            while let Some(&q) = queries.peek() {
+                if q >= value {
+                    break;
+                }
+                queries.next();
+            }
+
+            match queries.peek() {
+                Some(&q) if q == value => return Ok(true),
+                Some(_) => {}
+                None => return Ok(false),
+            }
+        }
+
+        Ok(false)
+    }
+}
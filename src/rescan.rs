@@ -0,0 +1,278 @@
+//! Rescanning the chain for wallet activity with block filters.
+//!
+//! A [`Rescan`][1] walks the filters of a range of blocks, given by a
+//! [`FilterSource`][2], and returns the heights of the blocks that may
+//! contain the watched scripts. Those blocks can then be downloaded and
+//! scanned for the actual transactions.
+//!
+//! [1]: struct.Rescan.html
+//! [2]: trait.FilterSource.html
+
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+
+#[cfg(feature = "async")]
+use futures::future::{self, Either, Loop};
+#[cfg(feature = "async")]
+use futures::Future;
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::TxOutRef;
+use bitcoin::util::hash::Sha256dHash;
+
+#[cfg(feature = "async")]
+use nonblocking::AsyncFilterSource;
+use metrics::SharedMetrics;
+use {outpoint_entry, DecodeError, Filter, FilterKey, Metrics, QuerySet};
+
+/// A source of block filters, e.g. a [`FilterStore`][1] or a P2P peer.
+///
+/// Sources retrieving filters asynchronously implement
+/// [`AsyncFilterSource`][2] instead.
+///
+/// [1]: ../store/struct.FilterStore.html
+/// [2]: ../nonblocking/trait.AsyncFilterSource.html
+pub trait FilterSource {
+    /// The error returned when a filter can't be retrieved.
+    type Error;
+
+    /// Returns the hash of the block at `height` along with its filter, or
+    /// `None` if the source doesn't have it.
+    fn get_filter(&mut self, height: u32) -> Result<Option<(Sha256dHash, Filter)>, Self::Error>;
+}
+
+/// Errors that can occur during a rescan.
+#[derive(Debug)]
+pub enum RescanError<E> {
+    /// The filter source failed.
+    Source(E),
+    /// The filter source doesn't have the filter of the block at the given
+    /// height.
+    MissingFilter(u32),
+    /// The filter of the block at the given height is invalid.
+    Decode(u32, DecodeError),
+}
+
+impl<E: fmt::Display> fmt::Display for RescanError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RescanError::Source(ref e) => write!(f, "filter source error: {}", e),
+            RescanError::MissingFilter(height) => write!(f, "missing filter at height {}", height),
+            RescanError::Decode(height, ref e) => write!(f, "invalid filter at height {}: {}", height, e),
+        }
+    }
+}
+
+impl<E: error::Error> error::Error for RescanError<E> {
+    fn description(&self) -> &str {
+        match *self {
+            RescanError::Source(_) => "filter source error",
+            RescanError::MissingFilter(_) => "missing filter",
+            RescanError::Decode(_, _) => "invalid filter",
+        }
+    }
+}
+
+/// A rescan of a range of blocks.
+///
+/// The filter of each block is matched against the watched items with the
+/// key derived from the block hash, as in BIP158. Items can be added while
+/// the rescan is in progress, e.g. when a match reveals that more addresses
+/// of a wallet have to be derived to keep the gap limit, and the following
+/// blocks are matched against them too. The blocks already scanned aren't
+/// scanned again.
+#[derive(Debug, Clone)]
+pub struct Rescan {
+    query: QuerySet,
+    next_height: u32,
+    stop_height: u32,
+    finished: bool,
+    metrics: SharedMetrics,
+}
+
+impl Rescan {
+    // Constructors
+
+    /// Creates a rescan of the blocks from `start_height` to `stop_height`,
+    /// both included, that doesn't watch any item yet.
+    pub fn new(start_height: u32, stop_height: u32) -> Rescan {
+        Rescan {
+            query: QuerySet::new(FilterKey::default(), Vec::<Vec<u8>>::new()),
+            next_height: start_height,
+            stop_height,
+            finished: start_height > stop_height,
+            metrics: SharedMetrics::default(),
+        }
+    }
+
+    // Accessors
+
+    /// Returns the height of the next block to be scanned, or of the last
+    /// block scanned once the rescan is finished.
+    pub fn next_height(&self) -> u32 {
+        self.next_height
+    }
+
+    /// Returns the height of the last block to be scanned.
+    pub fn stop_height(&self) -> u32 {
+        self.stop_height
+    }
+
+    /// Returns `true` if every block has been scanned.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Returns the watched items.
+    pub fn items(&self) -> &[Vec<u8>] {
+        self.query.items()
+    }
+
+    // Modifiers
+
+    /// Watches an item, it's matched against the blocks not scanned yet.
+    pub fn add_entry(&mut self, entry: &[u8]) -> &mut Rescan {
+        self.query.insert(entry);
+        self
+    }
+
+    /// Watches an output script.
+    pub fn add_script(&mut self, script: &Script) -> &mut Rescan {
+        self.add_entry(&script.data())
+    }
+
+    /// Watches an outpoint, as included in the filters of the original
+    /// BIP158 draft.
+    pub fn add_outpoint(&mut self, outpoint: &TxOutRef) -> &mut Rescan {
+        self.add_entry(&outpoint_entry(outpoint))
+    }
+
+    /// Sets the [metrics][1] the matched filters are reported to.
+    ///
+    /// [1]: ../trait.Metrics.html
+    pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) -> &mut Rescan {
+        self.metrics = SharedMetrics(metrics);
+        self
+    }
+
+    /// Reports that a matching block doesn't contain any watched item, once
+    /// it has been downloaded and scanned.
+    pub fn report_false_positive(&self) {
+        self.metrics.0.false_positive_confirmed();
+    }
+
+    // Scanning
+
+    /// Scans blocks until one of them matches, returning its height, or
+    /// `None` once every block has been scanned.
+    ///
+    /// While nothing is watched no block is scanned and `None` is returned,
+    /// the rescan goes on once items are added.
+    pub fn next_match<S>(&mut self, source: &mut S) -> Result<Option<u32>, RescanError<S::Error>>
+    where
+        S: FilterSource,
+    {
+        if self.query.is_empty() {
+            return Ok(None);
+        }
+
+        while !self.finished {
+            let height = self.next_height;
+            let (block_hash, filter) = source.get_filter(height)
+                .map_err(RescanError::Source)?
+                .ok_or(RescanError::MissingFilter(height))?;
+
+            if self.scan_filter(&block_hash, &filter)? {
+                return Ok(Some(height));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Scans the remaining blocks, returning the heights of the ones that
+    /// match.
+    pub fn run<S: FilterSource>(&mut self, source: &mut S) -> Result<Vec<u32>, RescanError<S::Error>> {
+        let mut heights = Vec::new();
+        while let Some(height) = self.next_match(source)? {
+            heights.push(height);
+        }
+        Ok(heights)
+    }
+
+    /// Like [`next_match`][1], with a filter source whose filters are
+    /// retrieved asynchronously.
+    ///
+    /// The rescan and the source are given back along with the result.
+    ///
+    /// [1]: #method.next_match
+    #[cfg(feature = "async")]
+    pub fn next_match_async<S>(self, source: S)
+        -> impl Future<Item = (Rescan, S, Option<u32>), Error = RescanError<S::Error>>
+    where
+        S: AsyncFilterSource,
+    {
+        future::loop_fn((self, source), |(mut rescan, mut source)| {
+            if rescan.query.is_empty() || rescan.finished {
+                return Either::A(future::ok(Loop::Break((rescan, source, None))));
+            }
+
+            let height = rescan.next_height;
+            Either::B(source.get_filter(height).then(move |result| {
+                let (block_hash, filter) = result
+                    .map_err(RescanError::Source)?
+                    .ok_or(RescanError::MissingFilter(height))?;
+
+                if rescan.scan_filter(&block_hash, &filter)? {
+                    Ok(Loop::Break((rescan, source, Some(height))))
+                } else {
+                    Ok(Loop::Continue((rescan, source)))
+                }
+            }))
+        })
+    }
+
+    /// Like [`run`][1], with a filter source whose filters are retrieved
+    /// asynchronously.
+    ///
+    /// The rescan and the source are given back along with the result.
+    ///
+    /// [1]: #method.run
+    #[cfg(feature = "async")]
+    pub fn run_async<S>(self, source: S)
+        -> impl Future<Item = (Rescan, S, Vec<u32>), Error = RescanError<S::Error>>
+    where
+        S: AsyncFilterSource,
+    {
+        future::loop_fn((self, source, Vec::new()), |(rescan, source, mut heights)| {
+            rescan.next_match_async(source).map(|(rescan, source, height)| {
+                match height {
+                    Some(height) => {
+                        heights.push(height);
+                        Loop::Continue((rescan, source, heights))
+                    }
+                    None => Loop::Break((rescan, source, heights)),
+                }
+            })
+        })
+    }
+
+    /// Matches the filter of the next block, moving to the following one.
+    fn scan_filter<E>(&mut self, block_hash: &Sha256dHash, filter: &Filter) -> Result<bool, RescanError<E>> {
+        let height = self.next_height;
+
+        self.query.set_key(FilterKey::from(block_hash));
+        let matched = filter.match_query_set(&self.query)
+            .map_err(|e| RescanError::Decode(height, e))?;
+        self.metrics.0.matches_run(matched);
+
+        if height == self.stop_height {
+            self.finished = true;
+        } else {
+            self.next_height = height + 1;
+        }
+
+        Ok(matched)
+    }
+}
@@ -0,0 +1,152 @@
+//! Filters over a sliding window of recent blocks.
+
+#[cfg(not(feature = "std"))]
+use alloc::collections::{BTreeMap, VecDeque};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::collections::{BTreeMap, VecDeque};
+
+use {siphash24, Filter, FilterKey, FilterParams};
+
+/// A filter of the entries of the last blocks, e.g. the last 144 blocks to
+/// answer whether anything touched a set of scripts in the last day.
+///
+/// The filters of different blocks are built with different keys, so they
+/// can't be merged. Instead, the entries of each block are hashed with the
+/// key of the rolling filter and the hashes are cached per block, so the
+/// filter is rebuilt from the hashes of the window when blocks enter or
+/// leave it, without the entries.
+#[derive(Debug, Clone)]
+pub struct RollingFilter {
+    params: FilterParams,
+    key: FilterKey,
+    window: usize,
+    blocks: VecDeque<Vec<u64>>,
+    counts: BTreeMap<u64, u32>,
+    filter: Filter,
+}
+
+impl RollingFilter {
+    // Constructors
+
+    /// Creates an empty filter over the last `window` blocks.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `window` is zero.
+    pub fn new(params: FilterParams, key: FilterKey, window: usize) -> RollingFilter {
+        assert!(window > 0, "the window can't be empty");
+
+        RollingFilter {
+            params,
+            key,
+            window,
+            blocks: VecDeque::with_capacity(window),
+            counts: BTreeMap::new(),
+            filter: Filter::from_hashes(params.p(), params.m(), Vec::new()),
+        }
+    }
+
+    // Accessors
+
+    /// Returns the key the entries are hashed with.
+    pub fn key(&self) -> FilterKey {
+        self.key
+    }
+
+    /// Returns the number of blocks of the window.
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Returns the number of blocks in the window.
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Returns `true` if there are no blocks in the window.
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Returns the filter of the entries of every block in the window.
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    /// Returns `true` if any of `queries` is likely to be an entry of a
+    /// block in the window.
+    pub fn match_any<I>(&self, queries: I) -> bool
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        self.filter.is_member_any(self.key, queries)
+            .expect("the filter is built by the rolling filter")
+    }
+
+    // Modifiers
+
+    /// Adds the entries of a new block, dropping the oldest block if the
+    /// window is full.
+    pub fn push_block<I>(&mut self, entries: I)
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        if self.blocks.len() == self.window {
+            self.remove_oldest();
+        }
+
+        let mut hashes = entries.into_iter()
+            .map(|entry| siphash24(self.key, entry.as_ref()))
+            .collect::<Vec<u64>>();
+        hashes.sort_unstable();
+        hashes.dedup();
+        for hash in &hashes {
+            *self.counts.entry(*hash).or_insert(0) += 1;
+        }
+        self.blocks.push_back(hashes);
+
+        self.rebuild();
+    }
+
+    /// Drops the oldest block of the window, returning `false` if the
+    /// window is empty.
+    pub fn pop_oldest(&mut self) -> bool {
+        if self.blocks.is_empty() {
+            return false;
+        }
+
+        self.remove_oldest();
+        self.rebuild();
+        true
+    }
+
+    /// Drops every block of the window.
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.counts.clear();
+        self.rebuild();
+    }
+
+    fn remove_oldest(&mut self) {
+        let hashes = self.blocks.pop_front().expect("the window isn't empty");
+        for hash in hashes {
+            let remove = {
+                let count = self.counts.get_mut(&hash).expect("the hashes of the window are counted");
+                *count -= 1;
+                *count == 0
+            };
+            if remove {
+                self.counts.remove(&hash);
+            }
+        }
+    }
+
+    fn rebuild(&mut self) {
+        let hashes = self.counts.keys().cloned().collect();
+        self.filter = Filter::from_hashes(self.params.p(), self.params.m(), hashes);
+    }
+}
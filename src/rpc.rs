@@ -0,0 +1,69 @@
+//! JSON types of the Bitcoin Core RPC.
+//!
+//! These match the results of the RPC calls dealing with block filters, so
+//! filters fetched from a node can be cross-verified against locally built
+//! ones.
+
+use serde::{Deserialize, Serialize};
+
+use {EncodeError, Filter, FilterHeader, ParseFilterError};
+
+/// The result of the `getblockfilter` RPC call.
+///
+/// The filter is the hex of its [N-prefixed serialization][1], and the
+/// header is the hex of the filter header in reverse byte order, as Bitcoin
+/// Core displays hashes. Unknown fields are skipped, as newer versions of
+/// Bitcoin Core may add some.
+///
+/// [1]: ../struct.Filter.html#method.to_nbytes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockFilterRpc {
+    /// The hex of the filter.
+    pub filter: String,
+    /// The hex of the filter header.
+    pub header: String,
+}
+
+impl BlockFilterRpc {
+    // Constructors
+
+    /// Creates the `getblockfilter` result of a filter and its header.
+    ///
+    /// An error is returned if the filter can't be [serialized][1].
+    ///
+    /// [1]: ../struct.Filter.html#method.try_to_nbytes
+    pub fn new(filter: &Filter, header: &FilterHeader) -> Result<BlockFilterRpc, EncodeError> {
+        if !filter.has_nm_modulus() {
+            return Err(EncodeError::ModulusMismatch);
+        }
+
+        Ok(BlockFilterRpc {
+            filter: format!("{:x}", filter),
+            header: header.to_string(),
+        })
+    }
+
+    // Accessors
+
+    /// Parses the filter, which is assumed to be a basic filter.
+    pub fn to_filter(&self) -> Result<Filter, ParseFilterError> {
+        self.filter.parse()
+    }
+
+    /// Parses the filter header.
+    pub fn to_header(&self) -> Result<FilterHeader, ParseFilterError> {
+        self.header.parse()
+    }
+
+    /// Returns `true` if the result matches a filter and its header.
+    ///
+    /// Hex strings are compared case-insensitively. A filter that can't be
+    /// [serialized][1] never matches.
+    ///
+    /// [1]: ../struct.Filter.html#method.try_to_nbytes
+    pub fn matches(&self, filter: &Filter, header: &FilterHeader) -> bool {
+        filter.has_nm_modulus()
+            && self.filter.eq_ignore_ascii_case(&format!("{:x}", filter))
+            && self.header.eq_ignore_ascii_case(&header.to_string())
+    }
+}
@@ -0,0 +1,174 @@
+//! Reverse index of the blocks containing each script.
+//!
+//! The values of a filter are keyed by its block hash, so they can't be
+//! mapped back to the scripts they were built from. Instead, the
+//! [`ScriptIndex`][1] is fed with the entries of each block as its filter is
+//! built, e.g. the ones returned by `BasicFilter::extract_entries`, and maps
+//! a short hash of each entry to the heights of the blocks that contain it.
+//! Looking up a script then returns the few blocks that may contain it,
+//! without matching the filters of every block.
+//!
+//! The index is stored in a flat file, as the [`FilterStore`][2], each
+//! record being:
+//!
+//! ```text
+//! height (u32) | count (u32) | hashes (count * u32)
+//! ```
+//!
+//! with the integers in little-endian. A record at a height which isn't
+//! above the previous one rolls the index back to that height, which handles
+//! block reorganizations.
+//!
+//! [1]: struct.ScriptIndex.html
+//! [2]: ../store/struct.FilterStore.html
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use {siphash24, FilterKey};
+
+/// A reverse index from scripts to the heights of the blocks containing
+/// them.
+///
+/// Scripts are indexed by a 32-bit hash, so a lookup may return blocks that
+/// don't contain the script, which should be checked with their filter.
+#[derive(Debug)]
+pub struct ScriptIndex {
+    file: File,
+    len: u64,
+    tip_height: Option<u32>,
+    heights: HashMap<u32, Vec<u32>>,
+}
+
+impl ScriptIndex {
+    // Constructors
+
+    /// Opens the index at `path`, creating it if it doesn't exist.
+    ///
+    /// A truncated record at the end of the file, such as the one left by a
+    /// crash while writing, is discarded.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<ScriptIndex> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let mut index = ScriptIndex {
+            file,
+            len: 0,
+            tip_height: None,
+            heights: HashMap::new(),
+        };
+
+        let mut data = Vec::new();
+        index.file.read_to_end(&mut data)?;
+
+        while let Some((height, hashes, len)) = read_record(&data[index.len as usize..]) {
+            index.insert(height, hashes);
+            index.len += len as u64;
+        }
+
+        if index.len < data.len() as u64 {
+            index.file.set_len(index.len)?;
+        }
+
+        Ok(index)
+    }
+
+    // Accessors
+
+    /// Height of the last block indexed.
+    pub fn tip_height(&self) -> Option<u32> {
+        self.tip_height
+    }
+
+    /// Returns the heights of the blocks that may contain `script`, in
+    /// ascending order.
+    pub fn heights(&self, script: &[u8]) -> &[u32] {
+        self.heights.get(&script_hash(script))
+            .map(|heights| heights.as_slice())
+            .unwrap_or(&[])
+    }
+
+    // Modifiers
+
+    /// Indexes the entries of the block at `height`.
+    ///
+    /// If `height` isn't above the [tip height][1] the blocks from `height`
+    /// on are removed from the index first.
+    ///
+    /// [1]: #method.tip_height
+    pub fn add_block<I>(&mut self, height: u32, entries: I) -> io::Result<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut hashes = entries.into_iter()
+            .map(|entry| script_hash(entry.as_ref()))
+            .collect::<Vec<u32>>();
+        hashes.sort();
+        hashes.dedup();
+
+        let mut record = Vec::with_capacity(8 + hashes.len() * 4);
+        record.extend_from_slice(&height.to_le_bytes());
+        record.extend_from_slice(&(hashes.len() as u32).to_le_bytes());
+        for hash in &hashes {
+            record.extend_from_slice(&hash.to_le_bytes());
+        }
+
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(&record)?;
+        self.file.flush()?;
+
+        self.len += record.len() as u64;
+        self.insert(height, hashes);
+        Ok(())
+    }
+
+    fn insert(&mut self, height: u32, hashes: Vec<u32>) {
+        if self.tip_height.is_some_and(|tip| height <= tip) {
+            self.heights.retain(|_, heights| {
+                heights.retain(|h| *h < height);
+                !heights.is_empty()
+            });
+        }
+
+        for hash in hashes {
+            self.heights.entry(hash).or_default().push(height);
+        }
+        self.tip_height = Some(height);
+    }
+}
+
+/// Hashes a script to its key in the index.
+fn script_hash(script: &[u8]) -> u32 {
+    (siphash24(FilterKey::default(), script) >> 32) as u32
+}
+
+/// Reads a record, returning its height, hashes and length, or `None` if
+/// it's truncated.
+fn read_record(data: &[u8]) -> Option<(u32, Vec<u32>, usize)> {
+    if data.len() < 8 {
+        return None;
+    }
+
+    let height = read_u32(&data[0..4]);
+    let count = read_u32(&data[4..8]) as usize;
+    let len = count.checked_mul(4)?.checked_add(8)?;
+    if data.len() < len {
+        return None;
+    }
+
+    let hashes = data[8..len].chunks(4).map(read_u32).collect();
+    Some((height, hashes, len))
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(buf);
+    u32::from_le_bytes(bytes)
+}
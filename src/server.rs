@@ -0,0 +1,275 @@
+//! BIP157 filter serving for full nodes.
+//!
+//! A [`FilterServer`][1] answers the `getcfilters`, `getcfheaders` and
+//! `getcfcheckpt` requests of light clients from a [`FilterStore`][2],
+//! enforcing the batch limits of BIP157 and keeping the chain of filter
+//! headers in memory. Node implementations only have to feed it the filter
+//! of each connected block and relay the messages.
+//!
+//! [1]: struct.FilterServer.html
+//! [2]: ../store/struct.FilterStore.html
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use bitcoin::util::hash::Sha256dHash;
+
+use metrics::SharedMetrics;
+use p2p::{CFCheckpt, CFHeaders, CFilter, GetCFCheckpt, GetCFHeaders, GetCFilters, MAX_CFHEADERS, MAX_CFILTERS};
+use store::FilterStore;
+use {Filter, FilterHeader, FilterHeaderChain, Metrics, BASIC_FILTER_TYPE, CHECKPOINT_INTERVAL};
+
+/// The best chain of a node, as known by its block index.
+pub trait BlockSource {
+    /// Returns the hash of the block at `height` in the best chain.
+    fn block_hash(&self, height: u32) -> Option<Sha256dHash>;
+
+    /// Returns the height of a block if it's in the best chain.
+    fn height(&self, block_hash: &Sha256dHash) -> Option<u32>;
+}
+
+impl BlockSource for Vec<Sha256dHash> {
+    fn block_hash(&self, height: u32) -> Option<Sha256dHash> {
+        self.get(height as usize).cloned()
+    }
+
+    fn height(&self, block_hash: &Sha256dHash) -> Option<u32> {
+        self.iter().rposition(|hash| hash == block_hash).map(|height| height as u32)
+    }
+}
+
+/// Errors that can occur while answering a request.
+///
+/// Except for I/O errors, they're caused by invalid requests, which BIP157
+/// says nodes should disconnect the peer for.
+#[derive(Debug)]
+pub enum ServerError {
+    /// Reading the store failed.
+    Io(io::Error),
+    /// The filter type isn't served.
+    UnknownFilterType(u8),
+    /// The stop hash isn't in the best chain.
+    UnknownBlock(Sha256dHash),
+    /// The start height is above the height of the stop hash.
+    InvalidRange(u32, u32),
+    /// The range is larger than the batch limit of the request.
+    TooManyBlocks(u32),
+    /// The filter of the block at the given height isn't stored yet.
+    MissingFilter(u32),
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ServerError::Io(ref e) => write!(f, "I/O error: {}", e),
+            ServerError::UnknownFilterType(filter_type) => write!(f, "unknown filter type {}", filter_type),
+            ServerError::UnknownBlock(ref hash) => write!(f, "unknown block {}", hash),
+            ServerError::InvalidRange(start, stop) => write!(f, "invalid range {}..={}", start, stop),
+            ServerError::TooManyBlocks(count) => write!(f, "too many blocks requested ({})", count),
+            ServerError::MissingFilter(height) => write!(f, "missing filter at height {}", height),
+        }
+    }
+}
+
+impl error::Error for ServerError {
+    fn description(&self) -> &str {
+        match *self {
+            ServerError::Io(_) => "I/O error",
+            ServerError::UnknownFilterType(_) => "unknown filter type",
+            ServerError::UnknownBlock(_) => "unknown block",
+            ServerError::InvalidRange(_, _) => "invalid range",
+            ServerError::TooManyBlocks(_) => "too many blocks requested",
+            ServerError::MissingFilter(_) => "missing filter",
+        }
+    }
+}
+
+impl From<io::Error> for ServerError {
+    fn from(e: io::Error) -> ServerError {
+        ServerError::Io(e)
+    }
+}
+
+/// Serves the basic filters of a [`FilterStore`][1] to BIP157 clients.
+///
+/// Only the filters of the blocks of the best chain, from the genesis block
+/// up to the first one missing in the store, are served.
+///
+/// [1]: ../store/struct.FilterStore.html
+#[derive(Debug)]
+pub struct FilterServer<B> {
+    blocks: B,
+    store: FilterStore,
+    headers: FilterHeaderChain,
+    metrics: SharedMetrics,
+}
+
+impl<B: BlockSource> FilterServer<B> {
+    // Constructors
+
+    /// Creates a server of the filters in `store`, computing their filter
+    /// headers.
+    pub fn new(blocks: B, store: FilterStore) -> io::Result<FilterServer<B>> {
+        let mut headers = FilterHeaderChain::new();
+        for result in store.range(..) {
+            let (height, block_hash, filter) = result?;
+            if height as usize != headers.len() || blocks.block_hash(height) != Some(block_hash) {
+                break;
+            }
+            headers.push_filter(&filter);
+        }
+
+        Ok(FilterServer {
+            blocks,
+            store,
+            headers,
+            metrics: SharedMetrics::default(),
+        })
+    }
+
+    // Accessors
+
+    /// Returns the block source.
+    pub fn blocks(&self) -> &B {
+        &self.blocks
+    }
+
+    /// Returns the block source, e.g. to connect new blocks.
+    pub fn blocks_mut(&mut self) -> &mut B {
+        &mut self.blocks
+    }
+
+    /// Returns the store.
+    pub fn store(&self) -> &FilterStore {
+        &self.store
+    }
+
+    /// Returns the filter headers of the served blocks.
+    pub fn headers(&self) -> &FilterHeaderChain {
+        &self.headers
+    }
+
+    /// Drops the server, returning the block source and the store.
+    pub fn into_parts(self) -> (B, FilterStore) {
+        (self.blocks, self.store)
+    }
+
+    // Modifiers
+
+    /// Sets the [metrics][1] the stored and served filters are reported to,
+    /// for the store too.
+    ///
+    /// [1]: ../trait.Metrics.html
+    pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) {
+        self.store.set_metrics(metrics.clone());
+        self.metrics = SharedMetrics(metrics);
+    }
+
+    /// Stores the filter of the block at `height`, dropping the headers of
+    /// the blocks above it if it replaces a stored filter.
+    ///
+    /// Filters must be stored in order, `height` can't be above the height
+    /// of the last served block plus one.
+    pub fn put(&mut self, height: u32, block_hash: &Sha256dHash, filter: &Filter) -> io::Result<()> {
+        if height as usize > self.headers.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the previous filter is missing"));
+        }
+
+        self.store.put(height, block_hash, filter)?;
+        self.headers.truncate(height);
+        self.headers.push_filter(filter);
+        Ok(())
+    }
+
+    // Requests
+
+    /// Answers a `getcfilters` request with a `cfilter` message per block.
+    pub fn get_cfilters(&self, request: &GetCFilters) -> Result<Vec<CFilter>, ServerError> {
+        let stop_height = self.check_range(request.filter_type, request.start_height,
+                                           &request.stop_hash, MAX_CFILTERS)?;
+
+        (request.start_height..=stop_height)
+            .map(|height| {
+                let (block_hash, filter) = self.filter(height)?;
+                self.metrics.0.bytes_encoded(filter.serialized_size());
+                Ok(CFilter {
+                    filter_type: request.filter_type,
+                    block_hash,
+                    filter,
+                })
+            })
+            .collect()
+    }
+
+    /// Answers a `getcfheaders` request.
+    pub fn get_cfheaders(&self, request: &GetCFHeaders) -> Result<CFHeaders, ServerError> {
+        let stop_height = self.check_range(request.filter_type, request.start_height,
+                                           &request.stop_hash, MAX_CFHEADERS)?;
+
+        let previous_filter_header = match request.start_height {
+            0 => FilterHeader::zero(),
+            height => *self.headers.get(height - 1).ok_or(ServerError::MissingFilter(height - 1))?,
+        };
+        let filter_hashes = (request.start_height..=stop_height)
+            .map(|height| self.filter(height).map(|(_, filter)| filter.filter_hash()))
+            .collect::<Result<Vec<Sha256dHash>, ServerError>>()?;
+
+        Ok(CFHeaders {
+            filter_type: request.filter_type,
+            stop_hash: request.stop_hash,
+            previous_filter_header,
+            filter_hashes,
+        })
+    }
+
+    /// Answers a `getcfcheckpt` request.
+    pub fn get_cfcheckpt(&self, request: &GetCFCheckpt) -> Result<CFCheckpt, ServerError> {
+        let stop_height = self.check_range(request.filter_type, 0, &request.stop_hash, u32::MAX)?;
+
+        let filter_headers = (1..=stop_height / CHECKPOINT_INTERVAL)
+            .map(|i| *self.headers.get(i * CHECKPOINT_INTERVAL).expect("the range is checked"))
+            .collect();
+
+        Ok(CFCheckpt {
+            filter_type: request.filter_type,
+            stop_hash: request.stop_hash,
+            filter_headers,
+        })
+    }
+
+    /// Checks a requested range, returning the height of the stop hash.
+    fn check_range(&self, filter_type: u8, start_height: u32, stop_hash: &Sha256dHash, limit: u32)
+        -> Result<u32, ServerError>
+    {
+        if filter_type != BASIC_FILTER_TYPE {
+            return Err(ServerError::UnknownFilterType(filter_type));
+        }
+
+        let stop_height = self.blocks.height(stop_hash).ok_or(ServerError::UnknownBlock(*stop_hash))?;
+        if start_height > stop_height {
+            return Err(ServerError::InvalidRange(start_height, stop_height));
+        }
+        if stop_height - start_height >= limit {
+            return Err(ServerError::TooManyBlocks(stop_height - start_height + 1));
+        }
+        if stop_height as usize >= self.headers.len() {
+            return Err(ServerError::MissingFilter(self.headers.len() as u32));
+        }
+
+        Ok(stop_height)
+    }
+
+    /// Reads the filter of the block at `height`, checking that it's still
+    /// in the best chain.
+    fn filter(&self, height: u32) -> Result<(Sha256dHash, Filter), ServerError> {
+        let block_hash = match (self.store.block_hash(height), self.blocks.block_hash(height)) {
+            (Some(stored), Some(best)) if stored == best => stored,
+            _ => return Err(ServerError::MissingFilter(height)),
+        };
+
+        let filter = self.store.get(height)?.ok_or(ServerError::MissingFilter(height))?;
+        Ok((block_hash, filter))
+    }
+}
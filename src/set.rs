@@ -0,0 +1,195 @@
+//! A compact probabilistic set for any kind of items.
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(feature = "serde")]
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+#[cfg(feature = "serde")]
+use serde::ser::{Serialize, Serializer};
+
+use {read_compact_size, DecodeError, DecodeLimits, Filter, FilterKey, FilterParams, DEFAULT_P, KEY_SIZE};
+
+/// A GCS filter along with the key it's built with, to be used as a compact
+/// probabilistic set outside of Bitcoin, e.g. of URLs or log entries.
+///
+/// The false positive rate of the set is `1/2^P`, with P being
+/// [`DEFAULT_P`][1] unless it's given on construction. Sets are serialized as
+/// the key, P and M followed by the [N-prefixed serialization][2] of the
+/// filter.
+///
+/// [1]: constant.DEFAULT_P.html
+/// [2]: struct.Filter.html#method.to_nbytes
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GcsSet {
+    key: FilterKey,
+    filter: Filter,
+}
+
+impl GcsSet {
+    // Constructors
+
+    /// Creates a set of the given items, hashed with `key`.
+    pub fn new<I>(key: FilterKey, items: I) -> GcsSet
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        GcsSet::with_p(key, DEFAULT_P, items)
+    }
+
+    /// Creates a set of the given items with a false positive rate of
+    /// `1/2^P`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if P is larger than 32.
+    pub fn with_p<I>(key: FilterKey, p: u8, items: I) -> GcsSet
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        assert!(p <= 32, "P is too big");
+
+        GcsSet {
+            key,
+            filter: Filter::build(p, 1 << p, key, items),
+        }
+    }
+
+    /// Reads a set serialized with [`to_bytes`][1], checking that the filter
+    /// data is valid.
+    ///
+    /// P, M and `N * M` out of the range of [`FilterParams`][2] are
+    /// [`ValueOutOfRange`][3] errors, and an N the data is too short for is
+    /// a [`Limit`][4] error.
+    ///
+    /// [1]: #method.to_bytes
+    /// [2]: struct.FilterParams.html
+    /// [3]: enum.DecodeError.html#variant.ValueOutOfRange
+    /// [4]: enum.DecodeError.html#variant.Limit
+    pub fn from_bytes(bytes: &[u8]) -> Result<GcsSet, DecodeError> {
+        if bytes.len() < KEY_SIZE + 1 + 8 {
+            return Err(DecodeError::UnexpectedEof);
+        }
+
+        let mut key = [0u8; KEY_SIZE];
+        key.copy_from_slice(&bytes[..KEY_SIZE]);
+        let p = bytes[KEY_SIZE];
+        let mut m = [0u8; 8];
+        m.copy_from_slice(&bytes[KEY_SIZE + 1..KEY_SIZE + 9]);
+
+        let params = FilterParams::new(p, u64::from_le_bytes(m)).map_err(|_| DecodeError::ValueOutOfRange)?;
+        let (n, len) = read_compact_size(&bytes[KEY_SIZE + 9..]).ok_or(DecodeError::UnexpectedEof)?;
+        if n > u64::from(u32::MAX) {
+            return Err(DecodeError::ValueOutOfRange);
+        }
+        let n = n as u32;
+        params.modulus(n).map_err(|_| DecodeError::ValueOutOfRange)?;
+
+        // Sets aren't relayed as filters, so only N is checked against the
+        // data size.
+        let data = &bytes[KEY_SIZE + 9 + len..];
+        DecodeLimits::unlimited().check(n, p, data.len())?;
+        let filter = Filter::from_bytes(n, p, params.m(), data.to_vec());
+        filter.validate_encoding()?;
+
+        Ok(GcsSet {
+            key: FilterKey::from(key),
+            filter,
+        })
+    }
+
+    // Accessors
+
+    /// Returns the key the items are hashed with.
+    pub fn key(&self) -> FilterKey {
+        self.key
+    }
+
+    /// Returns the underlying filter.
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    /// Returns the number of unique items in the set.
+    pub fn len(&self) -> usize {
+        self.filter.n() as usize
+    }
+
+    /// Returns `true` if the set has no items.
+    pub fn is_empty(&self) -> bool {
+        self.filter.is_empty()
+    }
+
+    /// Returns `true` if the item is likely (within the false positive rate)
+    /// to be in the set.
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.filter.is_member(self.key, item)
+            .expect("the filter data is always valid")
+    }
+
+    /// Returns `true` if any of the items is likely (within the false
+    /// positive rate) to be in the set.
+    pub fn contains_any<I>(&self, items: I) -> bool
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        self.filter.is_member_any(self.key, items)
+            .expect("the filter data is always valid")
+    }
+
+    /// Serializes the set as the key, P, M as a little-endian `u64` and the
+    /// [N-prefixed serialization][1] of the filter.
+    ///
+    /// [1]: struct.Filter.html#method.to_nbytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(KEY_SIZE + 9 + self.filter.serialized_size());
+        bytes.extend_from_slice(&self.key.to_bytes());
+        bytes.push(self.filter.p());
+        bytes.extend_from_slice(&self.filter.m().to_le_bytes());
+        bytes.extend_from_slice(&self.filter.to_nbytes());
+        bytes
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for GcsSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.to_bytes())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for GcsSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<GcsSet, D::Error> {
+        deserializer.deserialize_bytes(GcsSetVisitor)
+    }
+}
+
+#[cfg(feature = "serde")]
+struct GcsSetVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> Visitor<'de> for GcsSetVisitor {
+    type Value = GcsSet;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a serialized GCS set")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<GcsSet, E> {
+        GcsSet::from_bytes(v).map_err(E::custom)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<GcsSet, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        self.visit_bytes(&bytes)
+    }
+}
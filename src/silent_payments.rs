@@ -0,0 +1,144 @@
+//! Filters for BIP352 silent payment wallets.
+//!
+//! Silent payment outputs are taproot outputs whose key is tweaked with the
+//! public keys of the inputs of the transaction, so a wallet has to compute
+//! the candidate output keys of every transaction with eligible inputs from
+//! their "tweak data". The [`SilentPaymentFilter`][1] holds the output keys
+//! of the taproot outputs of those transactions, so the wallet can match its
+//! candidate keys against it and only download the blocks that may pay it.
+//!
+//! The tweak data itself involves elliptic curve operations, which are left
+//! to the caller. [`input_public_keys`][2] extracts the public keys of the
+//! eligible inputs of a transaction, as required to compute it.
+//!
+//! [1]: struct.SilentPaymentFilter.html
+//! [2]: fn.input_public_keys.html
+
+use bitcoin::blockdata::block::Block;
+use bitcoin::blockdata::script::{Instruction, Script};
+use bitcoin::blockdata::transaction::Transaction;
+
+use builder::{FilterType, ScriptClass};
+
+/// Filter type byte of the [`SilentPaymentFilter`][1].
+///
+/// The filter type isn't standardized, so it's taken from the range BIP157
+/// leaves for experimental filters.
+///
+/// [1]: struct.SilentPaymentFilter.html
+pub const SILENT_PAYMENT_FILTER_TYPE: u8 = 0x80;
+
+/// The "nothing up my sleeve" point BIP341 uses as the internal key of
+/// taproot outputs without a key path, their inputs aren't eligible.
+const NUMS_H: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// A filter of the x-only output keys of the taproot outputs of the
+/// transactions that may contain silent payments, those with at least one
+/// eligible input.
+///
+/// `prev_scripts` are the scripts spent by the inputs of every transaction
+/// of the block but the coinbase, in order, as in the undo data of Bitcoin
+/// Core. The inputs whose spent script is missing aren't eligible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SilentPaymentFilter;
+
+impl FilterType for SilentPaymentFilter {
+    fn filter_type_byte() -> u8 {
+        SILENT_PAYMENT_FILTER_TYPE
+    }
+
+    fn extract_entries<'a>(block: &'a Block, prev_scripts: &'a [Script]) -> Vec<&'a [u8]> {
+        let mut entries = Vec::new();
+        let mut spent = 0;
+
+        for tx in block.txdata.iter().skip(1) {
+            let end = (spent + tx.input.len()).min(prev_scripts.len());
+            let tx_prev_scripts = &prev_scripts[spent.min(end)..end];
+            spent += tx.input.len();
+
+            if input_public_keys(tx, tx_prev_scripts).is_empty() {
+                continue;
+            }
+
+            let keys = tx.output.iter()
+                .map(|txout| &txout.script_pubkey)
+                .filter(|script| ScriptClass::of(script) == ScriptClass::P2tr)
+                .map(|script| &script[2..]);
+            entries.extend(keys);
+        }
+
+        entries
+    }
+}
+
+/// Returns the public keys of the inputs of a transaction that are eligible
+/// for silent payments, as required to compute its tweak data.
+///
+/// `prev_scripts` are the scripts spent by the inputs, in order. Keys of
+/// taproot inputs are x-only (32 bytes), the others are compressed (33
+/// bytes). Only the last data push of the `scriptSig` of P2PKH inputs is
+/// considered.
+pub fn input_public_keys(tx: &Transaction, prev_scripts: &[Script]) -> Vec<Vec<u8>> {
+    let empty = Vec::new();
+
+    tx.input.iter()
+        .zip(prev_scripts)
+        .enumerate()
+        .filter_map(|(i, (txin, prev_script))| {
+            let witness = tx.witness.get(i).unwrap_or(&empty);
+            let prev_data = prev_script.data();
+
+            match ScriptClass::of(prev_script) {
+                ScriptClass::P2tr => taproot_key(&prev_data, witness),
+                ScriptClass::P2wpkh => witness.last().cloned().filter(|key| is_compressed_key(key)),
+                ScriptClass::P2sh => {
+                    // Only nested P2WPKH, the scriptSig being a single push
+                    // of the witness program.
+                    let script_sig = txin.script_sig.data();
+                    if script_sig.len() == 23 && script_sig[..3] == [0x16, 0x00, 0x14] {
+                        witness.last().cloned().filter(|key| is_compressed_key(key))
+                    } else {
+                        None
+                    }
+                }
+                ScriptClass::P2pkh => {
+                    let last_push = txin.script_sig.iter(false)
+                        .filter_map(|instruction| match instruction {
+                            Instruction::PushBytes(data) => Some(data),
+                            _ => None,
+                        })
+                        .last();
+                    last_push.filter(|key| is_compressed_key(key)).map(|key| key.to_vec())
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Returns the output key of a taproot input, unless it's spent through a
+/// script path whose internal key is `NUMS_H`.
+fn taproot_key(prev_script: &[u8], witness: &[Vec<u8>]) -> Option<Vec<u8>> {
+    // An annex is the last element, starting with 0x50, of a witness of
+    // at least two elements.
+    let stack = match witness.split_last() {
+        Some((last, rest)) if !rest.is_empty() && last.first() == Some(&0x50) => rest,
+        _ => witness,
+    };
+
+    if stack.len() > 1 {
+        let control_block = &stack[stack.len() - 1];
+        if control_block.len() >= 33 && control_block[1..33] == NUMS_H {
+            return None;
+        }
+    }
+
+    Some(prev_script[2..].to_vec())
+}
+
+fn is_compressed_key(key: &[u8]) -> bool {
+    key.len() == 33 && (key[0] == 0x02 || key[0] == 0x03)
+}
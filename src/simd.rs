@@ -0,0 +1,142 @@
+//! SipHash-2-4 over four items at once.
+//!
+//! The state of four hashes is kept in arrays, one lane per item, so the
+//! rounds are compiled to vector instructions where available. Items of
+//! different lengths share the rounds, the lanes of shorter items just
+//! discard the extra ones.
+
+// Lanes are indexed explicitly so the loops map to vector instructions.
+#![allow(clippy::needless_range_loop)]
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use {siphash24, FilterKey};
+
+const LANES: usize = 4;
+
+#[derive(Clone)]
+struct State {
+    v0: [u64; LANES],
+    v1: [u64; LANES],
+    v2: [u64; LANES],
+    v3: [u64; LANES],
+}
+
+impl State {
+    fn new(key: FilterKey) -> State {
+        let (k0, k1) = key.keys();
+        State {
+            v0: [k0 ^ 0x736f_6d65_7073_6575; LANES],
+            v1: [k1 ^ 0x646f_7261_6e64_6f6d; LANES],
+            v2: [k0 ^ 0x6c79_6765_6e65_7261; LANES],
+            v3: [k1 ^ 0x7465_6462_7974_6573; LANES],
+        }
+    }
+
+    fn round(&mut self) {
+        for i in 0..LANES {
+            self.v0[i] = self.v0[i].wrapping_add(self.v1[i]);
+            self.v1[i] = self.v1[i].rotate_left(13) ^ self.v0[i];
+            self.v0[i] = self.v0[i].rotate_left(32);
+            self.v2[i] = self.v2[i].wrapping_add(self.v3[i]);
+            self.v3[i] = self.v3[i].rotate_left(16) ^ self.v2[i];
+            self.v0[i] = self.v0[i].wrapping_add(self.v3[i]);
+            self.v3[i] = self.v3[i].rotate_left(21) ^ self.v0[i];
+            self.v2[i] = self.v2[i].wrapping_add(self.v1[i]);
+            self.v1[i] = self.v1[i].rotate_left(17) ^ self.v2[i];
+            self.v2[i] = self.v2[i].rotate_left(32);
+        }
+    }
+
+    /// Restores the lanes for which `inactive` is true from `saved`.
+    fn restore_inactive<F: Fn(usize) -> bool>(&mut self, saved: &State, inactive: F) {
+        for i in 0..LANES {
+            if inactive(i) {
+                self.v0[i] = saved.v0[i];
+                self.v1[i] = saved.v1[i];
+                self.v2[i] = saved.v2[i];
+                self.v3[i] = saved.v3[i];
+            }
+        }
+    }
+
+    fn compress(&mut self, m: &[u64; LANES]) {
+        for i in 0..LANES {
+            self.v3[i] ^= m[i];
+        }
+        self.round();
+        self.round();
+        for i in 0..LANES {
+            self.v0[i] ^= m[i];
+        }
+    }
+}
+
+/// Reads up to 8 bytes as a little-endian integer.
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    u64::from_le_bytes(buf)
+}
+
+/// Hashes four items.
+///
+/// The lanes compress as many blocks as the longest item, lanes whose item
+/// is already consumed keep their state.
+fn siphash24_x4(key: FilterKey, items: [&[u8]; LANES]) -> [u64; LANES] {
+    let mut blocks = [0usize; LANES];
+    for i in 0..LANES {
+        blocks[i] = items[i].len() / 8;
+    }
+    let max_blocks = blocks.iter().cloned().max().unwrap_or(0);
+
+    let mut state = State::new(key);
+    let mut m = [0u64; LANES];
+    for b in 0..max_blocks {
+        let saved = state.clone();
+        for i in 0..LANES {
+            if b < blocks[i] {
+                m[i] = read_u64(&items[i][b * 8..b * 8 + 8]);
+            }
+        }
+        state.compress(&m);
+        state.restore_inactive(&saved, |i| b >= blocks[i]);
+    }
+
+    // The last block holds the length and the remaining bytes.
+    for i in 0..LANES {
+        m[i] = ((items[i].len() as u64) << 56) | read_u64(&items[i][blocks[i] * 8..]);
+    }
+    state.compress(&m);
+
+    for i in 0..LANES {
+        state.v2[i] ^= 0xff;
+    }
+    for _ in 0..4 {
+        state.round();
+    }
+
+    let mut hashes = [0u64; LANES];
+    for i in 0..LANES {
+        hashes[i] = state.v0[i] ^ state.v1[i] ^ state.v2[i] ^ state.v3[i];
+    }
+    hashes
+}
+
+/// Hashes each item with SipHash-2-4, returning the hashes in the same
+/// order as the items.
+pub fn siphash24_batch(key: FilterKey, items: &[&[u8]]) -> Vec<u64> {
+    let mut hashes = Vec::with_capacity(items.len());
+
+    let mut lanes = items.chunks_exact(LANES);
+    for lane in &mut lanes {
+        hashes.extend_from_slice(&siphash24_x4(key, [lane[0], lane[1], lane[2], lane[3]]));
+    }
+
+    for item in lanes.remainder() {
+        hashes.push(siphash24(key, item));
+    }
+
+    hashes
+}
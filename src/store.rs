@@ -0,0 +1,915 @@
+//! Flat-file filter storage.
+//!
+//! Filters are appended to a single file, which starts with the magic
+//! bytes `GCSF` and a version byte, currently 1, followed by the records:
+//!
+//! ```text
+//! height (u32) | block hash (32 bytes) | N (u32) | P (u8) | M (u64) | length (u32) | data | CRC32 (u32)
+//! ```
+//!
+//! with the integers in little-endian. The checksum covers the whole record
+//! before it. A record whose P is 255, without filter data, marks the
+//! disconnection of the block at its height from the active chain. The index
+//! of the records is kept in memory and rebuilt by scanning the file when
+//! it's opened.
+//!
+//! Every record is checked when it's read, and a damaged store file can be
+//! inspected with [`FilterStore::scan`][2] and truncated to its last valid
+//! record with [`FilterStore::repair`][3]. Files written before the format
+//! was versioned, without a header nor checksums, are converted with
+//! [`FilterStore::upgrade`][4].
+//!
+//! With the `mmap` feature the store can be [memory-mapped][1], so the
+//! filters are matched straight from the page cache instead of being read
+//! into a buffer.
+//!
+//! [1]: struct.FilterStore.html#method.map
+//! [2]: struct.FilterStore.html#method.scan
+//! [3]: struct.FilterStore.html#method.repair
+//! [4]: struct.FilterStore.html#method.upgrade
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bitcoin::util::hash::Sha256dHash;
+
+#[cfg(feature = "mmap")]
+use memmap::Mmap;
+
+use metrics::SharedMetrics;
+#[cfg(feature = "mmap")]
+use FilterRef;
+use {EncodeError, Filter, Metrics, MAX_FILTER_SIZE};
+#[cfg(feature = "rescan")]
+use rescan::FilterSource;
+
+/// The magic bytes at the start of a store file.
+const MAGIC: [u8; 4] = *b"GCSF";
+
+/// The version of the store file format.
+const VERSION: u8 = 1;
+
+/// Length of the file header, the magic bytes and the version.
+const FILE_HEADER_SIZE: u64 = 5;
+
+/// Length of a record without the filter data and the checksum.
+const RECORD_HEADER_SIZE: usize = 4 + 32 + 4 + 1 + 8 + 4;
+
+/// Length of the checksum at the end of a record.
+const CHECKSUM_SIZE: usize = 4;
+
+/// The P of the records marking the disconnection of a block.
+const DISCONNECT_P: u8 = 0xff;
+
+/// The number of blocks the filters of orphaned blocks are kept for by
+/// default.
+const DEFAULT_ORPHAN_RETENTION: u32 = 144;
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    offset: u64,
+    block_hash: Sha256dHash,
+}
+
+/// An append-only store of filters keyed by block height and hash.
+///
+/// The store tracks the active chain, blocks are [connected][1] on top of
+/// it and [disconnected][2] from its tip during reorganizations. Storing a
+/// filter at a height which already has one replaces it too. The filters of
+/// the blocks which leave the active chain are kept as stale for a number
+/// of [blocks][3], so a wallet rescan in progress can still [look them
+/// up][4], and their records stay in the file until the store is
+/// [pruned][5].
+///
+/// [1]: #method.connect_block
+/// [2]: #method.disconnect_block
+/// [3]: #method.set_orphan_retention
+/// [4]: #method.lookup
+/// [5]: #method.prune
+#[derive(Debug)]
+pub struct FilterStore {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    heights: BTreeMap<u32, Entry>,
+    hashes: HashMap<Sha256dHash, u32>,
+    orphans: HashMap<Sha256dHash, (u32, u64)>,
+    orphan_retention: u32,
+    metrics: SharedMetrics,
+}
+
+impl FilterStore {
+    // Constructors
+
+    /// Opens the store at `path`, creating it if it doesn't exist.
+    ///
+    /// A truncated or corrupted record at the end of the file, such as the
+    /// one left by a crash while writing, is discarded. An error is returned
+    /// if a record before the last one is corrupted, the file has to be
+    /// [repaired][1] explicitly then, as the records after it are lost.
+    ///
+    /// Stores written before the file format was versioned aren't
+    /// recognized, they have to be [upgraded][2] first.
+    ///
+    /// [1]: #method.repair
+    /// [2]: #method.upgrade
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FilterStore> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let file_len = file.metadata()?.len();
+        if file_len < FILE_HEADER_SIZE {
+            // A new file, or one whose header wasn't fully written.
+            let mut header = vec![0u8; file_len as usize];
+            file.read_exact(&mut header)?;
+            if header[..] != file_header()[..header.len()] {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "not a filter store"));
+            }
+
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&file_header())?;
+            file.flush()?;
+        }
+
+        let mut records = Vec::new();
+        let report = scan_records(&file, |offset, header| {
+            records.push((offset, header.height, header.block_hash, header.p == DISCONNECT_P));
+        })?;
+
+        match report.damage {
+            Some(Damage::Corrupted { offset, len }) if offset + len < report.file_len => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "corrupted filter record"));
+            }
+            Some(Damage::InvalidLength { .. }) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid filter record length"));
+            }
+            Some(_) => file.set_len(report.valid_len)?,
+            None => {}
+        }
+
+        let mut store = FilterStore {
+            path,
+            file,
+            len: report.valid_len,
+            heights: BTreeMap::new(),
+            hashes: HashMap::new(),
+            orphans: HashMap::new(),
+            orphan_retention: DEFAULT_ORPHAN_RETENTION,
+            metrics: SharedMetrics::default(),
+        };
+
+        for (offset, height, block_hash, disconnect) in records {
+            if disconnect {
+                store.remove(height, &block_hash);
+            } else {
+                store.insert(height, Entry { offset, block_hash });
+            }
+        }
+        store.expire_orphans();
+
+        Ok(store)
+    }
+
+    /// Checks every record of the store file at `path`, without modifying
+    /// it.
+    ///
+    /// The records are checked in order, up to the first damaged one, as
+    /// the records after it can't be found reliably.
+    pub fn scan<P: AsRef<Path>>(path: P) -> io::Result<ScanReport> {
+        let file = File::open(path)?;
+        scan_records(&file, |_, _| {})
+    }
+
+    /// Truncates the store file at `path` to its last valid record,
+    /// returning the [report][1] of the records found before.
+    ///
+    /// The records after the first damaged one are lost.
+    ///
+    /// [1]: #method.scan
+    pub fn repair<P: AsRef<Path>>(path: P) -> io::Result<ScanReport> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let report = scan_records(&file, |_, _| {})?;
+        if report.damage.is_some() {
+            file.set_len(report.valid_len)?;
+            file.sync_all()?;
+        }
+        Ok(report)
+    }
+
+    /// Upgrades the store file at `path`, written before the file format
+    /// was versioned, to the current format, returning the number of records.
+    ///
+    /// The records of those files have neither checksums nor a file header.
+    /// A truncated record at the end is discarded, as it was when opening
+    /// them. The records are written to a temporary file next to `path`,
+    /// which then replaces it.
+    pub fn upgrade<P: AsRef<Path>>(path: P) -> io::Result<usize> {
+        let path = path.as_ref();
+        let mut reader = io::BufReader::new(File::open(path)?);
+
+        let upgraded = path.with_extension("upgrade");
+        let mut writer = io::BufWriter::new(File::create(&upgraded)?);
+        writer.write_all(&file_header())?;
+
+        let mut records = 0;
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        let mut data = Vec::new();
+        loop {
+            match reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            if records == 0 && header[..4] == MAGIC {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "the filter store is already versioned"));
+            }
+
+            let record = RecordHeader::parse(&header);
+            record.check()?;
+            data.resize(record.data_len as usize, 0);
+            match reader.read_exact(&mut data) {
+                Ok(()) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+
+            writer.write_all(&encode_record(record.height, &record.block_hash, record.n, record.p, record.m, &data))?;
+            records += 1;
+        }
+
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&upgraded, path)?;
+        Ok(records)
+    }
+
+    // Accessors
+
+    /// Number of filters in the store.
+    pub fn len(&self) -> usize {
+        self.heights.len()
+    }
+
+    /// Returns true if there are no filters in the store.
+    pub fn is_empty(&self) -> bool {
+        self.heights.is_empty()
+    }
+
+    /// Height of the highest filter in the store.
+    pub fn tip_height(&self) -> Option<u32> {
+        self.heights.keys().next_back().cloned()
+    }
+
+    /// Returns the hash of the block at `height`.
+    pub fn block_hash(&self, height: u32) -> Option<Sha256dHash> {
+        self.heights.get(&height).map(|e| e.block_hash)
+    }
+
+    /// Returns the height of a block of the active chain given its hash.
+    pub fn height(&self, block_hash: &Sha256dHash) -> Option<u32> {
+        self.hashes.get(block_hash).cloned()
+    }
+
+    /// Returns the number of blocks the filters of orphaned blocks are
+    /// kept for.
+    pub fn orphan_retention(&self) -> u32 {
+        self.orphan_retention
+    }
+
+    /// Returns whether a block is in the active chain, or an orphaned block
+    /// whose filter is still kept.
+    pub fn status(&self, block_hash: &Sha256dHash) -> Option<BlockStatus> {
+        match self.hashes.get(block_hash) {
+            Some(height) => Some(BlockStatus::Active(*height)),
+            None => self.orphans.get(block_hash).map(|&(height, _)| BlockStatus::Stale(height)),
+        }
+    }
+
+    /// Reads the filter of the block at `height`.
+    pub fn get(&self, height: u32) -> io::Result<Option<Filter>> {
+        match self.heights.get(&height) {
+            Some(entry) => self.read_filter(entry.offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the filter of a block of the active chain given its hash.
+    pub fn get_by_hash(&self, block_hash: &Sha256dHash) -> io::Result<Option<Filter>> {
+        match self.height(block_hash) {
+            Some(height) => self.get(height),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the filter of a block given its hash, along with its
+    /// [status][1], including the stale filters of orphaned blocks.
+    ///
+    /// [1]: #method.status
+    pub fn lookup(&self, block_hash: &Sha256dHash) -> io::Result<Option<(BlockStatus, Filter)>> {
+        let (status, offset) = match self.hashes.get(block_hash) {
+            Some(&height) => (BlockStatus::Active(height), self.heights[&height].offset),
+            None => match self.orphans.get(block_hash) {
+                Some(&(height, offset)) => (BlockStatus::Stale(height), offset),
+                None => return Ok(None),
+            },
+        };
+
+        self.read_filter(offset).map(|filter| Some((status, filter)))
+    }
+
+    /// Returns an iterator over the filters in a range of heights, in
+    /// ascending order.
+    pub fn range<R: RangeBounds<u32>>(&self, range: R) -> Range<'_> {
+        Range {
+            store: self,
+            entries: self.heights.range(range)
+                .map(|(height, entry)| (*height, *entry))
+                .collect::<Vec<_>>()
+                .into_iter(),
+        }
+    }
+
+    /// Maps the store into memory, to read the filters without copying them.
+    ///
+    /// The store can't be modified while it's mapped.
+    #[cfg(feature = "mmap")]
+    pub fn map(&self) -> io::Result<MappedStore<'_>> {
+        // Mapping an empty file fails on some platforms.
+        let map = if self.len <= FILE_HEADER_SIZE {
+            None
+        } else {
+            // The file is only modified through `&mut self`, which the
+            // returned value borrows. Records are only appended, so the
+            // mapped ones stay valid anyway.
+            Some(unsafe { Mmap::map(&self.file)? })
+        };
+
+        Ok(MappedStore { store: self, map })
+    }
+
+    // Modifiers
+
+    /// Sets the number of blocks the filters of orphaned blocks are kept
+    /// for, 144 by default.
+    ///
+    /// The filter of an orphaned block is dropped once the active chain is
+    /// `blocks` blocks above its height.
+    pub fn set_orphan_retention(&mut self, blocks: u32) {
+        self.orphan_retention = blocks;
+        self.expire_orphans();
+    }
+
+    /// Sets the [metrics][1] the stored filters are reported to.
+    ///
+    /// [1]: ../trait.Metrics.html
+    pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) {
+        self.metrics = SharedMetrics(metrics);
+    }
+
+    /// Appends the filter of the block at `height`.
+    ///
+    /// The filter stored at `height`, if any, becomes stale. An error is
+    /// returned if the filter data is longer than `u32::MAX` bytes, or if
+    /// the modulus of the filter isn't `N * M`, as it's stored as N, P and M.
+    pub fn put(&mut self, height: u32, block_hash: &Sha256dHash, filter: &Filter) -> io::Result<()> {
+        if filter.data.len() > u32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "filter is too big"));
+        }
+        if !filter.has_nm_modulus() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, EncodeError::ModulusMismatch));
+        }
+
+        let record = encode_record(height, block_hash, filter.n, filter.p, filter.m, &filter.data);
+        let offset = self.append(&record)?;
+        self.insert(height, Entry { offset, block_hash: *block_hash });
+        self.expire_orphans();
+
+        self.metrics.0.filter_stored();
+        self.metrics.0.bytes_encoded(filter.serialized_size());
+        Ok(())
+    }
+
+    /// Appends the filter of a block extending the active chain.
+    ///
+    /// An error is returned if `height` isn't the height after the tip.
+    pub fn connect_block(&mut self, height: u32, block_hash: &Sha256dHash, filter: &Filter) -> io::Result<()> {
+        if let Some(tip) = self.tip_height() {
+            if u64::from(height) != u64::from(tip) + 1 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "the block doesn't extend the active chain"));
+            }
+        }
+
+        self.put(height, block_hash, filter)
+    }
+
+    /// Disconnects the tip of the active chain, returning its height and
+    /// hash, or `None` if the store is empty.
+    ///
+    /// Its filter is kept as stale.
+    pub fn disconnect_block(&mut self) -> io::Result<Option<(u32, Sha256dHash)>> {
+        let (height, block_hash) = match self.heights.iter().next_back() {
+            Some((height, entry)) => (*height, entry.block_hash),
+            None => return Ok(None),
+        };
+
+        let record = encode_record(height, &block_hash, 0, DISCONNECT_P, 0, &[]);
+        self.append(&record)?;
+        self.remove(height, &block_hash);
+        Ok(Some((height, block_hash)))
+    }
+
+    /// Removes the filters below `height` and compacts the file, dropping
+    /// replaced records too.
+    ///
+    /// The stale filters of orphaned blocks at or above `height` are kept.
+    pub fn prune(&mut self, height: u32) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        {
+            let mut tmp = File::create(&tmp_path)?;
+            tmp.write_all(&file_header())?;
+            let mut len = FILE_HEADER_SIZE;
+            let mut heights = BTreeMap::new();
+            let mut orphans = HashMap::new();
+
+            // The orphaned blocks are disconnected right after their
+            // filters, before the active chain is written, so they're still
+            // known as orphaned when the store is opened again.
+            for (block_hash, &(h, offset)) in self.orphans.iter() {
+                if h < height {
+                    continue;
+                }
+
+                let record = self.read_record(offset)?;
+                tmp.write_all(&record)?;
+                orphans.insert(*block_hash, (h, len));
+                len += record.len() as u64;
+
+                let record = encode_record(h, block_hash, 0, DISCONNECT_P, 0, &[]);
+                tmp.write_all(&record)?;
+                len += record.len() as u64;
+            }
+
+            for (h, entry) in self.heights.range(height..) {
+                let record = self.read_record(entry.offset)?;
+                tmp.write_all(&record)?;
+                heights.insert(*h, Entry { offset: len, block_hash: entry.block_hash });
+                len += record.len() as u64;
+            }
+            tmp.sync_all()?;
+
+            self.hashes.retain(|_, h| *h >= height);
+            self.heights = heights;
+            self.orphans = orphans;
+            self.len = len;
+        }
+
+        fs::rename(&tmp_path, &self.path)?;
+        self.file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        Ok(())
+    }
+
+    /// Appends a record, returning its offset.
+    fn append(&mut self, record: &[u8]) -> io::Result<u64> {
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(record)?;
+        self.file.flush()?;
+
+        let offset = self.len;
+        self.len += record.len() as u64;
+        Ok(offset)
+    }
+
+    /// Adds a filter to the active chain, orphaning the block it replaces.
+    fn insert(&mut self, height: u32, entry: Entry) {
+        self.orphans.remove(&entry.block_hash);
+        if let Some(old) = self.heights.insert(height, entry) {
+            self.hashes.remove(&old.block_hash);
+            if old.block_hash != entry.block_hash {
+                self.orphans.insert(old.block_hash, (height, old.offset));
+            }
+        }
+        self.hashes.insert(entry.block_hash, height);
+    }
+
+    /// Removes a block from the active chain, orphaning it.
+    fn remove(&mut self, height: u32, block_hash: &Sha256dHash) {
+        let is_active = self.heights.get(&height).map(|e| e.block_hash == *block_hash);
+        if is_active == Some(true) {
+            let entry = self.heights.remove(&height).expect("the block is active");
+            self.hashes.remove(block_hash);
+            self.orphans.insert(*block_hash, (height, entry.offset));
+        }
+    }
+
+    /// Drops the orphaned blocks which are too far below the tip.
+    fn expire_orphans(&mut self) {
+        let tip = match self.tip_height() {
+            Some(tip) => u64::from(tip),
+            None => return,
+        };
+        let retention = u64::from(self.orphan_retention);
+        self.orphans.retain(|_, &mut (height, _)| u64::from(height) + retention > tip);
+    }
+
+    fn read_header(&self, offset: u64) -> io::Result<RecordHeader> {
+        let mut buf = [0u8; RECORD_HEADER_SIZE];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buf)?;
+
+        Ok(RecordHeader::parse(&buf))
+    }
+
+    /// Reads a whole record, checking its checksum.
+    fn read_record(&self, offset: u64) -> io::Result<Vec<u8>> {
+        let header = self.read_header(offset)?;
+        let mut record = vec![0u8; header.record_len()];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut record)?;
+
+        if !checksum_matches(&record) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "filter record checksum mismatch"));
+        }
+        Ok(record)
+    }
+
+    fn read_filter(&self, offset: u64) -> io::Result<Filter> {
+        let mut record = self.read_record(offset)?;
+        let header = RecordHeader::parse(&record);
+        header.check()?;
+
+        record.truncate(RECORD_HEADER_SIZE + header.data_len as usize);
+        record.drain(..RECORD_HEADER_SIZE);
+        Ok(Filter::from_bytes(header.n, header.p, header.m, record))
+    }
+}
+
+/// Whether a block is in the active chain of a [`FilterStore`][1].
+///
+/// [1]: struct.FilterStore.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockStatus {
+    /// The block is in the active chain, at the given height.
+    Active(u32),
+    /// The block was orphaned, at the given height, its filter is stale.
+    Stale(u32),
+}
+
+impl BlockStatus {
+    /// Returns the height of the block.
+    pub fn height(&self) -> u32 {
+        match *self {
+            BlockStatus::Active(height) | BlockStatus::Stale(height) => height,
+        }
+    }
+}
+
+/// The result of [scanning][1] a store file.
+///
+/// [1]: struct.FilterStore.html#method.scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanReport {
+    /// The number of valid records, including the ones replaced by later
+    /// records.
+    pub records: usize,
+    /// The length of the file up to the end of the last valid record.
+    pub valid_len: u64,
+    /// The length of the file.
+    pub file_len: u64,
+    /// The damage found after the last valid record, if any.
+    pub damage: Option<Damage>,
+}
+
+/// A damaged record of a store file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Damage {
+    /// The record at `offset`, the last one of the file, goes past its
+    /// end.
+    Truncated {
+        /// The offset of the record.
+        offset: u64,
+    },
+    /// The checksum of the record at `offset`, of `len` bytes, doesn't
+    /// match its contents.
+    Corrupted {
+        /// The offset of the record.
+        offset: u64,
+        /// The length of the record, according to its header.
+        len: u64,
+    },
+    /// The length of the record at `offset` goes past the end of the file,
+    /// but valid records follow it, so the length itself is damaged.
+    InvalidLength {
+        /// The offset of the record.
+        offset: u64,
+    },
+}
+
+impl Damage {
+    /// Returns the offset of the damaged record.
+    pub fn offset(&self) -> u64 {
+        match *self {
+            Damage::Truncated { offset }
+            | Damage::Corrupted { offset, .. }
+            | Damage::InvalidLength { offset } => offset,
+        }
+    }
+}
+
+fn file_header() -> [u8; FILE_HEADER_SIZE as usize] {
+    [MAGIC[0], MAGIC[1], MAGIC[2], MAGIC[3], VERSION]
+}
+
+fn check_file_header(mut file: &File) -> io::Result<()> {
+    let mut header = [0u8; FILE_HEADER_SIZE as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+
+    if header[..4] != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a filter store"));
+    }
+    if header[4] != VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported filter store version"));
+    }
+    Ok(())
+}
+
+/// Reads the records of a store file after its header, calling `f` with
+/// the offset and the header of each valid one, up to the first damaged
+/// one.
+fn scan_records<F>(mut file: &File, mut f: F) -> io::Result<ScanReport>
+where
+    F: FnMut(u64, &RecordHeader),
+{
+    check_file_header(file)?;
+
+    let file_len = file.metadata()?.len();
+    let mut report = ScanReport {
+        records: 0,
+        valid_len: FILE_HEADER_SIZE,
+        file_len,
+        damage: None,
+    };
+
+    let mut record = Vec::new();
+    while report.valid_len < file_len {
+        let offset = report.valid_len;
+        if file_len - offset < RECORD_HEADER_SIZE as u64 {
+            report.damage = Some(Damage::Truncated { offset });
+            break;
+        }
+
+        record.resize(RECORD_HEADER_SIZE, 0);
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut record)?;
+        let header = RecordHeader::parse(&record);
+
+        let len = header.record_len() as u64;
+        if len > file_len - offset {
+            // Only the last record can be cut short by a crash, a length
+            // running past a valid record is damaged itself.
+            report.damage = Some(if is_tail(file, offset, file_len)? {
+                Damage::Truncated { offset }
+            } else {
+                Damage::InvalidLength { offset }
+            });
+            break;
+        }
+
+        record.resize(len as usize, 0);
+        file.read_exact(&mut record[RECORD_HEADER_SIZE..])?;
+        if !checksum_matches(&record) {
+            report.damage = Some(Damage::Corrupted { offset, len });
+            break;
+        }
+
+        f(offset, &header);
+        report.records += 1;
+        report.valid_len += len;
+    }
+
+    Ok(report)
+}
+
+/// Returns whether no valid record starts after `offset`, that is, whether
+/// the record at `offset` is the last one of the file.
+///
+/// Tails longer than the largest record of a relayable filter aren't
+/// searched, they're assumed to hold more records.
+fn is_tail(mut file: &File, offset: u64, file_len: u64) -> io::Result<bool> {
+    let tail_len = file_len - offset;
+    if tail_len > (RECORD_HEADER_SIZE + MAX_FILTER_SIZE + CHECKSUM_SIZE) as u64 {
+        return Ok(false);
+    }
+
+    let mut tail = vec![0u8; tail_len as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut tail)?;
+
+    for start in 1..tail.len() {
+        let rest = &tail[start..];
+        if rest.len() < RECORD_HEADER_SIZE + CHECKSUM_SIZE {
+            break;
+        }
+        let len = RecordHeader::parse(rest).record_len();
+        if len <= rest.len() && checksum_matches(&rest[..len]) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn encode_record(height: u32, block_hash: &Sha256dHash, n: u32, p: u8, m: u64, data: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + data.len() + CHECKSUM_SIZE);
+    record.extend_from_slice(&height.to_le_bytes());
+    record.extend_from_slice(&block_hash[..]);
+    record.extend_from_slice(&n.to_le_bytes());
+    record.push(p);
+    record.extend_from_slice(&m.to_le_bytes());
+    record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    record.extend_from_slice(data);
+    let checksum = crc32(&record);
+    record.extend_from_slice(&checksum.to_le_bytes());
+    record
+}
+
+/// Returns whether the checksum at the end of a record matches the rest of
+/// it.
+fn checksum_matches(record: &[u8]) -> bool {
+    let (contents, checksum) = record.split_at(record.len() - CHECKSUM_SIZE);
+    crc32(contents) == read_u32(checksum)
+}
+
+/// The CRC-32 (IEEE) lookup table.
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Computes the CRC-32 (IEEE) checksum of `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc = CRC32_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(buf);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(buf: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(buf);
+    u64::from_le_bytes(bytes)
+}
+
+struct RecordHeader {
+    height: u32,
+    block_hash: Sha256dHash,
+    n: u32,
+    p: u8,
+    m: u64,
+    data_len: u32,
+}
+
+impl RecordHeader {
+    fn parse(buf: &[u8]) -> RecordHeader {
+        RecordHeader {
+            height: read_u32(&buf[0..4]),
+            block_hash: Sha256dHash::from(&buf[4..36]),
+            n: read_u32(&buf[36..40]),
+            p: buf[40],
+            m: read_u64(&buf[41..49]),
+            data_len: read_u32(&buf[49..53]),
+        }
+    }
+
+    /// Returns the length of the whole record.
+    fn record_len(&self) -> usize {
+        RECORD_HEADER_SIZE + self.data_len as usize + CHECKSUM_SIZE
+    }
+
+    fn check(&self) -> io::Result<()> {
+        if self.p > 32 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid filter P"));
+        }
+        Ok(())
+    }
+}
+
+/// A memory-mapped [`FilterStore`][1], whose filters borrow their data from
+/// the map.
+///
+/// This is created by [`FilterStore::map`][2].
+///
+/// [1]: struct.FilterStore.html
+/// [2]: struct.FilterStore.html#method.map
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub struct MappedStore<'a> {
+    store: &'a FilterStore,
+    map: Option<Mmap>,
+}
+
+#[cfg(feature = "mmap")]
+impl<'a> MappedStore<'a> {
+    /// Returns the filter of the block at `height`.
+    pub fn get(&self, height: u32) -> io::Result<Option<FilterRef<'_>>> {
+        match self.store.heights.get(&height) {
+            Some(entry) => self.filter_at(entry.offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the filter of a block given its hash.
+    pub fn get_by_hash(&self, block_hash: &Sha256dHash) -> io::Result<Option<FilterRef<'_>>> {
+        match self.store.height(block_hash) {
+            Some(height) => self.get(height),
+            None => Ok(None),
+        }
+    }
+
+    fn filter_at(&self, offset: u64) -> io::Result<FilterRef<'_>> {
+        let map = self.map.as_ref().map(|map| &map[..]).unwrap_or(&[]);
+        let start = offset as usize;
+        let record = map.get(start..start + RECORD_HEADER_SIZE)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+
+        let header = RecordHeader::parse(record);
+        header.check()?;
+
+        let record = map.get(start..start + header.record_len())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        if !checksum_matches(record) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "filter record checksum mismatch"));
+        }
+
+        let data = &record[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + header.data_len as usize];
+        Ok(Filter::from_slice(header.n, header.p, header.m, data))
+    }
+}
+
+/// An iterator over the filters of a range of heights.
+///
+/// This is created by [`FilterStore::range`][1].
+///
+/// [1]: struct.FilterStore.html#method.range
+#[derive(Debug)]
+pub struct Range<'a> {
+    store: &'a FilterStore,
+    entries: ::std::vec::IntoIter<(u32, Entry)>,
+}
+
+impl<'a> Iterator for Range<'a> {
+    type Item = io::Result<(u32, Sha256dHash, Filter)>;
+
+    fn next(&mut self) -> Option<io::Result<(u32, Sha256dHash, Filter)>> {
+        self.entries.next().map(|(height, entry)| {
+            self.store.read_filter(entry.offset)
+                .map(|filter| (height, entry.block_hash, filter))
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+#[cfg(feature = "rescan")]
+impl FilterSource for FilterStore {
+    type Error = io::Error;
+
+    fn get_filter(&mut self, height: u32) -> io::Result<Option<(Sha256dHash, Filter)>> {
+        match self.heights.get(&height) {
+            Some(entry) => self.read_filter(entry.offset).map(|f| Some((entry.block_hash, f))),
+            None => Ok(None),
+        }
+    }
+}
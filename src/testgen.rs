@@ -0,0 +1,227 @@
+//! Generation of BIP158 test vectors.
+//!
+//! The rows written by a [`TestVectorWriter`][1] have the columns and layout
+//! of `tests/testnet-19.json`, so the test vectors can be extended with
+//! blocks of other networks (testnet, signet, regtest...). The blocks are
+//! given either decoded or as the raw hex returned by the `getblock <hash> 0`
+//! RPC call, along with the scripts of the outputs they spend and the filter
+//! header of the previous block, e.g. from `getblockfilter`.
+//!
+//! [1]: struct.TestVectorWriter.html
+
+use std::error;
+use std::fmt;
+use std::io::{self, Write};
+
+use bitcoin::blockdata::block::Block;
+use bitcoin::blockdata::script::Script;
+use bitcoin::network::serialize::{deserialize, serialize, BitcoinHash};
+
+use builder::build_basic_filter_with_prev_scripts;
+use {decode_hex, Filter, FilterHeader};
+
+/// The description of the columns, the first row of the test vectors.
+pub const COLUMNS: &str = "Block Height,Block Hash,Block,[Prev Output Scripts for Block],Previous Basic Header,\
+                           Basic Filter,Basic Header,Notes";
+
+/// A row of the BIP158 test vectors.
+#[derive(Debug, Clone)]
+pub struct TestVector {
+    /// The height of the block.
+    pub height: u32,
+    /// The block.
+    pub block: Block,
+    /// The scripts of the outputs spent by the block, in the order of its
+    /// inputs.
+    pub prev_scripts: Vec<Script>,
+    /// The filter header of the previous block.
+    pub prev_header: FilterHeader,
+    /// The basic filter of the block.
+    pub filter: Filter,
+    /// The filter header of the block.
+    pub header: FilterHeader,
+    /// A comment on what the block covers.
+    pub notes: String,
+}
+
+impl TestVector {
+    // Constructors
+
+    /// Builds the basic filter of `block` and its filter header.
+    pub fn new(height: u32, block: Block, prev_scripts: Vec<Script>, prev_header: FilterHeader, notes: &str)
+        -> TestVector
+    {
+        let filter = build_basic_filter_with_prev_scripts(&block, &prev_scripts);
+        let header = filter.filter_header(&prev_header);
+        TestVector {
+            height,
+            block,
+            prev_scripts,
+            prev_header,
+            filter,
+            header,
+            notes: notes.to_owned(),
+        }
+    }
+
+    /// Like [`new`][1], but with the block and the previous output scripts
+    /// in hex.
+    ///
+    /// [1]: #method.new
+    pub fn from_hex(height: u32, block: &str, prev_scripts: &[&str], prev_header: FilterHeader, notes: &str)
+        -> Result<TestVector, TestGenError>
+    {
+        let block = decode_hex(block).ok_or(TestGenError::InvalidHex)?;
+        let block = deserialize(&block).map_err(|_| TestGenError::InvalidBlock)?;
+        let prev_scripts = prev_scripts.iter()
+            .map(|script| decode_hex(script).map(Script::from).ok_or(TestGenError::InvalidHex))
+            .collect::<Result<Vec<Script>, TestGenError>>()?;
+
+        Ok(TestVector::new(height, block, prev_scripts, prev_header, notes))
+    }
+
+    // Accessors
+
+    /// Writes the row as JSON, indented as in the test vector files.
+    ///
+    /// An `InvalidInput` error is returned if the filter can't be
+    /// [serialized][1].
+    ///
+    /// [1]: ../struct.Filter.html#method.try_to_nbytes
+    pub fn write_json<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        if !self.filter.has_nm_modulus() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the filter modulus isn't N * M"));
+        }
+        let block = serialize(&self.block).expect("writing to a vector can't fail");
+
+        writeln!(writer, "    [")?;
+        writeln!(writer, "        {},", self.height)?;
+        writeln!(writer, "        \"{}\",", self.block.bitcoin_hash().be_hex_string())?;
+        writeln!(writer, "        \"{}\",", Hex(&block))?;
+        if self.prev_scripts.is_empty() {
+            writeln!(writer, "        [],")?;
+        } else {
+            writeln!(writer, "        [")?;
+            for (i, script) in self.prev_scripts.iter().enumerate() {
+                let separator = if i + 1 < self.prev_scripts.len() { "," } else { "" };
+                writeln!(writer, "            \"{}\"{}", Hex(&script.data()), separator)?;
+            }
+            writeln!(writer, "        ],")?;
+        }
+        writeln!(writer, "        \"{}\",", self.prev_header)?;
+        writeln!(writer, "        \"{:x}\",", self.filter)?;
+        writeln!(writer, "        \"{}\",", self.header)?;
+        writeln!(writer, "        \"{}\"", JsonEscape(&self.notes))?;
+        write!(writer, "    ]")
+    }
+}
+
+/// Writes test vector rows as a JSON array.
+#[derive(Debug)]
+pub struct TestVectorWriter<W: Write> {
+    writer: W,
+    rows: usize,
+}
+
+impl<W: Write> TestVectorWriter<W> {
+    // Constructors
+
+    /// Creates a `TestVectorWriter` writing to `writer`.
+    pub fn new(writer: W) -> TestVectorWriter<W> {
+        TestVectorWriter {
+            writer,
+            rows: 0,
+        }
+    }
+
+    // Accessors
+
+    /// Returns the number of rows written, not counting the columns.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    // Modifiers
+
+    /// Writes a row, preceded by the [columns][1] if it's the first one.
+    ///
+    /// [1]: constant.COLUMNS.html
+    pub fn write(&mut self, test_vector: &TestVector) -> io::Result<()> {
+        if self.rows == 0 {
+            self.write_columns()?;
+        }
+        writeln!(self.writer, ",")?;
+        test_vector.write_json(&mut self.writer)?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Closes the JSON array and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.rows == 0 {
+            self.write_columns()?;
+        }
+        writeln!(self.writer, "\n]")?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_columns(&mut self) -> io::Result<()> {
+        write!(self.writer, "[\n    [\"{}\"]", COLUMNS)
+    }
+}
+
+/// Errors returned when a test vector can't be generated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestGenError {
+    /// The block or a script isn't valid hex.
+    InvalidHex,
+    /// The block can't be decoded.
+    InvalidBlock,
+}
+
+impl fmt::Display for TestGenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TestGenError::InvalidHex => write!(f, "invalid hex string"),
+            TestGenError::InvalidBlock => write!(f, "invalid block"),
+        }
+    }
+}
+
+impl error::Error for TestGenError {
+    fn description(&self) -> &str {
+        match *self {
+            TestGenError::InvalidHex => "invalid hex string",
+            TestGenError::InvalidBlock => "invalid block",
+        }
+    }
+}
+
+struct Hex<'a>(&'a [u8]);
+
+impl<'a> fmt::Display for Hex<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+struct JsonEscape<'a>(&'a str);
+
+impl<'a> fmt::Display for JsonEscape<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        Ok(())
+    }
+}
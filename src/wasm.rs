@@ -0,0 +1,39 @@
+//! WebAssembly bindings, to match filters from JavaScript.
+//!
+//! The crate itself compiles for `wasm32-unknown-unknown`, this module only
+//! adds the entry points exported with `wasm-bindgen`.
+
+use js_sys::{Array, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use {BASIC_FILTER_M, BASIC_FILTER_P, Filter, FilterKey, KEY_SIZE};
+
+/// Checks whether any of `scripts` is likely to be a member of a basic
+/// filter.
+///
+/// `filter_bytes` is the filter prefixed by its set length (N), as served in
+/// `cfilter` messages, `key` is the filter key (the first 16 bytes of the
+/// block hash) and `scripts` an array of `Uint8Array`.
+#[wasm_bindgen]
+pub fn match_scripts(filter_bytes: &[u8], key: &[u8], scripts: Array) -> Result<bool, JsValue> {
+    let filter = Filter::from_nbytes(BASIC_FILTER_P, BASIC_FILTER_M, filter_bytes)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+    if key.len() != KEY_SIZE {
+        return Err(JsValue::from_str("invalid filter key length"));
+    }
+    let mut key_bytes = [0u8; KEY_SIZE];
+    key_bytes.copy_from_slice(key);
+
+    let scripts = scripts.iter()
+        .map(|script| {
+            script.dyn_into::<Uint8Array>()
+                .map(|script| script.to_vec())
+                .map_err(|_| JsValue::from_str("scripts must be Uint8Array"))
+        })
+        .collect::<Result<Vec<Vec<u8>>, JsValue>>()?;
+
+    filter.is_member_any(FilterKey::from(key_bytes), &scripts)
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+}
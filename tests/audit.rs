@@ -0,0 +1,94 @@
+extern crate bitcoin;
+extern crate bitcoin_gcs;
+
+use std::env;
+use std::fs;
+
+use bitcoin::blockdata::block::{Block, BlockHeader};
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{Transaction, TxOut};
+use bitcoin::network::serialize::BitcoinHash;
+use bitcoin::util::hash::Sha256dHash;
+
+use bitcoin_gcs::audit::{verify_store, FullBlockSource, Inconsistency};
+use bitcoin_gcs::builder::build_basic_filter_with_prev_scripts;
+use bitcoin_gcs::store::FilterStore;
+use bitcoin_gcs::{BASIC_FILTER_M, BASIC_FILTER_P, Filter, FilterHeaderChain, FilterKey};
+
+struct Blocks(Vec<Block>);
+
+impl FullBlockSource for Blocks {
+    type Error = ();
+
+    fn block(&mut self, height: u32) -> Result<Option<(Block, Vec<Script>)>, ()> {
+        Ok(self.0.get(height as usize).map(|block| (block.clone(), Vec::new())))
+    }
+}
+
+fn block(height: u32) -> Block {
+    let tx = Transaction {
+        version: 1,
+        lock_time: 0,
+        input: vec![],
+        output: vec![TxOut { value: 1000, script_pubkey: Script::from(vec![0x00, 0x14, height as u8]) }],
+        witness: vec![],
+    };
+
+    Block {
+        header: BlockHeader {
+            version: 1,
+            prev_blockhash: Sha256dHash::from(&[1u8; 32][..]),
+            merkle_root: Sha256dHash::from(&[2u8; 32][..]),
+            time: 0,
+            bits: 0,
+            nonce: height,
+        },
+        txdata: vec![tx],
+    }
+}
+
+#[test]
+fn audit() {
+    let path = env::temp_dir().join(format!("bitcoin-gcs-audit-{}.dat", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let blocks = Blocks((0..20).map(block).collect());
+    let mut store = FilterStore::open(&path).unwrap();
+    let mut headers = FilterHeaderChain::new();
+    for (height, block) in blocks.0.iter().enumerate() {
+        let filter = build_basic_filter_with_prev_scripts(block, &[]);
+        store.put(height as u32, &block.bitcoin_hash(), &filter).unwrap();
+        headers.push_filter(&filter);
+    }
+
+    let mut blocks = blocks;
+    let report = verify_store(&store, &headers, &mut blocks, 1.0).unwrap();
+    assert_eq!(report.checked, (0..20).collect::<Vec<u32>>());
+    assert!(report.is_consistent());
+    assert!(verify_store(&store, &headers, &mut blocks, 0.0).unwrap().checked.is_empty());
+
+    // A filter built from the wrong entries, with its header, and a filter
+    // whose header is wrong.
+    let bogus = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::default(), [[0u8]]);
+    store.put(19, &blocks.0[19].bitcoin_hash(), &bogus).unwrap();
+    headers.truncate(19);
+    headers.push_filter(&bogus);
+    store.put(5, &blocks.0[5].bitcoin_hash(), &bogus).unwrap();
+    blocks.0.truncate(15);
+
+    let report = verify_store(&store, &headers, &mut blocks, 1.0).unwrap();
+    match report.inconsistencies[..] {
+        [Inconsistency::HeaderMismatch(5), Inconsistency::MissingBlock(15), Inconsistency::MissingBlock(16),
+         Inconsistency::MissingBlock(17), Inconsistency::MissingBlock(18), Inconsistency::MissingBlock(19)] => {}
+        ref inconsistencies => panic!("unexpected inconsistencies {:?}", inconsistencies),
+    }
+
+    blocks.0 = (0..20).map(block).collect();
+    let report = verify_store(&store, &headers, &mut blocks, 1.0).unwrap();
+    match report.inconsistencies[..] {
+        [Inconsistency::HeaderMismatch(5), Inconsistency::FilterMismatch(19, _)] => {}
+        ref inconsistencies => panic!("unexpected inconsistencies {:?}", inconsistencies),
+    }
+
+    fs::remove_file(&path).unwrap();
+}
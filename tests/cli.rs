@@ -0,0 +1,73 @@
+extern crate serde_json;
+
+use std::env;
+use std::fs;
+use std::process::Command;
+
+// The basic filter of the mainnet genesis block, and its only output script.
+const GENESIS_FILTER: &str = "017fa880";
+const GENESIS_HASH: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+const GENESIS_SCRIPT: &str = "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51\
+                              ec112de5c384df7ba0b8d578a4c702b6bf11d5fac";
+
+const TESTNET_19: &str = include_str!("testnet-19.json");
+
+fn gcs_filter(args: &[&str]) -> (bool, String) {
+    let (success, stdout, _) = gcs_filter_stderr(args);
+    (success, stdout)
+}
+
+fn gcs_filter_stderr(args: &[&str]) -> (bool, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_gcs-filter")).args(args).output().unwrap();
+    (output.status.success(), String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+fn inspect() {
+    let (success, stdout) = gcs_filter(&["inspect", GENESIS_FILTER, "--values"]);
+    assert!(success);
+    assert!(stdout.starts_with("n: 1\np: 19\nm: 784931\nserialized size: 4\n"));
+    assert!(stdout.contains("encoding: valid\n"));
+    assert_eq!(stdout.lines().count(), 9);
+
+    assert!(!gcs_filter(&["inspect", "zz"]).0);
+    assert!(!gcs_filter(&["unknown"]).0);
+}
+
+#[test]
+fn match_scripts() {
+    let path = env::temp_dir().join(format!("bitcoin-gcs-cli-{}.txt", std::process::id()));
+    fs::write(&path, format!("{}\n\n0014aa\n", GENESIS_SCRIPT)).unwrap();
+
+    let (success, stdout) = gcs_filter(&["match", GENESIS_FILTER, GENESIS_HASH, path.to_str().unwrap()]);
+    assert!(success);
+    assert_eq!(stdout, format!("{} match\n0014aa no match\n", GENESIS_SCRIPT));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn build_prev_scripts() {
+    // The testnet block 1263442, spending an output.
+    let vectors: serde_json::Value = serde_json::from_str(TESTNET_19).unwrap();
+    let row = vectors.as_array().unwrap().iter().find(|row| row[0] == 1263442).unwrap();
+    let block = env::temp_dir().join(format!("bitcoin-gcs-cli-block-{}.txt", std::process::id()));
+    let prev_scripts = env::temp_dir().join(format!("bitcoin-gcs-cli-prev-{}.txt", std::process::id()));
+    fs::write(&block, row[2].as_str().unwrap()).unwrap();
+    let scripts = row[3].as_array().unwrap().iter().map(|s| s.as_str().unwrap()).collect::<Vec<&str>>();
+    fs::write(&prev_scripts, scripts.join("\n")).unwrap();
+    let expected = format!("filter: {}\n", row[5].as_str().unwrap());
+
+    let (success, stdout, stderr) = gcs_filter_stderr(&["build", block.to_str().unwrap()]);
+    assert!(success);
+    assert!(stderr.starts_with("warning: the block spends 1 outputs"));
+    assert!(!stdout.ends_with(&expected));
+
+    let (success, stdout, stderr) = gcs_filter_stderr(&["build", block.to_str().unwrap(), prev_scripts.to_str().unwrap()]);
+    assert!(success);
+    assert_eq!(stderr, "");
+    assert!(stdout.ends_with(&expected));
+
+    fs::remove_file(&block).unwrap();
+    fs::remove_file(&prev_scripts).unwrap();
+}
@@ -0,0 +1,208 @@
+extern crate bitcoin;
+extern crate bitcoin_gcs;
+extern crate futures;
+
+use bitcoin::util::hash::Sha256dHash;
+
+use futures::future::{self, FutureResult};
+use futures::Future;
+
+use bitcoin_gcs::client::{Client, ClientError, PeerEvent, Request, Response, Transport};
+use bitcoin_gcs::p2p::{CFCheckpt, CFHeaders, CFilter, NODE_COMPACT_FILTERS};
+use bitcoin_gcs::{BASIC_FILTER_M, BASIC_FILTER_P, BASIC_FILTER_TYPE, Filter, FilterHeader, FilterHeaderChain, FilterKey};
+
+/// A peer serving the filters of a chain of blocks whose filter contains
+/// their height.
+struct Peer {
+    block_hashes: Vec<Sha256dHash>,
+    filters: Vec<Filter>,
+    headers: FilterHeaderChain,
+    services: u64,
+    bad_checkpoints: bool,
+    failures: u32,
+    requests: u32,
+    events: Vec<PeerEvent>,
+}
+
+impl Peer {
+    fn new(blocks: u32) -> Peer {
+        let block_hashes = (0..blocks)
+            .map(|height| {
+                let mut hash = [0u8; 32];
+                hash[..4].copy_from_slice(&height.to_le_bytes());
+                Sha256dHash::from(&hash[..])
+            })
+            .collect::<Vec<Sha256dHash>>();
+        let filters = block_hashes.iter()
+            .zip(0..blocks)
+            .map(|(hash, height)| {
+                Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::from(hash), [height.to_le_bytes()])
+            })
+            .collect::<Vec<Filter>>();
+        let headers = FilterHeaderChain::from_filters(block_hashes.iter().cloned().zip(filters.iter().cloned()));
+
+        Peer {
+            block_hashes,
+            filters,
+            headers,
+            services: NODE_COMPACT_FILTERS,
+            bad_checkpoints: false,
+            failures: 0,
+            requests: 0,
+            events: Vec::new(),
+        }
+    }
+
+    fn height(&self, hash: &Sha256dHash) -> usize {
+        self.block_hashes.iter().position(|h| h == hash).unwrap()
+    }
+}
+
+impl Transport for Peer {
+    type Error = &'static str;
+    type Future = FutureResult<Response, &'static str>;
+
+    fn services(&self) -> u64 {
+        self.services
+    }
+
+    fn request(&mut self, request: Request) -> Self::Future {
+        self.requests += 1;
+        if self.failures > 0 {
+            self.failures -= 1;
+            return future::err("timeout");
+        }
+
+        let response = match request {
+            Request::GetCFCheckpt(getcfcheckpt) => {
+                let mut filter_headers = self.headers.checkpoints();
+                if self.bad_checkpoints {
+                    filter_headers[1] = FilterHeader::from_hash(Sha256dHash::from(&[1u8; 32][..]));
+                }
+                Response::CFCheckpt(CFCheckpt {
+                    filter_type: getcfcheckpt.filter_type,
+                    stop_hash: getcfcheckpt.stop_hash,
+                    filter_headers,
+                })
+            }
+            Request::GetCFHeaders(getcfheaders) => {
+                let start = getcfheaders.start_height as usize;
+                let stop = self.height(&getcfheaders.stop_hash);
+                Response::CFHeaders(CFHeaders {
+                    filter_type: getcfheaders.filter_type,
+                    stop_hash: getcfheaders.stop_hash,
+                    previous_filter_header: match start {
+                        0 => FilterHeader::zero(),
+                        _ => *self.headers.get(start as u32 - 1).unwrap(),
+                    },
+                    filter_hashes: self.filters[start..=stop].iter().map(|f| f.filter_hash()).collect(),
+                })
+            }
+            Request::GetCFilters(getcfilters) => {
+                let start = getcfilters.start_height as usize;
+                let stop = self.height(&getcfilters.stop_hash);
+                Response::CFilters((start..=stop)
+                    .map(|height| CFilter {
+                        filter_type: getcfilters.filter_type,
+                        block_hash: self.block_hashes[height],
+                        filter: self.filters[height].clone(),
+                    })
+                    .collect())
+            }
+        };
+        future::ok(response)
+    }
+
+    fn report(&mut self, event: PeerEvent) {
+        self.events.push(event);
+    }
+}
+
+#[test]
+fn sync() {
+    let peer = Peer::new(4500);
+    let block_hashes = peer.block_hashes.clone();
+    let expected = peer.headers.clone();
+
+    let client = Client::new(peer, BASIC_FILTER_TYPE, block_hashes).unwrap();
+    let client = client.fetch_checkpoints().and_then(Client::sync_headers).wait().unwrap();
+    assert_eq!(client.checkpoints().len(), 4);
+    assert_eq!(client.headers(), &expected);
+    // One cfcheckpt and three cfheaders.
+    assert_eq!(client.transport().requests, 4);
+
+    let (client, filters) = client.get_filters(999, 2001).wait().unwrap();
+    assert_eq!(filters.len(), 1003);
+    assert_eq!(filters[0].block_hash, client.transport().block_hashes[999]);
+    assert_eq!(client.transport().requests, 6);
+    assert!(client.transport().events.is_empty());
+
+    match client.get_filters(4000, 4500).wait() {
+        Err(ClientError::UnknownHeight(4500)) => {}
+        result => panic!("unexpected result {:?}", result.map(|(_, filters)| filters.len())),
+    }
+}
+
+#[test]
+fn sync_from_checkpoint() {
+    let peer = Peer::new(2500);
+    let block_hashes = peer.block_hashes.clone();
+    let expected = peer.headers.clone();
+
+    let mut client = Client::new(peer, BASIC_FILTER_TYPE, block_hashes).unwrap();
+    client.set_headers(FilterHeaderChain::from_checkpoint(1999, *expected.get(1999).unwrap()));
+    let client = client.sync_headers().wait().unwrap();
+    assert_eq!(client.headers().tip(), expected.tip());
+    assert_eq!(client.headers().get(1000), None);
+    // A single cfheaders for the blocks above the checkpoint.
+    assert_eq!(client.transport().requests, 1);
+
+    let (_, filters) = client.get_filters(2000, 2499).wait().unwrap();
+    assert_eq!(filters.len(), 500);
+}
+
+#[test]
+fn checkpoint_mismatch() {
+    let mut peer = Peer::new(2500);
+    peer.bad_checkpoints = true;
+    let block_hashes = peer.block_hashes.clone();
+
+    let client = Client::new(peer, BASIC_FILTER_TYPE, block_hashes).unwrap();
+    match client.fetch_checkpoints().and_then(Client::sync_headers).wait() {
+        Err(ClientError::CheckpointMismatch(mismatch)) => assert_eq!(mismatch.height, 2000),
+        result => panic!("unexpected result {:?}", result.map(|client| client.headers().len())),
+    }
+}
+
+#[test]
+fn retries() {
+    let mut peer = Peer::new(10);
+    peer.failures = 3;
+    let block_hashes = peer.block_hashes.clone();
+
+    let client = Client::new(peer, BASIC_FILTER_TYPE, block_hashes).unwrap();
+    let client = client.sync_headers().wait().unwrap();
+    assert_eq!(client.headers().len(), 10);
+    assert_eq!(client.transport().events, vec![PeerEvent::RequestFailed; 3]);
+
+    let mut peer = client.into_transport();
+    peer.failures = 1;
+    let block_hashes = peer.block_hashes.clone();
+    let mut client = Client::new(peer, BASIC_FILTER_TYPE, block_hashes).unwrap();
+    client.set_max_retries(0);
+    match client.sync_headers().wait() {
+        Err(ClientError::Transport("timeout")) => {}
+        result => panic!("unexpected result {:?}", result.map(|client| client.headers().len())),
+    }
+}
+
+#[test]
+fn no_compact_filters() {
+    let mut peer = Peer::new(10);
+    peer.services = 1;
+
+    match Client::new(peer, BASIC_FILTER_TYPE, Vec::new()) {
+        Err(ClientError::NoCompactFilters) => {}
+        result => panic!("unexpected result {:?}", result.map(|_| ())),
+    }
+}
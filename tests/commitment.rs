@@ -0,0 +1,53 @@
+extern crate bitcoin_gcs;
+
+use bitcoin_gcs::commitment::{FilterCommitment, MerkleProof};
+use bitcoin_gcs::{BASIC_FILTER_M, BASIC_FILTER_P, Filter, FilterHeader, FilterHeaderChain, FilterKey};
+
+fn filters(n: u32) -> Vec<Filter> {
+    (0..n)
+        .map(|i| Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::default(), [i.to_le_bytes()]))
+        .collect()
+}
+
+#[test]
+fn merkle_proofs() {
+    for n in 1..10 {
+        let filters = filters(n);
+        let commitment = FilterCommitment::new(&filters);
+        assert_eq!(commitment.filter_hashes().len(), n as usize);
+
+        for (i, filter) in filters.iter().enumerate() {
+            let proof = commitment.prove(i).unwrap();
+            assert!(proof.verify(filter, &commitment.root()));
+            if n > 1 {
+                assert!(!proof.verify(&filters[(i + 1) % filters.len()], &commitment.root()));
+            }
+        }
+        assert_eq!(commitment.prove(n as usize), None);
+    }
+
+    let filters = filters(3);
+    let commitment = FilterCommitment::new(&filters);
+    let proof = commitment.prove(2).unwrap();
+    assert_eq!(proof.branch().len(), 2);
+
+    // The third filter is paired with itself, it can't be proven as a fourth
+    // one.
+    let moved = MerkleProof::new(3, 3, proof.branch().to_vec());
+    assert!(!moved.verify(&filters[2], &commitment.root()));
+    let truncated = MerkleProof::new(2, 3, proof.branch()[..1].to_vec());
+    assert_eq!(truncated.root(&filters[2].filter_hash()), None);
+}
+
+#[test]
+fn commitment_headers() {
+    let commitments = (1..5).map(|n| FilterCommitment::new(&filters(n))).collect::<Vec<_>>();
+
+    let mut chain = FilterHeaderChain::new();
+    let mut prev_header = FilterHeader::zero();
+    for commitment in &commitments {
+        prev_header = commitment.header(&prev_header);
+        assert_eq!(chain.push_filter_hash(&commitment.root()), prev_header);
+    }
+    assert_eq!(chain.tip(), prev_header);
+}
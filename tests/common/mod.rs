@@ -0,0 +1,14 @@
+//! Filters and block hashes shared by the storage tests.
+
+use bitcoin::util::hash::Sha256dHash;
+
+use bitcoin_gcs::{BASIC_FILTER_M, BASIC_FILTER_P, Filter, FilterKey};
+
+pub fn filter(i: u8) -> Filter {
+    let data = vec![vec![i], vec![i, i]];
+    Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::default(), &data)
+}
+
+pub fn hash(i: u8) -> Sha256dHash {
+    Sha256dHash::from(&[i; 32][..])
+}
@@ -0,0 +1,77 @@
+extern crate bitcoin;
+extern crate bitcoin_gcs;
+#[cfg(feature = "rocksdb-store")]
+extern crate rocksdb;
+extern crate sled;
+
+mod common;
+
+use std::env;
+use std::fs;
+#[cfg(feature = "rocksdb-store")]
+use std::sync::Arc;
+
+#[cfg(feature = "rocksdb-store")]
+use bitcoin_gcs::database::RocksDatabase;
+use bitcoin_gcs::database::{FilterDatabase, SledDatabase};
+use bitcoin_gcs::store::FilterStore;
+
+use common::{filter, hash};
+
+/// Stores the same filters in `db`, checking it behaves like the others.
+fn check_database<D: FilterDatabase>(db: &mut D)
+where
+    D::Error: ::std::fmt::Debug,
+{
+    for i in 0..10 {
+        db.put(u32::from(i), &hash(i), &filter(i)).unwrap();
+    }
+    // Replace the tip, as in a reorganization.
+    db.put(9, &hash(99), &filter(99)).unwrap();
+
+    assert_eq!(db.get_by_height(3).unwrap(), Some((hash(3), filter(3))));
+    assert_eq!(db.get_by_hash(&hash(99)).unwrap(), Some(filter(99)));
+    assert_eq!(db.get_by_hash(&hash(9)).unwrap(), None);
+    assert_eq!(db.get_by_height(10).unwrap(), None);
+
+    let heights = db.iter_range(6..=8)
+        .map(|r| r.unwrap().0)
+        .collect::<Vec<u32>>();
+    assert_eq!(heights, vec![6, 7, 8]);
+
+    db.prune_below(5).unwrap();
+    assert_eq!(db.get_by_height(4).unwrap(), None);
+    assert_eq!(db.get_by_hash(&hash(4)).unwrap(), None);
+
+    let records = db.iter_range(..).collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(records.len(), 5);
+    assert_eq!(records[4], (9, hash(99), filter(99)));
+}
+
+#[test]
+fn flat_file_database() {
+    let path = env::temp_dir().join(format!("bitcoin-gcs-database-{}.dat", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    check_database(&mut FilterStore::open(&path).unwrap());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn sled_database() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    check_database(&mut SledDatabase::new(db.open_tree("filters").unwrap()));
+}
+
+#[cfg(feature = "rocksdb-store")]
+#[test]
+fn rocksdb_database() {
+    let path = env::temp_dir().join(format!("bitcoin-gcs-rocksdb-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+
+    let db = Arc::new(rocksdb::DB::open_default(&path).unwrap());
+    check_database(&mut RocksDatabase::new(db, b"filters/"));
+
+    fs::remove_dir_all(&path).unwrap();
+}
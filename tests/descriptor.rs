@@ -0,0 +1,41 @@
+extern crate bitcoin;
+extern crate bitcoin_gcs;
+
+use bitcoin::blockdata::script::Script;
+
+use bitcoin_gcs::descriptor::{DescriptorWatch, DEFAULT_GAP_LIMIT};
+use bitcoin_gcs::WatchList;
+
+fn script(index: u32) -> Script {
+    let mut data = vec![0x00, 0x14];
+    data.extend((0..5).flat_map(|_| index.to_le_bytes().to_vec()));
+    Script::from(data)
+}
+
+#[test]
+fn gap_limit() {
+    let mut watch = DescriptorWatch::new(script, 0..10);
+    assert_eq!(watch.range(), 0..10);
+    assert_eq!(watch.gap_limit(), DEFAULT_GAP_LIMIT);
+    assert_eq!(watch.highest_used(), None);
+
+    let mut watch_list = WatchList::new();
+    watch.watch(&mut watch_list);
+    assert_eq!(watch_list.len(), 10);
+
+    // Using an index derives the scripts up to the gap limit after it.
+    assert_eq!(watch.set_gap_limit(5), &[] as &[Script]);
+    assert_eq!(watch.index_of(&script(7)), Some(7));
+    assert_eq!(watch.mark_used(7), &[script(10), script(11), script(12)]);
+    assert_eq!(watch.range(), 0..13);
+    assert_eq!(watch.highest_used(), Some(7));
+
+    // Lower indices don't change the highest used one.
+    assert!(watch.mark_used(3).is_empty());
+    assert_eq!(watch.highest_used(), Some(7));
+
+    assert_eq!(watch.set_gap_limit(6), &[script(13)]);
+    assert_eq!(watch.script(13), Some(&script(13)));
+    assert_eq!(watch.script(14), None);
+    assert_eq!(watch.index_of(&script(14)), None);
+}
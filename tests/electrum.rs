@@ -0,0 +1,46 @@
+extern crate bitcoin;
+extern crate bitcoin_gcs;
+extern crate hex;
+
+use bitcoin::blockdata::script::Script;
+
+use bitcoin_gcs::electrum::{ScriptHash, ScriptHashIndex, UnknownScriptHash};
+
+// The output script of the genesis block coinbase address, as in the
+// Electrum protocol documentation.
+const GENESIS_SCRIPT: &str = "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac";
+const GENESIS_SCRIPT_HASH: &str = "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161";
+
+fn script(s: &str) -> Script {
+    Script::from(hex::decode(s).unwrap())
+}
+
+#[test]
+fn script_hash() {
+    let script_hash = ScriptHash::from_script(&script(GENESIS_SCRIPT));
+    assert_eq!(script_hash.to_string(), GENESIS_SCRIPT_HASH);
+    assert_eq!(GENESIS_SCRIPT_HASH.parse::<ScriptHash>(), Ok(script_hash));
+
+    let empty = ScriptHash::from_script(&Script::new());
+    assert_eq!(hex::encode(empty.as_bytes()),
+               "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+
+    assert!("8b01df".parse::<ScriptHash>().is_err());
+    assert!(GENESIS_SCRIPT_HASH.replace('8', "x").parse::<ScriptHash>().is_err());
+}
+
+#[test]
+fn script_hash_index() {
+    let mut index = ScriptHashIndex::new();
+    let genesis = index.insert(script(GENESIS_SCRIPT));
+    let other = index.insert(script("0014751e76e8199196d454941c45d1b3a323f1433bd6"));
+    assert_eq!(index.len(), 2);
+    assert_eq!(index.script(&genesis), Some(&script(GENESIS_SCRIPT)));
+
+    let items = index.query_items(&[other, genesis]).unwrap();
+    assert_eq!(items, vec![hex::decode("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap(),
+                           hex::decode(GENESIS_SCRIPT).unwrap()]);
+
+    assert_eq!(index.remove(&genesis), Some(script(GENESIS_SCRIPT)));
+    assert_eq!(index.query_items(&[other, genesis]), Err(UnknownScriptHash(genesis)));
+}
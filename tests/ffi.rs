@@ -0,0 +1,49 @@
+extern crate bitcoin_gcs;
+
+use std::ptr;
+
+use bitcoin_gcs::ffi::*;
+use bitcoin_gcs::{BASIC_FILTER_M, BASIC_FILTER_P};
+
+const KEY: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+#[test]
+fn build_and_match() {
+    let items: [&[u8]; 3] = [b"one", b"two", b"three"];
+    let ptrs = items.iter().map(|i| i.as_ptr()).collect::<Vec<_>>();
+    let lens = items.iter().map(|i| i.len()).collect::<Vec<_>>();
+
+    unsafe {
+        let filter = gcs_filter_build(BASIC_FILTER_P, BASIC_FILTER_M, KEY.as_ptr(),
+                                      ptrs.as_ptr(), lens.as_ptr(), 3);
+        assert!(!filter.is_null());
+        assert_eq!(gcs_filter_n(filter), 3);
+
+        assert_eq!(gcs_filter_match(filter, KEY.as_ptr(), ptrs[1..].as_ptr(), lens[1..].as_ptr(), 1), 1);
+        let other: &[u8] = b"four";
+        assert_eq!(gcs_filter_match(filter, KEY.as_ptr(), &other.as_ptr(), &other.len(), 1), 0);
+        assert_eq!(gcs_filter_match(filter, ptr::null(), ptrs.as_ptr(), lens.as_ptr(), 3), -1);
+
+        let mut len = 0;
+        let data = gcs_filter_data(filter, &mut len);
+        let copy = gcs_filter_from_bytes(3, BASIC_FILTER_P, BASIC_FILTER_M, data, len);
+        assert_eq!(gcs_filter_match(copy, KEY.as_ptr(), ptrs.as_ptr(), lens.as_ptr(), 3), 1);
+
+        gcs_filter_free(copy);
+        gcs_filter_free(filter);
+        gcs_filter_free(ptr::null_mut());
+    }
+}
+
+#[test]
+fn modulus_overflow() {
+    let items: [&[u8]; 3] = [b"one", b"two", b"three"];
+    let ptrs = items.iter().map(|i| i.as_ptr()).collect::<Vec<_>>();
+    let lens = items.iter().map(|i| i.len()).collect::<Vec<_>>();
+
+    unsafe {
+        let m = u64::MAX / 2;
+        assert!(gcs_filter_build(BASIC_FILTER_P, m, KEY.as_ptr(), ptrs.as_ptr(), lens.as_ptr(), 3).is_null());
+        assert!(gcs_filter_from_bytes(3, BASIC_FILTER_P, m, [0u8; 4].as_ptr(), 4).is_null());
+    }
+}
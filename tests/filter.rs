@@ -0,0 +1,781 @@
+extern crate bitcoin_gcs;
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::thread;
+
+use bitcoin_gcs::golomb::GolombEncoder;
+use bitcoin_gcs::{diff, match_blocks, optimal_m, optimal_p, BASIC_FILTER_M, BASIC_FILTER_P, DecodeError, DecodeLimits, EncodeError, ExactFilter, Filter, FilterDiff, FilterKey, FilterParams,
+                  FilterRef, Match, MatchEngine, MemberProof, GcsHasher, LimitError, ParamsError, ParseFilterError, QuerySet, RollingFilter, SetError, SmallFilter, WatchList, DEFAULT_CACHED_KEYS, map_to_range, reduce, siphash24,
+                  MAX_FILTER_SIZE, MAX_QUOTIENT_BUCKETS};
+
+const KEY: (u64, u64) = (0x0706050403020100, 0x0f0e0d0c0b0a0908);
+
+fn items(n: usize) -> Vec<Vec<u8>> {
+    (0..n).map(|i| format!("item {}", i).into_bytes()).collect()
+}
+
+#[test]
+fn build_and_match() {
+    let key = FilterKey::from(KEY);
+    let data = items(100);
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data);
+
+    assert_eq!(filter.n(), 100);
+    for item in &data {
+        assert_eq!(filter.is_member(key, item), Ok(true));
+    }
+    assert_eq!(filter.is_member_any(key, &data[50..51]), Ok(true));
+    assert!(filter.match_all(key, &data).unwrap().iter().all(|m| *m));
+
+    let m = filter.match_any_verbose(key, vec![&b"other"[..], &data[7]]).unwrap();
+    assert!(m.matched);
+    assert_eq!(m.query_index, Some(1));
+
+    let values = filter.values().collect::<Result<Vec<u64>, _>>().unwrap();
+    assert_eq!(values.len(), 100);
+    assert!(values.windows(2).all(|w| w[0] <= w[1]));
+}
+
+#[test]
+fn build_and_match_map_to_the_same_range() {
+    let key = FilterKey::from(KEY);
+    let data = items(500);
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data);
+
+    let mut mapped = data.iter()
+        .map(|item| map_to_range(key, item, filter.n(), filter.m()))
+        .collect::<Vec<u64>>();
+    mapped.sort();
+    let values = filter.values().collect::<Result<Vec<u64>, DecodeError>>().unwrap();
+    assert_eq!(mapped, values);
+    assert!(mapped.iter().all(|value| *value < filter.modulus()));
+    assert_eq!(map_to_range(key, &data[0], filter.n(), filter.m()),
+               reduce(siphash24(key, &data[0]), filter.modulus()));
+
+    // The values are spread over the whole range, not just below P.
+    assert!(*mapped.last().unwrap() > u64::from(filter.p()) * 1000);
+    for item in &data {
+        assert_eq!(filter.is_member(key, item), Ok(true));
+    }
+    assert_eq!(map_to_range(key, &data[0], 0, BASIC_FILTER_M), 0);
+}
+
+#[test]
+fn collect() {
+    let filter = items(100).into_iter().collect::<Filter>();
+    assert_eq!(filter, Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::default(), items(100)));
+    assert_eq!(filter.is_member(FilterKey::default(), b"item 7"), Ok(true));
+}
+
+#[test]
+fn estimated_size() {
+    let data = (0..10000u32).map(|i| i.to_le_bytes());
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::from(KEY), data);
+    let estimate = Filter::estimated_size(10000, BASIC_FILTER_P, BASIC_FILTER_M);
+
+    let size = filter.as_bytes().len();
+    assert!(size * 95 / 100 < estimate && estimate < size * 105 / 100);
+    assert_eq!(Filter::estimated_size(0, BASIC_FILTER_P, BASIC_FILTER_M), 0);
+}
+
+#[test]
+fn optimal_params() {
+    assert_eq!(optimal_m(1000, BASIC_FILTER_P), BASIC_FILTER_M);
+    assert_eq!(optimal_p(1000, 1.0 / BASIC_FILTER_M as f64), BASIC_FILTER_P);
+    assert_eq!(optimal_p(1000, 1.0 / 1_000_000.0), 20);
+    assert_eq!(optimal_p(1000, 1.0), 0);
+    assert_eq!(optimal_p(1000, 1e-12), 32);
+    assert_eq!(optimal_m(u32::MAX, 32), u64::MAX / u64::from(u32::MAX));
+}
+
+#[test]
+fn stats() {
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::from(KEY), items(1000));
+    let stats = filter.stats();
+    assert_eq!(stats.n, 1000);
+    assert_eq!(stats.serialized_size, filter.serialized_size());
+    assert_eq!(stats.bits_per_entry, (filter.as_bytes().len() * 8) as f64 / 1000.0);
+    assert_eq!(stats.fp_rate, 1.0 / BASIC_FILTER_M as f64);
+    assert_eq!(stats.quotients, None);
+
+    let quotients = filter.detailed_stats().unwrap().quotients.unwrap();
+    assert_eq!(quotients.iter().sum::<u32>(), 1000);
+    assert!(quotients[0] > quotients[1] && quotients[1] > quotients[2]);
+
+    let empty = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::from(KEY), items(0));
+    assert_eq!(empty.detailed_stats().unwrap().quotients, Some(vec![]));
+
+    // The quotients above the last bucket are counted in it.
+    let mut encoder = GolombEncoder::new(Vec::new(), 0);
+    encoder.encode(100_000).unwrap();
+    let sparse = Filter::from_bytes(1, 0, 200_000, encoder.finish().unwrap());
+    let quotients = sparse.detailed_stats().unwrap().quotients.unwrap();
+    assert_eq!(quotients.len(), MAX_QUOTIENT_BUCKETS);
+    assert_eq!(quotients[MAX_QUOTIENT_BUCKETS - 1], 1);
+    assert_eq!(empty.stats().bits_per_entry, 0.0);
+}
+
+#[test]
+fn params() {
+    assert_eq!(FilterParams::new(33, 1), Err(ParamsError::PTooLarge));
+    assert_eq!(FilterParams::new(BASIC_FILTER_P, 0), Err(ParamsError::ZeroM));
+    assert_eq!(FilterParams::new(BASIC_FILTER_P, BASIC_FILTER_M), Ok(FilterParams::basic()));
+
+    let params = FilterParams::new(32, u64::MAX / 3).unwrap();
+    assert_eq!(params.max_n(), 3);
+    assert_eq!(params.modulus(3), Ok(u64::MAX / 3 * 3));
+    assert_eq!(params.modulus(4), Err(ParamsError::ModulusOverflow));
+    assert_eq!(Filter::from_bytes_with_params(4, params, vec![]), Err(ParamsError::ModulusOverflow));
+    assert_eq!(FilterParams::basic().max_n(), u32::MAX);
+}
+
+#[test]
+fn extreme_params() {
+    let data = items(1000);
+    for &(p, m) in &[(0, 1), (1, 2), (32, 1 << 32), (32, 3 << 32)] {
+        let filter = Filter::build(p, m, FilterKey::from(KEY), &data);
+        assert_eq!(filter.validate_encoding(), Ok(()), "P = {}", p);
+        assert!(filter.values().all(|v| v.unwrap() < filter.modulus()));
+        assert_eq!(filter.match_all(FilterKey::from(KEY), &data), Ok(vec![true; 1000]));
+
+        let params = FilterParams::new(p, m).unwrap();
+        let decoded = Filter::from_bytes_with_params(filter.n(), params, filter.as_bytes().to_vec());
+        assert_eq!(decoded, Ok(filter));
+    }
+}
+
+#[test]
+#[should_panic(expected = "N * M is too big")]
+fn modulus_overflow() {
+    Filter::build(32, u64::MAX / 2, FilterKey::from(KEY), items(3));
+}
+
+#[test]
+fn borrowed_data() {
+    let data = items(100);
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::from(KEY), &data);
+
+    let borrowed = Filter::from_slice(filter.n(), BASIC_FILTER_P, BASIC_FILTER_M, filter.as_bytes());
+    assert_eq!(borrowed.match_all(FilterKey::from(KEY), &data), Ok(vec![true; 100]));
+    assert_eq!(borrowed.to_string(), filter.to_string());
+    assert_eq!(borrowed.validate_encoding(), Ok(()));
+    assert_eq!(borrowed.into_owned(), filter);
+}
+
+#[test]
+fn small_filter() {
+    let key = FilterKey::from(KEY);
+    let data = items(10);
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data);
+
+    let small = filter.to_inline::<64>().unwrap();
+    assert_eq!(small.as_bytes(), filter.as_bytes());
+    assert_eq!(small.match_all(key, &data), Ok(vec![true; 10]));
+    assert_eq!(small.is_member(key, b"other"), Ok(false));
+    assert_eq!(small.to_nbytes(), filter.to_nbytes());
+    assert_eq!(small.clone().into_owned(), filter);
+
+    let decoded = SmallFilter::<64>::from_inline_nbytes(BASIC_FILTER_P, BASIC_FILTER_M, &filter.to_nbytes());
+    assert_eq!(decoded, Ok(Some(small)));
+    assert_eq!(filter.to_inline::<16>(), None);
+
+    // The modulus kept by insert_all isn't N * M anymore.
+    let mut inserted = filter.clone();
+    inserted.insert_all(key, &[b"other"]).unwrap();
+    let small = inserted.to_inline::<64>().unwrap();
+    assert_eq!(small.modulus(), inserted.modulus());
+    assert_eq!(small.is_member(key, b"other"), Ok(true));
+    assert_eq!(small.match_all(key, &data), Ok(vec![true; 10]));
+}
+
+#[test]
+fn filter_ref() {
+    let data = items(50);
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::from(KEY), &data);
+    let nbytes = filter.to_nbytes();
+
+    let filter_ref = FilterRef::from_nslice(BASIC_FILTER_P, BASIC_FILTER_M, &nbytes).unwrap();
+    assert_eq!(filter_ref, filter.as_filter_ref());
+    assert_eq!(filter_ref.is_member(FilterKey::from(KEY), &data[7]), Ok(true));
+    assert_eq!(filter_ref.is_member_any(FilterKey::from(KEY), &items(60)[50..]), Ok(false));
+    assert_eq!(Filter::from(filter_ref), filter);
+    assert_eq!(FilterRef::from(&filter).as_bytes(), filter.as_bytes());
+
+    assert_eq!(FilterRef::from_nslice(BASIC_FILTER_P, BASIC_FILTER_M, &[0xfd]),
+               Err(ParseFilterError::InvalidSetLength));
+}
+
+#[test]
+fn indexed_match() {
+    let key = FilterKey::from(KEY);
+    let data = items(1000);
+    let mut filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data);
+    filter.build_index(7).unwrap();
+
+    for item in &data {
+        assert_eq!(filter.is_member(key, item), Ok(true));
+    }
+    for item in items(1100).iter().skip(1000) {
+        let indexed = filter.is_member(key, item);
+        assert_eq!(indexed, filter.match_all(key, &[item]).map(|m| m[0]));
+    }
+}
+
+#[test]
+fn corrupt_quotient() {
+    // A long run of 1s can't be a valid quotient for such a small filter.
+    let filter = Filter::from_bytes(1, BASIC_FILTER_P, BASIC_FILTER_M, vec![0xff; 64]);
+    let values = filter.values().collect::<Vec<_>>();
+
+    assert_eq!(values, vec![Err(DecodeError::QuotientTooLarge)]);
+    assert_eq!(filter.is_member(FilterKey::from(KEY), b"item"),
+               Err(DecodeError::QuotientTooLarge));
+    assert_eq!(filter.is_member_any(FilterKey::from(KEY), &[b"item"]),
+               Err(DecodeError::QuotientTooLarge));
+}
+
+#[test]
+fn empty_sets() {
+    let key = FilterKey::from(KEY);
+    let data = items(10);
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data);
+    let no_queries: &[Vec<u8>] = &[];
+    let empty = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, no_queries);
+
+    assert!(empty.is_empty());
+    assert_eq!(empty.is_member(key, &data[0]), Ok(false));
+    assert_eq!(empty.is_member_any(key, &data), Ok(false));
+    assert_eq!(filter.is_member_any(key, no_queries), Ok(false));
+    assert_eq!(filter.match_any_verbose(key, no_queries), Ok(Match::default()));
+    assert_eq!(empty.is_member_any(key, no_queries), Ok(false));
+    assert_eq!(filter.match_all(key, no_queries), Ok(vec![]));
+    assert_eq!(empty.match_all(key, &data), Ok(vec![false; 10]));
+}
+
+#[test]
+fn query_set() {
+    let key = FilterKey::from(KEY);
+    let data = items(100);
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data);
+
+    let mut query = QuerySet::new(FilterKey::default(), vec![b"other".to_vec()]);
+    query.insert(&data[42]);
+    assert_eq!(query.len(), 2);
+
+    query.set_key(key);
+    assert_eq!(filter.match_query_set(&query), Ok(true));
+
+    let query = QuerySet::new(key, vec![b"other".to_vec()]);
+    assert_eq!(filter.match_query_set(&query), Ok(false));
+}
+
+#[test]
+fn merge() {
+    let key = FilterKey::from(KEY);
+    let data = items(150);
+    let a = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[..100]);
+    let b = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[50..]);
+
+    let merged = a.merge(&b).unwrap();
+    assert_eq!(merged.n(), 150);
+    assert_eq!(merged.modulus(), a.modulus());
+    assert_eq!(merged.validate_encoding(), Ok(()));
+    assert_eq!(merged.match_all(key, &data), Ok(vec![true; 150]));
+    assert_eq!(merged.try_to_nbytes(), Err(EncodeError::ModulusMismatch));
+
+    let c = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[..10]);
+    assert_eq!(a.merge(&c).unwrap_err(), SetError::IncompatibleParams);
+}
+
+#[test]
+fn modulus_consistency() {
+    let key = FilterKey::from(KEY);
+    let data = items(150);
+    let a = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[..100]);
+    assert_eq!(a.m(), BASIC_FILTER_M);
+    assert_eq!(a.modulus(), 100 * BASIC_FILTER_M);
+    assert!(a.has_nm_modulus());
+
+    let parsed = Filter::from_nbytes(BASIC_FILTER_P, BASIC_FILTER_M, &a.to_nbytes()).unwrap();
+    let copied = Filter::from_bytes(a.n(), a.p(), a.m(), a.as_bytes().to_vec());
+    assert_eq!(parsed.modulus(), a.modulus());
+    assert_eq!(copied.modulus(), a.modulus());
+
+    // The merged filter keeps the modulus of its operands, which has to be
+    // given back to reload it.
+    let b = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[50..]);
+    let merged = a.merge(&b).unwrap();
+    assert!(!merged.has_nm_modulus());
+    assert_eq!(merged.try_to_nbytes(), Err(EncodeError::ModulusMismatch));
+    assert_eq!(a.try_to_nbytes(), Ok(a.to_nbytes()));
+
+    let reloaded = Filter::from_data_with_modulus(merged.n(), merged.p(), merged.m(), merged.modulus(),
+                                                  merged.as_bytes().to_vec());
+    assert_eq!(reloaded, merged);
+    assert_eq!(reloaded.match_all(key, &data), Ok(vec![true; 150]));
+}
+
+#[test]
+fn insert_all() {
+    let key = FilterKey::from(KEY);
+    let data = items(150);
+    let a = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[..100]);
+    let b = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[50..]);
+
+    let mut inserted = a.clone();
+    inserted.insert_all(key, &data[50..]).unwrap();
+    assert_eq!(inserted, a.merge(&b).unwrap());
+    assert_eq!(inserted.validate_encoding(), Ok(()));
+
+    // Entries above every value only append codes, the bytes before the
+    // padding of the last one are kept.
+    let last = a.values().last().unwrap().unwrap();
+    let above = items(1000).into_iter()
+        .filter(|item| reduce(siphash24(key, item), a.modulus()) > last)
+        .collect::<Vec<_>>();
+    assert!(!above.is_empty());
+    let mut appended = a.clone();
+    appended.insert_all(key, &above).unwrap();
+    assert_eq!(appended.n() as usize, 100 + above.len());
+    let kept = a.as_bytes().len() - 1;
+    assert_eq!(&appended.as_bytes()[..kept], &a.as_bytes()[..kept]);
+
+    let mut empty = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, Vec::<Vec<u8>>::new());
+    empty.insert_all(key, &data[..10]).unwrap();
+    assert_eq!(empty, Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[..10]));
+}
+
+#[test]
+fn insert_all_round_trip() {
+    let key = FilterKey::from(KEY);
+    let data = items(1000);
+    let mut filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[..500]);
+    filter.insert_all(key, &data[500..]).unwrap();
+    assert_eq!(filter.match_all(key, &data), Ok(vec![true; 1000]));
+
+    // Read back with N * M, the values wouldn't match, so the filter can't
+    // be serialized and has to be rebuilt instead.
+    assert!(!filter.has_nm_modulus());
+    assert_eq!(filter.try_to_nbytes(), Err(EncodeError::ModulusMismatch));
+    assert_eq!(filter.to_string(), format!("{:x} (modulus {})", filter, filter.modulus()));
+    assert!(filter.to_string().parse::<Filter>().is_err());
+
+    let rebuilt = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data);
+    let parsed = Filter::from_nbytes(BASIC_FILTER_P, BASIC_FILTER_M, &rebuilt.try_to_nbytes().unwrap()).unwrap();
+    assert_eq!(parsed.match_all(key, &data), Ok(vec![true; 1000]));
+}
+
+#[test]
+fn rolling_filter() {
+    let key = FilterKey::from(KEY);
+    let data = items(50);
+    let mut rolling = RollingFilter::new(FilterParams::basic(), key, 3);
+
+    for block in data.chunks(10) {
+        rolling.push_block(block);
+    }
+    assert_eq!(rolling.len(), 3);
+    assert_eq!(rolling.filter(), &Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[20..]));
+    assert!(rolling.match_any(&data[45..46]));
+    assert!(!rolling.match_any(&data[..20]));
+
+    // Entries in several blocks stay until the last of them leaves.
+    rolling.push_block(&data[40..42]);
+    assert!(rolling.pop_oldest());
+    assert!(rolling.pop_oldest());
+    assert_eq!(rolling.filter(), &Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[40..42]));
+
+    assert!(rolling.pop_oldest());
+    assert!(!rolling.pop_oldest());
+    assert!(rolling.is_empty() && rolling.filter().is_empty());
+}
+
+#[test]
+fn intersect_and_difference() {
+    let key = FilterKey::from(KEY);
+    let data = items(100);
+    let a = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[..60]);
+    let b = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[40..]);
+
+    let common = a.intersect(&b).unwrap();
+    assert_eq!(common.n(), 20);
+    assert_eq!(common.validate_encoding(), Ok(()));
+    assert_eq!(common.match_all(key, &data[40..60]), Ok(vec![true; 20]));
+
+    let added = b.difference(&a).unwrap();
+    assert_eq!(added.n(), 40);
+    assert_eq!(added.validate_encoding(), Ok(()));
+    assert_eq!(added.match_all(key, &data[60..]), Ok(vec![true; 40]));
+
+    // Both keep the modulus of a and b, so they can't be serialized.
+    assert_eq!(common.try_to_nbytes(), Err(EncodeError::ModulusMismatch));
+    assert_eq!(added.try_to_nbytes(), Err(EncodeError::ModulusMismatch));
+}
+
+#[test]
+fn filter_diff() {
+    let key = FilterKey::from(KEY);
+    let data = items(100);
+    let a = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[..60]);
+    let b = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[40..]);
+    assert_eq!(diff(&a, &a), FilterDiff::default());
+    assert!(diff(&a, &a).is_identical());
+
+    let d = diff(&a, &b);
+    assert_eq!(d.n, None);
+    assert_eq!(d.only_in_a, a.difference(&b).unwrap().values().collect::<Result<Vec<u64>, _>>().unwrap());
+    assert_eq!(d.only_in_b, b.difference(&a).unwrap().values().collect::<Result<Vec<u64>, _>>().unwrap());
+    assert_eq!(d.first_divergence, Some(0));
+
+    // Flip a bit in the middle of the bitstream.
+    let mut data = a.as_bytes().to_vec();
+    let middle = data.len() / 2;
+    data[middle] ^= 0x10;
+    let c = Filter::from_bytes(a.n(), a.p(), a.m(), data);
+    let d = diff(&a, &c);
+    assert_eq!(d.first_divergence, Some(middle as u64 * 8 + 3));
+    assert!(!d.only_in_a.is_empty() || d.errors.1.is_some());
+    assert!(d.to_string().starts_with(&format!("filters differ, first differing bit at {}", middle * 8 + 3)));
+
+    let e = Filter::from_bytes(a.n() + 1, a.p(), a.m(), a.as_bytes().to_vec());
+    let d = diff(&a, &e);
+    assert_eq!(d.n, Some((60, 61)));
+    assert_eq!(d.errors, (None, Some(DecodeError::UnexpectedEof)));
+    assert!(d.only_in_a.is_empty());
+}
+
+#[test]
+fn multiple_blocks() {
+    let data = items(100);
+    let filters = (0..10u32)
+        .map(|h| {
+            let key = FilterKey::from((h as u64, 0));
+            let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key,
+                                       &data[h as usize * 10..(h as usize + 1) * 10]);
+            (h + 100, filter, key)
+        })
+        .collect::<Vec<_>>();
+
+    let query = QuerySet::new(FilterKey::from((3, 0)), vec![&data[35], &data[72], &data[79]]);
+    assert_eq!(match_blocks(&filters, &query), Ok(vec![103, 107]));
+
+    let query = QuerySet::new(FilterKey::default(), vec![b"other".to_vec()]);
+    assert_eq!(match_blocks(&filters, &query), Ok(vec![]));
+}
+
+#[test]
+fn equality_and_ordering() {
+    let key = FilterKey::from(KEY);
+    let data = items(100);
+    let a = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data);
+    let mut b = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data);
+    b.build_index(16).unwrap();
+    assert_eq!(a, b);
+
+    let c = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[..50]);
+    let d = Filter::build(BASIC_FILTER_P + 1, BASIC_FILTER_M, key, &data);
+    assert_ne!(a, c);
+    assert_ne!(a, d);
+
+    let set = vec![a.clone(), b, c.clone(), d.clone()].into_iter().collect::<HashSet<_>>();
+    assert_eq!(set.len(), 3);
+
+    let mut filters = vec![a.clone(), c.clone(), d.clone()];
+    filters.sort();
+    let mut expected = vec![a, c, d];
+    expected.sort_by(|x, y| x.to_nbytes().cmp(&y.to_nbytes()).then(x.p().cmp(&y.p())));
+    assert_eq!(filters, expected);
+}
+
+#[test]
+fn hex_formatting() {
+    let key = FilterKey::from(KEY);
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, items(10));
+
+    let hex = filter.to_string();
+    assert_eq!(hex, format!("{:x}", filter));
+    assert!(hex.starts_with("0a"));
+    assert_eq!(hex.len(), filter.serialized_size() * 2);
+    assert_eq!(hex.parse::<Filter>(), Ok(filter.clone()));
+    assert_eq!(hex.to_uppercase().parse::<Filter>(), Ok(filter));
+
+    assert_eq!("0".parse::<Filter>(), Err(ParseFilterError::InvalidHex));
+    assert_eq!("zz".parse::<Filter>(), Err(ParseFilterError::InvalidHex));
+    assert_eq!("".parse::<Filter>(), Err(ParseFilterError::InvalidSetLength));
+}
+
+#[test]
+fn from_nbytes() {
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::from(KEY), items(300));
+
+    let bytes = filter.to_nbytes();
+    assert_eq!(&bytes[..3], &[0xfd, 0x2c, 0x01]);
+    assert_eq!(Filter::from_nbytes(BASIC_FILTER_P, BASIC_FILTER_M, &bytes), Ok(filter));
+
+    assert_eq!(Filter::from_nbytes(BASIC_FILTER_P, BASIC_FILTER_M, &bytes[..2]),
+               Err(ParseFilterError::InvalidSetLength));
+    assert_eq!(Filter::from_nbytes(BASIC_FILTER_P, BASIC_FILTER_M, &[0xff, 0, 0, 0, 0, 1, 0, 0, 0]),
+               Err(ParseFilterError::InvalidSetLength));
+}
+
+#[test]
+fn decode_into() {
+    let key = FilterKey::from(KEY);
+    let mut buf = vec![1, 2, 3];
+
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, items(100));
+    filter.decode_into(&mut buf).unwrap();
+    assert_eq!(buf, filter.values().collect::<Result<Vec<u64>, _>>().unwrap());
+
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, items(10));
+    let capacity = buf.capacity();
+    filter.decode_into(&mut buf).unwrap();
+    assert_eq!((buf.len(), buf.capacity()), (10, capacity));
+
+    let truncated = Filter::from_bytes(10, BASIC_FILTER_P, BASIC_FILTER_M, filter.as_bytes()[..10].to_vec());
+    assert_eq!(truncated.decode_into(&mut buf), Err(DecodeError::UnexpectedEof));
+    assert!(buf.len() < 10);
+}
+
+#[test]
+fn member_proofs() {
+    let mut filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::from(KEY), items(1000));
+    let values = filter.values().collect::<Result<Vec<u64>, _>>().unwrap();
+
+    for indexed in &[false, true] {
+        if *indexed {
+            filter.build_index(16).unwrap();
+        }
+        for (i, value) in values.iter().enumerate().step_by(37) {
+            let proof = filter.prove_member(*value).unwrap().unwrap();
+            assert_eq!(proof.index, i as u32);
+            assert_eq!(proof.prev_value, if i == 0 { 0 } else { values[i - 1] });
+            assert_eq!(proof.value(BASIC_FILTER_P), Some(*value));
+            let range = proof.code_bytes(BASIC_FILTER_P);
+            let window = &filter.as_bytes()[range.start as usize..range.end as usize];
+            assert!(proof.verify(BASIC_FILTER_P, window, range.start * 8, *value));
+            assert!(!proof.verify(BASIC_FILTER_P, window, range.start * 8, value + 1));
+            assert!(!proof.verify(BASIC_FILTER_P, &window[..window.len() - 1], range.start * 8, *value));
+            assert!(proof.verify(BASIC_FILTER_P, filter.as_bytes(), 0, *value));
+        }
+        assert_eq!(filter.prove_member(values[10] + 1), Ok(None));
+        assert_eq!(filter.prove_member(values[0] - 1), Ok(None));
+    }
+
+    let bytes = filter.as_bytes();
+    let proof = filter.prove_member(values[500]).unwrap().unwrap();
+    let moved = MemberProof { bit_offset: proof.bit_offset + 1, ..proof };
+    assert!(!moved.verify(BASIC_FILTER_P, bytes, 0, values[500]));
+    let forged = MemberProof { quotient: proof.quotient + 1, prev_value: proof.prev_value - (1 << BASIC_FILTER_P), ..proof };
+    assert!(!forged.verify(BASIC_FILTER_P, bytes, 0, values[500]));
+    assert!(!proof.verify(BASIC_FILTER_P, &bytes[1..], 4, values[500]));
+
+    // The first value of a set can be zero.
+    let mut encoder = GolombEncoder::new(Vec::new(), BASIC_FILTER_P);
+    encoder.encode(0).unwrap();
+    encoder.encode(7).unwrap();
+    let filter = Filter::from_bytes(2, BASIC_FILTER_P, BASIC_FILTER_M, encoder.finish().unwrap());
+    let proof = filter.prove_member(0).unwrap().unwrap();
+    assert_eq!((proof.index, proof.bit_offset, proof.prev_value), (0, 0, 0));
+    assert!(proof.verify(BASIC_FILTER_P, filter.as_bytes(), 0, 0));
+    assert_eq!(filter.prove_member(7).unwrap().unwrap().index, 1);
+}
+
+#[test]
+fn decode_limits() {
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::from(KEY), items(300));
+    let bytes = filter.to_nbytes();
+    let size = filter.as_bytes().len();
+
+    let limits = DecodeLimits::new(300, size);
+    assert_eq!(Filter::from_nbytes_with_limits(BASIC_FILTER_P, BASIC_FILTER_M, &bytes, &limits), Ok(filter.clone()));
+    assert_eq!(Filter::from_nbytes_with_limits(BASIC_FILTER_P, BASIC_FILTER_M, &bytes, &DecodeLimits::new(299, size)),
+               Err(ParseFilterError::Limit(LimitError::TooManyItems(300))));
+    assert_eq!(Filter::from_bytes_with_limits(300, BASIC_FILTER_P, BASIC_FILTER_M, filter.as_bytes().to_vec(),
+                                              &DecodeLimits::new(300, size - 1)),
+               Err(LimitError::TooLarge(size)));
+
+    // A few bytes can't hold billions of items.
+    assert_eq!(Filter::from_nbytes(BASIC_FILTER_P, BASIC_FILTER_M, &[0xfe, 0xff, 0xff, 0xff, 0xff, 0, 0]),
+               Err(ParseFilterError::Limit(LimitError::TooManyItems(u32::MAX))));
+    assert_eq!("fe00ca9a3b00".parse::<Filter>(),
+               Err(ParseFilterError::Limit(LimitError::TooManyItems(1_000_000_000))));
+    assert!(Filter::from_nbytes(BASIC_FILTER_P, BASIC_FILTER_M, &vec![0; MAX_FILTER_SIZE + 2]).is_err());
+}
+
+#[test]
+fn decode_checked() {
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::from(KEY), items(100));
+    let decode = |n, data: &[u8]| Filter::decode_checked(n, BASIC_FILTER_P, BASIC_FILTER_M, data.to_vec());
+    let data = filter.as_bytes();
+
+    assert_eq!(decode(100, data), Ok(filter.clone()));
+    assert_eq!(decode(101, data), Err(DecodeError::UnexpectedEof));
+    assert_eq!(decode(99, data), Err(DecodeError::TrailingData));
+    assert_eq!(decode(100, &data[..data.len() - 1]), Err(DecodeError::UnexpectedEof));
+
+    let mut extended = data.to_vec();
+    extended.push(0);
+    assert_eq!(decode(100, &extended), Err(DecodeError::TrailingData));
+
+    let mut padded = data.to_vec();
+    *padded.last_mut().unwrap() |= 1;
+    assert_eq!(decode(100, &padded), Err(DecodeError::NonZeroPadding));
+    assert_eq!(Filter::from_bytes(100, BASIC_FILTER_P, BASIC_FILTER_M, padded).validate_encoding(), Ok(()));
+}
+
+#[test]
+fn watch_list() {
+    let data = items(40);
+    let filters = (0..4u64)
+        .map(|i| {
+            let key = FilterKey::from((i, i));
+            let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key,
+                                       &data[i as usize * 10..(i as usize + 1) * 10]);
+            (filter, key)
+        })
+        .collect::<Vec<_>>();
+
+    let mut watch_list = WatchList::new();
+    assert!(watch_list.insert(&data[15]));
+    assert!(!watch_list.insert(&data[15]));
+
+    let matches = |watch_list: &mut WatchList| {
+        filters.iter()
+            .map(|&(ref filter, key)| watch_list.matches(filter, key).unwrap())
+            .collect::<Vec<bool>>()
+    };
+    assert_eq!(matches(&mut watch_list), vec![false, true, false, false]);
+    assert_eq!(watch_list.cached_keys(), 4);
+
+    // The cached hashes are updated in place.
+    watch_list.insert(&data[35]);
+    assert!(watch_list.remove(&data[15]));
+    assert_eq!(watch_list.len(), 1);
+    assert_eq!(matches(&mut watch_list), vec![false, false, false, true]);
+
+    // Only the hashes for the last used keys are kept.
+    assert_eq!(watch_list.cache_capacity(), DEFAULT_CACHED_KEYS);
+    watch_list.set_cache_capacity(2);
+    assert_eq!(watch_list.cached_keys(), 2);
+    assert_eq!(matches(&mut watch_list), vec![false, false, false, true]);
+    assert_eq!(watch_list.cached_keys(), 2);
+    watch_list.set_cache_capacity(0);
+    assert_eq!(matches(&mut watch_list), vec![false, false, false, true]);
+    assert_eq!(watch_list.cached_keys(), 0);
+}
+
+/// A keyed FNV-1a, only to check that the hash function can be replaced.
+struct Fnv;
+
+impl GcsHasher for Fnv {
+    fn hash(key: FilterKey, data: &[u8]) -> u64 {
+        let (k0, k1) = key.keys();
+        data.iter().fold(0xcbf29ce484222325 ^ k0, |h, b| (h ^ u64::from(*b)).wrapping_mul(0x100000001b3)) ^ k1
+    }
+}
+
+/// Maps one item to 0, the smallest value of a filter.
+struct ZeroFirst;
+
+impl GcsHasher for ZeroFirst {
+    fn hash(key: FilterKey, data: &[u8]) -> u64 {
+        if data == b"zero" { 0 } else { Fnv::hash(key, data) }
+    }
+}
+
+#[test]
+fn zero_value() {
+    let key = FilterKey::from(KEY);
+    let mut data = items(20);
+    data.push(b"zero".to_vec());
+    let mut filter = Filter::<ZeroFirst>::build_with_hasher(BASIC_FILTER_P, BASIC_FILTER_M, key, &data);
+    assert_eq!(filter.values().next(), Some(Ok(0)));
+
+    assert_eq!(filter.is_member(key, b"zero"), Ok(true));
+    assert_eq!(filter.is_member_any(key, &[b"zero"]), Ok(true));
+    assert_eq!(filter.match_all(key, &data), Ok(vec![true; 21]));
+
+    filter.build_index(4).unwrap();
+    assert_eq!(filter.is_member(key, b"zero"), Ok(true));
+    assert_eq!(filter.match_all(key, &data), Ok(vec![true; 21]));
+}
+
+#[test]
+fn match_engine() {
+    let data = items(40);
+    let filters = (0..8u64)
+        .map(|i| {
+            let key = FilterKey::from((i % 4, i % 4));
+            let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key,
+                                       &data[i as usize * 5..(i as usize + 1) * 5]);
+            (filter, key)
+        })
+        .collect::<Vec<_>>();
+
+    let mut watch_list = WatchList::new();
+    watch_list.insert(&data[12]);
+    watch_list.insert(&data[33]);
+    watch_list.matches(&filters[0].0, filters[0].1).unwrap();
+
+    let engine = Arc::new(MatchEngine::from(watch_list));
+    assert_eq!(engine.len(), 2);
+    assert_eq!(engine.cached_keys(), 1);
+
+    let workers = filters.into_iter()
+        .map(|(filter, key)| {
+            let engine = engine.clone();
+            thread::spawn(move || engine.matches(&filter, key).unwrap())
+        })
+        .collect::<Vec<_>>();
+    let matches = workers.into_iter()
+        .map(|worker| worker.join().unwrap())
+        .collect::<Vec<bool>>();
+
+    assert_eq!(matches, vec![false, false, true, false, false, false, true, false]);
+    assert_eq!(engine.cached_keys(), 4);
+    engine.clear_cache();
+    assert_eq!(engine.cached_keys(), 0);
+
+    let mut watch_list = WatchList::new();
+    watch_list.insert(&data[12]);
+    watch_list.set_cache_capacity(0);
+    let engine = MatchEngine::from(watch_list);
+    let key = FilterKey::from(KEY);
+    assert!(engine.matches(&Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data[10..15]), key).unwrap());
+    assert_eq!(engine.cached_keys(), 0);
+}
+
+#[test]
+fn exact_filter() {
+    let data = items(100);
+    let key = FilterKey::from(KEY);
+    let exact = ExactFilter::build(1, 2, key, &data[..50]);
+    assert_eq!(exact.filter(), &Filter::build(1, 2, key, &data[..50]));
+    assert_eq!(exact.entries().count(), 50);
+
+    // With M = 2 about half of the other items are false positives.
+    let false_positives = data[50..].iter()
+        .filter(|item| exact.is_member(item).unwrap())
+        .count();
+    assert!(false_positives > 0);
+    assert!(data[50..].iter().all(|item| !exact.confirm(item)));
+    assert_eq!(exact.confirm_all(&data[48..52]), vec![true, true, false, false]);
+
+    let given = ExactFilter::new(exact.filter().clone(), key, &data[..50]);
+    assert_eq!(given, exact);
+    assert_eq!(given.into_filter(), *exact.filter());
+}
+
+#[test]
+fn custom_hasher() {
+    let key = FilterKey::from(KEY);
+    let data = items(100);
+    let filter = Filter::<Fnv>::build_with_hasher(BASIC_FILTER_P, BASIC_FILTER_M, key, &data);
+    let siphash = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data);
+
+    assert_ne!(filter.as_bytes(), siphash.as_bytes());
+    assert_eq!(filter.match_all(key, &data), Ok(vec![true; 100]));
+    assert_eq!(filter.is_member(key, b"other"), Ok(false));
+}
@@ -0,0 +1,103 @@
+extern crate bitcoin_gcs;
+extern crate serde_json;
+
+use serde_json::Value;
+
+use bitcoin_gcs::golomb::{BitOrder, GolombDecoder, GolombEncoder};
+use bitcoin_gcs::{DecodeError, Filter, BASIC_FILTER_M, BASIC_FILTER_P};
+
+#[test]
+fn roundtrip() {
+    let values = [0u64, 1, 7, 8, 9, 1000, 1 << 20];
+
+    let mut encoder = GolombEncoder::new(Vec::new(), 3);
+    for v in values.iter() {
+        encoder.encode(*v).unwrap();
+    }
+    let data = encoder.finish().unwrap();
+
+    let mut decoder = GolombDecoder::new(data.as_slice(), 3);
+    for v in values.iter() {
+        assert_eq!(decoder.decode(), Ok(*v));
+    }
+}
+
+#[test]
+fn known_encoding() {
+    // 9 = 0b1_001: quotient 1 (0b10) and remainder 1 (0b001).
+    let mut encoder = GolombEncoder::new(Vec::new(), 3);
+    encoder.encode(9).unwrap();
+    assert_eq!(encoder.finish().unwrap(), vec![0b1000_1000]);
+
+    let mut decoder = GolombDecoder::new(&[0b1111_1111][..], 3).with_max_quotient(4);
+    assert_eq!(decoder.decode(), Err(DecodeError::QuotientTooLarge));
+}
+
+#[test]
+fn bit_order() {
+    let values = [0u64, 1, 9, 1000];
+
+    let mut encoder = GolombEncoder::new(Vec::new(), 3);
+    let mut lsb_encoder = GolombEncoder::new(Vec::new(), 3).with_bit_order(BitOrder::LsbFirst);
+    for v in values.iter() {
+        encoder.encode(*v).unwrap();
+        lsb_encoder.encode(*v).unwrap();
+    }
+    let data = encoder.finish().unwrap();
+    let lsb_data = lsb_encoder.finish().unwrap();
+    assert_eq!(lsb_data, data.iter().map(|byte| byte.reverse_bits()).collect::<Vec<u8>>());
+
+    let mut decoder = GolombDecoder::new(lsb_data.as_slice(), 3).with_bit_order(BitOrder::LsbFirst);
+    for v in values.iter() {
+        assert_eq!(decoder.decode(), Ok(*v));
+    }
+}
+
+#[test]
+fn debug_bits() {
+    // The basic filter of the mainnet genesis block.
+    let filter = "017fa880".parse::<Filter>().unwrap();
+    assert_eq!(filter.debug_bits(),
+               "0 @0: 0 1111111101010001000 quotient 0 remainder 522888 value 522888\n\
+                padding @20: 0000\n");
+
+    let filter = Filter::from_bytes(3, 2, 4, vec![0b1101_1001]);
+    assert_eq!(filter.debug_bits(),
+               "0 @0: 110 11 quotient 2 remainder 3 value 11\n\
+                1 @5: 0 01 quotient 0 remainder 1 value 12\n\
+                2 @8: error: unexpected end of filter\n");
+}
+
+/// Returns the offset of the first bit that differs between two bitstreams.
+fn first_bit_difference(a: &[u8], b: &[u8]) -> Option<u64> {
+    let bytes = a.iter().zip(b).position(|(x, y)| x != y);
+    match bytes {
+        Some(i) => Some(i as u64 * 8 + u64::from((a[i] ^ b[i]).leading_zeros())),
+        None if a.len() != b.len() => Some(a.len().min(b.len()) as u64 * 8),
+        None => None,
+    }
+}
+
+/// Re-encodes the basic filters generated by btcd, checking that the
+/// bitstreams are identical bit for bit.
+#[test]
+fn btcd_bitstreams() {
+    let json: Value = serde_json::from_str(include_str!("testnet-19.json")).unwrap();
+
+    // The first row is a comment describing the columns.
+    for vector in &json.as_array().unwrap()[1..] {
+        let golden = vector[5].as_str().unwrap().parse::<Filter>().unwrap();
+
+        let mut decoder = GolombDecoder::new(golden.as_bytes(), BASIC_FILTER_P);
+        let mut encoder = GolombEncoder::new(Vec::new(), BASIC_FILTER_P);
+        for _ in 0..golden.n() {
+            encoder.encode(decoder.decode().unwrap()).unwrap();
+        }
+        let filter = Filter::from_bytes(golden.n(), BASIC_FILTER_P, BASIC_FILTER_M, encoder.finish().unwrap());
+
+        if let Some(bit) = first_bit_difference(golden.as_bytes(), filter.as_bytes()) {
+            panic!("bitstreams of block {} differ at bit {}\nbtcd:\n{}\nencoded:\n{}",
+                   vector[0], bit, golden.debug_bits(), filter.debug_bits());
+        }
+    }
+}
@@ -0,0 +1,99 @@
+extern crate bitcoin;
+extern crate bitcoin_gcs;
+
+use std::env;
+use std::fs;
+
+use bitcoin::blockdata::block::{Block, BlockHeader};
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{Transaction, TxOut};
+use bitcoin::util::hash::Sha256dHash;
+
+use bitcoin_gcs::interop::{load_fixtures, verify_fixture, FixtureError, Mismatch};
+use bitcoin_gcs::testgen::TestVector;
+use bitcoin_gcs::{Filter, FilterHeader};
+
+const TESTNET_19: &str = include_str!("testnet-19.json");
+
+#[test]
+fn testnet_19() {
+    let fixtures = load_fixtures(TESTNET_19).unwrap();
+    assert!(!fixtures.is_empty());
+    for fixture in &fixtures {
+        if let Err(mismatch) = verify_fixture(fixture) {
+            panic!("block {}: {}", fixture.height, mismatch);
+        }
+    }
+}
+
+/// Checks the fixtures recorded from a `bitcoind` node, e.g. mainnet blocks
+/// with taproot outputs, in the JSON files of `$BITCOIN_GCS_FIXTURES`.
+#[test]
+fn recorded_fixtures() {
+    let dir = match env::var_os("BITCOIN_GCS_FIXTURES") {
+        Some(dir) => dir,
+        None => return,
+    };
+
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+
+        let fixtures = load_fixtures(&fs::read_to_string(&path).unwrap())
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        for fixture in &fixtures {
+            if let Err(mismatch) = verify_fixture(fixture) {
+                panic!("{}: block {}: {}", path.display(), fixture.height, mismatch);
+            }
+        }
+    }
+}
+
+#[test]
+fn mismatches() {
+    let tx = Transaction {
+        version: 1,
+        lock_time: 0,
+        input: vec![],
+        output: vec![TxOut { value: 1000, script_pubkey: Script::from(vec![0x6a, 0x01, 0x2a]) }],
+        witness: vec![],
+    };
+    let block = Block {
+        header: BlockHeader {
+            version: 1,
+            prev_blockhash: Sha256dHash::from(&[1u8; 32][..]),
+            merkle_root: Sha256dHash::from(&[2u8; 32][..]),
+            time: 0,
+            bits: 0,
+            nonce: 0,
+        },
+        txdata: vec![tx],
+    };
+
+    let prev_scripts = vec![Script::from(vec![0x51, 0x20, 0xaa])];
+    let fixture = TestVector::new(3, block, prev_scripts, FilterHeader::zero(), "OP_RETURN output");
+    assert_eq!(verify_fixture(&fixture), Ok(()));
+
+    let mut wrong_header = fixture.clone();
+    wrong_header.header = FilterHeader::zero();
+    assert_eq!(verify_fixture(&wrong_header),
+               Err(Mismatch::Header { expected: FilterHeader::zero(), actual: fixture.header }));
+
+    let mut wrong_filter = fixture.clone();
+    wrong_filter.filter = Filter::from_bytes(0, 19, 784931, Vec::new());
+    match verify_fixture(&wrong_filter) {
+        Err(Mismatch::Filter(diff)) => assert_eq!(diff.n, Some((0, 1))),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn invalid_fixtures() {
+    assert_eq!(load_fixtures("{}").unwrap_err(), FixtureError::InvalidJson);
+    assert_eq!(load_fixtures("[[\"columns\"], [0, \"\", \"0x\", [], \"\", \"\", \"\", \"\"]]").unwrap_err(),
+               FixtureError::InvalidRow(1));
+    assert_eq!(load_fixtures("[[\"columns\"], [0]]").unwrap_err(), FixtureError::InvalidRow(1));
+    assert!(load_fixtures("[[\"columns\"]]").unwrap().is_empty());
+}
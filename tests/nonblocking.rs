@@ -0,0 +1,91 @@
+extern crate bitcoin;
+extern crate bitcoin_gcs;
+extern crate futures;
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::util::hash::Sha256dHash;
+
+use futures::future::{self, FutureResult};
+use futures::Future;
+
+use bitcoin_gcs::nonblocking::{sync_headers, AsyncFilterSource};
+use bitcoin_gcs::rescan::{Rescan, RescanError};
+use bitcoin_gcs::{BASIC_FILTER_M, BASIC_FILTER_P, Filter, FilterHeader, FilterHeaderChain, FilterKey};
+
+/// Blocks whose filter contains the script `[h, h]`.
+struct Blocks;
+
+fn script(i: u8) -> Script {
+    Script::from(vec![i, i])
+}
+
+impl AsyncFilterSource for Blocks {
+    type Error = ();
+    type Future = FutureResult<Option<(Sha256dHash, Filter)>, ()>;
+
+    fn get_filter(&mut self, height: u32) -> Self::Future {
+        let hash = Sha256dHash::from(&[height as u8; 32][..]);
+        let key = FilterKey::from(&hash);
+        let data = vec![script(height as u8).data()];
+        future::ok(Some((hash, Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data))))
+    }
+}
+
+#[test]
+fn rescan_async() {
+    let mut rescan = Rescan::new(0, 50);
+    rescan.add_script(&script(10));
+
+    let (mut rescan, source, height) = rescan.next_match_async(Blocks).wait().unwrap();
+    assert_eq!(height, Some(10));
+
+    rescan.add_script(&script(5)).add_script(&script(40));
+    let (rescan, _, heights) = rescan.run_async(source).wait().unwrap();
+    assert_eq!(heights, vec![40]);
+    assert!(rescan.is_finished());
+}
+
+#[test]
+fn empty_query_async() {
+    let (mut rescan, source, height) = Rescan::new(0, 50).next_match_async(Blocks).wait().unwrap();
+    assert_eq!(height, None);
+    assert!(!rescan.is_finished());
+
+    rescan.add_script(&script(3));
+    let (_, _, height) = rescan.next_match_async(source).wait().unwrap();
+    assert_eq!(height, Some(3));
+}
+
+#[test]
+fn sync_headers_async() {
+    let (chain, mut source) = sync_headers(FilterHeaderChain::new(), Blocks, 9).wait().unwrap();
+    assert_eq!(chain.height(), Some(9));
+
+    let mut expected = FilterHeaderChain::new();
+    for height in 0..10 {
+        expected.push_filter(&source.get_filter(height).wait().unwrap().unwrap().1);
+    }
+    assert_eq!(chain.tip(), expected.tip());
+
+    let (chain, _) = sync_headers(chain, source, 4).wait().unwrap();
+    assert_eq!(chain.height(), Some(9));
+}
+
+#[test]
+fn sync_headers_missing_filter() {
+    struct Empty;
+
+    impl AsyncFilterSource for Empty {
+        type Error = ();
+        type Future = FutureResult<Option<(Sha256dHash, Filter)>, ()>;
+
+        fn get_filter(&mut self, _: u32) -> Self::Future {
+            future::ok(None)
+        }
+    }
+
+    match sync_headers(FilterHeaderChain::from_checkpoint(4, FilterHeader::zero()), Empty, 9).wait() {
+        Err(RescanError::MissingFilter(5)) => {}
+        r => panic!("unexpected result {:?}", r.map(|(chain, _)| chain)),
+    }
+}
@@ -0,0 +1,35 @@
+extern crate bitcoin_gcs;
+extern crate proptest;
+
+use proptest::prelude::*;
+
+use bitcoin_gcs::{BASIC_FILTER_M, BASIC_FILTER_P, Filter, FilterKey};
+
+proptest! {
+    #[test]
+    fn no_false_negatives(k0: u64,
+                          k1: u64,
+                          items in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..40), 0..200)) {
+        let key = FilterKey::new(k0, k1);
+        let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &items);
+
+        prop_assert_eq!(filter.validate_encoding(), Ok(()));
+        for item in &items {
+            prop_assert_eq!(filter.is_member(key, item), Ok(true));
+        }
+    }
+
+    #[test]
+    fn arbitrary_bytes_dont_panic(n in 0u32..1000,
+                                  p in 0u8..=32,
+                                  m in 0u64..1_000_000,
+                                  data in prop::collection::vec(any::<u8>(), 0..256)) {
+        let filter = Filter::from_bytes(n, p, m, data);
+        let key = FilterKey::default();
+
+        let _ = filter.validate_encoding();
+        let _ = filter.is_member(key, b"item");
+        let _ = filter.is_member_any(key, &[&b"item"[..], &b"other"[..]]);
+        let _ = filter.values().count();
+    }
+}
@@ -0,0 +1,107 @@
+extern crate bitcoin;
+extern crate bitcoin_gcs;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::util::hash::Sha256dHash;
+
+use bitcoin_gcs::rescan::{FilterSource, Rescan, RescanError};
+use bitcoin_gcs::{BASIC_FILTER_M, BASIC_FILTER_P, Filter, FilterKey, Metrics};
+
+/// Blocks whose filter contains the scripts `[h]` and `[h, h]`.
+struct Blocks(u32);
+
+fn hash(height: u32) -> Sha256dHash {
+    Sha256dHash::from(&[height as u8; 32][..])
+}
+
+fn script(i: u8) -> Script {
+    Script::from(vec![i, i])
+}
+
+impl FilterSource for Blocks {
+    type Error = ();
+
+    fn get_filter(&mut self, height: u32) -> Result<Option<(Sha256dHash, Filter)>, ()> {
+        if height >= self.0 {
+            return Ok(None);
+        }
+
+        let key = FilterKey::from(&hash(height));
+        let data = vec![vec![height as u8], script(height as u8).data()];
+        Ok(Some((hash(height), Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &data))))
+    }
+}
+
+#[test]
+fn rescan() {
+    let mut rescan = Rescan::new(10, 49);
+    rescan.add_script(&script(5)).add_script(&script(20));
+
+    // Extend the watched scripts after the first match, as with the gap
+    // limit of a wallet.
+    assert_eq!(rescan.next_match(&mut Blocks(100)).unwrap(), Some(20));
+    rescan.add_script(&script(15)).add_script(&script(30));
+
+    assert_eq!(rescan.run(&mut Blocks(100)).unwrap(), vec![30]);
+    assert!(rescan.is_finished());
+    assert_eq!(rescan.next_match(&mut Blocks(100)).unwrap(), None);
+}
+
+#[test]
+fn empty_query() {
+    let mut rescan = Rescan::new(0, 20);
+    assert!(rescan.run(&mut Blocks(100)).unwrap().is_empty());
+    assert!(!rescan.is_finished());
+
+    rescan.add_script(&script(7));
+    assert_eq!(rescan.run(&mut Blocks(100)).unwrap(), vec![7]);
+    assert!(rescan.is_finished());
+}
+
+#[test]
+fn missing_filter() {
+    let mut rescan = Rescan::new(0, 20);
+    rescan.add_script(&script(30));
+
+    match rescan.run(&mut Blocks(10)) {
+        Err(RescanError::MissingFilter(10)) => {}
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    matches_run: AtomicUsize,
+    matched: AtomicUsize,
+    false_positives: AtomicUsize,
+}
+
+impl Metrics for Counters {
+    fn matches_run(&self, matched: bool) {
+        self.matches_run.fetch_add(1, Ordering::Relaxed);
+        if matched {
+            self.matched.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn false_positive_confirmed(&self) {
+        self.false_positives.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn metrics() {
+    let counters = Arc::new(Counters::default());
+    let mut rescan = Rescan::new(0, 19);
+    rescan.set_metrics(counters.clone()).add_script(&script(5));
+
+    assert_eq!(rescan.run(&mut Blocks(100)).unwrap(), vec![5]);
+    rescan.report_false_positive();
+
+    assert_eq!(counters.matches_run.load(Ordering::Relaxed), 20);
+    assert_eq!(counters.matched.load(Ordering::Relaxed), 1);
+    assert_eq!(counters.false_positives.load(Ordering::Relaxed), 1);
+}
@@ -0,0 +1,40 @@
+extern crate bitcoin_gcs;
+extern crate serde_json;
+
+use bitcoin_gcs::rpc::BlockFilterRpc;
+use bitcoin_gcs::{EncodeError, Filter, FilterHeader, FilterKey, BASIC_FILTER_M, BASIC_FILTER_P};
+
+// `getblockfilter` of the mainnet genesis block.
+const GENESIS: &str = r#"{"filter":"017fa880","header":"9f3c30f0c37fb977cf3e1a3173c631e8ff119ad3088b6f5b2bced0802139c202"}"#;
+
+#[test]
+fn getblockfilter() {
+    let result: BlockFilterRpc = serde_json::from_str(GENESIS).unwrap();
+    let filter = result.to_filter().unwrap();
+    let header = result.to_header().unwrap();
+    assert_eq!((filter.n(), filter.as_bytes()), (1, &[0x7f, 0xa8, 0x80][..]));
+    assert_eq!(header.as_hash()[0], 0x02);
+    assert_eq!(header.as_hash()[31], 0x9f);
+
+    assert_eq!(BlockFilterRpc::new(&filter, &header), Ok(result.clone()));
+    assert_eq!(serde_json::to_string(&result).unwrap(), GENESIS);
+    assert!(result.matches(&filter, &header));
+    assert!(!result.matches(&filter, &FilterHeader::zero()));
+    assert!(!result.matches(&"00".parse::<Filter>().unwrap(), &header));
+
+    let result: BlockFilterRpc = serde_json::from_str(r#"{"header":"00","filter":"00","extra":1}"#).unwrap();
+    assert!(result.to_header().is_err());
+    assert!(serde_json::from_str::<BlockFilterRpc>(r#"{"filter":"00"}"#).is_err());
+}
+
+#[test]
+fn set_operation_result() {
+    let key = FilterKey::default();
+    let mut filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, &[b"a"]);
+    filter.insert_all(key, &[b"b"]).unwrap();
+
+    let header = FilterHeader::zero();
+    assert_eq!(BlockFilterRpc::new(&filter, &header), Err(EncodeError::ModulusMismatch));
+    let result = BlockFilterRpc { filter: format!("{:x}", filter), header: header.to_string() };
+    assert!(!result.matches(&filter, &header));
+}
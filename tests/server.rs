@@ -0,0 +1,90 @@
+extern crate bitcoin;
+extern crate bitcoin_gcs;
+
+use std::env;
+use std::fs;
+
+use bitcoin::util::hash::Sha256dHash;
+
+use bitcoin_gcs::p2p::{GetCFCheckpt, GetCFHeaders, GetCFilters};
+use bitcoin_gcs::server::{FilterServer, ServerError};
+use bitcoin_gcs::store::FilterStore;
+use bitcoin_gcs::{BASIC_FILTER_M, BASIC_FILTER_P, BASIC_FILTER_TYPE, Filter, FilterHeader, FilterKey};
+
+fn hash(height: u32, fork: u8) -> Sha256dHash {
+    let mut hash = [fork; 32];
+    hash[..4].copy_from_slice(&height.to_le_bytes());
+    Sha256dHash::from(&hash[..])
+}
+
+fn filter(height: u32) -> Filter {
+    Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::default(), [height.to_le_bytes()])
+}
+
+fn getcfilters(start_height: u32, stop_hash: Sha256dHash) -> GetCFilters {
+    GetCFilters { filter_type: BASIC_FILTER_TYPE, start_height, stop_hash }
+}
+
+#[test]
+fn serve() {
+    let path = env::temp_dir().join(format!("bitcoin-gcs-server-{}.dat", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let blocks = (0..2100).map(|height| hash(height, 0)).collect::<Vec<Sha256dHash>>();
+    let mut server = FilterServer::new(blocks, FilterStore::open(&path).unwrap()).unwrap();
+    for height in 0..2100 {
+        server.put(height, &hash(height, 0), &filter(height)).unwrap();
+    }
+    assert!(server.put(2101, &hash(2101, 0), &filter(2101)).is_err());
+
+    let cfilters = server.get_cfilters(&getcfilters(1000, hash(1999, 0))).unwrap();
+    assert_eq!(cfilters.len(), 1000);
+    assert_eq!((cfilters[0].block_hash, cfilters[0].filter.as_bytes()), (hash(1000, 0), filter(1000).as_bytes()));
+    match server.get_cfilters(&getcfilters(1000, hash(2000, 0))) {
+        Err(ServerError::TooManyBlocks(1001)) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+    match server.get_cfilters(&getcfilters(10, hash(9, 0))) {
+        Err(ServerError::InvalidRange(10, 9)) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+    match server.get_cfilters(&getcfilters(0, hash(10, 1))) {
+        Err(ServerError::UnknownBlock(_)) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    let cfheaders = server.get_cfheaders(&GetCFHeaders {
+        filter_type: BASIC_FILTER_TYPE,
+        start_height: 0,
+        stop_hash: hash(1999, 0),
+    }).unwrap();
+    assert_eq!(cfheaders.previous_filter_header, FilterHeader::zero());
+    assert_eq!(cfheaders.filter_hashes.len(), 2000);
+    assert_eq!(cfheaders.filter_hashes[5], filter(5).filter_hash());
+
+    let cfcheckpt = server.get_cfcheckpt(&GetCFCheckpt {
+        filter_type: BASIC_FILTER_TYPE,
+        stop_hash: hash(2099, 0),
+    }).unwrap();
+    assert_eq!(cfcheckpt.filter_headers, server.headers().checkpoints());
+    assert_eq!(cfcheckpt.filter_headers.len(), 2);
+    match server.get_cfcheckpt(&GetCFCheckpt { filter_type: 1, stop_hash: hash(2099, 0) }) {
+        Err(ServerError::UnknownFilterType(1)) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    // Reorganize the tip, its filter isn't served until it's stored.
+    server.blocks_mut()[2099] = hash(2099, 1);
+    match server.get_cfilters(&getcfilters(2099, hash(2099, 1))) {
+        Err(ServerError::MissingFilter(2099)) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+    server.put(2099, &hash(2099, 1), &filter(2099)).unwrap();
+    assert_eq!(server.get_cfilters(&getcfilters(2099, hash(2099, 1))).unwrap().len(), 1);
+
+    let (blocks, store) = server.into_parts();
+    let server = FilterServer::new(blocks, store).unwrap();
+    assert_eq!(server.headers().len(), 2100);
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,63 @@
+extern crate bitcoin_gcs;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+
+use bitcoin_gcs::{DecodeError, FilterKey, GcsSet, LimitError};
+
+fn urls() -> Vec<String> {
+    (0..100).map(|i| format!("https://example.com/{}", i)).collect()
+}
+
+#[test]
+fn contains() {
+    let key = FilterKey::new(1, 2);
+    let set = GcsSet::new(key, urls());
+    assert_eq!(set.len(), 100);
+
+    assert!(urls().iter().all(|url| set.contains(url.as_bytes())));
+    assert!(!set.contains(b"https://example.org/"));
+    assert!(set.contains_any(vec!["https://example.org/", "https://example.com/42"]));
+
+    let bytes = set.to_bytes();
+    assert_eq!(GcsSet::from_bytes(&bytes), Ok(set));
+    assert_eq!(GcsSet::from_bytes(&bytes[..bytes.len() - 1]), Err(DecodeError::UnexpectedEof));
+    assert_eq!(GcsSet::from_bytes(&bytes[..25]), Err(DecodeError::UnexpectedEof));
+}
+
+#[test]
+fn invalid_params() {
+    let bytes = GcsSet::new(FilterKey::new(1, 2), urls()).to_bytes();
+
+    let mut huge_m = bytes.clone();
+    huge_m[17..25].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert_eq!(GcsSet::from_bytes(&huge_m), Err(DecodeError::ValueOutOfRange));
+
+    let mut zero_m = bytes.clone();
+    zero_m[17..25].copy_from_slice(&[0; 8]);
+    assert_eq!(GcsSet::from_bytes(&zero_m), Err(DecodeError::ValueOutOfRange));
+
+    let mut huge_p = bytes.clone();
+    huge_p[16] = 33;
+    assert_eq!(GcsSet::from_bytes(&huge_p), Err(DecodeError::ValueOutOfRange));
+
+    let mut huge_n = bytes[..25].to_vec();
+    huge_n.extend_from_slice(&[0xff, 0, 0, 0, 0, 1, 0, 0, 0]);
+    assert_eq!(GcsSet::from_bytes(&huge_n), Err(DecodeError::ValueOutOfRange));
+
+    let mut short_data = bytes[..25].to_vec();
+    short_data.extend_from_slice(&[0xfd, 0xe8, 0x03, 0]);
+    assert_eq!(GcsSet::from_bytes(&short_data), Err(DecodeError::Limit(LimitError::TooManyItems(1000))));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde() {
+    let set = GcsSet::with_p(FilterKey::new(1, 2), 10, urls());
+
+    let json = serde_json::to_string(&set).unwrap();
+    assert_eq!(serde_json::from_str::<GcsSet>(&json).unwrap(), set);
+
+    let mut bytes = set.to_bytes();
+    bytes[17..25].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(serde_json::from_str::<GcsSet>(&serde_json::to_string(&bytes).unwrap()).is_err());
+}
@@ -0,0 +1,316 @@
+extern crate bitcoin;
+extern crate bitcoin_gcs;
+
+mod common;
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use bitcoin_gcs::script_index::ScriptIndex;
+use bitcoin_gcs::store::{BlockStatus, Damage, FilterStore};
+#[cfg(feature = "mmap")]
+use bitcoin_gcs::FilterKey;
+use bitcoin_gcs::Metrics;
+
+use common::{filter, hash};
+
+#[test]
+fn put_get_prune() {
+    let path = env::temp_dir().join(format!("bitcoin-gcs-store-{}.dat", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    {
+        let mut store = FilterStore::open(&path).unwrap();
+        for i in 0..10 {
+            store.put(u32::from(i), &hash(i), &filter(i)).unwrap();
+        }
+        // Replace the tip, as in a reorganization.
+        store.put(9, &hash(99), &filter(99)).unwrap();
+    }
+
+    let mut store = FilterStore::open(&path).unwrap();
+    assert_eq!(store.len(), 10);
+    assert_eq!(store.tip_height(), Some(9));
+    assert_eq!(store.height(&hash(99)), Some(9));
+    assert_eq!(store.height(&hash(9)), None);
+    assert_eq!(store.get(3).unwrap().unwrap().as_bytes(), filter(3).as_bytes());
+
+    store.prune(5).unwrap();
+    assert_eq!(store.len(), 5);
+    assert!(store.get(4).unwrap().is_none());
+
+    let heights = store.range(6..8)
+        .map(|r| r.unwrap().0)
+        .collect::<Vec<u32>>();
+    assert_eq!(heights, vec![6, 7]);
+
+    let filter = store.get_by_hash(&hash(99)).unwrap().unwrap();
+    assert_eq!(filter.as_bytes(), self::filter(99).as_bytes());
+
+    // The merged filter would be read back with another modulus.
+    let merged = filter.merge(&self::filter(98)).unwrap();
+    assert_eq!(store.put(10, &hash(10), &merged).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(store.tip_height(), Some(9));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn damaged_store() {
+    let path = env::temp_dir().join(format!("bitcoin-gcs-damaged-{}.dat", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let mut store = FilterStore::open(&path).unwrap();
+    for i in 0..3 {
+        store.put(u32::from(i), &hash(i), &filter(i)).unwrap();
+    }
+    drop(store);
+
+    let report = FilterStore::scan(&path).unwrap();
+    assert_eq!(report.records, 3);
+    assert_eq!(report.valid_len, report.file_len);
+    assert_eq!(report.damage, None);
+    let record_len = (report.file_len - 5) / 3;
+
+    // A torn write at the end is discarded when the store is opened.
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[0u8; 20]).unwrap();
+    drop(file);
+    assert_eq!(FilterStore::scan(&path).unwrap().damage, Some(Damage::Truncated { offset: report.file_len }));
+    assert_eq!(FilterStore::open(&path).unwrap().len(), 3);
+    assert_eq!(FilterStore::scan(&path).unwrap(), report);
+
+    // Corrupt the end of the second record.
+    let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(5 + record_len + 60)).unwrap();
+    file.write_all(&[0xff]).unwrap();
+    drop(file);
+
+    let store = FilterStore::open(&path);
+    assert_eq!(store.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+    let damaged = FilterStore::repair(&path).unwrap();
+    assert_eq!(damaged.records, 1);
+    assert_eq!(damaged.damage, Some(Damage::Corrupted { offset: 5 + record_len, len: record_len }));
+
+    let store = FilterStore::open(&path).unwrap();
+    assert_eq!(store.len(), 1);
+    assert_eq!(store.get(0).unwrap().unwrap(), filter(0));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn damaged_record_length() {
+    let path = env::temp_dir().join(format!("bitcoin-gcs-damaged-length-{}.dat", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let mut store = FilterStore::open(&path).unwrap();
+    for i in 0..3 {
+        store.put(u32::from(i), &hash(i), &filter(i)).unwrap();
+    }
+    drop(store);
+    let report = FilterStore::scan(&path).unwrap();
+
+    // A record whose header was written but not all of its data is the
+    // last one, it's discarded.
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    let mut torn = vec![0u8; 49];
+    torn.extend_from_slice(&100u32.to_le_bytes());
+    torn.extend_from_slice(&[0xab; 10]);
+    file.write_all(&torn).unwrap();
+    drop(file);
+    assert_eq!(FilterStore::scan(&path).unwrap().damage, Some(Damage::Truncated { offset: report.file_len }));
+    assert_eq!(FilterStore::open(&path).unwrap().len(), 3);
+    assert_eq!(FilterStore::scan(&path).unwrap(), report);
+
+    // A damaged length in the first record runs past the end of the file,
+    // but the records after it are kept.
+    let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(5 + 49)).unwrap();
+    file.write_all(&0x00ff_ffffu32.to_le_bytes()).unwrap();
+    drop(file);
+    assert_eq!(FilterStore::scan(&path).unwrap().damage, Some(Damage::InvalidLength { offset: 5 }));
+    assert_eq!(FilterStore::open(&path).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(fs::metadata(&path).unwrap().len(), report.file_len);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn upgrade_unversioned_store() {
+    let path = env::temp_dir().join(format!("bitcoin-gcs-unversioned-{}.dat", std::process::id()));
+
+    // The records of the first format, without checksums, and a truncated
+    // one.
+    let mut legacy = Vec::new();
+    for i in 0..3 {
+        let filter = filter(i);
+        legacy.extend_from_slice(&u32::from(i).to_le_bytes());
+        legacy.extend_from_slice(&hash(i)[..]);
+        legacy.extend_from_slice(&filter.n().to_le_bytes());
+        legacy.push(filter.p());
+        legacy.extend_from_slice(&filter.m().to_le_bytes());
+        legacy.extend_from_slice(&(filter.as_bytes().len() as u32).to_le_bytes());
+        legacy.extend_from_slice(filter.as_bytes());
+    }
+    legacy.extend_from_slice(&[3, 0, 0]);
+    fs::write(&path, &legacy).unwrap();
+    assert!(FilterStore::open(&path).is_err());
+
+    assert_eq!(FilterStore::upgrade(&path).unwrap(), 3);
+    let store = FilterStore::open(&path).unwrap();
+    assert_eq!(store.len(), 3);
+    assert_eq!(store.get(1).unwrap().unwrap(), filter(1));
+    assert_eq!(store.height(&hash(2)), Some(2));
+    drop(store);
+
+    assert_eq!(FilterStore::upgrade(&path).unwrap_err().kind(), ErrorKind::InvalidInput);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[derive(Default)]
+struct Counters {
+    filters_stored: AtomicUsize,
+    bytes_encoded: AtomicUsize,
+}
+
+impl Metrics for Counters {
+    fn filter_stored(&self) {
+        self.filters_stored.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn bytes_encoded(&self, bytes: usize) {
+        self.bytes_encoded.fetch_add(bytes, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn metrics() {
+    let path = env::temp_dir().join(format!("bitcoin-gcs-metrics-{}.dat", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let counters = Arc::new(Counters::default());
+    let mut store = FilterStore::open(&path).unwrap();
+    store.set_metrics(counters.clone());
+    for i in 0..3 {
+        store.put(u32::from(i), &hash(i), &filter(i)).unwrap();
+    }
+
+    assert_eq!(counters.filters_stored.load(Ordering::SeqCst), 3);
+    assert_eq!(counters.bytes_encoded.load(Ordering::SeqCst),
+               (0..3).map(|i| filter(i).serialized_size()).sum::<usize>());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn not_a_store() {
+    let path = env::temp_dir().join(format!("bitcoin-gcs-not-a-store-{}.dat", std::process::id()));
+    fs::write(&path, b"not a filter store").unwrap();
+
+    assert!(FilterStore::open(&path).is_err());
+    assert!(FilterStore::scan(&path).is_err());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn reorg() {
+    let path = env::temp_dir().join(format!("bitcoin-gcs-reorg-{}.dat", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    {
+        let mut store = FilterStore::open(&path).unwrap();
+        for i in 0..5 {
+            store.connect_block(u32::from(i), &hash(i), &filter(i)).unwrap();
+        }
+        assert!(store.connect_block(6, &hash(6), &filter(6)).is_err());
+
+        // Replace the last two blocks by a longer branch.
+        assert_eq!(store.disconnect_block().unwrap(), Some((4, hash(4))));
+        assert_eq!(store.disconnect_block().unwrap(), Some((3, hash(3))));
+        for i in 3..6 {
+            store.connect_block(u32::from(i), &hash(30 + i), &filter(30 + i)).unwrap();
+        }
+    }
+
+    let mut store = FilterStore::open(&path).unwrap();
+    assert_eq!(store.tip_height(), Some(5));
+    assert_eq!(store.status(&hash(33)), Some(BlockStatus::Active(3)));
+    assert_eq!(store.status(&hash(4)), Some(BlockStatus::Stale(4)));
+    assert_eq!(store.get_by_hash(&hash(4)).unwrap(), None);
+    assert_eq!(store.lookup(&hash(4)).unwrap(), Some((BlockStatus::Stale(4), filter(4))));
+    assert_eq!(store.lookup(&hash(2)).unwrap(), Some((BlockStatus::Active(2), filter(2))));
+
+    store.prune(1).unwrap();
+    let mut store = FilterStore::open(&path).unwrap();
+    assert_eq!(store.len(), 5);
+    assert_eq!(store.status(&hash(3)), Some(BlockStatus::Stale(3)));
+    assert_eq!(store.lookup(&hash(35)).unwrap(), Some((BlockStatus::Active(5), filter(35))));
+
+    // The orphaned blocks are dropped once the chain is far enough above
+    // them.
+    store.set_orphan_retention(2);
+    assert_eq!(store.status(&hash(3)), None);
+    assert_eq!(store.status(&hash(4)), Some(BlockStatus::Stale(4)));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn script_index() {
+    let path = env::temp_dir().join(format!("bitcoin-gcs-index-{}.dat", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    {
+        let mut index = ScriptIndex::open(&path).unwrap();
+        for i in 0..10u8 {
+            index.add_block(u32::from(i), vec![vec![i % 3], vec![i % 3], vec![100 + i]]).unwrap();
+        }
+        // Replace the tip, as in a reorganization.
+        index.add_block(9, vec![vec![200]]).unwrap();
+    }
+
+    let mut index = ScriptIndex::open(&path).unwrap();
+    assert_eq!(index.tip_height(), Some(9));
+    assert_eq!(index.heights(&[1]), &[1, 4, 7]);
+    assert_eq!(index.heights(&[0]), &[0, 3, 6]);
+    assert_eq!(index.heights(&[105]), &[5]);
+    assert_eq!(index.heights(&[200]), &[9]);
+    assert_eq!(index.heights(&[109]), &[] as &[u32]);
+
+    index.add_block(5, vec![vec![1]]).unwrap();
+    assert_eq!(index.heights(&[1]), &[1, 4, 5]);
+    assert_eq!(index.heights(&[200]), &[] as &[u32]);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mapped_store() {
+    let path = env::temp_dir().join(format!("bitcoin-gcs-mmap-{}.dat", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let mut store = FilterStore::open(&path).unwrap();
+    assert!(store.map().unwrap().get(0).unwrap().is_none());
+
+    for i in 0..10 {
+        store.put(u32::from(i), &hash(i), &filter(i)).unwrap();
+    }
+
+    let map = store.map().unwrap();
+    let mapped = map.get(3).unwrap().unwrap();
+    assert_eq!(mapped.as_bytes(), filter(3).as_bytes());
+    assert_eq!(mapped.is_member(FilterKey::default(), &[3, 3]), Ok(true));
+    assert_eq!(mapped.into_owned(), filter(3));
+    assert_eq!(map.get_by_hash(&hash(7)).unwrap().unwrap().n(), 2);
+    assert!(map.get(10).unwrap().is_none());
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,86 @@
+extern crate bitcoin;
+extern crate bitcoin_gcs;
+extern crate serde_json;
+
+use bitcoin::blockdata::block::{Block, BlockHeader};
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{Transaction, TxOut};
+use bitcoin::util::hash::Sha256dHash;
+
+use bitcoin_gcs::testgen::{TestGenError, TestVector, TestVectorWriter, COLUMNS};
+use bitcoin_gcs::FilterHeader;
+
+use serde_json::Value;
+
+const TESTNET_19: &str = include_str!("testnet-19.json");
+
+#[test]
+fn regenerate_testnet_19() {
+    let json: Value = serde_json::from_str(TESTNET_19).unwrap();
+    let rows = json.as_array().unwrap();
+
+    let mut writer = TestVectorWriter::new(Vec::new());
+    for row in &rows[1..] {
+        let prev_scripts = row[3].as_array().unwrap()
+            .iter()
+            .map(|script| script.as_str().unwrap())
+            .collect::<Vec<&str>>();
+        let test_vector = TestVector::from_hex(row[0].as_u64().unwrap() as u32,
+                                               row[2].as_str().unwrap(),
+                                               &prev_scripts,
+                                               row[4].as_str().unwrap().parse().unwrap(),
+                                               row[7].as_str().unwrap())
+            .unwrap();
+        writer.write(&test_vector).unwrap();
+    }
+    assert_eq!(writer.rows(), rows.len() - 1);
+
+    let generated = writer.finish().unwrap();
+    assert_eq!(String::from_utf8(generated).unwrap(), TESTNET_19);
+}
+
+#[test]
+fn generated_rows() {
+    let tx = Transaction {
+        version: 1,
+        lock_time: 0,
+        input: vec![],
+        output: vec![TxOut { value: 1000, script_pubkey: Script::from(vec![0x51]) }],
+        witness: vec![],
+    };
+    let block = Block {
+        header: BlockHeader {
+            version: 1,
+            prev_blockhash: Sha256dHash::from(&[1u8; 32][..]),
+            merkle_root: Sha256dHash::from(&[2u8; 32][..]),
+            time: 0,
+            bits: 0,
+            nonce: 0,
+        },
+        txdata: vec![tx],
+    };
+
+    let prev_scripts = vec![Script::from(vec![0x00, 0x14, 0xaa])];
+    let test_vector = TestVector::new(7, block, prev_scripts, FilterHeader::zero(), "Regtest \"block\"");
+    assert_eq!(test_vector.header, test_vector.filter.filter_header(&FilterHeader::zero()));
+
+    let mut writer = TestVectorWriter::new(Vec::new());
+    writer.write(&test_vector).unwrap();
+    writer.write(&test_vector).unwrap();
+    let json: Value = serde_json::from_slice(&writer.finish().unwrap()).unwrap();
+
+    let rows = json.as_array().unwrap();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[0][0], COLUMNS);
+    assert_eq!(rows[1][0], 7);
+    assert_eq!(rows[1][3][0], "0014aa");
+    assert_eq!(rows[1][5], test_vector.filter.to_string());
+    assert_eq!(rows[1][6], test_vector.header.to_string());
+    assert_eq!(rows[1][7], "Regtest \"block\"");
+
+    let empty = TestVectorWriter::new(Vec::new()).finish().unwrap();
+    assert_eq!(serde_json::from_slice::<Value>(&empty).unwrap()[0][0], COLUMNS);
+
+    let error = TestVector::from_hex(0, "0x", &[], FilterHeader::zero(), "").unwrap_err();
+    assert_eq!(error, TestGenError::InvalidHex);
+}
@@ -0,0 +1,70 @@
+extern crate bitcoin_gcs;
+extern crate tracing;
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+use bitcoin_gcs::{Filter, FilterKey, BASIC_FILTER_M, BASIC_FILTER_P};
+
+#[derive(Default)]
+struct Fields(Vec<(&'static str, String)>);
+
+// Records the fields of every event.
+struct Recorder(Arc<Mutex<Vec<Fields>>>);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.push((field.name(), format!("{:?}", value)));
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn new_span(&self, _: &Attributes) -> Id {
+        Id::from_u64(1)
+    }
+
+    fn record(&self, _: &Id, _: &Record) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        self.0.lock().unwrap().push(fields);
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+fn field<'a>(fields: &'a Fields, name: &str) -> Option<&'a str> {
+    fields.0.iter().find(|f| f.0 == name).map(|f| f.1.as_str())
+}
+
+#[test]
+fn build_and_match_events() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    tracing::subscriber::set_global_default(Recorder(events.clone())).unwrap();
+
+    let data = (0..10u8).map(|i| vec![i % 8]).collect::<Vec<Vec<u8>>>();
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::default(), &data);
+    assert_eq!(filter.is_member(FilterKey::default(), &[3]), Ok(true));
+
+    let events = events.lock().unwrap();
+    assert_eq!(field(&events[0], "entries"), Some("10"));
+    assert_eq!(field(&events[0], "duplicates"), Some("2"));
+    assert_eq!(field(&events[1], "message"), Some("built filter"));
+    assert_eq!(field(&events[1], "n"), Some("8"));
+    assert_eq!(field(&events[2], "message"), Some("matched filter"));
+    assert_eq!(field(&events[2], "matched"), Some("true"));
+    assert!(field(&events[2], "read").unwrap().parse::<u32>().unwrap() <= 8);
+}
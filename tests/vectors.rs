@@ -5,47 +5,589 @@ extern crate serde_json;
 extern crate hex;
 
 use std::io::Cursor;
+use std::str::FromStr;
 
-use bitcoin::blockdata::block::Block;
+use bitcoin::blockdata::block::{Block, BlockHeader};
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{Transaction, TxIn, TxOut};
 use bitcoin::network::encodable::ConsensusDecodable;
-use bitcoin::network::serialize::RawDecoder;
+use bitcoin::network::serialize::{deserialize, serialize, BitcoinHash, RawDecoder};
+use bitcoin::util::address::Address;
 use bitcoin::util::hash::Sha256dHash;
 
+use bitcoin_gcs::builder::{build_basic_filter, build_basic_filter_with_manifest, build_basic_filter_with_prev_scripts, build_filters_parallel,
+                           build_script_class_filter, build_tx_filter, verify_filter, BasicFilter, Builder, EntrySource, FilterType,
+                           ScriptClass, TemplateFilter};
+use bitcoin_gcs::silent_payments::{input_public_keys, SilentPaymentFilter};
+use bitcoin_gcs::{DecodeError, ExactFilter, FaultyPeer, Filter, FilterHeader, FilterHeaderChain, FilterHeaders, FilterKey, FilterParams,
+                  HeaderMismatchResolver, BASIC_FILTER_M, BASIC_FILTER_P};
+
 use serde_json::Value;
 
-const TESTNET_19: &'static str = include_str!("testnet-19.json");
+const TESTNET_19: &str = include_str!("testnet-19.json");
 
 #[test]
 fn testnet_19() {
-    let json: Value = serde_json::from_str(TESTNET_19)
-        .expect("invalid test vector");
+    for tv in test_vectors() {
+        let filter = bitcoin_gcs::builder::build_basic_filter_with_prev_scripts(
+            &tv.block,
+            &tv.prevoutputscriptsforblock,
+        );
 
-    let data = json.as_array().expect("invalid test vector");
+        let diff = bitcoin_gcs::diff(&filter, &tv.basicfilter);
+        assert!(diff.is_identical(), "block {}: {}", tv.blockheight, diff);
+    }
+}
+
+#[test]
+fn testnet_19_streaming() {
+    for tv in test_vectors() {
+        let mut builder = Builder::with_params(FilterParams::basic(), FilterKey::from(&tv.block.bitcoin_hash()));
+        builder.streaming();
+
+        for entry in BasicFilter::extract_entries(&tv.block, &tv.prevoutputscriptsforblock) {
+            builder.add_entry(entry);
+        }
+
+        assert_eq!(builder.build().as_bytes(), tv.basicfilter.as_bytes(), "block {}", tv.blockheight);
+    }
+}
+
+#[test]
+fn testnet_19_filter_headers() {
+    for tv in test_vectors() {
+        let prev_header = FilterHeader::from_hash(tv.previousbasicheader);
+        let header = tv.basicfilter.filter_header(&prev_header);
+
+        assert_eq!(*header.as_hash(), tv.basicheader, "block {}", tv.blockheight);
+    }
+}
+
+#[test]
+fn testnet_19_header_chain() {
+    let tv = &test_vectors()[0];
+
+    let mut chain = FilterHeaderChain::new();
+    let header = FilterHeader::from_hash(tv.basicheader);
+    chain.push_checked(&[tv.basicfilter.filter_hash()], &header)
+        .expect("genesis header mismatch");
+
+    assert_eq!(chain.height(), Some(0));
+    assert_eq!(chain.find_divergence(0, &[header]), None);
+    assert_eq!(chain.find_divergence(0, &[FilterHeader::zero()]), Some(0));
+    assert!(chain.push_checked(&[tv.basicfilter.filter_hash()], &header).is_err());
+    assert_eq!(chain.len(), 1);
+}
+
+#[test]
+fn corrupt_expected_filter() {
+    let key = FilterKey::new(1, 2);
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, key, vec![b"a", b"b", b"c"]);
+    let corrupt = Filter::from_bytes(3, BASIC_FILTER_P, BASIC_FILTER_M, filter.as_bytes()[..2].to_vec());
+    assert_eq!(verify_filter(&filter, &filter), Ok(()));
+
+    let mismatch = verify_filter(&corrupt, &filter).unwrap_err();
+    assert_eq!(mismatch.expected_decode_error, Some(DecodeError::UnexpectedEof));
+    assert_eq!(mismatch.decode_error, None);
+    let mismatch = verify_filter(&filter, &corrupt).unwrap_err();
+    assert_eq!(mismatch.decode_error, Some(DecodeError::UnexpectedEof));
+    assert_eq!(mismatch.expected_decode_error, None);
+}
+
+#[test]
+fn empty_header_batch() {
+    let mut chain = FilterHeaderChain::new();
+    let checkpoint = FilterHeader::from_hash(Sha256dHash::from(&[1u8; 32][..]));
+    assert_eq!(chain.push_checked(&[], &checkpoint), Ok(()));
+    assert!(chain.is_empty());
+
+    chain.push_filter_hash(&Sha256dHash::from(&[2u8; 32][..]));
+    assert_eq!(chain.push_checked(&[], &checkpoint).unwrap_err().height, 0);
+    assert_eq!(chain.push_checked(&[], &chain.tip()), Ok(()));
+    assert_eq!(chain.len(), 1);
+}
+
+#[test]
+fn header_chain_from_filters() {
+    let blocks = (0..2500u32)
+        .map(|i| {
+            let block_hash = Sha256dHash::from_data(&i.to_le_bytes());
+            let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::from(&block_hash),
+                                       [i.to_be_bytes()]);
+            (block_hash, filter)
+        })
+        .collect::<Vec<_>>();
+
+    let mut expected = FilterHeaderChain::new();
+    for (_, filter) in &blocks {
+        expected.push_filter(filter);
+    }
+
+    let chain = FilterHeaderChain::from_filters(blocks.clone());
+    assert_eq!(chain, expected);
+    assert_eq!(chain.checkpoints(), vec![*chain.get(1000).unwrap(), *chain.get(2000).unwrap()]);
+
+    // Resume from the middle of the chain.
+    let mut headers = FilterHeaders::new(*chain.get(1499).unwrap(), blocks[1500..].to_vec());
+    assert_eq!(headers.next(), Some((blocks[1500].0, *chain.get(1500).unwrap())));
+    assert_eq!(headers.last(), Some((blocks[2499].0, chain.tip())));
+}
 
-    let tv = data[1].as_array().expect("invalid test vector");
-    let tv = TestVector::from_json(tv);
+#[test]
+fn header_chain_from_checkpoint() {
+    let blocks = (0..2500u32)
+        .map(|i| {
+            let block_hash = Sha256dHash::from_data(&i.to_le_bytes());
+            let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::from(&block_hash),
+                                       [i.to_be_bytes()]);
+            (block_hash, filter)
+        })
+        .collect::<Vec<_>>();
+    let full = FilterHeaderChain::from_filters(blocks.clone());
 
-    println!("{:?}", tv);
+    let mut chain = FilterHeaderChain::from_checkpoint(1499, *full.get(1499).unwrap());
+    assert_eq!(chain.height(), Some(1499));
+    assert_eq!(chain.tip(), *full.get(1499).unwrap());
+    for (_, filter) in &blocks[1500..] {
+        chain.push_filter(filter);
+    }
 
-    let filter = bitcoin_gcs::builder::build_basic_filter(&tv.block);
+    assert_eq!(chain.len(), full.len());
+    assert_eq!(chain.start_height(), 1499);
+    assert_eq!(chain.get(1498), None);
+    assert_eq!(chain.get(2000), full.get(2000));
+    assert_eq!(chain.verify_checkpoints(&full.checkpoints()), Ok(()));
+    assert_eq!(chain.find_divergence(1000, &full.checkpoints()), None);
+    assert_eq!(chain.find_divergence(1000, &[FilterHeader::zero(); 1000]), Some(1499));
 
-    assert_eq!(filter.as_bytes(), tv.basicfilter.as_bytes());
+    assert_eq!(FilterHeaderChain::from_bytes(&chain.to_bytes()), Ok(chain.clone()));
+    assert_eq!(FilterHeaderChain::from_bytes(&full.to_bytes()), Ok(full));
+    assert!(FilterHeaderChain::from_bytes(&chain.to_bytes()[..99]).is_err());
+
+    // The checkpoint is kept when the whole chain above it is disconnected.
+    chain.truncate(1000);
+    assert_eq!(chain.height(), Some(1499));
 }
 
+#[test]
+fn header_mismatch_bisection() {
+    let header = |height: u32, peer: u8| {
+        let mut hash = [0u8; 32];
+        hash[..4].copy_from_slice(&height.to_le_bytes());
+        if height >= 1234 {
+            hash[4] = peer;
+        }
+        FilterHeader::from_hash(Sha256dHash::from(&hash[..]))
+    };
+    let checkpoints = |peer| (1..4).map(|i| header(i * 1000, peer)).collect::<Vec<_>>();
+
+    assert_eq!(HeaderMismatchResolver::from_checkpoints(&checkpoints(1), &checkpoints(1)), None);
+
+    let mut resolver = HeaderMismatchResolver::from_checkpoints(&checkpoints(1), &checkpoints(2)).unwrap();
+    assert_eq!(resolver.prev_header(), header(1000, 1));
+    while let Some(height) = resolver.bisect_height() {
+        assert!(height > 1000 && height < 2000);
+        resolver.update(height, header(height, 1), header(height, 2));
+    }
+    assert_eq!(resolver.mismatch_height(), Some(1234));
+    assert_eq!(resolver.prev_header(), header(1233, 1));
+}
+
+#[test]
+fn header_mismatch_resolution() {
+    let block = block_paying(&[Script::from(vec![0x00, 0x14, 0xaa])]);
+
+    let prev_header = FilterHeader::from_hash(Sha256dHash::from(&[3u8; 32][..]));
+    let filter = build_basic_filter_with_prev_scripts(&block, &[]);
+    let bogus = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::default(), [[0u8]]);
+    let resolver = HeaderMismatchResolver::new(5, prev_header, 5,
+                                               filter.filter_header(&prev_header),
+                                               bogus.filter_header(&prev_header));
+
+    assert_eq!(resolver.resolve(&block, &[], &filter, &bogus), Some(FaultyPeer::B));
+    assert_eq!(resolver.resolve(&block, &[], &bogus, &bogus), Some(FaultyPeer::Both));
+}
+
+#[test]
+fn exact_block_filter() {
+    let scripts = [Script::from(vec![0x00, 0x14, 0xaa]), Script::from(vec![0x00, 0x14, 0xbb])];
+    let prev_scripts = [Script::from(vec![0x00, 0x14, 0xcc])];
+    let block = block_paying(&scripts);
+
+    let exact = ExactFilter::from_block(&block, &prev_scripts);
+    assert_eq!(exact.filter(), &build_basic_filter_with_prev_scripts(&block, &prev_scripts));
+
+    let wallet = [scripts[1].clone(), Script::from(vec![0x00, 0x14, 0xdd]), prev_scripts[0].clone()];
+    assert_eq!(exact.confirmed_scripts(&wallet), vec![&wallet[0], &wallet[2]]);
+}
+
+#[test]
+fn parallel_filters() {
+    let blocks = (0..300u32)
+        .map(|i| {
+            let script = Script::from([&[0x00, 0x14][..], &i.to_le_bytes()[..]].concat());
+            (block_paying(::std::slice::from_ref(&script)), vec![script])
+        })
+        .collect::<Vec<(Block, Vec<Script>)>>();
+
+    let filters = build_filters_parallel(blocks.clone());
+    assert_eq!(filters.len(), blocks.len());
+    for (filter, (block, prev_scripts)) in filters.iter().zip(&blocks) {
+        assert_eq!(filter, &build_basic_filter_with_prev_scripts(block, prev_scripts));
+    }
+}
+
+#[test]
+fn wallet_queries() {
+    let paid = Script::from(vec![0x00, 0x14, 1, 2, 3]);
+    let spent = TxOut { value: 1000, script_pubkey: Script::from(vec![0x00, 0x14, 4, 5, 6]) };
+    let other = TxOut { value: 1000, script_pubkey: Script::from(vec![0x00, 0x14, 7, 8, 9]) };
+
+    let block = block_paying(::std::slice::from_ref(&paid));
+    let filter = build_basic_filter_with_prev_scripts(&block, ::std::slice::from_ref(&spent.script_pubkey));
+    let key = FilterKey::from(&block.bitcoin_hash());
+
+    assert_eq!(filter.contains_payment_to(key, vec![&paid]), Ok(true));
+    assert_eq!(filter.contains_payment_to(key, vec![&other.script_pubkey]), Ok(false));
+    assert_eq!(filter.contains_spend_of(key, vec![&other, &spent]), Ok(true));
+    assert_eq!(filter.contains_spend_of(key, vec![&other]), Ok(false));
+}
+
+#[test]
+fn draft_basic_filter_adds_whole_scripts() {
+    let script = Script::from(vec![0x00, 0x14, 1, 2, 3]);
+    let block = block_paying(::std::slice::from_ref(&script));
+    let filter = build_basic_filter(&block);
+    let key = FilterKey::from(&block.bitcoin_hash());
+
+    assert_eq!(filter.is_member(key, &script.data()), Ok(true));
+    assert_eq!(filter.is_member(key, &[1, 2, 3]), Ok(false));
+}
+
+#[test]
+fn template_filter() {
+    let paid = Script::from(vec![0x00, 0x14, 1, 2, 3]);
+    let data = Script::from(vec![0x6a, 0x01, 0x2a]);
+    let prev_scripts = [Script::from(vec![0x51, 0x20, 4, 5, 6]), Script::new(), paid.clone()];
+
+    let mut block = block_paying(&[paid.clone(), paid.clone(), data]);
+    let template = TemplateFilter::new(&block.txdata, &prev_scripts);
+    assert_eq!(template.n(), 2);
+    assert_eq!(template.entries(), &[vec![0x00, 0x14, 1, 2, 3], vec![0x51, 0x20, 4, 5, 6]][..]);
+    assert_eq!(template.estimated_size(), Filter::estimated_size(2, BASIC_FILTER_P, BASIC_FILTER_M));
+
+    // The filter is only known once the header is found.
+    for nonce in 0..10 {
+        block.header.nonce = nonce;
+        let filter = template.build(&block.bitcoin_hash());
+        assert_eq!(filter, build_basic_filter_with_prev_scripts(&block, &prev_scripts));
+        assert!((filter.as_bytes().len() as isize - template.estimated_size() as isize).abs() <= 2);
+    }
+}
+
+#[test]
+fn address_queries() {
+    // The output script of the genesis coinbase address, and a P2SH one.
+    let paid = Address::from_str("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa").unwrap();
+    let other = Address::from_str("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy").unwrap();
+    let script = Script::from(hex::decode("76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac").unwrap());
+
+    let block = block_paying(&[script]);
+    let filter = build_basic_filter_with_prev_scripts(&block, &[]);
+    let key = FilterKey::from(&block.bitcoin_hash());
+
+    assert_eq!(filter.matches_address(key, &paid), Ok(true));
+    assert_eq!(filter.matches_address(key, &other), Ok(false));
+    assert_eq!(filter.matches_any_address(key, vec![&other, &paid]), Ok(true));
+    assert_eq!(filter.matches_any_address(key, vec![&other]), Ok(false));
+    assert_eq!(filter.matches_any_address(key, vec![]), Ok(false));
+}
+
+#[test]
+fn script_class_filter() {
+    let script = |prefix: &[u8], len: usize, suffix: &[u8]| {
+        let mut data = prefix.to_vec();
+        data.resize(prefix.len() + len, 0xab);
+        data.extend_from_slice(suffix);
+        Script::from(data)
+    };
+    let p2pkh = script(&[0x76, 0xa9, 0x14], 20, &[0x88, 0xac]);
+    let p2wpkh = script(&[0x00, 0x14], 20, &[]);
+    let p2tr = script(&[0x51, 0x20], 32, &[]);
+    let mut spent_p2tr = vec![0x51, 0x20];
+    spent_p2tr.extend_from_slice(&[0xcd; 32]);
+    let prev_scripts = [Script::from(spent_p2tr)];
+
+    assert_eq!(ScriptClass::of(&p2pkh), ScriptClass::P2pkh);
+    assert_eq!(ScriptClass::of(&script(&[0xa9, 0x14], 20, &[0x87])), ScriptClass::P2sh);
+    assert_eq!(ScriptClass::of(&p2wpkh), ScriptClass::P2wpkh);
+    assert_eq!(ScriptClass::of(&script(&[0x00, 0x20], 32, &[])), ScriptClass::P2wsh);
+    assert_eq!(ScriptClass::of(&p2tr), ScriptClass::P2tr);
+    assert_eq!(ScriptClass::of(&script(&[0x52, 0x02], 2, &[])), ScriptClass::WitnessUnknown);
+    assert_eq!(ScriptClass::of(&script(&[33], 33, &[0xac])), ScriptClass::P2pk);
+    assert_eq!(ScriptClass::of(&script(&[0x6a, 0x01], 1, &[])), ScriptClass::NullData);
+    assert_eq!(ScriptClass::of(&Script::new()), ScriptClass::NonStandard);
+
+    let block = block_paying(&[p2pkh.clone(), p2wpkh.clone(), p2tr.clone()]);
+    let key = FilterKey::from(&block.bitcoin_hash());
+    let filter = build_script_class_filter(&block, &prev_scripts, &[ScriptClass::P2tr]);
+    assert_eq!(filter.n(), 2);
+    assert_eq!(filter.match_all(key, [p2tr.data(), prev_scripts[0].data()]), Ok(vec![true, true]));
+
+    let filter = build_script_class_filter(&block, &[], &[ScriptClass::P2pkh, ScriptClass::P2wpkh]);
+    assert_eq!(filter.match_all(key, [p2pkh.data(), p2wpkh.data(), p2tr.data()]), Ok(vec![true, true, false]));
+}
+
+#[test]
+fn silent_payment_filter() {
+    let spend = |prev_index: u32, script_sig: Vec<u8>, witness: Vec<Vec<u8>>, output_key: u8| {
+        let mut p2tr = vec![0x51, 0x20];
+        p2tr.extend_from_slice(&[output_key; 32]);
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                prev_hash: Sha256dHash::from(&[9u8; 32][..]),
+                prev_index,
+                script_sig: Script::from(script_sig),
+                sequence: 0xffffffff,
+            }],
+            output: vec![TxOut { value: 1000, script_pubkey: Script::from(p2tr) }],
+            witness: vec![witness],
+        }
+    };
+    let mut pubkey = vec![0x02];
+    pubkey.extend_from_slice(&[0x11; 32]);
+    let mut nums_control_block = vec![0xc0];
+    nums_control_block.extend_from_slice(&hex::decode("50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0").unwrap());
+    let mut nested_p2wpkh = vec![0x16, 0x00, 0x14];
+    nested_p2wpkh.extend_from_slice(&[0x22; 20]);
+
+    let mut p2wpkh = vec![0x00, 0x14];
+    p2wpkh.extend_from_slice(&[0x22; 20]);
+    let mut p2sh = vec![0xa9, 0x14];
+    p2sh.extend_from_slice(&[0x33; 20]);
+    p2sh.push(0x87);
+    let mut p2tr = vec![0x51, 0x20];
+    p2tr.extend_from_slice(&[0x44; 32]);
+    let mut p2wsh = vec![0x00, 0x20];
+    p2wsh.extend_from_slice(&[0x55; 32]);
+
+    let mut block = block_paying(&[]);
+    block.txdata.push(spend(0, vec![], vec![vec![0x30; 71], pubkey.clone()], 0xa1));
+    block.txdata.push(spend(1, nested_p2wpkh, vec![vec![0x30; 71], pubkey.clone()], 0xa2));
+    block.txdata.push(spend(2, vec![], vec![vec![0x40; 64], vec![0x50, 0x01]], 0xa3));
+    block.txdata.push(spend(3, vec![], vec![vec![0x51], nums_control_block], 0xa4));
+    block.txdata.push(spend(4, vec![], vec![vec![0x51], vec![0x51]], 0xa5));
+    let prev_scripts = [p2wpkh, p2sh, p2tr.clone(), p2tr, p2wsh].iter()
+        .map(|script| Script::from(script.clone()))
+        .collect::<Vec<Script>>();
+
+    assert_eq!(input_public_keys(&block.txdata[1], &prev_scripts[0..1]), vec![pubkey.clone()]);
+    assert_eq!(input_public_keys(&block.txdata[2], &prev_scripts[1..2]), vec![pubkey]);
+    assert_eq!(input_public_keys(&block.txdata[3], &prev_scripts[2..3]), vec![vec![0x44; 32]]);
+    assert!(input_public_keys(&block.txdata[4], &prev_scripts[3..4]).is_empty());
+    assert!(input_public_keys(&block.txdata[5], &prev_scripts[4..5]).is_empty());
+
+    let entries = SilentPaymentFilter::extract_entries(&block, &prev_scripts);
+    assert_eq!(entries, vec![vec![0xa1; 32], vec![0xa2; 32], vec![0xa3; 32]]);
+    assert!(SilentPaymentFilter::extract_entries(&block, &[]).is_empty());
+}
+
+#[test]
+fn filter_manifest() {
+    let p2wpkh = Script::from(vec![0x00, 0x14, 0xaa]);
+    let spent = Script::from(vec![0x51, 0x20, 0xbb]);
+    let mut block = block_paying(&[p2wpkh.clone(), Script::from(vec![0x6a, 0x01, 0xcc])]);
+    block.txdata.push(Transaction {
+        version: 1,
+        lock_time: 0,
+        input: vec![TxIn {
+            prev_hash: Sha256dHash::from(&[7u8; 32][..]),
+            prev_index: 0,
+            script_sig: Script::new(),
+            sequence: 0xffffffff,
+        }],
+        output: vec![TxOut { value: 1000, script_pubkey: p2wpkh.clone() }],
+        witness: vec![],
+    });
+    let prev_scripts = [spent.clone()];
+
+    let (filter, manifest) = build_basic_filter_with_manifest(&block, &prev_scripts);
+    let expected = build_basic_filter_with_prev_scripts(&block, &prev_scripts);
+    assert_eq!((filter.n(), filter.as_bytes()), (expected.n(), expected.as_bytes()));
+    assert_eq!((manifest.n(), manifest.entries().len()), (2, 3));
+
+    let sources = manifest.sources(&p2wpkh.data()).cloned().collect::<Vec<EntrySource>>();
+    assert_eq!(sources.len(), 2);
+    assert_eq!((sources[0].txid, sources[0].vout), (Some(block.txdata[0].txid()), Some(0)));
+    assert_eq!((sources[1].txid, sources[1].vout), (Some(block.txdata[1].txid()), Some(0)));
+    assert_eq!(manifest.sources(&spent.data()).collect::<Vec<_>>(), vec![&EntrySource {
+        txid: Some(block.txdata[1].txid()),
+        vout: None,
+        input: Some(0),
+        script_class: Some(ScriptClass::NonStandard),
+    }]);
+    assert_eq!(manifest.sources(&[0x6a, 0x01, 0xcc]).count(), 0);
+
+    for value in filter.values() {
+        assert!(manifest.by_value(value.unwrap()).count() > 0);
+    }
+
+    let mut builder = Builder::with_params(FilterParams::basic(), FilterKey::default());
+    builder.add_entry(b"untracked").track_provenance();
+    builder.set_entry_source(EntrySource { vout: Some(3), ..EntrySource::default() }).add_entry(b"tracked");
+    let (filter, manifest) = builder.build_with_manifest();
+    assert_eq!((filter.n(), manifest.entries().len()), (2, 1));
+    assert_eq!(manifest.entries()[0].data, b"tracked".to_vec());
+    assert_eq!(manifest.entries()[0].source.vout, Some(3));
+}
+
+#[test]
+fn tx_filter() {
+    let prev_hash = Sha256dHash::from(&[7u8; 32][..]);
+    let tx = Transaction {
+        version: 1,
+        lock_time: 0,
+        input: vec![TxIn {
+            prev_hash,
+            prev_index: 3,
+            script_sig: Script::new(),
+            sequence: 0xffffffff,
+        }],
+        output: vec![
+            TxOut { value: 1000, script_pubkey: Script::from(vec![0x00, 0x14, 0xaa]) },
+            TxOut { value: 0, script_pubkey: Script::from(vec![0x6a, 0x01, 0xbb]) },
+        ],
+        witness: vec![],
+    };
+
+    let key = FilterKey::from((1, 2));
+    let filter = build_tx_filter(&tx, key, 20);
+    assert_eq!(filter.n(), 2);
+
+    let mut outpoint = prev_hash.data().to_vec();
+    outpoint.extend_from_slice(&[3, 0, 0, 0]);
+    assert_eq!(filter.is_member(key, &outpoint), Ok(true));
+    assert_eq!(filter.is_member(key, &[0x00, 0x14, 0xaa]), Ok(true));
+    assert_eq!(filter.is_member(key, &[0x6a, 0x01, 0xbb]), Ok(false));
+}
+
+#[test]
+fn target_fp_rate() {
+    let mut builder = Builder::with_params(FilterParams::basic(), FilterKey::default());
+    builder.set_target_fp_rate(1.0 / 1_000_000.0);
+    for i in 0..100u32 {
+        builder.add_entry(&i.to_le_bytes());
+    }
+
+    let filter = builder.build();
+    assert_eq!((filter.p(), filter.m()), (20, 1569862));
+}
+
+#[test]
+fn builder_extend() {
+    let entries = (0..100u32).map(|i| i.to_le_bytes()).collect::<Vec<[u8; 4]>>();
+
+    let mut builder = Builder::with_params(FilterParams::basic(), FilterKey::default());
+    builder.extend(&entries[..50]).add_entry(b"other");
+    Extend::extend(&mut builder, &entries[50..]);
+    let filter = builder.build();
+
+    assert_eq!(filter.n(), 101);
+    assert!(filter.match_all(FilterKey::default(), &entries).unwrap().iter().all(|m| *m));
+}
+
+#[test]
+fn builder_entries() {
+    let entries: Vec<Vec<u8>> = (0..64usize).map(|i| vec![i as u8; i % 7]).collect();
+
+    let mut builder = Builder::with_params(FilterParams::basic(), FilterKey::default());
+    builder.extend(&entries);
+    let filter = builder.build();
+
+    assert_eq!(filter, Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::default(), &entries));
+}
+
+#[test]
+#[should_panic(expected = "the filter key must be set before streaming")]
+fn streaming_without_key() {
+    let mut builder = Builder::default();
+    builder.set_p(BASIC_FILTER_P).set_m(BASIC_FILTER_M).streaming();
+}
+
+#[test]
+#[should_panic(expected = "M must be set")]
+fn build_without_m() {
+    let mut builder = Builder::default();
+    builder.set_key(FilterKey::default()).add_entry(b"entry");
+    builder.build();
+}
+
+#[test]
+fn consensus_encoding() {
+    let items = (0..100u32).map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+    let filter = Filter::build(BASIC_FILTER_P, BASIC_FILTER_M, FilterKey::default(), &items);
+
+    let bytes = serialize(&filter).unwrap();
+    let decoded: Filter = deserialize(&bytes).unwrap();
+    assert_eq!(decoded.n(), filter.n());
+    assert_eq!(decoded.as_bytes(), filter.as_bytes());
+}
+
+/// Creates a block with a transaction paying to each script.
+fn block_paying(scripts: &[Script]) -> Block {
+    let tx = Transaction {
+        version: 1,
+        lock_time: 0,
+        input: vec![],
+        output: scripts.iter()
+            .map(|script| TxOut { value: 1000, script_pubkey: script.clone() })
+            .collect(),
+        witness: vec![],
+    };
+
+    Block {
+        header: BlockHeader {
+            version: 1,
+            prev_blockhash: Sha256dHash::from(&[1u8; 32][..]),
+            merkle_root: Sha256dHash::from(&[2u8; 32][..]),
+            time: 0,
+            bits: 0,
+            nonce: 0,
+        },
+        txdata: vec![tx],
+    }
+}
+
+fn test_vectors() -> Vec<TestVector> {
+    let json: Value = serde_json::from_str(TESTNET_19)
+        .expect("invalid test vector");
+
+    let data = json.as_array().expect("invalid test vector");
+
+    // The first row is a comment describing the columns.
+    data[1..].iter()
+        .map(|tv| TestVector::from_json(tv.as_array().expect("invalid test vector")))
+        .collect()
+}
+
+// Mirrors the columns of the test vectors, not all of them are checked.
+#[allow(dead_code)]
 #[derive(Debug)]
 struct TestVector {
     pub blockheight: u64,
     pub blockhash: Sha256dHash,
     pub block: Block,
-    pub prevoutputscriptsforblock: Value,
-    pub previousbasicheader: String,
+    pub prevoutputscriptsforblock: Vec<Script>,
+    pub previousbasicheader: Sha256dHash,
     pub basicfilter: bitcoin_gcs::Filter,
-    pub basicheader: String,
+    pub basicheader: Sha256dHash,
     pub notes: String,
 }
 
 impl TestVector {
-    fn from_json(v: &Vec<Value>) -> TestVector {
+    fn from_json(v: &[Value]) -> TestVector {
         let blockheight = v[0].as_u64().expect("Block Height");
         let blockhash = v[1].as_str()
             .map(|b| {
@@ -61,16 +603,31 @@ impl TestVector {
                 Block::consensus_decode(&mut d).expect("couldn't read block")
             })
             .expect("Block");
-        let prevoutputscriptsforblock = v[3].clone();
-        let previousbasicheader = v[4].as_str().map(|v| v.to_string()).expect("Previous Basic Header");
+        let prevoutputscriptsforblock = v[3].as_array()
+            .map(|scripts| {
+                scripts.iter()
+                    .map(|s| {
+                        let s = s.as_str().expect("invalid script");
+                        Script::from(hex::decode(s).expect("invalid hex string"))
+                    })
+                    .collect()
+            })
+            .expect("Prev Output Scripts for Block");
+        let previousbasicheader = v[4].as_str()
+            .map(|h| Sha256dHash::from_hex(h).expect("invalid hash"))
+            .expect("Previous Basic Header");
         let basicfilter = v[5].as_str()
             .map(|v| {
                 let raw = hex::decode(v).expect("invalid hex string");
-                bitcoin_gcs::Filter::from_nbytes(bitcoin_gcs::DEFAULT_P, &raw)
+                bitcoin_gcs::Filter::from_nbytes(bitcoin_gcs::BASIC_FILTER_P,
+                                                 bitcoin_gcs::BASIC_FILTER_M,
+                                                 &raw)
                     .expect("invalid filter")
             })
             .expect("Basic Filter");
-        let basicheader = v[6].as_str().map(|v| v.to_string()).expect("Basic Header");
+        let basicheader = v[6].as_str()
+            .map(|h| Sha256dHash::from_hex(h).expect("invalid hash"))
+            .expect("Basic Header");
         let notes = v[7].as_str().map(|v| v.to_string()).expect("Notes");
 
         TestVector {